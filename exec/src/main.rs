@@ -1,4 +1,4 @@
-use std::fs;
+use std::{fs, net::SocketAddr};
 
 use bold::ServerBuilder;
 use clap::Parser;
@@ -12,6 +12,17 @@ mod memoryfs;
 struct Cli {
     /// Path to a memory fs YAML file
     fakefs: Option<String>,
+    /// The address to listen on, e.g. "[::1]:11112". Can be repeated.
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:11112")]
+    listen: Vec<SocketAddr>,
+    /// Register with the rpcbind of this host, so clients find the server
+    /// without being told the port, e.g. with "mount -o port=0"
+    #[arg(long)]
+    rpcbind_register: bool,
+    /// Answer rpcbind queries at ADDR, e.g. "0.0.0.0:111" on hosts without
+    /// rpcbind. Can be repeated.
+    #[arg(long, value_name = "ADDR")]
+    rpcbind_listen: Vec<SocketAddr>,
     /// Enable debug logging
     #[arg(short, long)]
     debug: bool,
@@ -36,6 +47,13 @@ fn main() {
 
     let root = create_memory_fs(root_dir);
 
-    let server = ServerBuilder::new(root).bind("127.0.0.1:11112").build();
-    server.start();
+    let server = ServerBuilder::new(root)
+        .listen(&cli.listen)
+        .rpcbind_register(cli.rpcbind_register)
+        .rpcbind_listen(&cli.rpcbind_listen)
+        .build();
+    if let Err(e) = server.start() {
+        tracing::error!("{:#}", e);
+        std::process::exit(1);
+    }
 }
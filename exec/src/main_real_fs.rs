@@ -1,6 +1,14 @@
-use bold::ServerBuilder;
+use std::{net::SocketAddr, path::PathBuf};
+
+use bold::{
+    server::{
+        access::{ClientRule, SecFlavor},
+        transport::read_pem,
+    },
+    storage::PhysicalBackend,
+    ServerBuilder,
+};
 use clap::Parser;
-use vfs::PhysicalFS;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -9,6 +17,59 @@ struct Cli {
     #[arg(required = true)]
     path: String,
 
+    /// Clients allowed to use the share, in /etc/exports syntax, e.g.
+    /// "192.168.1.0/24(rw,insecure)". Can be repeated, the first matching
+    /// rule applies. Without rules every client has write access.
+    #[arg(long, value_name = "RULE")]
+    allow: Vec<ClientRule>,
+
+    /// The security flavors clients may use, separated by colons and the
+    /// preferred one first, e.g. "sys". Any of "none" and "sys" by default.
+    #[arg(long, value_name = "FLAVORS", value_delimiter = ':')]
+    sec: Vec<SecFlavor>,
+
+    /// The directory clients get with PUTPUBFH, relative to the share, e.g.
+    /// "/pub". The root of the share by default.
+    #[arg(long, value_name = "PATH")]
+    public: Option<String>,
+
+    /// Serve the directory read-only
+    #[arg(long)]
+    read_only: bool,
+
+    /// The address to listen on, e.g. "[::]:2049" for IPv4 and IPv6 clients.
+    /// Can be repeated.
+    #[arg(long, value_name = "ADDR", default_value = "0.0.0.0:11112")]
+    listen: Vec<SocketAddr>,
+
+    /// Remember the filehandles handed out in the file at PATH, so clients
+    /// keep using them after a restart of the server. Defaults to a file in
+    /// $XDG_STATE_HOME/bold, ~/.local/state/bold or /var/lib/bold for root
+    #[arg(long, value_name = "PATH")]
+    handle_db: Option<PathBuf>,
+
+    /// Also listen on a Unix domain socket at PATH
+    #[arg(long, value_name = "PATH")]
+    unix_socket: Option<PathBuf>,
+
+    /// Offer RPC-over-TLS with the certificate chain in this PEM file
+    #[arg(long, value_name = "PEM", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// The private key of the TLS certificate, a PEM file
+    #[arg(long, value_name = "PEM", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Register with the rpcbind of this host, so clients find the server
+    /// without being told the port, e.g. with "mount -o port=0"
+    #[arg(long)]
+    rpcbind_register: bool,
+
+    /// Answer rpcbind queries at ADDR, e.g. "0.0.0.0:111" on hosts without
+    /// rpcbind. Can be repeated.
+    #[arg(long, value_name = "ADDR")]
+    rpcbind_listen: Vec<SocketAddr>,
+
     /// Enable debug logging
     #[arg(short, long)]
     debug: bool,
@@ -30,9 +91,40 @@ fn main() {
     let root_path = &cli.path;
     println!("Sharing directory: {}", root_path);
 
-    let fs = PhysicalFS::new(root_path);
-    let root = fs.into();
+    let root = PhysicalBackend::new(root_path);
 
-    let server = ServerBuilder::new(root).bind("0.0.0.0:11112").build();
-    server.start();
-}
\ No newline at end of file
+    let mut builder = ServerBuilder::new(root);
+    builder
+        .listen(&cli.listen)
+        .read_only(cli.read_only)
+        .rpcbind_register(cli.rpcbind_register)
+        .rpcbind_listen(&cli.rpcbind_listen);
+    if cli.allow.is_empty() && !cli.read_only {
+        tracing::warn!("No --allow rules given, every client can write to the share");
+    }
+    for rule in cli.allow {
+        builder.allow("/", rule);
+    }
+    if !cli.sec.is_empty() {
+        builder.sec("/", &cli.sec);
+    }
+    if let Some(public) = &cli.public {
+        builder.public(public);
+    }
+    if let Some(path) = cli.handle_db {
+        builder.handle_db(path);
+    }
+    if let Some(path) = cli.unix_socket {
+        builder.unix_socket(path);
+    }
+    if let (Some(cert), Some(key)) = (&cli.tls_cert, &cli.tls_key) {
+        let (certs, key) =
+            read_pem(cert, key).expect("Should have been able to read the TLS files");
+        builder.tls(certs, key);
+    }
+    let server = builder.build();
+    if let Err(e) = server.start() {
+        tracing::error!("{:#}", e);
+        std::process::exit(1);
+    }
+}
@@ -1,4 +1,4 @@
-use bold::vfs;
+use bold::storage::{join_path, MemoryBackend};
 use serde::Deserialize;
 
 #[derive(Deserialize, PartialEq, Debug)]
@@ -19,26 +19,22 @@ pub enum Node {
     File(File),
 }
 
-pub fn create_memory_fs(fs_root: Directory) -> vfs::VfsPath {
-    fn create_dir(fs: &vfs::VfsPath, dir: &Directory) {
-        let dir_path = fs.join(&dir.name).unwrap();
-        dir_path.create_dir_all().unwrap();
+pub fn create_memory_fs(fs_root: Directory) -> MemoryBackend {
+    fn create_dir(fs: &MemoryBackend, parent: &str, dir: &Directory) {
+        let dir_path = join_path(parent, &dir.name);
+        fs.create_dir_all(&dir_path).unwrap();
         for node in &dir.contents {
             match node {
-                Node::Dir(dir) => create_dir(&dir_path, dir),
+                Node::Dir(dir) => create_dir(fs, &dir_path, dir),
                 Node::File(file) => {
-                    let file_path = dir_path.join(&file.name).unwrap();
-                    file_path
-                        .create_file()
-                        .unwrap()
-                        .write_all(file.contents.as_bytes())
-                        .unwrap();
+                    let file_path = join_path(&dir_path, &file.name);
+                    fs.write_file(&file_path, file.contents.as_bytes()).unwrap();
                 }
             }
         }
     }
 
-    let root: vfs::VfsPath = vfs::MemoryFS::new().into();
-    create_dir(&root, &fs_root);
+    let root = MemoryBackend::new();
+    create_dir(&root, "/", &fs_root);
     root
 }
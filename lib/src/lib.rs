@@ -1,29 +1,53 @@
 #![doc = include_str!("../README.md")]
 
 pub mod server;
+pub mod storage;
 
-use std::collections::HashMap;
-
-use bold_proto::rpc_proto::{AcceptBody, AcceptedReply, OpaqueAuth, ReplyBody};
-use bold_proto::XDRProtoCodec;
-use futures::SinkExt;
-use server::clientmanager::ClientManagerHandle;
-use server::filemanager::FileManagerHandle;
-use tokio::net::TcpListener;
-use tokio_stream::StreamExt;
-use tokio_util::codec::Framed;
-use tracing::{error, info, span, trace, Level};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Context;
+
+pub use rustls;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use server::access::{ClientRule, SecFlavor};
+use server::exports::{export_path, ExportConfig};
+use server::gss::GssMechanism;
+use server::rpcbind::{self, Responder};
+use server::transport::{Connections, Listener};
+use storage::StorageBackend;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tracing::{error, info};
 pub use vfs;
 pub use vfs::VfsPath;
 
-use crate::server::request::NfsRequest;
-use crate::server::{NFSService, NfsProtoImpl};
+use crate::server::NfsProtoImpl;
 
 pub struct NFSServer {
-    /// The listining address of the server
-    bind: String,
-    /// The root of this NFS file system
-    root: VfsPath,
+    /// The TCP addresses the server listens on
+    listen: Vec<SocketAddr>,
+    /// The exported storage backends, their mount points and client rules
+    exports: Vec<ExportConfig>,
+    /// Where filehandles are persisted, for backends without persistent handles
+    handle_db: Option<PathBuf>,
+    /// The directory of the public filehandle
+    public: String,
+    /// The mechanism behind RPCSEC_GSS, RPCSEC_GSS is not offered without one
+    gss_mechanism: Option<Arc<dyn GssMechanism>>,
+    /// The largest RPC record a client may send
+    max_record_size: usize,
+    /// The memory the calls of a connection may keep in use, beyond it
+    /// unstable WRITEs are written through
+    max_connection_memory: usize,
+    /// The Unix domain sockets the server listens on besides TCP
+    unix_sockets: Vec<PathBuf>,
+    /// The certificate chain and private key of RPC-over-TLS, not offered without them
+    tls: Option<TlsKeys>,
+    /// Whether NFSv4 is registered with the rpcbind of the host
+    rpcbind_register: bool,
+    /// The addresses the minimal rpcbind listens on, it isn't started without
+    rpcbind_listen: Vec<SocketAddr>,
     /// NFSv4.0 service
     service_0: Option<server::nfs40::NFS40Server>,
     /// The time the server was started
@@ -33,128 +57,352 @@ pub struct NFSServer {
 
 impl NFSServer {
     // This method will help users to discover the builder
-    pub fn builder(root: VfsPath) -> ServerBuilder {
-        ServerBuilder::new(root)
+    pub fn builder(backend: impl StorageBackend + 'static) -> ServerBuilder {
+        ServerBuilder::new(backend)
     }
 
-    /// Start the NFS server, serve forever
+    /// Start the NFS server, serve until Ctrl-C or SIGTERM
     /// This starts a tokio runtime and serves the NFS requests
-    pub fn start(&self) {
+    ///
+    /// Fails if an address or socket can't be listened on or the TLS
+    /// certificate can't be used.
+    pub fn start(&self) -> anyhow::Result<()> {
         tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
-            .unwrap()
+            .context("couldn't start the tokio runtime")?
             .block_on(async {
-                let listener = TcpListener::bind(self.bind.clone()).await.unwrap();
-                info!(%self.bind, "Server listening");
-
-                // start the client manager and file manager
-                // configs go here
-                let client_manager_handle = ClientManagerHandle::new();
-                let file_manager_handle = FileManagerHandle::new(self.root.clone(), None);
-
-                loop {
-                    match listener.accept().await {
-                        Ok((stream, addr)) => {
-                            let _ = stream.set_nodelay(true);
-                            info!(%addr, "Client connected");
-                            let span = span!(Level::TRACE, "client", %addr);
-                            let _enter = span.enter();
-                            // Reading NFS RPC messages over record marking codec
-                            let mut nfs_transport = Framed::new(stream, XDRProtoCodec::new());
-                            // clone NFS server to move into the pipeline and actor connects with shared state
-                            // a per-client based filehandle cache
-                            let mut filehandle_cache = HashMap::new();
-
-                            loop {
-                                let msg = nfs_transport.next().await;
-                                match msg {
-                                    Some(Ok(msg)) => {
-                                        // create a NFS request
-                                        let request = NfsRequest::new(
-                                            addr.to_string(),
-                                            client_manager_handle.clone(),
-                                            file_manager_handle.clone(),
-                                            self.boot_time,
-                                            Some(&mut filehandle_cache),
-                                        );
-                                        // ToDo implement and select correct version of NFS protocol, this services all with minor version 0
-                                        let nfs_protocol = self.service_0.as_ref().unwrap();
-                                        let service = NFSService::new(nfs_protocol.clone());
-
-                                        let resp = service.call(msg, request).await;
-                                        match nfs_transport.send(resp).await {
-                                            Ok(_) => {
-                                                trace!("response sent");
-                                            }
-                                            Err(e) => {
-                                                error!("couldn't send response: {:?}", e);
-                                                break;
-                                            }
-                                        }
-                                    }
-                                    Some(Err(e)) => {
-                                        error!("couldn't get message: {:?}", e);
-                                        let resp = Box::new(bold_proto::rpc_proto::RpcReplyMsg {
-                                            xid: 0,
-                                            body: bold_proto::rpc_proto::MsgType::Reply(
-                                                ReplyBody::MsgAccepted(AcceptedReply {
-                                                    verf: OpaqueAuth::AuthNull(Vec::<u8>::new()),
-                                                    reply_data: AcceptBody::GarbageArgs,
-                                                }),
-                                            ),
-                                        });
-                                        match nfs_transport.send(resp).await {
-                                            Ok(_) => {
-                                                trace!("response sent");
-                                            }
-                                            Err(e) => {
-                                                error!("couldn't send response: {:?}", e);
-                                                break;
-                                            }
-                                        }
-                                    }
-                                    None => {
-                                        // client closed connection
-                                        info!(%addr, "Client disconnected");
-                                        break;
-                                    }
-                                }
-                            }
+                let mut terminate =
+                    signal(SignalKind::terminate()).context("couldn't watch for SIGTERM")?;
+                let connections = Connections::new(self).context("couldn't set up TLS")?;
+
+                // with an IPv4 address to listen on as well, IPv4 clients
+                // can't connect to an IPv6 socket
+                let only_v6 = self.listen.iter().any(SocketAddr::is_ipv4);
+                let mut listeners = Vec::new();
+                for addr in self.listen.iter() {
+                    let listener = Listener::tcp(*addr, only_v6)
+                        .with_context(|| format!("couldn't listen on {}", addr))?;
+                    listeners.push(listener);
+                }
+                for path in self.unix_sockets.iter() {
+                    let listener = Listener::unix(path)
+                        .with_context(|| format!("couldn't listen on {}", path.display()))?;
+                    listeners.push(listener);
+                }
+                for listener in listeners.iter() {
+                    info!(%listener, "Server listening");
+                }
+
+                let addrs: Vec<SocketAddr> =
+                    listeners.iter().filter_map(Listener::tcp_addr).collect();
+                let mappings = rpcbind::mappings(&addrs, only_v6);
+                let responder = Responder::bind(&self.rpcbind_listen).with_context(|| {
+                    format!("couldn't listen for rpcbind on {:?}", self.rpcbind_listen)
+                })?;
+                for addr in self.rpcbind_listen.iter() {
+                    info!(%addr, "rpcbind listening");
+                }
+                let registered = self.rpcbind_register
+                    && match rpcbind::register(&mappings).await {
+                        Ok(()) => {
+                            info!("Registered with rpcbind");
+                            true
+                        }
+                        Err(e) => {
+                            error!("couldn't register with rpcbind: {:?}", e);
+                            false
                         }
-                        Err(e) => error!("couldn't get client: {:?}", e),
+                    };
+
+                tokio::select! {
+                    _ = connections.serve(&listeners) => {}
+                    _ = responder.serve(&mappings) => {}
+                    _ = shutdown(&mut terminate) => info!("Server shutting down"),
+                }
+                if registered {
+                    if let Err(e) = rpcbind::unregister(&mappings).await {
+                        error!("couldn't unregister from rpcbind: {:?}", e);
                     }
                 }
-            });
+                Ok(())
+            })
+    }
+}
+
+// Ctrl-C or SIGTERM
+async fn shutdown(terminate: &mut Signal) {
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
     }
 }
 
+/// The memory the calls of a connection may keep in use by default.
+pub const MAX_CONNECTION_MEMORY: usize = 64 * 1024 * 1024;
+
+// the key material of RPC-over-TLS, checked when the server starts
+struct TlsKeys {
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+}
+
 pub struct ServerBuilder {
-    /// The listining address of the server
-    bind: String,
-    /// The root of this NFS file system
-    root: VfsPath,
+    /// The TCP addresses to listen on
+    listen: Vec<SocketAddr>,
+    /// The exported storage backends, their mount points and client rules
+    exports: Vec<ExportConfig>,
+    /// Where filehandles are persisted, for backends without persistent handles
+    handle_db: Option<PathBuf>,
+    /// The directory of the public filehandle
+    public: String,
+    /// Whether no export may be changed
+    read_only: bool,
+    /// The mechanism behind RPCSEC_GSS
+    gss_mechanism: Option<Arc<dyn GssMechanism>>,
+    /// The largest RPC record a client may send
+    max_record_size: usize,
+    /// The memory the calls of a connection may keep in use
+    max_connection_memory: usize,
+    /// The Unix domain sockets to listen on
+    unix_sockets: Vec<PathBuf>,
+    /// The certificate chain and private key of RPC-over-TLS
+    tls: Option<TlsKeys>,
+    /// Whether to register with the rpcbind of the host
+    rpcbind_register: bool,
+    /// The addresses of the minimal rpcbind
+    rpcbind_listen: Vec<SocketAddr>,
 }
 
 impl ServerBuilder {
-    pub fn new(root: VfsPath) -> Self {
+    /// Export `backend` as the root of the server
+    pub fn new(backend: impl StorageBackend + 'static) -> Self {
+        let mut builder = Self::pseudo_root();
+        builder
+            .exports
+            .push(ExportConfig::new("/", Arc::new(backend)));
+        builder
+    }
+
+    /// Start without a root export, the file systems added with
+    /// [`ServerBuilder::export`] are joined by a read-only pseudo file system
+    pub fn pseudo_root() -> Self {
         ServerBuilder {
-            bind: "127.0.0.1:11112".to_string(),
-            root,
+            listen: vec![SocketAddr::from(([127, 0, 0, 1], 11112))],
+            exports: Vec::new(),
+            handle_db: None,
+            public: "/".to_string(),
+            read_only: false,
+            gss_mechanism: None,
+            max_record_size: bold_proto::MAX_RECORD,
+            max_connection_memory: MAX_CONNECTION_MEMORY,
+            unix_sockets: Vec::new(),
+            tls: None,
+            rpcbind_register: false,
+            rpcbind_listen: Vec::new(),
         }
     }
 
+    /// Export `backend` at `path`, e.g. `/data`.
+    ///
+    /// Panics if `path` is not absolute, is already taken, is nested in another
+    /// export or if the server was built with a root export.
+    pub fn export(&mut self, path: &str, backend: impl StorageBackend + 'static) -> &mut Self {
+        let path = match export_path(path) {
+            Some(path) if path != "/" => path,
+            _ => panic!("Invalid export path {:?}", path),
+        };
+        for other in self.exports.iter().map(|export| &export.path) {
+            let nested = |a: &str, b: &str| a.starts_with(&format!("{}/", b));
+            if *other == "/" || *other == path || nested(&path, other) || nested(other, &path) {
+                panic!("Export {:?} overlaps with export {:?}", path, other);
+            }
+        }
+        self.exports
+            .push(ExportConfig::new(&path, Arc::new(backend)));
+        self
+    }
+
+    /// Allow the clients of `rule` on the export at `path`, like a line of
+    /// `/etc/exports`. Rules are tried in the order they were added and the
+    /// first matching one applies. An export without rules is open to every
+    /// client, with write access.
+    ///
+    /// Panics if there is no export at `path`.
+    pub fn allow(&mut self, path: &str, rule: ClientRule) -> &mut Self {
+        let export = export_path(path)
+            .and_then(|path| self.exports.iter_mut().find(|export| export.path == path));
+        match export {
+            Some(export) => export.rules.push(rule),
+            None => panic!("No export at {:?}", path),
+        }
+        self
+    }
+
+    /// Only accept requests made with one of `flavors` on the export at
+    /// `path`, like `sec=` in `/etc/exports`. SECINFO offers the flavors to
+    /// clients in this order. An export without flavors accepts any flavor.
+    ///
+    /// Panics if there is no export at `path`.
+    pub fn sec(&mut self, path: &str, flavors: &[SecFlavor]) -> &mut Self {
+        let export = export_path(path)
+            .and_then(|path| self.exports.iter_mut().find(|export| export.path == path));
+        match export {
+            Some(export) => export.sec = flavors.to_vec(),
+            None => panic!("No export at {:?}", path),
+        }
+        self
+    }
+
+    /// Listen on TCP at `addrs` instead of `127.0.0.1:11112`, IPv4 and IPv6
+    /// addresses alike. An IPv6 address takes IPv4 clients as well if no
+    /// IPv4 address is given, e.g. `[::]:2049` alone is dual-stack.
+    pub fn listen(&mut self, addrs: &[SocketAddr]) -> &mut Self {
+        self.listen = addrs.to_vec();
+        self
+    }
+
+    /// Listen on TCP at `bind` only, e.g. `0.0.0.0:2049`.
+    ///
+    /// Panics if `bind` is not a socket address.
     pub fn bind(&mut self, bind: &str) -> &mut Self {
-        self.bind = bind.to_string();
+        match bind.parse() {
+            Ok(addr) => self.listen(&[addr]),
+            Err(_) => panic!("Invalid listen address {:?}", bind),
+        }
+    }
+
+    /// Keep a map of filehandles in the file at `path`, so clients can use
+    /// their filehandles across server restarts. With several exports every
+    /// export gets a file of its own, named `path` with the export number appended.
+    pub fn handle_db(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.handle_db = Some(path.into());
+        self
+    }
+
+    /// Hand out the directory at `path` as the public filehandle, which
+    /// clients get with PUTPUBFH, e.g. for WebNFS-style anonymous access.
+    /// The root of the namespace by default. The public filehandle is subject
+    /// to the client rules of the export it belongs to.
+    ///
+    /// Panics if `path` is not absolute.
+    pub fn public(&mut self, path: &str) -> &mut Self {
+        match export_path(path) {
+            Some(path) => self.public = path,
+            None => panic!("Invalid public path {:?}", path),
+        }
+        self
+    }
+
+    /// Serve every export read-only, operations changing a file system fail
+    /// with NFS4ERR_ROFS no matter what the client rules allow.
+    pub fn read_only(&mut self, read_only: bool) -> &mut Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Accept RPCSEC_GSS credentials, with the security contexts established
+    /// by `mechanism`, e.g. Kerberos 5.
+    pub fn gss_mechanism(&mut self, mechanism: Arc<dyn GssMechanism>) -> &mut Self {
+        self.gss_mechanism = Some(mechanism);
+        self
+    }
+
+    /// Close the connection of a client sending an RPC record larger than
+    /// `bytes`, which bounds the memory a connection takes to receive a call.
+    /// 8 MiB by default, a WRITE needs its data and about 1 KiB besides.
+    pub fn max_record_size(&mut self, bytes: usize) -> &mut Self {
+        self.max_record_size = bytes;
+        self
+    }
+
+    /// Write unstable WRITEs through instead of keeping their data for
+    /// COMMIT while the calls of a connection keep more than `bytes` of the
+    /// records they were received in, which bounds the memory a connection
+    /// takes besides its receive buffer. 64 MiB by default.
+    pub fn max_connection_memory(&mut self, bytes: usize) -> &mut Self {
+        self.max_connection_memory = bytes;
+        self
+    }
+
+    /// Also listen on a Unix domain socket at `path`, e.g. for clients in
+    /// local containers. Clients on the socket are matched against the client
+    /// rules as 127.0.0.1, from an unprivileged port.
+    pub fn unix_socket(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.unix_sockets.push(path.into());
+        self
+    }
+
+    /// Offer RPC-over-TLS with the certificate chain `certs` and its private
+    /// `key`, clients start TLS on any transport with an AUTH_TLS probe, see
+    /// [`server::transport::read_pem`] to read them from PEM files.
+    ///
+    /// [`NFSServer::start`] fails if `key` doesn't go with the certificate.
+    pub fn tls(
+        &mut self,
+        certs: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> &mut Self {
+        self.tls = Some(TlsKeys { certs, key });
+        self
+    }
+
+    /// Register NFSv4 with the rpcbind of this host on start and remove the
+    /// registration on shutdown, so clients asking rpcbind find the server,
+    /// e.g. Linux mounting with `port=0`. IPv6 addresses are left out with a
+    /// portmapper that only has version 2.
+    pub fn rpcbind_register(&mut self, register: bool) -> &mut Self {
+        self.rpcbind_register = register;
+        self
+    }
+
+    /// Answer rpcbind queries for NFSv4 on TCP and UDP at `addrs`, e.g.
+    /// `0.0.0.0:111`, on hosts without rpcbind. Registrations of other
+    /// services are refused.
+    pub fn rpcbind_listen(&mut self, addrs: &[SocketAddr]) -> &mut Self {
+        self.rpcbind_listen = addrs.to_vec();
         self
     }
 
     pub fn build(&self) -> NFSServer {
+        assert!(!self.exports.is_empty(), "No file system exported");
+        assert!(
+            !self.listen.is_empty() || !self.unix_sockets.is_empty(),
+            "No address to listen on"
+        );
+        for export in self.exports.iter() {
+            let needs_gss = export.sec.iter().any(SecFlavor::is_gss);
+            assert!(
+                !needs_gss || self.gss_mechanism.is_some(),
+                "Export {:?} uses Kerberos without a GSS mechanism",
+                export.path
+            );
+        }
         // set the boot time to now
         let boot_time = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
         NFSServer {
-            bind: self.bind.clone(),
-            root: self.root.clone(),
+            listen: self.listen.clone(),
+            exports: self
+                .exports
+                .iter()
+                .cloned()
+                .map(|mut export| {
+                    export.read_only |= self.read_only;
+                    export
+                })
+                .collect(),
+            handle_db: self.handle_db.clone(),
+            public: self.public.clone(),
+            gss_mechanism: self.gss_mechanism.clone(),
+            max_record_size: self.max_record_size,
+            max_connection_memory: self.max_connection_memory,
+            unix_sockets: self.unix_sockets.clone(),
+            tls: self.tls.as_ref().map(|tls| TlsKeys {
+                certs: tls.certs.clone(),
+                key: tls.key.clone_key(),
+            }),
+            rpcbind_register: self.rpcbind_register,
+            rpcbind_listen: self.rpcbind_listen.clone(),
             service_0: Some(server::nfs40::NFS40Server::new()),
             boot_time,
         }
@@ -163,12 +411,165 @@ impl ServerBuilder {
 
 #[cfg(test)]
 mod test_utils {
-    use crate::server::{
-        clientmanager::ClientManagerHandle, filemanager::FileManagerHandle, request::NfsRequest,
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+
+    use crate::{
+        server::{
+            clientmanager::ClientManagerHandle, exports::Exports, filemanager::FileManagerHandle,
+            request::NfsRequest,
+        },
+        storage::{
+            join_path, Capabilities, Metadata, ReadDirPage, SetAttrs, StatFs, StorageBackend,
+            StorageError, StorageResult, VfsBackend,
+        },
+    };
+    use bold_proto::nfs4_proto::{
+        CbClient4, ClientAddr4, NfsClientId4, NfsFtype4, SetClientId4args,
     };
-    use bold_proto::nfs4_proto::{CbClient4, ClientAddr4, NfsClientId4, SetClientId4args};
     use vfs::{MemoryFS, VfsPath};
 
+    /// A backend that fails every operation on the paths given to [`FaultyBackend::fail`]
+    /// and passes everything else on to the backend it wraps.
+    #[derive(Debug)]
+    pub struct FaultyBackend {
+        inner: Arc<dyn StorageBackend>,
+        faults: Mutex<Vec<(String, StorageError)>>,
+    }
+
+    impl FaultyBackend {
+        pub fn new(inner: Arc<dyn StorageBackend>) -> Self {
+            FaultyBackend {
+                inner,
+                faults: Mutex::new(Vec::new()),
+            }
+        }
+
+        pub fn fail(&self, path: &str, error: StorageError) {
+            self.faults.lock().unwrap().push((path.to_string(), error));
+        }
+
+        pub fn clear(&self) {
+            self.faults.lock().unwrap().clear();
+        }
+
+        fn check(&self, path: &str) -> StorageResult<()> {
+            match self.faults.lock().unwrap().iter().find(|(p, _)| p == path) {
+                Some((_, error)) => Err(error.clone()),
+                None => Ok(()),
+            }
+        }
+
+        fn check_entry(&self, dir: &str, name: &str) -> StorageResult<()> {
+            self.check(dir)?;
+            self.check(&join_path(dir, name))
+        }
+    }
+
+    #[async_trait]
+    impl StorageBackend for FaultyBackend {
+        fn capabilities(&self) -> Capabilities {
+            self.inner.capabilities()
+        }
+
+        async fn lookup(&self, dir: &str, name: &str) -> StorageResult<Metadata> {
+            self.check_entry(dir, name)?;
+            self.inner.lookup(dir, name).await
+        }
+
+        async fn getattr(&self, path: &str) -> StorageResult<Metadata> {
+            self.check(path)?;
+            self.inner.getattr(path).await
+        }
+
+        async fn setattr(&self, path: &str, attrs: &SetAttrs) -> StorageResult<Metadata> {
+            self.check(path)?;
+            self.inner.setattr(path, attrs).await
+        }
+
+        async fn read(
+            &self,
+            path: &str,
+            offset: u64,
+            count: u32,
+        ) -> StorageResult<(Vec<u8>, bool)> {
+            self.check(path)?;
+            self.inner.read(path, offset, count).await
+        }
+
+        async fn write(&self, path: &str, offset: u64, data: &[u8]) -> StorageResult<u32> {
+            self.check(path)?;
+            self.inner.write(path, offset, data).await
+        }
+
+        async fn commit(&self, path: &str) -> StorageResult<()> {
+            self.check(path)?;
+            self.inner.commit(path).await
+        }
+
+        async fn create(
+            &self,
+            dir: &str,
+            name: &str,
+            file_type: NfsFtype4,
+            mode: u32,
+        ) -> StorageResult<Metadata> {
+            self.check_entry(dir, name)?;
+            self.inner.create(dir, name, file_type, mode).await
+        }
+
+        async fn remove(&self, dir: &str, name: &str) -> StorageResult<()> {
+            self.check_entry(dir, name)?;
+            self.inner.remove(dir, name).await
+        }
+
+        async fn rename(
+            &self,
+            from_dir: &str,
+            from_name: &str,
+            to_dir: &str,
+            to_name: &str,
+        ) -> StorageResult<()> {
+            self.check_entry(from_dir, from_name)?;
+            self.check_entry(to_dir, to_name)?;
+            self.inner
+                .rename(from_dir, from_name, to_dir, to_name)
+                .await
+        }
+
+        async fn link(&self, path: &str, dir: &str, name: &str) -> StorageResult<Metadata> {
+            self.check(path)?;
+            self.check_entry(dir, name)?;
+            self.inner.link(path, dir, name).await
+        }
+
+        async fn symlink(&self, dir: &str, name: &str, target: &str) -> StorageResult<Metadata> {
+            self.check_entry(dir, name)?;
+            self.inner.symlink(dir, name, target).await
+        }
+
+        async fn readlink(&self, path: &str) -> StorageResult<String> {
+            self.check(path)?;
+            self.inner.readlink(path).await
+        }
+
+        async fn readdir(
+            &self,
+            path: &str,
+            cookie: u64,
+            max_entries: usize,
+        ) -> StorageResult<ReadDirPage> {
+            self.check(path)?;
+            self.inner.readdir(path, cookie, max_entries).await
+        }
+
+        async fn statfs(&self, path: &str) -> StorageResult<StatFs> {
+            self.check(path)?;
+            self.inner.statfs(path).await
+        }
+    }
+
     pub fn create_dummyfs() -> VfsPath {
         let root: VfsPath = MemoryFS::new().into();
         root.create_dir_all().unwrap();
@@ -206,7 +607,10 @@ mod test_utils {
 
     pub fn create_client(verifier: [u8; 8], id: String) -> SetClientId4args {
         SetClientId4args {
-            client: NfsClientId4 { verifier, id },
+            client: NfsClientId4 {
+                verifier,
+                id: id.into_bytes(),
+            },
             callback: CbClient4 {
                 cb_program: 0,
                 cb_location: ClientAddr4 {
@@ -219,19 +623,20 @@ mod test_utils {
     }
 
     pub async fn create_nfs40_server(root: Option<VfsPath>) -> NfsRequest<'static> {
-        let root = if root.is_none() {
-            create_dummyfs()
-        } else {
-            root.unwrap()
-        };
+        let root = root.unwrap_or_else(create_dummyfs);
+        create_nfs40_server_with_backend(Arc::new(VfsBackend::new(root))).await
+    }
 
+    pub async fn create_nfs40_server_with_backend(
+        backend: Arc<dyn StorageBackend>,
+    ) -> NfsRequest<'static> {
         let client_mananger_handle = ClientManagerHandle::new();
-        let file_mananger_handle = FileManagerHandle::new(root, None);
+        let file_mananger_handle = FileManagerHandle::new(backend, None, None);
 
         NfsRequest::new(
             "127.0.0.1:12345".to_owned(),
             client_mananger_handle,
-            file_mananger_handle,
+            Exports::single(file_mananger_handle),
             0_u64,
             None,
         )
@@ -0,0 +1,1137 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use bold_proto::{
+    nfs4_proto::{
+        NfsArgOp, NfsFtype4, NfsStat4, OpenFlag4, RpcSecGssInfo, SeCinfo4, ACCESS4_DELETE,
+        ACCESS4_EXECUTE, ACCESS4_EXTEND, ACCESS4_LOOKUP, ACCESS4_MODIFY, ACCESS4_READ,
+        OPEN4_SHARE_ACCESS_READ, OPEN4_SHARE_ACCESS_WRITE,
+    },
+    rpc_proto::{
+        OpaqueAuth, AUTH_NONE, AUTH_SYS, RPC_GSS_SVC_INTEGRITY, RPC_GSS_SVC_NONE,
+        RPC_GSS_SVC_PRIVACY,
+    },
+};
+
+use super::{acl, filemanager::Filehandle, gss::KRB5_OID};
+
+// uid and gid of nobody, used for squashed users
+pub const ANON_ID: u32 = 65534;
+
+/// The clients an export rule applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientMatch {
+    // `*`
+    Any,
+    // `192.168.1.0/24`, a single address is a network of its own
+    Network(IpAddr, u8),
+    // `client.example.com`, resolved when the client is matched
+    Host(String),
+}
+
+impl ClientMatch {
+    pub async fn matches(&self, ip: IpAddr) -> bool {
+        match self {
+            ClientMatch::Any => true,
+            ClientMatch::Network(network, prefix) => in_network(ip, *network, *prefix),
+            ClientMatch::Host(host) => match tokio::net::lookup_host((host.as_str(), 0)).await {
+                Ok(mut addrs) => addrs.any(|addr| addr.ip().to_canonical() == ip),
+                Err(_) => false,
+            },
+        }
+    }
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+impl FromStr for ClientMatch {
+    type Err = RuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "*" {
+            return Ok(ClientMatch::Any);
+        }
+        if let Some((network, prefix)) = s.split_once('/') {
+            let network: IpAddr = network
+                .parse()
+                .map_err(|_| RuleError(format!("invalid network {:?}", s)))?;
+            let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+            return match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= max_prefix => Ok(ClientMatch::Network(network, prefix)),
+                _ => Err(RuleError(format!("invalid prefix length {:?}", s))),
+            };
+        }
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            let prefix = if ip.is_ipv4() { 32 } else { 128 };
+            return Ok(ClientMatch::Network(ip, prefix));
+        }
+        let valid_host = !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+        if !valid_host {
+            return Err(RuleError(format!("invalid host name {:?}", s)));
+        }
+        Ok(ClientMatch::Host(s.to_string()))
+    }
+}
+
+/// Which users are mapped to the anonymous user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Squash {
+    None,
+    Root,
+    All,
+}
+
+/// What a client may do on an export, the options of an `/etc/exports` line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportOptions {
+    pub read_only: bool,
+    pub squash: Squash,
+    pub anonuid: u32,
+    pub anongid: u32,
+    // requests have to come from a privileged port (below 1024)
+    pub secure: bool,
+}
+
+impl Default for ExportOptions {
+    // the defaults of /etc/exports
+    fn default() -> Self {
+        ExportOptions {
+            read_only: true,
+            squash: Squash::Root,
+            anonuid: ANON_ID,
+            anongid: ANON_ID,
+            secure: true,
+        }
+    }
+}
+
+impl ExportOptions {
+    /// The options of an export without any rules.
+    pub fn unrestricted() -> Self {
+        ExportOptions {
+            read_only: false,
+            squash: Squash::None,
+            secure: false,
+            ..Default::default()
+        }
+    }
+}
+
+/// A client rule of an export, like `192.168.1.0/24(rw,no_root_squash)` in `/etc/exports`.
+///
+/// The options default to those of `/etc/exports`: read-only, root squashed
+/// and only privileged ports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientRule {
+    pub clients: ClientMatch,
+    pub options: ExportOptions,
+}
+
+impl ClientRule {
+    pub fn new(clients: ClientMatch) -> Self {
+        ClientRule {
+            clients,
+            options: ExportOptions::default(),
+        }
+    }
+
+    pub fn rw(mut self) -> Self {
+        self.options.read_only = false;
+        self
+    }
+
+    pub fn ro(mut self) -> Self {
+        self.options.read_only = true;
+        self
+    }
+
+    pub fn no_root_squash(mut self) -> Self {
+        self.options.squash = Squash::None;
+        self
+    }
+
+    pub fn all_squash(mut self) -> Self {
+        self.options.squash = Squash::All;
+        self
+    }
+
+    pub fn anonuid(mut self, uid: u32) -> Self {
+        self.options.anonuid = uid;
+        self
+    }
+
+    pub fn anongid(mut self, gid: u32) -> Self {
+        self.options.anongid = gid;
+        self
+    }
+
+    pub fn insecure(mut self) -> Self {
+        self.options.secure = false;
+        self
+    }
+}
+
+impl FromStr for ClientRule {
+    type Err = RuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (clients, options) = match s.split_once('(') {
+            Some((clients, options)) => match options.strip_suffix(')') {
+                Some(options) => (clients, options),
+                None => return Err(RuleError(format!("unclosed options in {:?}", s))),
+            },
+            None => (s, ""),
+        };
+        let mut rule = ClientRule::new(clients.parse()?);
+        for option in options.split(',').filter(|o| !o.is_empty()) {
+            let id = |value: &str| {
+                value
+                    .parse::<u32>()
+                    .map_err(|_| RuleError(format!("invalid id in {:?}", option)))
+            };
+            rule = match option.split_once('=') {
+                Some(("anonuid", value)) => rule.anonuid(id(value)?),
+                Some(("anongid", value)) => rule.anongid(id(value)?),
+                _ => match option {
+                    "rw" => rule.rw(),
+                    "ro" => rule.ro(),
+                    "root_squash" => {
+                        rule.options.squash = Squash::Root;
+                        rule
+                    }
+                    "no_root_squash" => rule.no_root_squash(),
+                    "no_all_squash" => {
+                        if rule.options.squash == Squash::All {
+                            rule.options.squash = Squash::Root;
+                        }
+                        rule
+                    }
+                    "all_squash" => rule.all_squash(),
+                    "secure" => {
+                        rule.options.secure = true;
+                        rule
+                    }
+                    "insecure" => rule.insecure(),
+                    _ => return Err(RuleError(format!("unknown option {:?}", option))),
+                },
+            };
+        }
+        Ok(rule)
+    }
+}
+
+/// A security flavor an export can be used with, as in `sec=` of `/etc/exports`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecFlavor {
+    // AUTH_NONE
+    AuthNone,
+    // AUTH_SYS
+    AuthSys,
+    // RPCSEC_GSS with Kerberos 5, authentication only
+    Krb5,
+    // with integrity protection
+    Krb5i,
+    // with privacy protection
+    Krb5p,
+}
+
+impl SecFlavor {
+    /// The flavor of a call that is not protected by RPCSEC_GSS.
+    pub fn from_auth(auth: &OpaqueAuth) -> Option<Self> {
+        match auth {
+            OpaqueAuth::AuthNull(_) => Some(SecFlavor::AuthNone),
+            OpaqueAuth::AuthUnix(_) => Some(SecFlavor::AuthSys),
+            _ => None,
+        }
+    }
+
+    /// The flavor of a call protected with the RPCSEC_GSS `service`.
+    pub fn from_gss_service(service: u32) -> Option<Self> {
+        match service {
+            RPC_GSS_SVC_NONE => Some(SecFlavor::Krb5),
+            RPC_GSS_SVC_INTEGRITY => Some(SecFlavor::Krb5i),
+            RPC_GSS_SVC_PRIVACY => Some(SecFlavor::Krb5p),
+            _ => None,
+        }
+    }
+
+    pub fn is_gss(&self) -> bool {
+        matches!(self, SecFlavor::Krb5 | SecFlavor::Krb5i | SecFlavor::Krb5p)
+    }
+
+    /// The entry of the flavor in a SECINFO reply.
+    pub fn secinfo(&self) -> SeCinfo4 {
+        let gss = |service| {
+            SeCinfo4::FlavorInfo(RpcSecGssInfo {
+                oid: KRB5_OID.to_vec(),
+                qop: 0,
+                service,
+            })
+        };
+        match self {
+            SecFlavor::AuthNone => SeCinfo4::Flavor(AUTH_NONE),
+            SecFlavor::AuthSys => SeCinfo4::Flavor(AUTH_SYS),
+            SecFlavor::Krb5 => gss(RPC_GSS_SVC_NONE),
+            SecFlavor::Krb5i => gss(RPC_GSS_SVC_INTEGRITY),
+            SecFlavor::Krb5p => gss(RPC_GSS_SVC_PRIVACY),
+        }
+    }
+
+    /// Parse a list of flavors separated by colons, e.g. `krb5p:sys`.
+    pub fn parse_list(s: &str) -> Result<Vec<Self>, RuleError> {
+        s.split(':').map(str::parse).collect()
+    }
+}
+
+impl FromStr for SecFlavor {
+    type Err = RuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(SecFlavor::AuthNone),
+            "sys" => Ok(SecFlavor::AuthSys),
+            "krb5" => Ok(SecFlavor::Krb5),
+            "krb5i" => Ok(SecFlavor::Krb5i),
+            "krb5p" => Ok(SecFlavor::Krb5p),
+            _ => Err(RuleError(format!("unknown security flavor {:?}", s))),
+        }
+    }
+}
+
+impl fmt::Display for SecFlavor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SecFlavor::AuthNone => "none",
+            SecFlavor::AuthSys => "sys",
+            SecFlavor::Krb5 => "krb5",
+            SecFlavor::Krb5i => "krb5i",
+            SecFlavor::Krb5p => "krb5p",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleError(pub String);
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RuleError: {}", self.0)
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+/// The user a request is made for, as sent in an AUTH_SYS credential.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    pub gids: Vec<u32>,
+}
+
+impl Credentials {
+    /// The user of an AUTH_SYS credential, requests without credentials
+    /// (AUTH_NONE) are made for the anonymous user. `None` for flavors bold
+    /// does not support.
+    pub fn from_auth(auth: &OpaqueAuth) -> Option<Self> {
+        match auth {
+            OpaqueAuth::AuthUnix(auth) => Some(Credentials {
+                uid: auth.uid,
+                gid: auth.gid,
+                gids: auth.gids.clone(),
+            }),
+            OpaqueAuth::AuthNull(_) => Some(Credentials {
+                uid: ANON_ID,
+                gid: ANON_ID,
+                gids: Vec::new(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.gids.contains(&gid)
+    }
+
+    /// The ACCESS4 bits the ACL or else the mode of `filehandle` grants to
+    /// this user.
+    ///
+    /// root may do anything, except executing files no one may execute.
+    pub fn access(&self, filehandle: &Filehandle) -> u32 {
+        let mode = filehandle.attr_mode;
+        let is_dir = matches!(
+            filehandle.attr_type,
+            NfsFtype4::Nf4dir | NfsFtype4::Nf4attrdir
+        );
+        if let (Some(acl), false) = (&filehandle.attr_acl, self.uid == 0) {
+            return acl::access(acl, self, filehandle.attr_uid, filehandle.attr_gid, is_dir);
+        }
+        let bits = if self.uid == 0 {
+            if is_dir || mode & 0o111 != 0 {
+                0o7
+            } else {
+                0o6
+            }
+        } else if self.uid == filehandle.attr_uid {
+            (mode >> 6) & 0o7
+        } else if self.in_group(filehandle.attr_gid) {
+            (mode >> 3) & 0o7
+        } else {
+            mode & 0o7
+        };
+
+        let mut access = 0;
+        if bits & 0o4 != 0 {
+            access |= ACCESS4_READ;
+        }
+        if bits & 0o2 != 0 {
+            access |= ACCESS4_MODIFY | ACCESS4_EXTEND;
+            if is_dir {
+                access |= ACCESS4_DELETE;
+            }
+        }
+        if bits & 0o1 != 0 {
+            access |= if is_dir {
+                ACCESS4_LOOKUP
+            } else {
+                ACCESS4_EXECUTE
+            };
+        }
+        access
+    }
+
+    /// The credentials after mapping squashed users to the anonymous user.
+    pub fn squash(&self, options: &ExportOptions) -> Self {
+        let anon = |id: u32, anon_id: u32| if id == 0 { anon_id } else { id };
+        match options.squash {
+            Squash::None => self.clone(),
+            Squash::Root => Credentials {
+                uid: anon(self.uid, options.anonuid),
+                gid: anon(self.gid, options.anongid),
+                gids: self
+                    .gids
+                    .iter()
+                    .map(|gid| anon(*gid, options.anongid))
+                    .collect(),
+            },
+            Squash::All => Credentials {
+                uid: options.anonuid,
+                gid: options.anongid,
+                gids: Vec::new(),
+            },
+        }
+    }
+}
+
+/// The client a connection comes from, [`NfsRequest::client_addr`] is what
+/// it is known by.
+///
+/// [`NfsRequest::client_addr`]: super::request::NfsRequest::client_addr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    // a Unix domain socket has no address, its connections are numbered,
+    // and the user of the client process is taken from the socket
+    Unix { connection: u64, uid: Option<u32> },
+}
+
+impl Peer {
+    /// The address matched against the client rules, clients on a Unix
+    /// domain socket are on this host.
+    pub fn ip(&self) -> IpAddr {
+        match self {
+            Peer::Tcp(addr) => addr.ip().to_canonical(),
+            Peer::Unix { .. } => IpAddr::from([127, 0, 0, 1]),
+        }
+    }
+
+    /// Whether root on the client host sent the calls, from a privileged
+    /// port or as the user of the client process.
+    pub fn privileged(&self) -> bool {
+        match self {
+            Peer::Tcp(addr) => addr.port() < 1024,
+            Peer::Unix { uid, .. } => *uid == Some(0),
+        }
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Peer::Tcp(addr) => write!(f, "{}", addr),
+            Peer::Unix {
+                connection,
+                uid: Some(uid),
+            } => write!(f, "unix:{}:{}", connection, uid),
+            Peer::Unix {
+                connection,
+                uid: None,
+            } => write!(f, "unix:{}", connection),
+        }
+    }
+}
+
+impl FromStr for Peer {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(unix) = s.strip_prefix("unix:") else {
+            return s.parse().map(Peer::Tcp).map_err(|_| ());
+        };
+        let (connection, uid) = match unix.split_once(':') {
+            Some((connection, uid)) => (connection, Some(uid.parse().map_err(|_| ())?)),
+            None => (unix, None),
+        };
+        Ok(Peer::Unix {
+            connection: connection.parse().map_err(|_| ())?,
+            uid,
+        })
+    }
+}
+
+/// What a client may do on every export, the outcome of matching its address
+/// against the export rules.
+#[derive(Debug, Clone)]
+pub struct ClientAccess {
+    // indexed by export id, None if the client is not allowed on the export
+    exports: Arc<Vec<Option<ExportOptions>>>,
+    privileged_port: bool,
+}
+
+impl ClientAccess {
+    pub fn new(exports: Arc<Vec<Option<ExportOptions>>>, peer: &Peer) -> Self {
+        ClientAccess {
+            exports,
+            privileged_port: peer.privileged(),
+        }
+    }
+
+    /// A client that is not allowed on any export.
+    pub fn denied() -> Self {
+        ClientAccess {
+            exports: Arc::new(Vec::new()),
+            privileged_port: false,
+        }
+    }
+
+    pub fn options(&self, export_id: u8) -> Option<&ExportOptions> {
+        self.exports.get(export_id as usize)?.as_ref()
+    }
+
+    pub fn allowed_anywhere(&self) -> bool {
+        self.exports.iter().any(Option::is_some)
+    }
+
+    /// Check that the client may use the export, and change it if `modify` is set.
+    pub fn check(&self, export_id: u8, modify: bool) -> Result<&ExportOptions, NfsStat4> {
+        let options = self.options(export_id).ok_or(NfsStat4::Nfs4errAccess)?;
+        // the port is no security flavor, WRONGSEC would have the client try
+        // the flavors of SECINFO forever, no other one can succeed
+        if options.secure && !self.privileged_port {
+            return Err(NfsStat4::Nfs4errPerm);
+        }
+        if modify && options.read_only {
+            return Err(NfsStat4::Nfs4errRofs);
+        }
+        Ok(options)
+    }
+}
+
+/// The ACCESS4 bits needed to open a file with `share_access`.
+pub fn share_access_mask(share_access: u32) -> u32 {
+    let mut access = 0;
+    if share_access & OPEN4_SHARE_ACCESS_READ != 0 {
+        access |= ACCESS4_READ;
+    }
+    if share_access & OPEN4_SHARE_ACCESS_WRITE != 0 {
+        access |= ACCESS4_MODIFY;
+    }
+    access
+}
+
+/// Whether `op` works on the current filehandle, all others set it or do not need one.
+pub fn uses_current_filehandle(op: &NfsArgOp) -> bool {
+    !matches!(
+        op,
+        NfsArgOp::Opputfh(_)
+            | NfsArgOp::Opputpubfh(_)
+            | NfsArgOp::Opputrootfh(_)
+            | NfsArgOp::Oprestorefh(_)
+            | NfsArgOp::Opsetclientid(_)
+            | NfsArgOp::OpsetclientidConfirm(_)
+            | NfsArgOp::Oprenew(_)
+            | NfsArgOp::OpreleaseLockOwner(_)
+    )
+}
+
+/// Whether `op` changes the file system.
+pub fn modifies(op: &NfsArgOp) -> bool {
+    match op {
+        NfsArgOp::Opwrite(_)
+        | NfsArgOp::Opcreate(_)
+        | NfsArgOp::Opremove(_)
+        | NfsArgOp::Oprename(_)
+        | NfsArgOp::Opsetattr(_)
+        | NfsArgOp::Oplink(_)
+        | NfsArgOp::Opcommit(_) => true,
+        NfsArgOp::Opopen(args) => {
+            matches!(args.openhow, OpenFlag4::How(_))
+                || args.share_access & OPEN4_SHARE_ACCESS_WRITE != 0
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, sync::Arc};
+
+    use bold_proto::{nfs4_proto::*, rpc_proto::*};
+    use bytes::Bytes;
+    use tracing_test::traced_test;
+
+    use super::{
+        ClientAccess, ClientMatch, ClientRule, Credentials, ExportOptions, Peer, SecFlavor, Squash,
+        ANON_ID,
+    };
+    use crate::{
+        server::{
+            clientmanager::ClientManagerHandle,
+            exports::{ExportConfig, Exports},
+            filemanager::FileManagerHandle,
+            nfs40::NFS40Server,
+            request::NfsRequest,
+            NFSService, NfsProtoImpl,
+        },
+        storage::{MemoryBackend, SetAttrs, StorageBackend},
+    };
+
+    #[tokio::test]
+    async fn test_client_rules() {
+        let rule: ClientRule = "192.168.1.0/24(rw,no_root_squash,insecure)"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            rule,
+            ClientRule::new(ClientMatch::Network("192.168.1.0".parse().unwrap(), 24))
+                .rw()
+                .no_root_squash()
+                .insecure()
+        );
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(rule.clients.matches(ip("192.168.1.77")).await);
+        assert!(!rule.clients.matches(ip("192.168.2.77")).await);
+        assert!(!rule.clients.matches(ip("::1")).await);
+
+        let rule: ClientRule = "10.0.0.1".parse().unwrap();
+        assert_eq!(rule.options, ExportOptions::default());
+        assert!(rule.clients.matches(ip("10.0.0.1")).await);
+        assert!(!rule.clients.matches(ip("10.0.0.2")).await);
+
+        let rule: ClientRule = "*(all_squash,anonuid=1000,anongid=100)".parse().unwrap();
+        assert_eq!(rule.options.squash, Squash::All);
+        assert_eq!((rule.options.anonuid, rule.options.anongid), (1000, 100));
+        assert!(rule.clients.matches(ip("fe80::1")).await);
+
+        let rule: ClientRule = "fd00::/8(rw)".parse().unwrap();
+        assert!(rule.clients.matches(ip("fd12::1")).await);
+        assert!(!rule.clients.matches(ip("fe80::1")).await);
+
+        let rule: ClientRule = "localhost(ro)".parse().unwrap();
+        assert_eq!(rule.clients, ClientMatch::Host("localhost".to_string()));
+        assert!(rule.clients.matches(ip("127.0.0.1")).await);
+
+        assert!("10.0.0.0/33".parse::<ClientRule>().is_err());
+        assert!("10.0.0.0/8(rw".parse::<ClientRule>().is_err());
+        assert!("10.0.0.0/8(sync)".parse::<ClientRule>().is_err());
+        assert!("*(anonuid=x)".parse::<ClientRule>().is_err());
+        assert!("bad host".parse::<ClientRule>().is_err());
+    }
+
+    #[test]
+    fn test_squash() {
+        let root = Credentials {
+            uid: 0,
+            gid: 0,
+            gids: vec![0, 10],
+        };
+        let user = Credentials {
+            uid: 1000,
+            gid: 1000,
+            gids: vec![1000],
+        };
+        let options = ExportOptions::default();
+        assert_eq!(
+            root.squash(&options),
+            Credentials {
+                uid: ANON_ID,
+                gid: ANON_ID,
+                gids: vec![ANON_ID, 10],
+            }
+        );
+        assert_eq!(user.squash(&options), user);
+        assert_eq!(root.squash(&ExportOptions::unrestricted()), root);
+        let options = ExportOptions {
+            squash: Squash::All,
+            anonuid: 99,
+            anongid: 98,
+            ..Default::default()
+        };
+        assert_eq!(
+            user.squash(&options),
+            Credentials {
+                uid: 99,
+                gid: 98,
+                gids: vec![],
+            }
+        );
+    }
+
+    fn user(uid: u32) -> OpaqueAuth {
+        OpaqueAuth::AuthUnix(AuthUnix {
+            uid,
+            gid: uid,
+            ..Default::default()
+        })
+    }
+
+    fn call(cred: OpaqueAuth, argarray: Vec<NfsArgOp>) -> RpcCallMsg {
+        RpcCallMsg {
+            xid: 7,
+            body: MsgType::Call(CallBody {
+                rpcvers: 2,
+                prog: 100003,
+                vers: 4,
+                proc: 1,
+                cred,
+                verf: OpaqueAuth::AuthNull(Vec::new()),
+                gss_args: None,
+                args: Some(Compound4args {
+                    tag: "".to_string(),
+                    minor_version: 0,
+                    argarray,
+                }),
+            }),
+        }
+    }
+
+    fn mkdir(name: &str) -> NfsArgOp {
+        NfsArgOp::Opcreate(Create4args {
+            objtype: Createtype4::Nf4dir,
+            objname: name.as_bytes().to_vec(),
+            createattrs: Fattr4 {
+                attrmask: Attrlist4::<FileAttr>::new(None),
+                attr_vals: Attrlist4::<FileAttrValue>::new(None),
+                decode_error: None,
+            },
+        })
+    }
+
+    fn lookup(name: &str) -> NfsArgOp {
+        NfsArgOp::Oplookup(Lookup4args {
+            objname: name.as_bytes().to_vec(),
+        })
+    }
+
+    async fn reply(
+        exports: &Exports,
+        client_addr: &str,
+        cred: OpaqueAuth,
+        argarray: Vec<NfsArgOp>,
+    ) -> Box<RpcReplyMsg> {
+        let request = NfsRequest::new(
+            client_addr.to_string(),
+            ClientManagerHandle::new(),
+            exports.clone(),
+            0,
+            None,
+        );
+        NFSService::new(NFS40Server::new())
+            .call(call(cred, argarray), request)
+            .await
+            .unwrap()
+    }
+
+    async fn compound_as(
+        exports: &Exports,
+        client_addr: &str,
+        cred: OpaqueAuth,
+        argarray: Vec<NfsArgOp>,
+    ) -> Compound4res {
+        match reply(exports, client_addr, cred, argarray).await.body {
+            MsgType::Reply(ReplyBody::MsgAccepted(AcceptedReply {
+                reply_data: AcceptBody::Success(res),
+                ..
+            })) => res,
+            body => panic!("Unexpected reply {:?}", body),
+        }
+    }
+
+    async fn status(exports: &Exports, client_addr: &str, argarray: Vec<NfsArgOp>) -> NfsStat4 {
+        compound_as(exports, client_addr, user(0), argarray)
+            .await
+            .status
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_export_rules() {
+        let export = |path: &str, rules: &[&str]| ExportConfig {
+            path: path.to_string(),
+            backend: Arc::new(MemoryBackend::new()),
+            rules: rules.iter().map(|rule| rule.parse().unwrap()).collect(),
+            read_only: false,
+            sec: Vec::new(),
+        };
+        let exports = Exports::new(
+            vec![
+                export("/open", &[]),
+                export(
+                    "/rw",
+                    &[
+                        "10.0.0.0/8(ro,insecure)",
+                        "127.0.0.1(rw,no_root_squash,insecure)",
+                    ],
+                ),
+                export("/ro", &["127.0.0.0/8(insecure)"]),
+                export("/secure", &["*(rw)"]),
+                export("/other", &["192.168.0.0/16(rw,insecure)"]),
+            ],
+            None,
+        );
+        let client = "127.0.0.1:40000";
+        let root = NfsArgOp::Opputrootfh(());
+
+        let ok = status(
+            &exports,
+            client,
+            vec![root.clone(), lookup("open"), mkdir("d")],
+        )
+        .await;
+        assert_eq!(ok, NfsStat4::Nfs4Ok);
+        let ok = status(
+            &exports,
+            client,
+            vec![root.clone(), lookup("rw"), mkdir("d")],
+        )
+        .await;
+        assert_eq!(ok, NfsStat4::Nfs4Ok);
+        // the first matching rule applies
+        let rofs = status(
+            &exports,
+            "10.1.2.3:40000",
+            vec![root.clone(), lookup("rw"), mkdir("d")],
+        );
+        assert_eq!(rofs.await, NfsStat4::Nfs4errRofs);
+        let rofs = status(
+            &exports,
+            client,
+            vec![root.clone(), lookup("ro"), mkdir("d")],
+        )
+        .await;
+        assert_eq!(rofs, NfsStat4::Nfs4errRofs);
+        // the pseudo file system is read-only for everyone
+        let rofs = status(&exports, client, vec![root.clone(), mkdir("d")]).await;
+        assert_eq!(rofs, NfsStat4::Nfs4errRofs);
+
+        let perm = status(&exports, client, vec![root.clone(), lookup("secure")]).await;
+        assert_eq!(perm, NfsStat4::Nfs4errPerm);
+        let ok = status(
+            &exports,
+            "127.0.0.1:700",
+            vec![root.clone(), lookup("secure")],
+        )
+        .await;
+        assert_eq!(ok, NfsStat4::Nfs4Ok);
+        let access = status(&exports, client, vec![root.clone(), lookup("other")]).await;
+        assert_eq!(access, NfsStat4::Nfs4errAccess);
+
+        // a client allowed nowhere can not even reach the root
+        let exports = Exports::new(vec![export("/other", &["192.168.0.0/16"])], None);
+        let access = status(&exports, client, vec![root.clone()]).await;
+        assert_eq!(access, NfsStat4::Nfs4errAccess);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_public_filehandle() {
+        let backend = MemoryBackend::new();
+        backend.create_dir_all("/docs").unwrap();
+        backend.write_file("/docs/readme.txt", b"read me").unwrap();
+        let export = ExportConfig {
+            rules: vec!["127.0.0.1(ro,insecure)".parse().unwrap()],
+            ..ExportConfig::new("/pub", Arc::new(backend))
+        };
+        let exports = Exports::new(vec![export], None);
+        let client = "127.0.0.1:40000";
+        let public = NfsArgOp::Opputpubfh(());
+
+        // the root of the namespace by default
+        let ok = status(&exports, client, vec![public.clone(), lookup("pub")]).await;
+        assert_eq!(ok, NfsStat4::Nfs4Ok);
+
+        let exports = exports.with_public("/pub/docs");
+        let ok = status(&exports, client, vec![public.clone(), lookup("readme.txt")]).await;
+        assert_eq!(ok, NfsStat4::Nfs4Ok);
+        let rofs = status(&exports, client, vec![public.clone(), mkdir("d")]).await;
+        assert_eq!(rofs, NfsStat4::Nfs4errRofs);
+        let access = status(&exports, "192.168.1.1:40000", vec![public.clone()]).await;
+        assert_eq!(access, NfsStat4::Nfs4errAccess);
+
+        let exports = exports.with_public("/pub/missing");
+        let noent = status(&exports, client, vec![public]).await;
+        assert_eq!(noent, NfsStat4::Nfs4errNoent);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_squashed_credentials() {
+        let exports = Exports::new(
+            vec![ExportConfig {
+                path: "/".to_string(),
+                backend: Arc::new(MemoryBackend::new()),
+                rules: vec!["127.0.0.1(rw,insecure,anonuid=99,anongid=98)"
+                    .parse()
+                    .unwrap()],
+                read_only: false,
+                sec: Vec::new(),
+            }],
+            None,
+        );
+        let mut request = NfsRequest::new(
+            "127.0.0.1:40000".to_string(),
+            ClientManagerHandle::new(),
+            exports.clone(),
+            0,
+            None,
+        );
+        request.set_client_access(
+            exports
+                .client_access(&"127.0.0.1:40000".parse().unwrap())
+                .await,
+        );
+        request.set_credentials(Credentials::from_auth(&OpaqueAuth::AuthUnix(AuthUnix {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+            ..Default::default()
+        })));
+        assert_eq!(
+            request.credentials(),
+            Some(Credentials {
+                uid: 99,
+                gid: 98,
+                gids: vec![98],
+            })
+        );
+    }
+
+    #[test]
+    fn test_check_access() {
+        let exports = Arc::new(vec![
+            Some(ExportOptions::unrestricted()),
+            Some(ExportOptions::default()),
+            None,
+        ]);
+        let access = ClientAccess::new(exports.clone(), &"10.0.0.1:40000".parse().unwrap());
+        assert!(access.check(0, true).is_ok());
+        assert_eq!(access.check(1, false), Err(NfsStat4::Nfs4errPerm));
+        assert_eq!(access.check(2, false), Err(NfsStat4::Nfs4errAccess));
+        assert_eq!(access.check(3, false), Err(NfsStat4::Nfs4errAccess));
+        let access = ClientAccess::new(exports.clone(), &"10.0.0.1:700".parse().unwrap());
+        assert!(access.check(1, false).is_ok());
+        assert_eq!(access.check(1, true), Err(NfsStat4::Nfs4errRofs));
+        // on a Unix domain socket, root is privileged
+        let peer = "unix:7:0".parse().unwrap();
+        assert_eq!(
+            peer,
+            Peer::Unix {
+                connection: 7,
+                uid: Some(0)
+            }
+        );
+        assert!(ClientAccess::new(exports.clone(), &peer)
+            .check(1, false)
+            .is_ok());
+        let peer: Peer = "unix:8:1000".parse().unwrap();
+        assert_eq!(peer.to_string(), "unix:8:1000");
+        assert_eq!(peer.ip(), IpAddr::from([127, 0, 0, 1]));
+        let access = ClientAccess::new(exports, &peer);
+        assert_eq!(access.check(1, false), Err(NfsStat4::Nfs4errPerm));
+        assert!(!ClientAccess::denied().allowed_anywhere());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_security_flavors() {
+        assert_eq!(
+            SecFlavor::parse_list("krb5p:krb5i:krb5:sys:none"),
+            Ok(vec![
+                SecFlavor::Krb5p,
+                SecFlavor::Krb5i,
+                SecFlavor::Krb5,
+                SecFlavor::AuthSys,
+                SecFlavor::AuthNone
+            ])
+        );
+        assert!(SecFlavor::parse_list("sys:").is_err());
+        assert!("dh".parse::<SecFlavor>().is_err());
+        assert_eq!(SecFlavor::Krb5i.to_string(), "krb5i");
+
+        let mut config = ExportConfig::new("/", Arc::new(MemoryBackend::new()));
+        config.sec = vec![SecFlavor::AuthSys];
+        let exports = Exports::new(vec![config], None);
+        let getfh = || vec![NfsArgOp::Opputrootfh(()), NfsArgOp::Opgetfh(())];
+        let anonymous = OpaqueAuth::AuthNull(Vec::new());
+        let res = compound_as(&exports, "127.0.0.1:700", anonymous, getfh()).await;
+        assert_eq!(res.status, NfsStat4::Nfs4errWrongsec);
+        // PUTROOTFH succeeds, the client learns the flavor with SECINFO
+        assert_eq!(res.resarray.len(), 1);
+        assert_eq!(
+            status(&exports, "127.0.0.1:700", getfh()).await,
+            NfsStat4::Nfs4Ok
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_credential_flavors() {
+        // an AUTH_SYS credential as sent by Linux, the body is opaque data
+        let mut msg = vec![0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0, 2];
+        msg.extend([0, 1, 0x86, 0xa3, 0, 0, 0, 4, 0, 0, 0, 0]);
+        msg.extend([0, 0, 0, 1, 0, 0, 0, 28, 0, 0, 0, 9, 0, 0, 0, 4]);
+        msg.extend(b"host");
+        msg.extend([0, 0, 0x03, 0xe8, 0, 0, 0x03, 0xe9, 0, 0, 0, 1, 0, 0, 0, 10]);
+        msg.extend([0, 0, 0, 0, 0, 0, 0, 0]);
+        let msg = bold_proto::from_bytes(msg.into()).unwrap();
+        let MsgType::Call(body) = msg.body else {
+            panic!("Unexpected message {:?}", msg.body);
+        };
+        assert_eq!(
+            Credentials::from_auth(&body.cred),
+            Some(Credentials {
+                uid: 1000,
+                gid: 1001,
+                gids: vec![10],
+            })
+        );
+
+        // AUTH_NONE is the anonymous user, other flavors are rejected
+        assert_eq!(
+            Credentials::from_auth(&OpaqueAuth::AuthNull(Vec::new())).map(|c| c.uid),
+            Some(ANON_ID)
+        );
+        let exports = Exports::single(FileManagerHandle::new(
+            Arc::new(MemoryBackend::new()),
+            None,
+            None,
+        ));
+        let root = vec![NfsArgOp::Opputrootfh(())];
+        let res = compound_as(
+            &exports,
+            "127.0.0.1:700",
+            OpaqueAuth::AuthNull(Vec::new()),
+            root.clone(),
+        );
+        assert_eq!(res.await.status, NfsStat4::Nfs4Ok);
+        for (cred, expected) in [
+            (OpaqueAuth::Unsupported(6), AuthStat::AuthTooWeak),
+            (OpaqueAuth::AuthDes, AuthStat::AuthTooWeak),
+            (OpaqueAuth::Malformed(1), AuthStat::AuthBadCred),
+        ] {
+            let reply = reply(&exports, "127.0.0.1:700", cred, root.clone()).await;
+            assert_eq!(reply.xid, 7);
+            match reply.body {
+                MsgType::Reply(ReplyBody::MsgDenied(RejectedReply::AuthError(stat))) => {
+                    assert_eq!(stat as u32, expected as u32)
+                }
+                body => panic!("Unexpected reply {:?}", body),
+            }
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_permissions() {
+        let backend = MemoryBackend::new();
+        backend.create_dir_all("/tmp").unwrap();
+        backend.write_file("/file1.txt", b"data").unwrap();
+        backend
+            .setattr(
+                "/tmp",
+                &SetAttrs {
+                    mode: Some(0o1777),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let exports = Exports::new(vec![ExportConfig::new("/", Arc::new(backend))], None);
+        let client = "127.0.0.1:700";
+        let root = NfsArgOp::Opputrootfh(());
+        let write = |data: &[u8]| {
+            NfsArgOp::Opwrite(Write4args {
+                stateid: Stateid4 {
+                    seqid: 0,
+                    other: [0; 12],
+                },
+                offset: 0,
+                stable: StableHow4::FileSync4,
+                data: Bytes::copy_from_slice(data),
+            })
+        };
+        let remove = |name: &str| {
+            NfsArgOp::Opremove(Remove4args {
+                target: name.as_bytes().to_vec(),
+            })
+        };
+        let owner = NfsArgOp::Opgetattr(Getattr4args {
+            attr_request: Attrlist4::<FileAttr>::new(Some(vec![FileAttr::Owner])),
+        });
+
+        // a user may not change what root owns
+        let res = compound_as(&exports, client, user(1000), vec![root.clone(), mkdir("d")]);
+        assert_eq!(res.await.status, NfsStat4::Nfs4errAccess);
+        let argarray = vec![root.clone(), lookup("file1.txt"), write(b"changed")];
+        let res = compound_as(&exports, client, user(1000), argarray.clone());
+        assert_eq!(res.await.status, NfsStat4::Nfs4errAccess);
+        let res = compound_as(&exports, client, user(0), argarray);
+        assert_eq!(res.await.status, NfsStat4::Nfs4Ok);
+
+        // what a user creates belongs to it
+        let argarray = vec![root.clone(), lookup("tmp"), mkdir("mine"), owner];
+        let res = compound_as(&exports, client, user(1000), argarray).await;
+        assert_eq!(res.status, NfsStat4::Nfs4Ok);
+        let NfsResOp4::Opgetattr(getattr) = &res.resarray[3] else {
+            panic!("Unexpected response {:?}", res.resarray[3]);
+        };
+        let attrs = getattr.obj_attributes.as_ref().unwrap();
+        assert!(matches!(&attrs.attr_vals[0], FileAttrValue::Owner(owner) if owner == "1000"));
+
+        // in a sticky directory only the owner may remove an entry
+        let argarray = vec![root.clone(), lookup("tmp"), remove("mine")];
+        let res = compound_as(&exports, client, user(1001), argarray.clone());
+        assert_eq!(res.await.status, NfsStat4::Nfs4errPerm);
+        let res = compound_as(&exports, client, user(1000), argarray);
+        assert_eq!(res.await.status, NfsStat4::Nfs4Ok);
+    }
+}
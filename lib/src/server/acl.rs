@@ -0,0 +1,294 @@
+//! NFSv4 ACLs, see https://datatracker.ietf.org/doc/html/rfc7530#section-6
+//!
+//! Objects without a stored ACL are presented with the ACL equivalent to
+//! their mode. Setting an ACL also sets the mode it is equivalent to, and
+//! setting the mode rewrites the entries of the special principals.
+
+use bold_proto::nfs4_proto::{
+    NfsStat4, Nfsace4, ACCESS4_DELETE, ACCESS4_EXECUTE, ACCESS4_EXTEND, ACCESS4_LOOKUP,
+    ACCESS4_MODIFY, ACCESS4_READ, ACE4_ACCESS_ALLOWED_ACE_TYPE, ACE4_ACCESS_DENIED_ACE_TYPE,
+    ACE4_ADD_FILE, ACE4_ADD_SUBDIRECTORY, ACE4_APPEND_DATA, ACE4_DELETE_CHILD, ACE4_EXECUTE,
+    ACE4_IDENTIFIER_GROUP, ACE4_INHERIT_ONLY_ACE, ACE4_READ_DATA, ACE4_WRITE_DATA,
+    ACL4_SUPPORT_ALLOW_ACL, ACL4_SUPPORT_DENY_ACL,
+};
+
+use super::access::Credentials;
+
+pub const OWNER: &[u8] = b"OWNER@";
+pub const GROUP: &[u8] = b"GROUP@";
+pub const EVERYONE: &[u8] = b"EVERYONE@";
+
+/// The ACE types bold stores and enforces, audit and alarm ACEs are not supported.
+pub const ACL_SUPPORT: u32 = ACL4_SUPPORT_ALLOW_ACL | ACL4_SUPPORT_DENY_ACL;
+
+// the ACE4 bits equivalent to the read, write and execute bits of a mode
+const MODE_READ: u32 = ACE4_READ_DATA;
+const MODE_WRITE: u32 = ACE4_WRITE_DATA | ACE4_APPEND_DATA | ACE4_DELETE_CHILD;
+const MODE_EXECUTE: u32 = ACE4_EXECUTE;
+
+fn mask_from_bits(bits: u32, is_dir: bool) -> u32 {
+    let mut mask = 0;
+    if bits & 0o4 != 0 {
+        mask |= MODE_READ;
+    }
+    if bits & 0o2 != 0 {
+        // DELETE_CHILD only means something for directories
+        mask |= if is_dir {
+            MODE_WRITE
+        } else {
+            MODE_WRITE & !ACE4_DELETE_CHILD
+        };
+    }
+    if bits & 0o1 != 0 {
+        mask |= MODE_EXECUTE;
+    }
+    mask
+}
+
+fn bits_from_mask(mask: u32) -> u32 {
+    let mut bits = 0;
+    if mask & MODE_READ != 0 {
+        bits |= 0o4;
+    }
+    if mask & ACE4_WRITE_DATA != 0 {
+        bits |= 0o2;
+    }
+    if mask & MODE_EXECUTE != 0 {
+        bits |= 0o1;
+    }
+    bits
+}
+
+fn ace(acetype: u32, who: &[u8], access_mask: u32) -> Nfsace4 {
+    Nfsace4 {
+        acetype,
+        flag: 0,
+        access_mask,
+        who: who.to_vec(),
+    }
+}
+
+fn is_special(ace: &Nfsace4) -> bool {
+    ace.who == OWNER || ace.who == GROUP || ace.who == EVERYONE
+}
+
+/// The ACL equivalent to `mode`.
+///
+/// The owner and the group are denied what they are not granted but later
+/// entries would grant them, as the first entry that matches decides.
+pub fn from_mode(mode: u32, is_dir: bool) -> Vec<Nfsace4> {
+    let owner = mask_from_bits((mode >> 6) & 0o7, is_dir);
+    let group = mask_from_bits((mode >> 3) & 0o7, is_dir);
+    let other = mask_from_bits(mode & 0o7, is_dir);
+
+    let mut acl = Vec::new();
+    let mut add = |acetype, who, mask| {
+        if mask != 0 {
+            acl.push(ace(acetype, who, mask));
+        }
+    };
+    add(ACE4_ACCESS_ALLOWED_ACE_TYPE, OWNER, owner);
+    add(ACE4_ACCESS_DENIED_ACE_TYPE, OWNER, (group | other) & !owner);
+    add(ACE4_ACCESS_ALLOWED_ACE_TYPE, GROUP, group);
+    add(ACE4_ACCESS_DENIED_ACE_TYPE, GROUP, other & !group);
+    add(ACE4_ACCESS_ALLOWED_ACE_TYPE, EVERYONE, other);
+    acl
+}
+
+// the mask allowed to the principals `matches` accepts, the first entry
+// that matches decides each bit
+fn allowed_mask(acl: &[Nfsace4], matches: impl Fn(&Nfsace4) -> bool) -> u32 {
+    let mut allowed = 0;
+    let mut decided = 0;
+    for ace in acl {
+        if ace.flag & ACE4_INHERIT_ONLY_ACE != 0 || !matches(ace) {
+            continue;
+        }
+        let bits = ace.access_mask & !decided;
+        if ace.acetype == ACE4_ACCESS_ALLOWED_ACE_TYPE {
+            allowed |= bits;
+        }
+        decided |= bits;
+    }
+    allowed
+}
+
+/// The mode equivalent to `acl`, keeping the set-id and sticky bits of `mode`.
+///
+/// https://datatracker.ietf.org/doc/html/rfc7530#section-6.4.1.1
+pub fn mode_from_acl(acl: &[Nfsace4], mode: u32) -> u32 {
+    let class = |whos: &[&[u8]]| {
+        bits_from_mask(allowed_mask(acl, |ace| whos.contains(&ace.who.as_slice())))
+    };
+    let owner = class(&[OWNER, EVERYONE]);
+    let group = class(&[GROUP, EVERYONE]);
+    let other = class(&[EVERYONE]);
+    (mode & 0o7000) | (owner << 6) | (group << 3) | other
+}
+
+/// `acl` after setting the mode to `mode`: the entries of named users and
+/// groups are kept, those of the special principals follow the mode.
+pub fn apply_mode(acl: &[Nfsace4], mode: u32, is_dir: bool) -> Vec<Nfsace4> {
+    let mut result: Vec<Nfsace4> = acl.iter().filter(|ace| !is_special(ace)).cloned().collect();
+    result.extend(from_mode(mode, is_dir));
+    result
+}
+
+/// Check that bold can store and enforce `acl`.
+pub fn validate(acl: &[Nfsace4]) -> Result<(), NfsStat4> {
+    for ace in acl {
+        if ace.acetype != ACE4_ACCESS_ALLOWED_ACE_TYPE && ace.acetype != ACE4_ACCESS_DENIED_ACE_TYPE
+        {
+            return Err(NfsStat4::Nfs4errAttrnotsupp);
+        }
+        if std::str::from_utf8(&ace.who).is_err() {
+            return Err(NfsStat4::Nfs4errInval);
+        }
+    }
+    Ok(())
+}
+
+// Named principals are numeric ids, the way owner and owner_group are
+// reported, a domain after the @ is ignored.
+fn named_id(who: &[u8]) -> Option<u32> {
+    let who = std::str::from_utf8(who).ok()?;
+    who.split('@').next()?.parse().ok()
+}
+
+/// The ACCESS4 bits `acl` grants to `credentials` on an object owned by
+/// `uid` and `gid`.
+pub fn access(acl: &[Nfsace4], credentials: &Credentials, uid: u32, gid: u32, is_dir: bool) -> u32 {
+    let allowed = allowed_mask(acl, |ace| match ace.who.as_slice() {
+        OWNER => credentials.uid == uid,
+        GROUP => credentials.in_group(gid),
+        EVERYONE => true,
+        who => match named_id(who) {
+            Some(id) if ace.flag & ACE4_IDENTIFIER_GROUP != 0 => credentials.in_group(id),
+            Some(id) => credentials.uid == id,
+            None => false,
+        },
+    });
+
+    let required = if is_dir {
+        [
+            (ACCESS4_READ, ACE4_READ_DATA),
+            (ACCESS4_LOOKUP, ACE4_EXECUTE),
+            (ACCESS4_MODIFY, ACE4_ADD_FILE),
+            (ACCESS4_EXTEND, ACE4_ADD_FILE | ACE4_ADD_SUBDIRECTORY),
+            (ACCESS4_DELETE, ACE4_DELETE_CHILD),
+        ]
+        .as_slice()
+    } else {
+        [
+            (ACCESS4_READ, ACE4_READ_DATA),
+            (ACCESS4_MODIFY, ACE4_WRITE_DATA),
+            (ACCESS4_EXTEND, ACE4_APPEND_DATA),
+            (ACCESS4_EXECUTE, ACE4_EXECUTE),
+        ]
+        .as_slice()
+    };
+    required
+        .iter()
+        .filter(|(_, mask)| allowed & mask == *mask)
+        .fold(0, |access, (bit, _)| access | bit)
+}
+
+#[cfg(test)]
+mod tests {
+    use bold_proto::nfs4_proto::{
+        NfsStat4, ACCESS4_DELETE, ACCESS4_EXECUTE, ACCESS4_EXTEND, ACCESS4_LOOKUP, ACCESS4_MODIFY,
+        ACCESS4_READ, ACE4_ACCESS_ALLOWED_ACE_TYPE, ACE4_ACCESS_DENIED_ACE_TYPE,
+        ACE4_IDENTIFIER_GROUP, ACE4_READ_DATA, ACE4_SYSTEM_AUDIT_ACE_TYPE, ACE4_WRITE_DATA,
+    };
+
+    use super::*;
+
+    fn user(uid: u32, gid: u32) -> Credentials {
+        Credentials {
+            uid,
+            gid,
+            gids: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_mode_round_trip() {
+        for mode in [0o755, 0o644, 0o600, 0o077, 0o4751, 0o000, 0o707, 0o1777] {
+            for is_dir in [false, true] {
+                let acl = from_mode(mode, is_dir);
+                assert_eq!(mode_from_acl(&acl, mode & 0o7000), mode, "{:o}", mode);
+            }
+        }
+
+        // the acl of a mode grants what the mode grants
+        let acl = from_mode(0o640, false);
+        assert_eq!(
+            access(&acl, &user(1000, 100), 1000, 100, false),
+            ACCESS4_READ | ACCESS4_MODIFY | ACCESS4_EXTEND
+        );
+        assert_eq!(
+            access(&acl, &user(1001, 100), 1000, 100, false),
+            ACCESS4_READ
+        );
+        assert_eq!(access(&acl, &user(1001, 101), 1000, 100, false), 0);
+        // the owner is denied what only others are granted
+        let acl = from_mode(0o007, true);
+        assert_eq!(access(&acl, &user(1000, 100), 1000, 100, true), 0);
+        assert_eq!(
+            access(&acl, &user(1001, 101), 1000, 100, true),
+            ACCESS4_READ | ACCESS4_LOOKUP | ACCESS4_MODIFY | ACCESS4_EXTEND | ACCESS4_DELETE
+        );
+    }
+
+    #[test]
+    fn test_named_principals() {
+        let acl = vec![
+            ace(ACE4_ACCESS_DENIED_ACE_TYPE, b"1001", ACE4_WRITE_DATA),
+            Nfsace4 {
+                acetype: ACE4_ACCESS_ALLOWED_ACE_TYPE,
+                flag: ACE4_IDENTIFIER_GROUP,
+                access_mask: ACE4_READ_DATA | ACE4_WRITE_DATA,
+                who: b"200@example.com".to_vec(),
+            },
+            ace(ACE4_ACCESS_ALLOWED_ACE_TYPE, EVERYONE, ACE4_EXECUTE),
+        ];
+        let mut member = user(1002, 100);
+        member.gids.push(200);
+        assert_eq!(
+            access(&acl, &member, 1000, 100, false),
+            ACCESS4_READ | ACCESS4_MODIFY | ACCESS4_EXECUTE
+        );
+        // the first entry that matches decides
+        let mut denied = user(1001, 200);
+        assert_eq!(
+            access(&acl, &denied, 1000, 100, false),
+            ACCESS4_READ | ACCESS4_EXECUTE
+        );
+        denied.gid = 100;
+        assert_eq!(access(&acl, &denied, 1000, 100, false), ACCESS4_EXECUTE);
+
+        // setting the mode keeps the named entries
+        let acl = apply_mode(&acl, 0o700, false);
+        assert_eq!(acl.len(), 3);
+        assert_eq!(acl[0].who, b"1001");
+        assert_eq!(acl[2].who, OWNER);
+        assert_eq!(mode_from_acl(&acl, 0), 0o700);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(validate(&from_mode(0o755, true)), Ok(()));
+        assert_eq!(
+            validate(&[ace(ACE4_SYSTEM_AUDIT_ACE_TYPE, EVERYONE, ACE4_READ_DATA)]),
+            Err(NfsStat4::Nfs4errAttrnotsupp)
+        );
+        assert_eq!(
+            validate(&[ace(
+                ACE4_ACCESS_ALLOWED_ACE_TYPE,
+                &[0xff, 0xfe],
+                ACE4_READ_DATA
+            )]),
+            Err(NfsStat4::Nfs4errInval)
+        );
+    }
+}
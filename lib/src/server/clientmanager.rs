@@ -1,14 +1,15 @@
 use multi_index_map::MultiIndexMap;
 use rand::distributions::Uniform;
 use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::{mpsc, oneshot};
 use tracing::error;
-use serde_derive::{Deserialize, Serialize};
 
-use bold_proto::nfs4_proto::NfsStat4;
+use bold_proto::{nfs4_proto::NfsStat4, rpc_proto::parse_uaddr};
 
 type ClientDb = MultiIndexClientEntryMap;
 
@@ -28,6 +29,19 @@ pub struct ClientCallback {
     pub callback_ident: u32,
 }
 
+impl ClientCallback {
+    /// The address of the callback service of the client, None if the
+    /// universal address doesn't go with the netid, e.g. an IPv6 address
+    /// with netid `tcp`.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        let addr = parse_uaddr(&self.raddr)?;
+        match (self.rnetid.as_str(), addr) {
+            ("tcp" | "udp", SocketAddr::V4(_)) | ("tcp6" | "udp6", SocketAddr::V6(_)) => Some(addr),
+            _ => None,
+        }
+    }
+}
+
 /// Please read: [RFC 7530, Section 16.33.5](https://datatracker.ietf.org/doc/html/rfc7530#section-16.33.5)
 #[derive(MultiIndexMap, Debug, Clone, Serialize, Deserialize)]
 #[multi_index_derive(Debug, Clone)]
@@ -254,7 +268,10 @@ impl ClientManager {
         });
 
         // Return the now confirmed entry
-        Ok(client_to_confirm)
+        Ok(ClientEntry {
+            confirmed: true,
+            ..client_to_confirm
+        })
     }
 
     fn renew_leases(&mut self, client_id: u64) -> Result<(), ClientManagerError> {
@@ -584,4 +601,38 @@ mod tests {
         assert_eq!(same_client.principal, Some("Linux".to_string()));
         assert!(same_client.confirmed);
     }
+
+    #[test]
+    fn test_callback_addr() {
+        let addr = |rnetid: &str, raddr: &str| {
+            super::ClientCallback {
+                program: 0,
+                rnetid: rnetid.to_string(),
+                raddr: raddr.to_string(),
+                callback_ident: 0,
+            }
+            .addr()
+            .map(|addr| addr.to_string())
+        };
+
+        assert_eq!(
+            addr("tcp", "192.168.1.7.8.1"),
+            Some("192.168.1.7:2049".into())
+        );
+        assert_eq!(addr("tcp6", "fe80::1.3.255"), Some("[fe80::1]:1023".into()));
+        assert_eq!(addr("tcp6", "::.0.0"), Some("[::]:0".into()));
+        assert_eq!(
+            addr("udp6", "::ffff:10.0.0.1.149.18"),
+            Some("[::ffff:10.0.0.1]:38162".into())
+        );
+        // the address doesn't go with the netid
+        assert_eq!(addr("tcp", "fe80::1.3.255"), None);
+        assert_eq!(addr("tcp6", "192.168.1.7.8.1"), None);
+        assert_eq!(addr("rdma", "192.168.1.7.8.1"), None);
+        // no port, or parts of the port out of range
+        assert_eq!(addr("tcp", "192.168.1.7"), None);
+        assert_eq!(addr("tcp", "192.168.1.7.256.1"), None);
+        assert_eq!(addr("tcp6", "fe80::1"), None);
+        assert_eq!(addr("tcp", ""), None);
+    }
 }
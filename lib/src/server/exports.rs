@@ -0,0 +1,894 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ffi::OsString,
+    net::IpAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use async_trait::async_trait;
+use bold_proto::nfs4_proto::{NfsFh4, NfsFtype4};
+
+use super::{
+    access::{ClientAccess, ClientRule, ExportOptions, Peer, SecFlavor},
+    filemanager::{
+        FileManagerConfig, FileManagerError, FileManagerHandle, Filehandle, FH_EXPORT_BYTE,
+    },
+};
+use crate::storage::{
+    split_path, Capabilities, DirEntry, Metadata, ReadDirPage, SetAttrs, StatFs, StorageBackend,
+    StorageError, StorageResult,
+};
+
+// how long the outcome of matching a client against the export rules is kept
+const ACCESS_CACHE_TTL: Duration = Duration::from_secs(60);
+// client addresses remembered at most, expired ones are dropped first
+const ACCESS_CACHE_SIZE: usize = 4096;
+
+// the export options of recently seen client addresses, by export id
+type AccessCache = HashMap<IpAddr, (Instant, Arc<Vec<Option<ExportOptions>>>)>;
+
+/// An export as configured on the [`crate::ServerBuilder`].
+#[derive(Debug, Clone)]
+pub struct ExportConfig {
+    pub path: String,
+    pub backend: Arc<dyn StorageBackend>,
+    // the clients allowed to use the export, everyone if there are no rules
+    pub rules: Vec<ClientRule>,
+    // no client may change the export, whatever its rule says
+    pub read_only: bool,
+    // the security flavors the export can be used with, by preference, any if empty
+    pub sec: Vec<SecFlavor>,
+}
+
+impl ExportConfig {
+    pub fn new(path: &str, backend: Arc<dyn StorageBackend>) -> Self {
+        ExportConfig {
+            path: path.to_string(),
+            backend,
+            rules: Vec::new(),
+            read_only: false,
+            sec: Vec::new(),
+        }
+    }
+}
+
+/// A file system served by this server.
+#[derive(Debug, Clone)]
+pub struct Export {
+    // recorded in every filehandle of this export
+    pub id: u8,
+    // where the export is mounted in the namespace of the server
+    pub path: String,
+    pub file_manager: FileManagerHandle,
+    pub rules: Vec<ClientRule>,
+    pub read_only: bool,
+    pub sec: Vec<SecFlavor>,
+}
+
+impl Export {
+    /// Whether requests made with `flavor` may use the export.
+    pub fn allows(&self, flavor: SecFlavor) -> bool {
+        self.sec.is_empty() || self.sec.contains(&flavor)
+    }
+
+    /// The flavors to use the export with, the most preferred first.
+    pub fn flavors(&self) -> Vec<SecFlavor> {
+        if self.sec.is_empty() {
+            vec![SecFlavor::AuthSys, SecFlavor::AuthNone]
+        } else {
+            self.sec.clone()
+        }
+    }
+
+    // the options of the first rule matching ip
+    async fn options_for(&self, ip: IpAddr) -> Option<ExportOptions> {
+        if self.rules.is_empty() {
+            return Some(ExportOptions::unrestricted());
+        }
+        for rule in self.rules.iter() {
+            if rule.clients.matches(ip).await {
+                return Some(rule.options);
+            }
+        }
+        None
+    }
+}
+
+/// All exports of a server.
+///
+/// A single export at `/` is served as it is. Otherwise the exports are
+/// joined by a read-only pseudo file system, which holds the directories
+/// leading to the mount points of the exports.
+/// https://datatracker.ietf.org/doc/html/rfc7530#section-7
+#[derive(Debug, Clone)]
+pub struct Exports {
+    // indexed by export id, id 0 is the root of the namespace: either the
+    // only export or the pseudo file system
+    exports: Arc<Vec<Export>>,
+    access_cache: Arc<Mutex<AccessCache>>,
+    // the path of the public filehandle in the namespace
+    public: String,
+}
+
+impl Exports {
+    /// Serve a single file system at `/`.
+    pub fn single(file_manager: FileManagerHandle) -> Self {
+        Self::from_exports(vec![Export {
+            id: 0,
+            path: "/".to_string(),
+            file_manager,
+            rules: Vec::new(),
+            read_only: false,
+            sec: Vec::new(),
+        }])
+    }
+
+    fn from_exports(exports: Vec<Export>) -> Self {
+        Exports {
+            exports: Arc::new(exports),
+            access_cache: Arc::new(Mutex::new(HashMap::new())),
+            public: "/".to_string(),
+        }
+    }
+
+    /// Hand out the directory at `path` as the public filehandle instead of
+    /// the root.
+    pub fn with_public(mut self, path: &str) -> Self {
+        self.public = path.to_string();
+        self
+    }
+
+    /// Serve `configs` at their paths. Every export keeps its filehandles
+    /// in a file of its own next to `handle_db`.
+    pub fn new(mut configs: Vec<ExportConfig>, handle_db: Option<PathBuf>) -> Self {
+        if configs.len() == 1 && configs[0].path == "/" {
+            let config = configs.remove(0);
+            return Self::from_exports(vec![Export {
+                id: 0,
+                path: config.path,
+                file_manager: FileManagerHandle::new(config.backend, None, handle_db),
+                rules: config.rules,
+                read_only: config.read_only,
+                sec: config.sec,
+            }]);
+        }
+
+        // the export id has to fit into a single byte of the filehandle
+        assert!(configs.len() < u8::MAX as usize, "Too many exports");
+        let pseudo_fs = PseudoFs::new(configs.iter().map(|config| config.path.as_str()));
+        // the pseudo file system can be browsed with the flavor of any export
+        let mut pseudo_sec = Vec::new();
+        if configs.iter().all(|config| !config.sec.is_empty()) {
+            for flavor in configs.iter().flat_map(|config| config.sec.iter()) {
+                if !pseudo_sec.contains(flavor) {
+                    pseudo_sec.push(*flavor);
+                }
+            }
+        }
+        let mut exports = Vec::with_capacity(configs.len() + 1);
+        for (idx, config) in configs.into_iter().enumerate() {
+            let ExportConfig {
+                path,
+                backend,
+                rules,
+                read_only,
+                sec,
+            } = config;
+            let id = idx as u8 + 1;
+            let config = FileManagerConfig {
+                handle_db: handle_db.as_ref().map(|db| {
+                    let mut name = OsString::from(db);
+                    name.push(format!(".{}", id));
+                    PathBuf::from(name)
+                }),
+                export_id: id,
+                mounted_on_fileid: pseudo_fs.ino(&path),
+                ..Default::default()
+            };
+            exports.push(Export {
+                id,
+                path,
+                file_manager: FileManagerHandle::with_config(backend, config),
+                rules,
+                read_only,
+                sec,
+            });
+        }
+        exports.insert(
+            0,
+            Export {
+                id: 0,
+                path: "/".to_string(),
+                file_manager: FileManagerHandle::new(Arc::new(pseudo_fs), None, None),
+                rules: Vec::new(),
+                read_only: true,
+                sec: pseudo_sec,
+            },
+        );
+        Self::from_exports(exports)
+    }
+
+    /// Match the client `peer` against the rules of every export.
+    pub async fn client_access(&self, peer: &Peer) -> ClientAccess {
+        let ip = peer.ip();
+        let cached = self
+            .access_cache
+            .lock()
+            .unwrap()
+            .get(&ip)
+            .filter(|(time, _)| time.elapsed() < ACCESS_CACHE_TTL)
+            .map(|(_, options)| options.clone());
+        if let Some(options) = cached {
+            return ClientAccess::new(options, peer);
+        }
+
+        let mut options = Vec::with_capacity(self.exports.len());
+        for export in self.exports.iter() {
+            options.push(export.options_for(ip).await);
+        }
+        // the pseudo file system can be browsed by every client allowed on an export
+        if self.exports.len() > 1 {
+            options[0] = options[1..]
+                .iter()
+                .any(Option::is_some)
+                .then_some(ExportOptions {
+                    read_only: true,
+                    ..ExportOptions::unrestricted()
+                });
+        }
+        let options = Arc::new(options);
+        let mut cache = self.access_cache.lock().unwrap();
+        if cache.len() >= ACCESS_CACHE_SIZE && !cache.contains_key(&ip) {
+            cache.retain(|_, (time, _)| time.elapsed() < ACCESS_CACHE_TTL);
+        }
+        if cache.len() >= ACCESS_CACHE_SIZE && !cache.contains_key(&ip) {
+            let oldest = cache
+                .iter()
+                .min_by_key(|(_, (time, _))| *time)
+                .map(|(ip, _)| *ip);
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+        cache.insert(ip, (Instant::now(), options.clone()));
+        ClientAccess::new(options, peer)
+    }
+
+    /// The file manager serving the root of the namespace.
+    pub fn root(&self) -> FileManagerHandle {
+        self.exports[0].file_manager.clone()
+    }
+
+    /// The filehandle PUTPUBFH sets, in the export holding the public path.
+    /// https://datatracker.ietf.org/doc/html/rfc7530#section-16.22
+    pub async fn public_filehandle(&self) -> Result<Filehandle, FileManagerError> {
+        let export = self.exports[1..]
+            .iter()
+            .find(|export| {
+                self.public == export.path || self.public.starts_with(&format!("{}/", export.path))
+            })
+            .unwrap_or(&self.exports[0]);
+        let path = match &self.public[export.path.len()..] {
+            _ if export.path == "/" => self.public.as_str(),
+            "" => "/",
+            path => path,
+        };
+        export
+            .file_manager
+            .get_filehandle_for_path(path.to_string())
+            .await
+    }
+
+    /// Write the filehandles handed out by any export to its filehandle db.
+    pub async fn sync_handles(&self) {
+        for export in self.exports.iter() {
+            export.file_manager.sync_handles().await;
+        }
+    }
+
+    /// The export a filehandle belongs to.
+    pub fn get(&self, id: &NfsFh4) -> Option<&Export> {
+        self.exports.get(id[FH_EXPORT_BYTE] as usize)
+    }
+
+    /// The export mounted at `path`, if `dir` is a directory of the pseudo file system.
+    pub fn mounted_at(&self, dir: &NfsFh4, path: &str) -> Option<&Export> {
+        if dir[FH_EXPORT_BYTE] != 0 {
+            return None;
+        }
+        self.exports[1..].iter().find(|export| export.path == path)
+    }
+}
+
+/// Normalize the mount point of an export, `None` if it is not an absolute path
+/// made of plain names.
+pub fn export_path(path: &str) -> Option<String> {
+    if !path.starts_with('/') {
+        return None;
+    }
+    let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+    if components.iter().any(|c| *c == "." || *c == "..") {
+        return None;
+    }
+    Some(format!("/{}", components.join("/")))
+}
+
+// the directories leading to the mount points of the exports
+#[derive(Debug)]
+struct PseudoFs {
+    dirs: BTreeMap<String, PseudoDir>,
+    created: SystemTime,
+}
+
+#[derive(Debug)]
+struct PseudoDir {
+    ino: u64,
+    entries: BTreeSet<String>,
+}
+
+impl PseudoFs {
+    fn new<'a>(mount_points: impl Iterator<Item = &'a str>) -> Self {
+        let mut pseudo_fs = PseudoFs {
+            dirs: BTreeMap::new(),
+            created: SystemTime::now(),
+        };
+        pseudo_fs.add_dir("/");
+        for path in mount_points {
+            pseudo_fs.add_dir(path);
+        }
+        pseudo_fs
+    }
+
+    fn add_dir(&mut self, path: &str) {
+        if self.dirs.contains_key(path) {
+            return;
+        }
+        if path != "/" {
+            let (parent, name) = split_path(path);
+            self.add_dir(&parent);
+            self.dirs.get_mut(&parent).unwrap().entries.insert(name);
+        }
+        let ino = self.dirs.len() as u64 + 1;
+        self.dirs.insert(
+            path.to_string(),
+            PseudoDir {
+                ino,
+                entries: BTreeSet::new(),
+            },
+        );
+    }
+
+    fn ino(&self, path: &str) -> Option<u64> {
+        self.dirs.get(path).map(|dir| dir.ino)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for PseudoFs {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            hard_links: false,
+            symlinks: false,
+            unique_handles: true,
+            // the tree only changes with the configuration of the server
+            persistent_handles: true,
+            inode_lookup: true,
+            exclusive_access: true,
+        }
+    }
+
+    async fn getattr(&self, path: &str) -> StorageResult<Metadata> {
+        let dir = self.dirs.get(path).ok_or(StorageError::NotFound)?;
+        Ok(Metadata {
+            file_type: NfsFtype4::Nf4dir,
+            size: 0,
+            space_used: 0,
+            mode: 0o555,
+            nlink: 2,
+            uid: 0,
+            gid: 0,
+            dev: 0,
+            ino: dir.ino,
+            generation: 0,
+            atime: Some(self.created),
+            mtime: Some(self.created),
+            ctime: Some(self.created),
+            btime: None,
+            backup_time: None,
+            acl: None,
+            named_attrs: false,
+        })
+    }
+
+    async fn setattr(&self, _path: &str, _attrs: &SetAttrs) -> StorageResult<Metadata> {
+        Err(StorageError::ReadOnly)
+    }
+
+    async fn read(&self, path: &str, _offset: u64, _count: u32) -> StorageResult<(Vec<u8>, bool)> {
+        self.getattr(path).await?;
+        Err(StorageError::IsDir)
+    }
+
+    async fn write(&self, _path: &str, _offset: u64, _data: &[u8]) -> StorageResult<u32> {
+        Err(StorageError::ReadOnly)
+    }
+
+    async fn create(
+        &self,
+        _dir: &str,
+        _name: &str,
+        _file_type: NfsFtype4,
+        _mode: u32,
+    ) -> StorageResult<Metadata> {
+        Err(StorageError::ReadOnly)
+    }
+
+    async fn remove(&self, _dir: &str, _name: &str) -> StorageResult<()> {
+        Err(StorageError::ReadOnly)
+    }
+
+    async fn rename(
+        &self,
+        _from_dir: &str,
+        _from_name: &str,
+        _to_dir: &str,
+        _to_name: &str,
+    ) -> StorageResult<()> {
+        Err(StorageError::ReadOnly)
+    }
+
+    async fn link(&self, _path: &str, _dir: &str, _name: &str) -> StorageResult<Metadata> {
+        Err(StorageError::ReadOnly)
+    }
+
+    async fn symlink(&self, _dir: &str, _name: &str, _target: &str) -> StorageResult<Metadata> {
+        Err(StorageError::ReadOnly)
+    }
+
+    async fn readlink(&self, _path: &str) -> StorageResult<String> {
+        Err(StorageError::InvalidArgument)
+    }
+
+    async fn readdir(
+        &self,
+        path: &str,
+        cookie: u64,
+        max_entries: usize,
+    ) -> StorageResult<ReadDirPage> {
+        let dir = self.dirs.get(path).ok_or(StorageError::NotFound)?;
+        let entries: Vec<DirEntry> = dir
+            .entries
+            .iter()
+            .enumerate()
+            .skip(cookie as usize)
+            .take(max_entries)
+            .map(|(idx, name)| DirEntry {
+                name: name.clone().into_bytes(),
+                cookie: idx as u64 + 1,
+            })
+            .collect();
+        let eof = match entries.last() {
+            Some(last) => last.cookie as usize >= dir.entries.len(),
+            None => true,
+        };
+        Ok(ReadDirPage { entries, eof })
+    }
+
+    async fn statfs(&self, _path: &str) -> StorageResult<StatFs> {
+        Ok(StatFs::default())
+    }
+
+    async fn lookup_inode(&self, _dev: u64, ino: u64, _generation: u32) -> StorageResult<String> {
+        self.dirs
+            .iter()
+            .find(|(_, dir)| dir.ino == ino)
+            .map(|(path, _)| path.clone())
+            .ok_or(StorageError::NotFound)
+    }
+}
+
+#[cfg(test)]
+mod integration_tests {
+    use std::sync::Arc;
+
+    use bold_proto::{nfs4_proto::*, rpc_proto::*};
+    use bytes::Bytes;
+    use tracing_test::traced_test;
+
+    use super::{export_path, ExportConfig, Exports, Peer, ACCESS_CACHE_SIZE};
+    use crate::{
+        server::{
+            clientmanager::ClientManagerHandle, nfs40::NFS40Server, request::NfsRequest,
+            NfsProtoImpl,
+        },
+        storage::MemoryBackend,
+    };
+
+    fn create_exports() -> Exports {
+        let data = MemoryBackend::new();
+        data.write_file("/file1.txt", b"data").unwrap();
+        let scratch = MemoryBackend::new();
+        scratch.create_dir_all("/dir1").unwrap();
+        Exports::new(
+            vec![
+                ExportConfig::new("/data", Arc::new(data)),
+                ExportConfig::new("/srv/scratch", Arc::new(scratch)),
+            ],
+            None,
+        )
+    }
+
+    async fn compound(
+        request: NfsRequest<'static>,
+        argarray: Vec<NfsArgOp>,
+    ) -> (NfsRequest<'static>, Compound4res) {
+        let call = CallBody {
+            rpcvers: 2,
+            prog: 100003,
+            vers: 4,
+            proc: 1,
+            cred: OpaqueAuth::AuthNull(Vec::new()),
+            verf: OpaqueAuth::AuthNull(Vec::new()),
+            gss_args: None,
+            args: Some(Compound4args {
+                tag: "".to_string(),
+                minor_version: 0,
+                argarray,
+            }),
+        };
+        let (request, reply) = NFS40Server::new().compound(call, request).await;
+        let ReplyBody::MsgAccepted(AcceptedReply {
+            reply_data: AcceptBody::Success(res),
+            ..
+        }) = reply
+        else {
+            panic!("Unexpected reply {:?}", reply);
+        };
+        (request, res)
+    }
+
+    fn lookup(name: &str) -> NfsArgOp {
+        NfsArgOp::Oplookup(Lookup4args {
+            objname: name.as_bytes().to_vec(),
+        })
+    }
+
+    fn getattr() -> NfsArgOp {
+        NfsArgOp::Opgetattr(Getattr4args {
+            attr_request: Attrlist4::<FileAttr>::new(Some(vec![
+                FileAttr::Fsid,
+                FileAttr::Fileid,
+                FileAttr::MountedOnFileid,
+            ])),
+        })
+    }
+
+    // fsid, fileid and mounted_on_fileid of the last GETATTR
+    fn attrs(resarray: &[NfsResOp4]) -> (Fsid4, u64, u64) {
+        let getattr = resarray
+            .iter()
+            .rev()
+            .find_map(|res| match res {
+                NfsResOp4::Opgetattr(getattr) => getattr.obj_attributes.clone(),
+                _ => None,
+            })
+            .unwrap();
+        let mut fsid = None;
+        let mut fileid = None;
+        let mut mounted_on_fileid = None;
+        for value in getattr.attr_vals.iter() {
+            match value {
+                FileAttrValue::Fsid(v) => fsid = Some(*v),
+                FileAttrValue::Fileid(v) => fileid = Some(*v),
+                FileAttrValue::MountedOnFileid(v) => mounted_on_fileid = Some(*v),
+                _ => {}
+            }
+        }
+        (fsid.unwrap(), fileid.unwrap(), mounted_on_fileid.unwrap())
+    }
+
+    #[test]
+    fn test_export_path() {
+        assert_eq!(export_path("/data"), Some("/data".to_string()));
+        assert_eq!(
+            export_path("//srv/scratch/"),
+            Some("/srv/scratch".to_string())
+        );
+        assert_eq!(export_path("/"), Some("/".to_string()));
+        assert_eq!(export_path("data"), None);
+        assert_eq!(export_path("/srv/../etc"), None);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_access_cache_is_bounded() {
+        let exports = create_exports();
+        for i in 0..ACCESS_CACHE_SIZE as u32 + 10 {
+            let ip = std::net::Ipv4Addr::from(0x0a00_0000 + i);
+            exports.client_access(&Peer::Tcp((ip, 700).into())).await;
+        }
+        assert_eq!(
+            exports.access_cache.lock().unwrap().len(),
+            ACCESS_CACHE_SIZE
+        );
+        // the newest clients are the ones remembered
+        let last = std::net::Ipv4Addr::from(0x0a00_0000 + ACCESS_CACHE_SIZE as u32 + 9);
+        assert!(exports
+            .access_cache
+            .lock()
+            .unwrap()
+            .contains_key(&std::net::IpAddr::V4(last)));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_lookup_across_exports() {
+        let request = NfsRequest::new(
+            "127.0.0.1:12345".to_owned(),
+            ClientManagerHandle::new(),
+            create_exports(),
+            0_u64,
+            None,
+        );
+
+        // the pseudo root lists the top directories of the mount points
+        let (request, res) = compound(
+            request,
+            vec![
+                NfsArgOp::Opputrootfh(()),
+                NfsArgOp::Opreaddir(Readdir4args {
+                    cookie: 0,
+                    cookieverf: [0; 8],
+                    dircount: 0,
+                    maxcount: 4096,
+                    attr_request: Attrlist4::<FileAttr>::new(Some(vec![FileAttr::Fsid])),
+                }),
+                getattr(),
+            ],
+        )
+        .await;
+        assert_eq!(res.status, NfsStat4::Nfs4Ok);
+        let NfsResOp4::Opreaddir(ReadDir4res::Resok4(readdir)) = &res.resarray[1] else {
+            panic!("Unexpected result {:?}", res.resarray[1]);
+        };
+        let mut names = Vec::new();
+        let mut entry = readdir.reply.entries.clone();
+        while let Some(e) = entry {
+            names.push(String::from_utf8(e.name).unwrap());
+            entry = e.nextentry.map(|next| *next);
+        }
+        assert_eq!(names, vec!["data", "srv"]);
+        let (root_fsid, root_fileid, _) = attrs(&res.resarray);
+
+        // the pseudo file system is read-only
+        let (request, res) = compound(
+            request,
+            vec![
+                NfsArgOp::Opputrootfh(()),
+                NfsArgOp::Opcreate(Create4args {
+                    objtype: Createtype4::Nf4dir,
+                    objname: b"dir2".to_vec(),
+                    createattrs: Fattr4 {
+                        attrmask: Attrlist4::<FileAttr>::new(None),
+                        attr_vals: Attrlist4::<FileAttrValue>::new(None),
+                        decode_error: None,
+                    },
+                }),
+            ],
+        )
+        .await;
+        assert_eq!(res.status, NfsStat4::Nfs4errRofs);
+
+        // LOOKUP crosses into the export at its root
+        let (request, res) = compound(
+            request,
+            vec![
+                NfsArgOp::Opputrootfh(()),
+                lookup("srv"),
+                getattr(),
+                lookup("scratch"),
+                getattr(),
+                lookup("dir1"),
+                getattr(),
+            ],
+        )
+        .await;
+        assert_eq!(res.status, NfsStat4::Nfs4Ok);
+        let (srv_fsid, srv_fileid, _) = attrs(&res.resarray[..=2]);
+        let (scratch_fsid, scratch_fileid, scratch_mounted_on) = attrs(&res.resarray[..=4]);
+        let (dir1_fsid, dir1_fileid, dir1_mounted_on) = attrs(&res.resarray);
+        assert_eq!(srv_fsid, root_fsid);
+        assert_ne!(srv_fileid, root_fileid);
+        // every export is a file system of its own
+        assert_ne!(scratch_fsid, root_fsid);
+        assert_eq!(dir1_fsid, scratch_fsid);
+        // the root of an export reports the junction it is mounted on
+        assert_ne!(scratch_mounted_on, scratch_fileid);
+        assert_eq!(dir1_mounted_on, dir1_fileid);
+        let fh = request.current_filehandle().unwrap().clone();
+        assert_eq!(fh.path, "/dir1");
+
+        // the junction itself is still found in the pseudo file system
+        let (request, res) = compound(
+            request,
+            vec![
+                NfsArgOp::Opputrootfh(()),
+                lookup("srv"),
+                NfsArgOp::Opreaddir(Readdir4args {
+                    cookie: 0,
+                    cookieverf: [0; 8],
+                    dircount: 0,
+                    maxcount: 4096,
+                    attr_request: Attrlist4::<FileAttr>::new(Some(vec![
+                        FileAttr::Fsid,
+                        FileAttr::MountedOnFileid,
+                    ])),
+                }),
+            ],
+        )
+        .await;
+        assert_eq!(res.status, NfsStat4::Nfs4Ok);
+        let NfsResOp4::Opreaddir(ReadDir4res::Resok4(readdir)) = &res.resarray[2] else {
+            panic!("Unexpected result {:?}", res.resarray[2]);
+        };
+        let entry = readdir.reply.entries.clone().unwrap();
+        assert_eq!(entry.name, b"scratch");
+        // READDIR reports the attributes of the export root
+        assert_eq!(
+            entry.attrs.attr_vals.iter().cloned().collect::<Vec<_>>(),
+            vec![
+                FileAttrValue::Fsid(scratch_fsid),
+                FileAttrValue::MountedOnFileid(scratch_mounted_on)
+            ]
+        );
+
+        // LOOKUPP goes back up across the junction
+        let (request, res) = compound(
+            request,
+            vec![
+                NfsArgOp::Opputfh(PutFh4args { object: fh.id }),
+                NfsArgOp::Oplookupp(()),
+                NfsArgOp::Oplookupp(()),
+                getattr(),
+                NfsArgOp::Oplookupp(()),
+                NfsArgOp::Oplookupp(()),
+            ],
+        )
+        .await;
+        assert_eq!(res.status, NfsStat4::Nfs4errNoent);
+        assert_eq!(res.resarray.len(), 6);
+        let (fsid, fileid, _) = attrs(&res.resarray);
+        assert_eq!(fsid, root_fsid);
+        assert_eq!(fileid, srv_fileid);
+        let fh = request.current_filehandle().unwrap();
+        assert_eq!(fh.path, "/");
+
+        // the other export is independent
+        let (_, res) = compound(
+            request,
+            vec![
+                NfsArgOp::Opputrootfh(()),
+                lookup("data"),
+                lookup("file1.txt"),
+                getattr(),
+            ],
+        )
+        .await;
+        assert_eq!(res.status, NfsStat4::Nfs4Ok);
+        let (data_fsid, _, _) = attrs(&res.resarray);
+        assert_ne!(data_fsid, scratch_fsid);
+        assert_ne!(data_fsid, root_fsid);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_read_only_export() {
+        let data = MemoryBackend::new();
+        data.write_file("/file1.txt", b"data").unwrap();
+        let exports = Exports::new(
+            vec![ExportConfig {
+                read_only: true,
+                ..ExportConfig::new("/", Arc::new(data))
+            }],
+            None,
+        );
+        let new_request = || {
+            NfsRequest::new(
+                "127.0.0.1:12345".to_owned(),
+                ClientManagerHandle::new(),
+                exports.clone(),
+                0,
+                None,
+            )
+        };
+        let stateid = Stateid4 {
+            seqid: 0,
+            other: [0; 12],
+        };
+
+        // ACCESS does not offer to change anything
+        let (_, res) = compound(
+            new_request(),
+            vec![
+                NfsArgOp::Opputrootfh(()),
+                NfsArgOp::OpAccess(Access4args {
+                    access: ACCESS4_READ | ACCESS4_MODIFY | ACCESS4_EXTEND | ACCESS4_DELETE,
+                }),
+            ],
+        )
+        .await;
+        assert_eq!(res.status, NfsStat4::Nfs4Ok);
+        let NfsResOp4::OpAccess(Access4res::Resok4(access)) = &res.resarray[1] else {
+            panic!("Unexpected response {:?}", res.resarray[1]);
+        };
+        assert_eq!(
+            access.supported & (ACCESS4_MODIFY | ACCESS4_EXTEND | ACCESS4_DELETE),
+            0
+        );
+        assert_eq!(access.access, ACCESS4_READ);
+
+        // reading works, every operation changing the export fails
+        let (_, res) = compound(
+            new_request(),
+            vec![
+                NfsArgOp::Opputrootfh(()),
+                lookup("file1.txt"),
+                NfsArgOp::Opread(Read4args {
+                    stateid: stateid.clone(),
+                    offset: 0,
+                    count: 4,
+                }),
+            ],
+        )
+        .await;
+        assert_eq!(res.status, NfsStat4::Nfs4Ok);
+
+        let open = |share_access, openhow| {
+            NfsArgOp::Opopen(Open4args {
+                seqid: 0,
+                share_access,
+                share_deny: 0,
+                owner: OpenOwner4 {
+                    clientid: 0,
+                    owner: b"owner".to_vec(),
+                },
+                openhow,
+                claim: OpenClaim4::ClaimNull(b"file1.txt".to_vec()),
+            })
+        };
+        let changes = vec![
+            vec![
+                lookup("file1.txt"),
+                NfsArgOp::Opwrite(Write4args {
+                    stateid,
+                    offset: 0,
+                    stable: StableHow4::FileSync4,
+                    data: Bytes::from_static(b"changed"),
+                }),
+            ],
+            vec![NfsArgOp::Opremove(Remove4args {
+                target: b"file1.txt".to_vec(),
+            })],
+            vec![open(OPEN4_SHARE_ACCESS_WRITE, OpenFlag4::Open4Nocreate)],
+            vec![open(
+                OPEN4_SHARE_ACCESS_READ,
+                OpenFlag4::How(CreateHow4::GUARDED4(Fattr4 {
+                    attrmask: Attrlist4::<FileAttr>::new(None),
+                    attr_vals: Attrlist4::<FileAttrValue>::new(None),
+                    decode_error: None,
+                })),
+            )],
+            vec![NfsArgOp::Opcommit(Commit4args {
+                offset: 0,
+                count: 0,
+            })],
+        ];
+        for ops in changes {
+            let argarray = [vec![NfsArgOp::Opputrootfh(())], ops].concat();
+            let (_, res) = compound(new_request(), argarray).await;
+            assert_eq!(res.status, NfsStat4::Nfs4errRofs);
+        }
+    }
+}
@@ -1,13 +1,13 @@
-use std::io::{Cursor, Seek, SeekFrom, Write};
-
+use bytes::Bytes;
 use tokio::sync::mpsc;
+use tracing::error;
 
 use super::{handle::WriteCacheMessage, FileManagerHandle, Filehandle};
 
 #[derive(Debug)]
 pub struct WriteCache {
-    pub filelike: Cursor<Vec<u8>>,
-    pub changed: bool,
+    // unstable writes as (offset, data), flushed in order on commit
+    pub pending: Vec<(u64, Bytes)>,
     pub filehandle: Filehandle,
     pub receiver: mpsc::Receiver<WriteCacheMessage>,
     pub filemanager: FileManagerHandle,
@@ -19,12 +19,8 @@ impl WriteCache {
         filehandle: Filehandle,
         filemanager: FileManagerHandle,
     ) -> Self {
-        let mut filelike = Cursor::new(Vec::new());
-        let mut file = filehandle.file.open_file().unwrap();
-        file.read_to_end(&mut filelike.get_mut()).unwrap();
         WriteCache {
-            filelike,
-            changed: false,
+            pending: Vec::new(),
             filehandle,
             receiver,
             filemanager,
@@ -35,38 +31,32 @@ impl WriteCache {
         match msg {
             WriteCacheMessage::Write(req) => {
                 // write to cache
-                self.filelike.seek(SeekFrom::Start(req.offset)).unwrap();
-                self.filelike.write_all(req.data.as_slice()).unwrap();
-                self.changed = true;
-                // update filehandle size (probably not needed here)
-                // let new_size = self.filelike.get_ref().len() as u64;
-                // self.filehandle.attr_size = new_size;
-                // self.filehandle.attr_space_used = new_size;
-                // // update change markers
-                // self.filehandle.attr_time_modify = Filehandle::attr_time_access();
-                // self.filehandle.attr_change =
-                //     Filehandle::attr_change(&self.filehandle.file, self.filehandle.version + 1);
-                // self.filemanager
-                //     .update_filehandle(self.filehandle.clone())
-                //     .await;
+                self.pending.push((req.offset, req.data));
             }
             WriteCacheMessage::Commit => {
                 // commit cache
-                if self.changed {
-                    let mut file = self.filehandle.file.append_file().unwrap();
-                    let _ = file.seek(SeekFrom::Start(0));
-                    let content = self.filelike.get_ref();
-                    let count = file.write(content.as_slice()).unwrap() as u32;
+                if !self.pending.is_empty() {
+                    let mut count = 0;
+                    for (offset, data) in self.pending.drain(..) {
+                        match self
+                            .filemanager
+                            .write(&self.filehandle, offset, &data)
+                            .await
+                        {
+                            Ok(written) => count += written,
+                            Err(e) => error!("couldn't flush write cache: {:?}", e),
+                        }
+                    }
+                    if let Err(e) = self.filemanager.commit(&self.filehandle).await {
+                        error!("couldn't commit write cache: {:?}", e);
+                    }
 
                     if count > 0 {
-                        file.flush().unwrap();
-                        self.filemanager
-                            .touch_file(self.filehandle.id.clone())
-                            .await;
+                        self.filemanager.touch_file(self.filehandle.id).await;
                     }
                 }
                 self.filemanager
-                    .drop_write_cache_handle(self.filehandle.id.clone())
+                    .drop_write_cache_handle(self.filehandle.id)
                     .await;
             }
         }
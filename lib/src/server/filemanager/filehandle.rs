@@ -1,17 +1,11 @@
-use std::{
-    hash::{DefaultHasher, Hash, Hasher},
-    time::{SystemTime, UNIX_EPOCH},
-};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use multi_index_map::MultiIndexMap;
-use tracing::debug;
-use vfs::VfsPath;
 
-use bold_proto::nfs4_proto::{
-    Fsid4, NfsFh4, NfsFtype4, Nfstime4, MODE4_RGRP, MODE4_ROTH, MODE4_RUSR,
-};
+use bold_proto::nfs4_proto::{Fsid4, NfsFh4, NfsFtype4, Nfsace4, Nfstime4};
 
 use super::{handle::WriteCacheHandle, locking::LockingState};
+use crate::storage::Metadata;
 
 pub type FilehandleDb = MultiIndexFilehandleMap;
 
@@ -20,7 +14,6 @@ pub type FilehandleDb = MultiIndexFilehandleMap;
 pub struct Filehandle {
     #[multi_index(hashed_unique)]
     pub id: NfsFh4,
-    pub file: VfsPath,
     // path:
     // the full path of the file including filename
     #[multi_index(hashed_unique)]
@@ -50,12 +43,18 @@ pub struct Filehandle {
     // mode:
     // The NFSv4.0 mode attribute is based on the UNIX mode bits.
     pub attr_mode: u32,
+    // numlinks:
+    // Number of hard links to this object.
+    pub attr_numlinks: u32,
     // owner:
     // The string name of the owner of this object.
     pub attr_owner: String,
     // owner_group:
     // The string name of the group ownership of this object.
     pub attr_owner_group: String,
+    // the numeric owner and group, for permission checks
+    pub attr_uid: u32,
+    pub attr_gid: u32,
     // space_used:
     // Number of file system bytes allocated to this object.
     pub attr_space_used: u64,
@@ -69,6 +68,20 @@ pub struct Filehandle {
     // time_modified:
     // The time of last modification to the object.
     pub attr_time_modify: Nfstime4,
+    // time_create:
+    // The time of creation of the object.  This attribute does not have
+    // any relation to the traditional UNIX file attribute "ctime".
+    pub attr_time_create: Option<Nfstime4>,
+    // time_backup:
+    // The time of last backup of the object.
+    pub attr_time_backup: Option<Nfstime4>,
+    // named_attr:
+    // TRUE, if this object has named attributes.
+    pub attr_named_attr: bool,
+    // acl:
+    // The access control list of the object, None if the mode bits are
+    // the only access control.
+    pub attr_acl: Option<Vec<Nfsace4>>,
     // this filehandle has exclusive open
     pub verifier: Option<[u8; 8]>,
     // attached locks, see LockingState
@@ -81,29 +94,37 @@ pub struct Filehandle {
 }
 
 impl Filehandle {
-    pub fn new(file: VfsPath, id: NfsFh4, major: u64, minor: u64, version: u64) -> Self {
-        let init_time = Self::attr_time_access();
-        let mut path = file.as_str().to_string();
-        if path.is_empty() {
-            path = "/".to_string();
-        }
+    pub fn new(
+        path: String,
+        id: NfsFh4,
+        meta: &Metadata,
+        major: u64,
+        minor: u64,
+        version: u64,
+    ) -> Self {
         let version = version + 1;
         Filehandle {
+            attr_type: meta.file_type,
+            attr_change: Self::attr_change(meta, version),
+            attr_size: meta.size,
+            attr_fileid: meta.ino,
+            attr_fsid: Self::attr_fsid(major, minor),
+            attr_mode: meta.mode,
+            attr_numlinks: meta.nlink,
+            attr_owner: meta.uid.to_string(),
+            attr_owner_group: meta.gid.to_string(),
+            attr_uid: meta.uid,
+            attr_gid: meta.gid,
+            attr_space_used: meta.space_used,
+            attr_time_access: Self::nfstime(meta.atime.or(meta.mtime)),
+            attr_time_metadata: Self::nfstime(meta.ctime.or(meta.mtime)),
+            attr_time_modify: Self::nfstime(meta.mtime),
+            attr_time_create: meta.btime.map(|btime| Self::nfstime(Some(btime))),
+            attr_time_backup: meta.backup_time.map(|backup| Self::nfstime(Some(backup))),
+            attr_named_attr: meta.named_attrs,
+            attr_acl: meta.acl.clone(),
             id,
             path,
-            attr_type: Self::attr_type(&file),
-            attr_change: Self::attr_change(&file, version),
-            attr_size: Self::attr_size(&file),
-            attr_fileid: Self::attr_fileid(&file),
-            attr_fsid: Self::attr_fsid(major, minor),
-            attr_mode: Self::attr_mode(&file),
-            attr_owner: Self::attr_owner(&file),
-            attr_owner_group: Self::attr_owner_group(&file),
-            attr_space_used: Self::attr_space_used(&file),
-            attr_time_access: init_time,
-            attr_time_metadata: init_time,
-            attr_time_modify: init_time,
-            file,
             verifier: None,
             locks: Vec::new(),
             write_cache: None,
@@ -111,65 +132,87 @@ impl Filehandle {
         }
     }
 
-    fn attr_type(file: &VfsPath) -> NfsFtype4 {
-        if file.is_dir().unwrap() {
-            return NfsFtype4::Nf4dir;
+    // the change attribute is a counter that bold increases on every change
+    // it makes, it starts at the ctime in nanoseconds to also cover changes
+    // made outside of bold
+    pub fn attr_change(meta: &Metadata, min: u64) -> u64 {
+        match meta.ctime.map(|ctime| ctime.duration_since(UNIX_EPOCH)) {
+            Some(Ok(since_epoch)) => (since_epoch.as_nanos() as u64).max(min),
+            _ => min,
         }
-        if file.is_file().unwrap() {
-            return NfsFtype4::Nf4reg;
-        }
-        NfsFtype4::Nf4Undef
-    }
-
-    pub fn attr_change(file: &VfsPath, default: u64) -> u64 {
-        let v = file.metadata();
-        debug!("### attr_change ### {:?}", v);
-        if v.is_ok() {
-            if let Some(v) = v.unwrap().modified {
-                return v.duration_since(UNIX_EPOCH).unwrap().as_secs();
-            }
-        }
-        default
-    }
-
-    fn attr_fileid(file: &VfsPath) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        file.as_str().hash(&mut hasher);
-
-        u64::try_from(hasher.finish()).unwrap()
     }
 
     fn attr_fsid(major: u64, minor: u64) -> Fsid4 {
         Fsid4 { major, minor }
     }
 
-    fn attr_mode(_file: &VfsPath) -> u32 {
-        MODE4_RUSR + MODE4_RGRP + MODE4_ROTH
+    // unknown times are reported as the epoch
+    pub fn nfstime(time: Option<SystemTime>) -> Nfstime4 {
+        let nanos = match time.map(|time| time.duration_since(UNIX_EPOCH)) {
+            Some(Ok(since_epoch)) => since_epoch.as_nanos() as i128,
+            Some(Err(e)) => -(e.duration().as_nanos() as i128),
+            None => 0,
+        };
+        // seconds are rounded down, so nseconds always counts forward, even before the epoch
+        Nfstime4 {
+            seconds: nanos.div_euclid(1_000_000_000) as i64,
+            nseconds: nanos.rem_euclid(1_000_000_000) as u32,
+        }
     }
+}
 
-    fn attr_owner(_file: &VfsPath) -> String {
-        "1000".to_string()
-    }
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
 
-    fn attr_owner_group(_file: &VfsPath) -> String {
-        "1000".to_string()
-    }
+    use bold_proto::nfs4_proto::Nfstime4;
 
-    pub fn attr_size(file: &VfsPath) -> u64 {
-        file.metadata().unwrap().len
-    }
+    use super::Filehandle;
+    use crate::storage::{MemoryBackend, SetAttrs, StorageBackend};
 
-    fn attr_space_used(file: &VfsPath) -> u64 {
-        file.metadata().unwrap().len
+    #[test]
+    fn test_nfstime() {
+        let time = UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+        assert_eq!(
+            Filehandle::nfstime(Some(time)),
+            Nfstime4 {
+                seconds: 1_700_000_000,
+                nseconds: 123_456_789
+            }
+        );
+        let time = UNIX_EPOCH - Duration::new(1, 250_000_000);
+        assert_eq!(
+            Filehandle::nfstime(Some(time)),
+            Nfstime4 {
+                seconds: -2,
+                nseconds: 750_000_000
+            }
+        );
     }
 
-    pub fn attr_time_access() -> Nfstime4 {
-        let since_epoch = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
+    #[tokio::test]
+    async fn test_times_from_backend() {
+        let backend = MemoryBackend::new();
+        backend.write_file("/file1.txt", b"Hello").unwrap();
+        let atime = UNIX_EPOCH + Duration::new(1_000_000_000, 1);
+        let mtime = UNIX_EPOCH + Duration::new(1_100_000_000, 2);
+        let meta = backend
+            .setattr(
+                "/file1.txt",
+                &SetAttrs {
+                    atime: Some(atime),
+                    mtime: Some(mtime),
+                    ..Default::default()
+                },
+            )
+            .await
             .unwrap();
-        Nfstime4 {
-            seconds: since_epoch.as_secs() as i64,
-            nseconds: since_epoch.subsec_nanos(),
-        }
+
+        let fh = Filehandle::new("/file1.txt".to_string(), [0; 26], &meta, 1, 1, 0);
+        assert_eq!(fh.attr_time_access, Filehandle::nfstime(Some(atime)));
+        assert_eq!(fh.attr_time_modify, Filehandle::nfstime(Some(mtime)));
+        assert_eq!(fh.attr_time_metadata, Filehandle::nfstime(meta.ctime));
+        assert_eq!(fh.attr_time_create, Some(Filehandle::nfstime(meta.btime)));
+        assert_eq!(fh.attr_time_backup, None);
     }
 }
@@ -1,24 +1,34 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
 use bold_proto::nfs4_proto::ChangeInfo4;
+use bytes::Bytes;
 use tokio::sync::{mpsc, oneshot};
-use tracing::debug;
-use vfs::VfsPath;
+use tracing::{debug, error};
 
 use bold_proto::nfs4_proto::{
-    Attrlist4, FileAttr, FileAttrValue, NfsLease4, NfsStat4, ACL4_SUPPORT_ALLOW_ACL,
-    FH4_VOLATILE_ANY, MODE4_RGRP, MODE4_ROTH, MODE4_RUSR,
+    Attrlist4, FileAttr, FileAttrValue, NfsFtype4, NfsLease4, NfsStat4, Nfsace4, FH4_PERSISTENT,
+    FH4_VOLATILE_ANY,
 };
 
 use super::{
-    caching::run_file_write_cache, caching::WriteCache, filehandle::Filehandle, run_file_manager,
-    FileManager,
+    caching::run_file_write_cache, caching::WriteCache, filehandle::Filehandle,
+    persistent::HandleStore, run_file_manager, FileManager, FileManagerConfig,
 };
-use crate::server::filemanager::NfsFh4;
+use crate::server::{acl, filemanager::NfsFh4};
+use crate::storage::{NamedAttrBackend, ReadDirPage, SetAttrs, StorageBackend, StorageError};
 
 pub enum FileManagerMessage {
     GetRootFilehandle(GetRootFilehandleRequest),
     GetFilehandle(GetFilehandleRequest),
     GetFilehandleAttrs(GetFilehandleAttrsRequest),
     CreateFile(CreateFileRequest),
+    CreateDir(CreateDirRequest),
     RemoveFile(RemoveFileRequest),
     TouchFile(TouchFileRequest),
     UpdateFilehandle(Filehandle),
@@ -27,6 +37,7 @@ pub enum FileManagerMessage {
     CloseFile(CloseFileRequest),
     GetWriteCacheHandle(WriteCacheHandleRequest),
     DropWriteCacheHandle(DropCacheHandleRequest),
+    SyncHandles(SyncHandlesRequest),
 }
 
 #[derive(Debug)]
@@ -52,13 +63,13 @@ pub struct CloseFileRequest {
 }
 
 pub struct GetRootFilehandleRequest {
-    pub respond_to: oneshot::Sender<Filehandle>,
+    pub respond_to: oneshot::Sender<Result<Filehandle, FileManagerError>>,
 }
 
 pub struct GetFilehandleRequest {
     pub path: Option<String>,
     pub filehandle: Option<NfsFh4>,
-    pub respond_to: oneshot::Sender<Option<Filehandle>>,
+    pub respond_to: oneshot::Sender<Result<Filehandle, FileManagerError>>,
 }
 
 pub struct GetFilehandleAttrsRequest {
@@ -68,7 +79,7 @@ pub struct GetFilehandleAttrsRequest {
 }
 
 pub struct CreateFileRequest {
-    pub path: VfsPath,
+    pub path: String,
     pub client_id: u64,
     pub owner: Vec<u8>,
     pub share_access: u32,
@@ -77,8 +88,13 @@ pub struct CreateFileRequest {
     pub respond_to: oneshot::Sender<Result<(Filehandle, ChangeInfo4), FileManagerError>>,
 }
 
+pub struct CreateDirRequest {
+    pub path: String,
+    pub respond_to: oneshot::Sender<Result<(Filehandle, ChangeInfo4), FileManagerError>>,
+}
+
 pub struct RemoveFileRequest {
-    pub path: VfsPath,
+    pub path: String,
     pub respond_to: oneshot::Sender<Result<ChangeInfo4, FileManagerError>>,
 }
 
@@ -96,84 +112,135 @@ pub struct DropCacheHandleRequest {
     pub filehandle_id: NfsFh4,
 }
 
+pub struct SyncHandlesRequest {
+    pub respond_to: oneshot::Sender<()>,
+}
+
 #[derive(Debug, Clone)]
 pub struct FileManagerError {
     pub nfs_error: NfsStat4,
 }
 
+impl From<StorageError> for FileManagerError {
+    fn from(e: StorageError) -> Self {
+        FileManagerError {
+            nfs_error: e.nfs_error(),
+        }
+    }
+}
+
+// the FileManager actor is gone, the request can't be served
+impl From<mpsc::error::SendError<FileManagerMessage>> for FileManagerError {
+    fn from(_: mpsc::error::SendError<FileManagerMessage>) -> Self {
+        error!("FileManager is not running");
+        FileManagerError {
+            nfs_error: NfsStat4::Nfs4errServerfault,
+        }
+    }
+}
+
+// the FileManager actor dropped the request without answering it
+impl From<oneshot::error::RecvError> for FileManagerError {
+    fn from(_: oneshot::error::RecvError) -> Self {
+        error!("FileManager didn't answer");
+        FileManagerError {
+            nfs_error: NfsStat4::Nfs4errServerfault,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FileManagerHandle {
     sender: mpsc::Sender<FileManagerMessage>,
+    // file data is read and written directly, without going through the actor
+    backend: Arc<dyn StorageBackend>,
     lease_time: u32,
     hard_link_support: bool,
     symlink_support: bool,
     unique_handles: bool,
+    persistent_handles: bool,
+    mounted_on_fileid: Option<u64>,
+    // set while the filehandle db has changes to write, see sync_handles
+    unsynced_handles: Option<Arc<AtomicBool>>,
 }
 
 impl FileManagerHandle {
-    pub fn new(root: VfsPath, fsid: Option<u64>) -> Self {
+    pub fn new(
+        backend: Arc<dyn StorageBackend>,
+        fsid: Option<u64>,
+        handle_db: Option<PathBuf>,
+    ) -> Self {
+        Self::with_config(
+            backend,
+            FileManagerConfig {
+                fsid,
+                handle_db,
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn with_config(backend: Arc<dyn StorageBackend>, mut config: FileManagerConfig) -> Self {
         let (sender, receiver) = mpsc::channel(16);
-        let fmanager = FileManager::new(receiver, root, fsid);
+        // extended attributes are served as named attributes, see OPENATTR
+        let backend: Arc<dyn StorageBackend> = Arc::new(NamedAttrBackend::new(backend));
+        if config.handle_db.is_none() {
+            config.handle_db = backend.default_handle_db();
+        }
+        let handle_store =
+            config
+                .handle_db
+                .as_ref()
+                .and_then(|path| match HandleStore::open(path) {
+                    Ok(store) => Some(store),
+                    Err(e) => {
+                        error!("Couldn't open filehandle db {:?}: {:?}", path, e);
+                        None
+                    }
+                });
+        let unsynced_handles = handle_store.as_ref().map(HandleStore::unsynced);
+        let capabilities = backend.capabilities();
+        let persistent_handles = capabilities.inode_lookup || handle_store.is_some();
+        let mounted_on_fileid = config.mounted_on_fileid;
+        let actor_backend = backend.clone();
         // start the filemanager actor
-        tokio::spawn(run_file_manager(fmanager));
+        tokio::spawn(async move {
+            let fmanager = FileManager::new(receiver, actor_backend, config, handle_store).await;
+            run_file_manager(fmanager).await
+        });
 
         Self {
             sender,
+            backend,
             lease_time: 60,
-            hard_link_support: false,
-            symlink_support: false,
-            unique_handles: false,
+            hard_link_support: capabilities.hard_links,
+            symlink_support: capabilities.symlinks,
+            unique_handles: capabilities.unique_handles,
+            persistent_handles,
+            mounted_on_fileid,
+            unsynced_handles,
         }
     }
 
+    pub fn backend(&self) -> Arc<dyn StorageBackend> {
+        self.backend.clone()
+    }
+
     async fn send_filehandle_request(
         &self,
         path: Option<String>,
         filehandle: Option<NfsFh4>,
     ) -> Result<Filehandle, FileManagerError> {
-        let filehandle_set = filehandle.is_some();
         let (tx, rx) = oneshot::channel();
         let req = GetFilehandleRequest {
-            path: path.clone(),
+            path,
             filehandle,
             respond_to: tx,
         };
         self.sender
             .send(FileManagerMessage::GetFilehandle(req))
-            .await
-            .unwrap();
-        match rx.await {
-            Ok(fh) => {
-                if let Some(fh) = fh {
-                    return Ok(fh);
-                }
-                if let Some(path) = path {
-                    debug!("File not found: {:?}", path);
-                    if !filehandle_set {
-                        Err(FileManagerError {
-                            nfs_error: NfsStat4::Nfs4errNoent,
-                        })
-                    } else {
-                        Err(FileManagerError {
-                            nfs_error: NfsStat4::Nfs4errStale,
-                        })
-                    }
-                } else {
-                    debug!("Filehandle not found");
-                    // https://datatracker.ietf.org/doc/html/rfc7530#section-4.2.3
-                    // If the server can definitively determine that a
-                    // volatile filehandle refers to an object that has been removed, the
-                    // server should return NFS4ERR_STALE to the client (as is the case for
-                    // persistent filehandles)
-                    Err(FileManagerError {
-                        nfs_error: NfsStat4::Nfs4errStale,
-                    })
-                }
-            }
-            Err(_) => Err(FileManagerError {
-                nfs_error: NfsStat4::Nfs4errServerfault,
-            }),
-        }
+            .await?;
+        rx.await?
     }
 
     pub async fn get_root_filehandle(&self) -> Result<Filehandle, FileManagerError> {
@@ -204,26 +271,15 @@ impl FileManagerHandle {
         };
         self.sender
             .send(FileManagerMessage::GetFilehandleAttrs(req))
-            .await
-            .unwrap();
-        match rx.await {
-            Ok(attrs) => {
-                if let Some(attrs) = attrs {
-                    return Ok(attrs);
-                }
-                Err(FileManagerError {
-                    nfs_error: NfsStat4::Nfs4errBadhandle,
-                })
-            }
-            Err(_) => Err(FileManagerError {
-                nfs_error: NfsStat4::Nfs4errServerfault,
-            }),
-        }
+            .await?;
+        rx.await?.ok_or(FileManagerError {
+            nfs_error: NfsStat4::Nfs4errBadhandle,
+        })
     }
 
     pub async fn create_file(
         &self,
-        path: VfsPath,
+        path: String,
         client_id: u64,
         owner: Vec<u8>,
         access: u32,
@@ -242,35 +298,69 @@ impl FileManagerHandle {
         };
         self.sender
             .send(FileManagerMessage::CreateFile(req))
-            .await
-            .unwrap();
-        rx.await.unwrap()
+            .await?;
+        rx.await?
     }
 
-    pub async fn remove_file(&self, path: VfsPath) -> Result<ChangeInfo4, FileManagerError> {
+    pub async fn create_dir(
+        &self,
+        path: String,
+    ) -> Result<(Filehandle, ChangeInfo4), FileManagerError> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(FileManagerMessage::CreateDir(CreateDirRequest {
+                path,
+                respond_to: tx,
+            }))
+            .await?;
+        rx.await?
+    }
+
+    pub async fn remove_file(&self, path: String) -> Result<ChangeInfo4, FileManagerError> {
         let (tx, rx) = oneshot::channel();
         self.sender
             .send(FileManagerMessage::RemoveFile(RemoveFileRequest {
                 path,
                 respond_to: tx,
             }))
-            .await
-            .unwrap();
-        rx.await.unwrap()
+            .await?;
+        rx.await?
     }
 
+    // notifications without an answer, a FileManager that is gone has
+    // nothing to update anymore
     pub async fn touch_file(&self, id: NfsFh4) {
-        self.sender
-            .send(FileManagerMessage::TouchFile(TouchFileRequest { id }))
-            .await
-            .unwrap();
+        self.notify(FileManagerMessage::TouchFile(TouchFileRequest { id }))
+            .await;
     }
 
     pub async fn update_filehandle(&self, filehandle: Filehandle) {
-        self.sender
-            .send(FileManagerMessage::UpdateFilehandle(filehandle))
-            .await
-            .unwrap();
+        self.notify(FileManagerMessage::UpdateFilehandle(filehandle))
+            .await;
+    }
+
+    /// Write the filehandles handed out since the last call to the
+    /// filehandle db, before a client gets them.
+    pub async fn sync_handles(&self) {
+        let unsynced = self
+            .unsynced_handles
+            .as_ref()
+            .is_some_and(|unsynced| unsynced.load(Ordering::Acquire));
+        if !unsynced {
+            return;
+        }
+        let (tx, rx) = oneshot::channel();
+        self.notify(FileManagerMessage::SyncHandles(SyncHandlesRequest {
+            respond_to: tx,
+        }))
+        .await;
+        let _ = rx.await;
+    }
+
+    async fn notify(&self, msg: FileManagerMessage) {
+        if self.sender.send(msg).await.is_err() {
+            error!("FileManager is not running");
+        }
     }
 
     pub async fn lock_file(
@@ -291,9 +381,8 @@ impl FileManagerHandle {
                 share_deny,
                 respond_to: tx,
             }))
-            .await
-            .unwrap();
-        rx.await.unwrap()
+            .await?;
+        rx.await?
     }
 
     pub async fn confirm_lock(&self, stateid: [u8; 12]) -> Result<(), FileManagerError> {
@@ -303,9 +392,8 @@ impl FileManagerHandle {
                 stateid,
                 respond_to: tx,
             }))
-            .await
-            .unwrap();
-        rx.await.unwrap()
+            .await?;
+        rx.await?
     }
 
     pub async fn close_file(&self, stateid: [u8; 12]) -> Result<(), FileManagerError> {
@@ -315,9 +403,8 @@ impl FileManagerHandle {
                 stateid,
                 respond_to: tx,
             }))
-            .await
-            .unwrap();
-        rx.await.unwrap()
+            .await?;
+        rx.await?
     }
 
     pub async fn get_write_cache_handle(
@@ -333,28 +420,56 @@ impl FileManagerHandle {
                     respond_to: tx,
                 },
             ))
-            .await
-            .unwrap();
-        match rx.await {
-            Ok(handle) => Ok(handle),
-            Err(_) => Err(FileManagerError {
-                nfs_error: NfsStat4::Nfs4errServerfault,
-            }),
-        }
+            .await?;
+        Ok(rx.await?)
     }
 
     pub async fn drop_write_cache_handle(&self, filehandle_id: NfsFh4) {
-        self.sender
-            .send(FileManagerMessage::DropWriteCacheHandle(
-                DropCacheHandleRequest { filehandle_id },
-            ))
-            .await
-            .unwrap();
+        self.notify(FileManagerMessage::DropWriteCacheHandle(
+            DropCacheHandleRequest { filehandle_id },
+        ))
+        .await;
+    }
+
+    pub async fn read(
+        &self,
+        filehandle: &Filehandle,
+        offset: u64,
+        count: u32,
+    ) -> Result<(Vec<u8>, bool), FileManagerError> {
+        Ok(self.backend.read(&filehandle.path, offset, count).await?)
+    }
+
+    pub async fn write(
+        &self,
+        filehandle: &Filehandle,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<u32, FileManagerError> {
+        Ok(self.backend.write(&filehandle.path, offset, data).await?)
+    }
+
+    pub async fn commit(&self, filehandle: &Filehandle) -> Result<(), FileManagerError> {
+        Ok(self.backend.commit(&filehandle.path).await?)
+    }
+
+    /// Up to `max_entries` entries of the directory, starting after `cookie`,
+    /// see [`StorageBackend::readdir`].
+    pub async fn read_dir_page(
+        &self,
+        filehandle: &Filehandle,
+        cookie: u64,
+        max_entries: usize,
+    ) -> Result<ReadDirPage, FileManagerError> {
+        Ok(self
+            .backend
+            .readdir(&filehandle.path, cookie, max_entries)
+            .await?)
     }
 
     pub fn filehandle_attrs(
         &mut self,
-        attr_request: &Vec<FileAttr>,
+        attr_request: &[FileAttr],
         filehandle: &Filehandle,
     ) -> Option<(Attrlist4<FileAttr>, Attrlist4<FileAttrValue>)> {
         let mut answer_attrs = Attrlist4::<FileAttr>::new(None);
@@ -391,9 +506,13 @@ impl FileManagerHandle {
                     answer_attrs.push(FileAttr::SymlinkSupport);
                 }
                 FileAttr::NamedAttr => {
-                    attrs.push(FileAttrValue::NamedAttr(self.attr_named_attr()));
+                    attrs.push(FileAttrValue::NamedAttr(self.attr_named_attr(filehandle)));
                     answer_attrs.push(FileAttr::NamedAttr);
                 }
+                FileAttr::Acl => {
+                    attrs.push(FileAttrValue::Acl(self.attr_acl(filehandle)));
+                    answer_attrs.push(FileAttr::Acl);
+                }
                 FileAttr::AclSupport => {
                     attrs.push(FileAttrValue::AclSupport(self.attr_acl_support()));
                     answer_attrs.push(FileAttr::AclSupport);
@@ -419,11 +538,11 @@ impl FileManagerHandle {
                     answer_attrs.push(FileAttr::Fileid);
                 }
                 FileAttr::Mode => {
-                    attrs.push(FileAttrValue::Mode(self.attr_mode()));
+                    attrs.push(FileAttrValue::Mode(filehandle.attr_mode));
                     answer_attrs.push(FileAttr::Mode);
                 }
                 FileAttr::Numlinks => {
-                    attrs.push(FileAttrValue::Numlinks(self.attr_numlinks()));
+                    attrs.push(FileAttrValue::Numlinks(filehandle.attr_numlinks));
                     answer_attrs.push(FileAttr::Numlinks);
                 }
                 FileAttr::Owner => {
@@ -452,45 +571,76 @@ impl FileManagerHandle {
                     attrs.push(FileAttrValue::TimeModify(filehandle.attr_time_modify));
                     answer_attrs.push(FileAttr::TimeModify);
                 }
-                // FileAttr::MountedOnFileid => {
-                //     attrs.push(FileAttrValue::MountedOnFileid(
-                //         filehandle.attr_mounted_on_fileid,
-                //     ));
-                //     answer_attrs.push(FileAttr::MountedOnFileid);
-                // }
+                // only reported if the backend records them
+                FileAttr::TimeCreate => {
+                    if let Some(time_create) = filehandle.attr_time_create {
+                        attrs.push(FileAttrValue::TimeCreate(time_create));
+                        answer_attrs.push(FileAttr::TimeCreate);
+                    }
+                }
+                FileAttr::TimeBackup => {
+                    if let Some(time_backup) = filehandle.attr_time_backup {
+                        attrs.push(FileAttrValue::TimeBackup(time_backup));
+                        answer_attrs.push(FileAttr::TimeBackup);
+                    }
+                }
+                FileAttr::MountedOnFileid => {
+                    attrs.push(FileAttrValue::MountedOnFileid(
+                        self.attr_mounted_on_fileid(filehandle),
+                    ));
+                    answer_attrs.push(FileAttr::MountedOnFileid);
+                }
                 _ => {}
             }
         }
         Some((answer_attrs, attrs))
     }
 
-    pub fn set_attr(
+    pub async fn set_attr(
         &self,
         filehandle: &Filehandle,
         attr_vals: &Attrlist4<FileAttrValue>,
-    ) -> Attrlist4<FileAttr> {
+    ) -> Result<Attrlist4<FileAttr>, FileManagerError> {
         let mut attrsset = Attrlist4::<FileAttr>::new(None);
+        let mut setattrs = SetAttrs::default();
         for attr in attr_vals.iter() {
             match attr {
                 FileAttrValue::Size(args) => {
                     debug!("Set size to: {:?}", args);
-                    let mut buf = vec![0_u8; *args as usize];
-                    let mut file = filehandle.file.open_file().unwrap();
-                    let _ = file.rewind();
-                    file.read_exact(&mut buf).unwrap();
-
-                    let mut file = filehandle.file.append_file().unwrap();
-                    let _ = file.rewind();
-                    file.write_all(&buf).unwrap();
-                    file.flush().unwrap();
+                    setattrs.size = Some(*args);
                     attrsset.push(FileAttr::Size);
                 }
+                FileAttrValue::Acl(args) => {
+                    debug!("Set acl to: {:?}", args);
+                    acl::validate(args).map_err(|nfs_error| FileManagerError { nfs_error })?;
+                    // the mode follows the acl
+                    let mode = setattrs.mode.unwrap_or(filehandle.attr_mode);
+                    setattrs.mode = Some(acl::mode_from_acl(args, mode));
+                    setattrs.acl = Some(args.clone());
+                    attrsset.push(FileAttr::Acl);
+                }
+                FileAttrValue::Mode(args) => {
+                    debug!("Set mode to: {:o}", args);
+                    setattrs.mode = Some(*args);
+                    // and the acl follows the mode, if there is one
+                    if let Some(acl) = setattrs.acl.as_ref().or(filehandle.attr_acl.as_ref()) {
+                        setattrs.acl = Some(acl::apply_mode(
+                            acl,
+                            *args,
+                            filehandle.attr_type == NfsFtype4::Nf4dir,
+                        ));
+                    }
+                    attrsset.push(FileAttr::Mode);
+                }
                 _ => {
                     debug!("Not supported set attr requested for: {:?}", attr);
                 }
             }
         }
-        attrsset
+        if !attrsset.is_empty() {
+            self.backend.setattr(&filehandle.path, &setattrs).await?;
+        }
+        Ok(attrsset)
     }
 
     pub fn attr_lease_time(&self) -> NfsLease4 {
@@ -501,7 +651,9 @@ impl FileManagerHandle {
         // rdattr_error:
         // The server uses this to specify the behavior of the client when
         // reading attributes.  See Section 4 for additional description.
-        NfsStat4::Nfs4errInval
+        // The attributes of a filehandle are always read successfully,
+        // READDIR reports the errors of entries it can't read itself.
+        NfsStat4::Nfs4Ok
     }
 
     pub fn attr_supported_attrs(&self) -> Attrlist4<FileAttr> {
@@ -534,17 +686,34 @@ impl FileManagerHandle {
             FileAttr::OwnerGroup,
             FileAttr::SpaceUsed,
             FileAttr::TimeAccess,
+            FileAttr::TimeBackup,
+            FileAttr::TimeCreate,
             FileAttr::TimeMetadata,
             FileAttr::TimeModify,
-            // FileAttr::MountedOnFileid,
+            FileAttr::MountedOnFileid,
         ]))
     }
 
+    pub fn attr_mounted_on_fileid(&self, filehandle: &Filehandle) -> u64 {
+        // mounted_on_fileid:
+        // Like fileid, but if the target filehandle is the root of a file
+        // system, this attribute represents the fileid of the underlying
+        // directory.
+        match self.mounted_on_fileid {
+            Some(fileid) if filehandle.path == "/" => fileid,
+            _ => filehandle.attr_fileid,
+        }
+    }
+
     pub fn attr_expire_type(&self) -> u32 {
         // fh_expire_type:
         // The server uses this to specify filehandle expiration behavior to the
         // client.  See Section 4 for additional description.
-        FH4_VOLATILE_ANY
+        if self.persistent_handles {
+            FH4_PERSISTENT
+        } else {
+            FH4_VOLATILE_ANY
+        }
     }
 
     pub fn attr_link_support(&self) -> bool {
@@ -559,11 +728,11 @@ impl FileManagerHandle {
         self.symlink_support
     }
 
-    pub fn attr_named_attr(&self) -> bool {
+    pub fn attr_named_attr(&self, filehandle: &Filehandle) -> bool {
         // named_attr:
         // TRUE, if the object's has named attributes.  In other words, this
         // object has a non-empty named attribute directory.
-        false
+        filehandle.attr_named_attr
     }
 
     pub fn attr_unique_handles(&self) -> bool {
@@ -573,7 +742,7 @@ impl FileManagerHandle {
         self.unique_handles
     }
 
-    pub fn attr_acl(&self) -> bool {
+    pub fn attr_acl(&self, filehandle: &Filehandle) -> Vec<Nfsace4> {
         // acl:
         // The NFSv4.0 ACL attribute contains an array of ACEs that are
         // associated with the file system object.  Although the client can read
@@ -581,13 +750,19 @@ impl FileManagerHandle {
         // ACL to perform access control.  The client can use the OPEN or ACCESS
         // operations to check access without modifying or reading data or
         // metadata.
-        false
+        match &filehandle.attr_acl {
+            Some(acl) => acl.clone(),
+            None => acl::from_mode(
+                filehandle.attr_mode,
+                filehandle.attr_type == NfsFtype4::Nf4dir,
+            ),
+        }
     }
 
     pub fn attr_acl_support(&self) -> u32 {
         // acl_support:
         // TRUE, if the object's file system supports Access Control Lists.
-        ACL4_SUPPORT_ALLOW_ACL
+        acl::ACL_SUPPORT
     }
 
     pub fn attr_archive(&self) -> bool {
@@ -595,18 +770,6 @@ impl FileManagerHandle {
         // TRUE, if the object's file system supports the archive attribute.
         false
     }
-
-    pub fn attr_mode(&self) -> u32 {
-        // mode:
-        // The NFSv4.0 mode attribute is based on the UNIX mode bits.
-        MODE4_RUSR + MODE4_RGRP + MODE4_ROTH
-    }
-
-    pub fn attr_numlinks(&self) -> u32 {
-        // numlinks:
-        // Number of hard links to this object.
-        1
-    }
 }
 
 pub enum WriteCacheMessage {
@@ -617,8 +780,8 @@ pub enum WriteCacheMessage {
 pub struct WriteBytesRequest {
     // seek offset
     pub offset: u64,
-    // bytes to insert
-    pub data: Vec<u8>,
+    // bytes to insert, a slice of the received WRITE
+    pub data: Bytes,
 }
 
 #[derive(Debug, Clone)]
@@ -636,14 +799,112 @@ impl WriteCacheHandle {
         Self { sender }
     }
 
-    pub async fn write_bytes(&self, offset: u64, data: Vec<u8>) {
-        self.sender
-            .send(WriteCacheMessage::Write(WriteBytesRequest { offset, data }))
+    pub async fn write_bytes(&self, offset: u64, data: Bytes) -> Result<(), FileManagerError> {
+        self.send(WriteCacheMessage::Write(WriteBytesRequest { offset, data }))
             .await
-            .unwrap();
     }
 
-    pub async fn commit(&self) {
-        self.sender.send(WriteCacheMessage::Commit).await.unwrap();
+    pub async fn commit(&self) -> Result<(), FileManagerError> {
+        self.send(WriteCacheMessage::Commit).await
+    }
+
+    // a write cache that isn't running anymore can't take writes
+    async fn send(&self, msg: WriteCacheMessage) -> Result<(), FileManagerError> {
+        self.sender.send(msg).await.map_err(|_| {
+            error!("Write cache is not running");
+            FileManagerError {
+                nfs_error: NfsStat4::Nfs4errServerfault,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bold_proto::nfs4_proto::NfsStat4;
+    use tracing_test::traced_test;
+
+    use super::{
+        super::{FileManager, FileManagerConfig},
+        FileManagerHandle, Filehandle,
+    };
+    use crate::{
+        storage::{MemoryBackend, StorageBackend, StorageError},
+        test_utils::FaultyBackend,
+    };
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_backend_errors() {
+        let memory = MemoryBackend::new();
+        memory.write_file("/file1.txt", b"").unwrap();
+        let backend = Arc::new(FaultyBackend::new(Arc::new(memory)));
+        let fm = FileManagerHandle::new(backend.clone(), None, None);
+
+        let cases = [
+            (StorageError::NoSpace, NfsStat4::Nfs4errNospc),
+            (StorageError::QuotaExceeded, NfsStat4::Nfs4errDquot),
+            (StorageError::AccessDenied, NfsStat4::Nfs4errAccess),
+            (StorageError::Io("EIO".to_string()), NfsStat4::Nfs4errIo),
+        ];
+        for (error, nfs_error) in cases {
+            backend.clear();
+            backend.fail("/new", error.clone());
+            backend.fail("/file1.txt", error);
+            let e = fm.create_dir("/new".to_string()).await.unwrap_err();
+            assert_eq!(e.nfs_error, nfs_error);
+            let e = fm
+                .create_file("/new".to_string(), 0, vec![], 0, 0, None)
+                .await
+                .unwrap_err();
+            assert_eq!(e.nfs_error, nfs_error);
+            let e = fm
+                .get_filehandle_for_path("/file1.txt".to_string())
+                .await
+                .unwrap_err();
+            assert_eq!(e.nfs_error, nfs_error);
+            let e = fm.remove_file("/file1.txt".to_string()).await.unwrap_err();
+            assert_eq!(e.nfs_error, nfs_error);
+        }
+
+        // the FileManager survived all of them
+        backend.clear();
+        assert!(fm.get_root_filehandle().await.is_ok());
+        let fh = fm
+            .get_filehandle_for_path("/file1.txt".to_string())
+            .await
+            .unwrap();
+
+        // an object that can't be read right now keeps its filehandle
+        backend.fail("/file1.txt", StorageError::Io("EIO".to_string()));
+        let e = fm.get_filehandle_for_id(fh.id).await.unwrap_err();
+        assert_eq!(e.nfs_error, NfsStat4::Nfs4errIo);
+        backend.clear();
+        assert_eq!(fm.get_filehandle_for_id(fh.id).await.unwrap().id, fh.id);
+        assert!(fm.create_dir("/dir1".to_string()).await.is_ok());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_change_survives_dropped_filehandle() {
+        let backend = MemoryBackend::new();
+        backend.write_file("/file1.txt", b"hello").unwrap();
+        let backend: Arc<dyn StorageBackend> = Arc::new(backend);
+        let meta = backend.getattr("/file1.txt").await.unwrap();
+        let (_sender, receiver) = tokio::sync::mpsc::channel(1);
+        let mut fm = FileManager::new(receiver, backend, FileManagerConfig::default(), None).await;
+
+        // changes made within one tick of the ctime only show in the counter
+        let fh = fm.get_filehandle("/file1.txt", &meta);
+        let fh = fm.touch_filehandle(fh).await.unwrap();
+        let fh = fm.touch_filehandle(fh).await.unwrap();
+        assert_eq!(fh.attr_change, Filehandle::attr_change(&meta, 0) + 2);
+
+        // a new filehandle for the object doesn't go back
+        fm.fhdb.remove_by_id(&fh.id);
+        let again = fm.get_filehandle("/file1.txt", &meta);
+        assert!(again.attr_change >= fh.attr_change);
     }
 }
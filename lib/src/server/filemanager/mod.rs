@@ -1,32 +1,58 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
 use bold_proto::nfs4_proto::{
-    Attrlist4, ChangeInfo4, FileAttr, FileAttrValue, NfsFh4, NfsLease4, NfsStat4,
-    ACL4_SUPPORT_ALLOW_ACL, FH4_VOLATILE_ANY, MODE4_RGRP, MODE4_ROTH, MODE4_RUSR,
+    Attrlist4, ChangeInfo4, FileAttr, FileAttrValue, NfsFh4, NfsFtype4, NfsLease4, NfsStat4,
+    Nfsace4, FH4_PERSISTENT, FH4_VOLATILE_ANY,
 };
 
 mod filehandle;
 pub use filehandle::Filehandle;
-pub use handle::FileManagerHandle;
+pub use handle::{FileManagerError, FileManagerHandle};
+pub use persistent::FH_EXPORT_BYTE;
 mod caching;
 mod handle;
 mod locking;
+mod persistent;
 
 use filehandle::FilehandleDb;
-use handle::{FileManagerError, FileManagerMessage, WriteCacheHandle};
+use handle::{CreateFileRequest, FileManagerMessage, WriteCacheHandle};
 use locking::{LockingState, LockingStateDb};
+use persistent::HandleStore;
 use tokio::sync::mpsc;
 use tracing::{debug, error};
-use vfs::VfsPath;
+
+use super::acl;
+use crate::storage::{split_path, Metadata, StorageBackend, StorageError};
+
+/// Settings of the file system served by a [`FileManager`].
+#[derive(Debug, Clone, Default)]
+pub struct FileManagerConfig {
+    pub fsid: Option<u64>,
+    // where filehandles are persisted, so they resolve after a restart
+    pub handle_db: Option<PathBuf>,
+    // the export this file system is served as, recorded in every filehandle
+    pub export_id: u8,
+    // fileid of the pseudo file system directory the export is mounted on
+    pub mounted_on_fileid: Option<u64>,
+}
 
 #[derive(Debug)]
 pub struct FileManager {
-    pub root: VfsPath,
+    pub backend: Arc<dyn StorageBackend>,
     pub lease_time: u32,
     pub hard_link_support: bool,
     pub symlink_support: bool,
     pub unique_handles: bool,
+    pub exclusive_access: bool,
+    // backend filehandles are built from inode numbers
+    pub persistent_handles: bool,
+    // the backend finds inode filehandles without a filehandle db
+    pub inode_lookup: bool,
+    // on-disk map of the filehandles handed out, by id or by inode
+    pub handle_store: Option<HandleStore>,
     pub fsid: u64,
+    pub export_id: u8,
+    pub mounted_on_fileid: Option<u64>,
     // database for all managed filehandles
     pub fhdb: FilehandleDb,
     // this field trackes a sequence number for filehandles
@@ -39,87 +65,98 @@ pub struct FileManager {
     // endpoint for incoming messages
     pub receiver: mpsc::Receiver<FileManagerMessage>,
     pub cachedb: HashMap<NfsFh4, WriteCacheHandle>,
+    // the change attribute of the objects bold changed more often than their
+    // ctime shows, by fileid, kept when their filehandles are dropped so the
+    // attribute never goes back
+    pub change_floor: HashMap<u64, u64>,
 }
 
 impl FileManager {
-    pub fn new(
+    pub async fn new(
         receiver: mpsc::Receiver<FileManagerMessage>,
-        root: VfsPath,
-        fsid: Option<u64>,
+        backend: Arc<dyn StorageBackend>,
+        config: FileManagerConfig,
+        handle_store: Option<HandleStore>,
     ) -> Self {
-        let fsid = fsid.unwrap_or(152);
-        let boot_time = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
+        // every export gets a file system id of its own
+        let fsid = config.fsid.unwrap_or(152 + config.export_id as u64);
+        let boot_time = std::time::UNIX_EPOCH
+            .elapsed()
+            .unwrap_or_default()
+            .as_secs();
+        let capabilities = backend.capabilities();
         let mut fmanager = FileManager {
             receiver,
-            root: root.clone(),
+            backend,
             // lease time in seconds
             lease_time: 60,
-            hard_link_support: false,
-            symlink_support: false,
-            unique_handles: false,
+            hard_link_support: capabilities.hard_links,
+            symlink_support: capabilities.symlinks,
+            unique_handles: capabilities.unique_handles,
+            exclusive_access: capabilities.exclusive_access,
+            persistent_handles: capabilities.persistent_handles,
+            inode_lookup: capabilities.inode_lookup,
+            handle_store,
             boot_time,
             fsid,
+            export_id: config.export_id,
+            mounted_on_fileid: config.mounted_on_fileid,
             next_fh_id: 100,
             next_stateid_id: 100,
             fhdb: FilehandleDb::default(),
             lockdb: LockingStateDb::default(),
             cachedb: HashMap::new(),
+            change_floor: HashMap::new(),
         };
         // always have a root filehandle upon start
-        fmanager.root_fh();
+        if let Err(e) = fmanager.root_fh().await {
+            error!("Export root is not accessible: {:?}", e);
+        }
         fmanager
     }
 
     // actor main message handler for FileManager
-    fn handle_message(&mut self, msg: FileManagerMessage) {
+    async fn handle_message(&mut self, msg: FileManagerMessage) {
         match msg {
             FileManagerMessage::GetRootFilehandle(req) => {
-                let fh_wo_locks = self.root_fh();
-                let fh = self.attach_locks(fh_wo_locks);
-                req.respond_to.send(fh).unwrap();
+                let fh = match self.root_fh().await {
+                    Ok(fh_wo_locks) => Ok(self.attach_locks(fh_wo_locks)),
+                    Err(e) => Err(e),
+                };
+                let _ = req.respond_to.send(fh);
             }
             FileManagerMessage::GetFilehandle(req) => {
-                if req.filehandle.is_some() {
-                    let fh = self.get_filehandle_by_id(&req.filehandle.unwrap());
-                    match fh {
-                        Some(fh_wo_locks) => {
-                            let fh = self.attach_locks(fh_wo_locks);
-                            req.respond_to.send(Some(fh)).unwrap();
+                let fh = if let Some(id) = req.filehandle {
+                    self.get_filehandle_by_id(&id).await
+                } else if let Some(path) = req.path {
+                    // check if file exists
+                    match self.backend.getattr(&path).await {
+                        Ok(meta) => {
+                            let fh = self.get_filehandle(&path, &meta);
+                            Ok(self.refresh_filehandle(fh, &meta))
                         }
-                        None => {
-                            debug!("Filehandle not found");
-                            req.respond_to.send(None).unwrap();
+                        Err(e) => {
+                            debug!("File not readable {:?}: {:?}", path, e);
+                            Err(e.into())
                         }
                     }
-                } else if req.path.is_some() {
-                    let path = self.root.join(req.path.unwrap()).unwrap();
-                    // check if file exists
-                    if path.exists().unwrap() {
-                        let fh_wo_locks = self.get_filehandle(&path);
-                        let fh = self.attach_locks(fh_wo_locks);
-                        req.respond_to.send(Some(fh)).unwrap();
-                    } else {
-                        debug!("File not found {:?}", path);
-                        req.respond_to.send(None).unwrap();
-                    }
                 } else {
-                    let fh_wo_locks = self.root_fh();
-                    let fh = self.attach_locks(fh_wo_locks);
-                    req.respond_to.send(Some(fh)).unwrap();
-                }
+                    self.root_fh().await
+                };
+                let _ = req.respond_to.send(fh.map(|fh| self.attach_locks(fh)));
             }
             FileManagerMessage::GetFilehandleAttrs(req) => {
-                req.respond_to
-                    .send(self.filehandle_attrs(&req.attrs_request, &req.filehandle_id))
-                    .unwrap();
+                let attrs = self
+                    .filehandle_attrs(&req.attrs_request, &req.filehandle_id)
+                    .await;
+                let _ = req.respond_to.send(attrs);
             }
             FileManagerMessage::CreateFile(req) => {
-                let result = self.create_file(&req.path);
-                if let Some((fh, change_info)) = result {
-                    let mut fh = fh;
+                let result = self.open_or_create_file(&req).await;
+                let result = result.map(|(mut fh, change_info)| {
                     let stateid = self.get_new_lockingstate_id();
                     let lock = LockingState::new_shared_reservation(
-                        fh.id.clone(),
+                        fh.id,
                         stateid,
                         req.client_id,
                         req.owner,
@@ -129,20 +166,21 @@ impl FileManager {
                     // add this new locking state to the db
                     self.lockdb.insert(lock.clone());
                     fh.locks = vec![lock];
-                    req.respond_to.send(Ok((fh, change_info))).unwrap();
-                } else {
-                    req.respond_to
-                        .send(Err(FileManagerError {
-                            nfs_error: NfsStat4::Nfs4errIo,
-                        }))
-                        .unwrap();
-                }
+                    (fh, change_info)
+                });
+                let _ = req.respond_to.send(result);
+            }
+            FileManagerMessage::CreateDir(req) => {
+                let result = self
+                    .create_object(&req.path, NfsFtype4::Nf4dir, 0o755)
+                    .await;
+                let _ = req.respond_to.send(result);
             }
             FileManagerMessage::LockFile(req) => {
                 let mut fh = req.filehandle;
                 let stateid = self.get_new_lockingstate_id();
                 let lock = LockingState::new_shared_reservation(
-                    fh.id.clone(),
+                    fh.id,
                     stateid,
                     req.client_id,
                     req.owner,
@@ -152,117 +190,130 @@ impl FileManager {
                 // add this new locking state to the db
                 self.lockdb.insert(lock.clone());
                 fh.locks = vec![lock];
-                req.respond_to.send(Ok(fh)).unwrap();
+                let _ = req.respond_to.send(Ok(fh));
             }
             FileManagerMessage::ConfirmLock(req) => {
                 // TODO check seqid
                 let lock = self.lockdb.get_by_stateid(&req.stateid);
-                if let Some(_) = lock {
+                if lock.is_some() {
                     self.lockdb.modify_by_stateid(&req.stateid, |l| {
                         l.confirmed = true;
                     });
-                    req.respond_to.send(Ok(())).unwrap();
+                    let _ = req.respond_to.send(Ok(()));
                 } else {
-                    req.respond_to
-                        .send(Err(FileManagerError {
-                            nfs_error: NfsStat4::Nfs4errBadStateid,
-                        }))
-                        .unwrap();
+                    let _ = req.respond_to.send(Err(FileManagerError {
+                        nfs_error: NfsStat4::Nfs4errBadStateid,
+                    }));
                 }
             }
             FileManagerMessage::CloseFile(req) => {
                 let lock = self.lockdb.get_by_stateid(&req.stateid);
-                if let Some(_) = lock {
+                if lock.is_some() {
                     self.lockdb.remove_by_stateid(&req.stateid);
-                    req.respond_to.send(Ok(())).unwrap();
+                    let _ = req.respond_to.send(Ok(()));
                 } else {
-                    req.respond_to
-                        .send(Err(FileManagerError {
-                            nfs_error: NfsStat4::Nfs4errBadStateid,
-                        }))
-                        .unwrap();
+                    let _ = req.respond_to.send(Err(FileManagerError {
+                        nfs_error: NfsStat4::Nfs4errBadStateid,
+                    }));
                 }
             }
             FileManagerMessage::RemoveFile(req) => {
-                let filehandle = self.get_filehandle_by_path(&req.path.as_str().to_string());
-                let mut parent_path = req.path.parent().as_str().to_string();
-                match filehandle {
-                    Some(filehandle) => {
-                        // TODO check locks
-                        if req.path.is_dir().unwrap() {
-                            let _ = req.path.remove_dir();
-                        } else {
-                            let _ = req.path.remove_file();
-                        }
-                        self.fhdb.remove_by_id(&filehandle.id);
-                    }
-                    None => {
-                        if req.path.is_dir().unwrap() {
-                            let _ = req.path.remove_dir();
-                        } else {
-                            let _ = req.path.remove_file();
-                        }
-                    }
-                }
-
-                if parent_path.is_empty() {
-                    // this is root
-                    parent_path = "/".to_string();
-                }
-
-                let parent_filehandle = self.get_filehandle_by_path(&parent_path).unwrap();
-                let before = parent_filehandle.attr_change;
-                // TODO: check locks
-                self.touch_filehandle(parent_filehandle);
-                let parent_filehandle = self.get_filehandle_by_path(&parent_path).unwrap();
-                let after = parent_filehandle.attr_change;
-
-                let change_info = ChangeInfo4 {
-                    atomic: true,
-                    before,
-                    after,
-                };
-
-                req.respond_to.send(Ok(change_info)).unwrap()
+                let result = self.remove_file(&req.path).await;
+                let _ = req.respond_to.send(result);
             }
             FileManagerMessage::TouchFile(req) => {
-                let filehandle = self.get_filehandle_by_id(&req.id);
-                match filehandle {
-                    Some(filehandle) => {
-                        // TODO: check locks
-                        self.touch_filehandle(filehandle);
-                    }
-                    None => {
-                        // we don't do nothing here
-                    }
+                // TODO: check locks
+                if let Ok(filehandle) = self.get_filehandle_by_id(&req.id).await {
+                    self.touch_filehandle(filehandle).await;
                 }
             }
             FileManagerMessage::GetWriteCacheHandle(req) => {
                 let handle = self.get_cache_handle(req.filehandle, req.filemanager);
-                req.respond_to.send(handle).unwrap();
+                let _ = req.respond_to.send(handle);
             }
             FileManagerMessage::DropWriteCacheHandle(req) => {
-                self.drop_cache_handle(&req.filehandle_id);
+                self.drop_cache_handle(&req.filehandle_id).await;
             }
             FileManagerMessage::UpdateFilehandle(req) => {
                 self.update_filehandle(req);
             }
+            FileManagerMessage::SyncHandles(req) => {
+                if let Some(store) = self.handle_store.as_mut() {
+                    if let Err(e) = store.sync().await {
+                        error!("Couldn't write filehandle db {:?}: {:?}", store.path(), e);
+                    }
+                }
+                let _ = req.respond_to.send(());
+            }
         }
     }
 
-    fn touch_filehandle(&mut self, filehandle: Filehandle) {
-        // create a new filehandle with refreshed attributes
-        let fh = Filehandle::new(
-            filehandle.file.clone(),
-            filehandle.id.clone(),
+    // refresh the attributes of a filehandle after bold changed the object
+    async fn touch_filehandle(&mut self, filehandle: Filehandle) -> Option<Filehandle> {
+        match self.backend.getattr(&filehandle.path).await {
+            Ok(meta) => {
+                // every change made through bold increases the change attribute
+                let change = filehandle.attr_change + 1;
+                let fh = self.replace_filehandle(&filehandle, &meta, change);
+                if fh.attr_change > Filehandle::attr_change(&meta, 0) {
+                    self.change_floor.insert(fh.attr_fileid, fh.attr_change);
+                }
+                debug!("Touching filehandle: {:?}", fh);
+                Some(fh)
+            }
+            Err(e) => {
+                // the file is gone, its filehandle is stale now
+                debug!("Removing stale filehandle {:?}: {:?}", filehandle.path, e);
+                self.fhdb.remove_by_id(&filehandle.id);
+                None
+            }
+        }
+    }
+
+    // pick up changes made outside of bold
+    fn refresh_filehandle(&mut self, filehandle: Filehandle, meta: &Metadata) -> Filehandle {
+        if Filehandle::attr_change(meta, 0) > filehandle.attr_change {
+            debug!("Filehandle changed outside of bold: {:?}", filehandle.path);
+            let change = filehandle.attr_change;
+            self.replace_filehandle(&filehandle, meta, change)
+        } else {
+            filehandle
+        }
+    }
+
+    // the lowest change attribute a new filehandle for meta may report, the
+    // counter of its object is forgotten once its ctime caught up
+    fn change_floor(&mut self, meta: &Metadata) -> u64 {
+        match self.change_floor.get(&meta.ino) {
+            Some(floor) if *floor > Filehandle::attr_change(meta, 0) => *floor,
+            Some(_) => {
+                self.change_floor.remove(&meta.ino);
+                0
+            }
+            None => 0,
+        }
+    }
+
+    // create a new filehandle with the attributes of meta and replace the old one
+    fn replace_filehandle(
+        &mut self,
+        filehandle: &Filehandle,
+        meta: &Metadata,
+        min_change: u64,
+    ) -> Filehandle {
+        let mut fh = Filehandle::new(
+            filehandle.path.clone(),
+            filehandle.id,
+            meta,
             self.fsid,
             self.fsid,
             filehandle.version,
         );
-        self.fhdb.remove_by_id(&filehandle.id);
-        debug!("Touching filehandle: {:?}", fh);
-        // and replace the old one
-        self.fhdb.insert(fh);
+        fh.attr_change = Filehandle::attr_change(meta, min_change);
+        fh.verifier = filehandle.verifier;
+        fh.write_cache = filehandle.write_cache.clone();
+        self.update_filehandle(fh.clone());
+        fh
     }
 
     fn update_filehandle(&mut self, filehandle: Filehandle) {
@@ -272,107 +323,255 @@ impl FileManager {
         self.fhdb.insert(filehandle);
     }
 
-    fn create_file(&mut self, request_file: &VfsPath) -> Option<(Filehandle, ChangeInfo4)> {
-        let newfile = match request_file.create_file() {
-            Ok(_) => {
-                debug!("File created successfully");
-                request_file
+    // the change attribute of the parent directory of path, taken before changing it
+    async fn parent_change(&mut self, path: &str) -> Result<u64, FileManagerError> {
+        let (parent_path, _) = split_path(path);
+        let meta = self.backend.getattr(&parent_path).await?;
+        let parent_filehandle = self.get_filehandle(&parent_path, &meta);
+        Ok(self
+            .refresh_filehandle(parent_filehandle, &meta)
+            .attr_change)
+    }
+
+    // touch the parent directory of path and report the change
+    async fn touch_parent(&mut self, path: &str, before: u64) -> ChangeInfo4 {
+        let (parent_path, _) = split_path(path);
+        // TODO: check locks
+        let after = match self.get_filehandle_by_path(&parent_path) {
+            Some(parent_filehandle) => self
+                .touch_filehandle(parent_filehandle)
+                .await
+                .map_or(before, |fh| fh.attr_change),
+            None => before,
+        };
+        // https://datatracker.ietf.org/doc/html/rfc7530#section-3.3.3
+        // before and after are only atomic if nobody else could have changed
+        // the directory in between
+        ChangeInfo4 {
+            atomic: self.exclusive_access,
+            before,
+            after,
+        }
+    }
+
+    async fn create_object(
+        &mut self,
+        path: &str,
+        file_type: NfsFtype4,
+        mode: u32,
+    ) -> Result<(Filehandle, ChangeInfo4), FileManagerError> {
+        let (dir, name) = split_path(path);
+        let before = self.parent_change(path).await?;
+        let meta = self.backend.create(&dir, &name, file_type, mode).await?;
+        debug!("File created successfully");
+        // this filehandle is already added to the db
+        let fh = self.get_filehandle(path, &meta);
+        let change_info = self.touch_parent(path, before).await;
+        Ok((fh, change_info))
+    }
+
+    async fn open_or_create_file(
+        &mut self,
+        req: &CreateFileRequest,
+    ) -> Result<(Filehandle, ChangeInfo4), FileManagerError> {
+        match self
+            .create_object(&req.path, NfsFtype4::Nf4reg, 0o644)
+            .await
+        {
+            Ok((mut fh, change_info)) => {
+                if req.verifier.is_some() {
+                    fh.verifier = req.verifier;
+                    self.update_filehandle(fh.clone());
+                }
+                Ok((fh, change_info))
+            }
+            Err(e) if e.nfs_error == NfsStat4::Nfs4errExist => {
+                let meta = self.backend.getattr(&req.path).await?;
+                if meta.file_type != NfsFtype4::Nf4reg && meta.file_type != NfsFtype4::Nf4namedattr
+                {
+                    return Err(FileManagerError {
+                        nfs_error: NfsStat4::Nfs4errExist,
+                    });
+                }
+                let fh = self.get_filehandle(&req.path, &meta);
+                // https://datatracker.ietf.org/doc/html/rfc7530#section-16.16.5
+                // an EXCLUSIVE4 create of an existing file only succeeds if the
+                // verifier matches the one stored at creation
+                if req.verifier.is_some() && fh.verifier != req.verifier {
+                    return Err(FileManagerError {
+                        nfs_error: NfsStat4::Nfs4errExist,
+                    });
+                }
+                let change_info = ChangeInfo4 {
+                    atomic: true,
+                    before: fh.attr_change,
+                    after: fh.attr_change,
+                };
+                Ok((fh, change_info))
             }
             Err(e) => {
                 error!("Error creating file {:?}", e);
-                return None;
+                Err(e)
             }
-        };
-
-        // this filehandle is already added to the db
-        let fh = self.get_filehandle(newfile);
-        let mut path = newfile.parent().as_str().to_string();
-        if path.is_empty() {
-            // this is root
-            path = "/".to_string();
         }
-        // TODO: check locks
-        let parent_filehandle = self.get_filehandle_by_path(&path).unwrap();
-        let before = parent_filehandle.attr_change;
-        self.touch_filehandle(parent_filehandle);
-        let parent_filehandle = self.get_filehandle_by_path(&path).unwrap();
-        let after = parent_filehandle.attr_change;
-        let change_info = ChangeInfo4 {
-            atomic: true,
-            before,
-            after,
-        };
+    }
 
-        Some((fh, change_info))
+    async fn remove_file(&mut self, path: &str) -> Result<ChangeInfo4, FileManagerError> {
+        let (dir, name) = split_path(path);
+        // TODO check locks
+        let before = self.parent_change(path).await?;
+        self.backend.remove(&dir, &name).await?;
+        if let Some(filehandle) = self.get_filehandle_by_path(path) {
+            self.fhdb.remove_by_id(&filehandle.id);
+            // other links keep the object around
+            if filehandle.attr_numlinks <= 1 {
+                self.change_floor.remove(&filehandle.attr_fileid);
+            }
+        }
+        if let Some(store) = self.handle_store.as_mut() {
+            store.remove(path);
+        }
+        Ok(self.touch_parent(path, before).await)
     }
 
     fn get_new_lockingstate_id(&mut self) -> [u8; 12] {
         // create a new unique lockingstate id
-        let mut id = vec![0_u8, 0_u8, 0_u8, 0_u8];
-        id.extend(self.next_stateid_id.to_be_bytes().to_vec());
+        let mut id = [0_u8; 12];
+        id[4..].copy_from_slice(&self.next_stateid_id.to_be_bytes());
         self.next_stateid_id += 1;
-        id.try_into().unwrap()
+        id
     }
 
-    fn get_filehandle_id(&mut self, file: &VfsPath) -> NfsFh4 {
+    fn get_filehandle_id(&mut self, path: &str, meta: &Metadata) -> NfsFh4 {
         // if there is already a filehandle for this path, return it
-        let mut path = file.as_str().to_string();
-        if path.is_empty() {
-            // this is root
-            path = "/".to_string();
-        }
-        let exists = self.get_filehandle_by_path(&path);
-        if let Some(exists) = exists {
+        if let Some(exists) = self.get_filehandle_by_path(path) {
             return exists.id;
         }
 
+        // https://tools.ietf.org/html/rfc7530#section-4.2.2
+        // persistent filehandles, either built from the inode of the object
+        // or looked up in the filehandle db
+        if self.persistent_handles {
+            // remember where the inode is, there is no cheap way to find it after a restart
+            if let Some(store) = self.handle_store.as_mut() {
+                store.insert(persistent::inode_key(meta), path);
+            }
+            return persistent::inode_fh(self.export_id, meta);
+        }
+        if let Some(store) = self.handle_store.as_mut() {
+            return persistent::stored_fh(self.export_id, store.get_or_insert(path));
+        }
+
         // https://tools.ietf.org/html/rfc7530#section-4.2.3
         // this implements a "Volatile Filehandle"
-        let mut id = vec![128_u8];
-        id.extend(self.boot_time.to_be_bytes().to_vec());
-        id.extend(self.next_fh_id.to_be_bytes().to_vec());
-        id.extend(vec![1_u8]);
+        let id = persistent::volatile_fh(self.export_id, self.boot_time, self.next_fh_id);
 
         debug!("created new filehandle id: {:?}", id);
         self.next_fh_id += 1;
-        id.try_into().expect("Cannot convert Vec to NfsFh4")
-    }
-
-    fn get_filehandle_by_id(&mut self, id: &NfsFh4) -> Option<Filehandle> {
-        let fh = self.fhdb.get_by_id(id);
-        if let Some(fh) = fh {
-            if fh.file.exists().unwrap() {
-                debug!("Found filehandle: {:?}", fh);
-                return Some(fh.clone());
-            } else {
-                // this filehandle is stale, remove it
-                debug!("Removing stale filehandle: {:?}", fh);
-                self.fhdb.remove_by_id(id);
+        id
+    }
+
+    async fn get_filehandle_by_id(&mut self, id: &NfsFh4) -> Result<Filehandle, FileManagerError> {
+        // https://datatracker.ietf.org/doc/html/rfc7530#section-4.2.3
+        // If the server can definitively determine that a
+        // volatile filehandle refers to an object that has been removed, the
+        // server should return NFS4ERR_STALE to the client (as is the case for
+        // persistent filehandles)
+        let stale = FileManagerError {
+            nfs_error: NfsStat4::Nfs4errStale,
+        };
+        if id[persistent::FH_EXPORT_BYTE] != self.export_id {
+            return Err(stale);
+        }
+        if let Some(fh) = self.fhdb.get_by_id(id).cloned() {
+            match self.backend.getattr(&fh.path).await {
+                // an inode filehandle is only valid as long as its path points to the same inode
+                Ok(meta)
+                    if persistent::parse_inode_fh(id).is_none_or(|(_, ino, _)| ino == meta.ino) =>
+                {
+                    debug!("Found filehandle: {:?}", fh);
+                    return Ok(self.refresh_filehandle(fh, &meta));
+                }
+                // the object may still be there, it just can't be read right now
+                Err(e) if e != StorageError::NotFound => {
+                    error!("Couldn't read {:?}: {:?}", fh.path, e);
+                    return Err(e.into());
+                }
+                _ => {
+                    // this filehandle is stale, remove it
+                    debug!("Removing stale filehandle: {:?}", fh);
+                    self.fhdb.remove_by_id(id);
+                }
             }
         }
-        None
+        self.resolve_persistent_filehandle(id).await.ok_or(stale)
     }
 
-    pub fn get_filehandle_by_path(&self, path: &String) -> Option<Filehandle> {
-        debug!("get_filehandle_by_path: {}", path);
-        self.fhdb.get_by_path(path).cloned()
-    }
-
-    pub fn get_filehandle(&mut self, file: &VfsPath) -> Filehandle {
-        let id = self.get_filehandle_id(file);
-        match self.get_filehandle_by_id(&id) {
-            Some(fh) => fh.clone(),
-            None => {
-                let fh = Filehandle::new(file.clone(), id, self.fsid, self.fsid, 0);
-                debug!("Storing new filehandle: {:?}", fh);
-                self.fhdb.insert(fh.clone());
-                fh
+    // look up a persistent filehandle that is not known to this server instance,
+    // e.g. one handed out before a restart
+    async fn resolve_persistent_filehandle(&mut self, id: &NfsFh4) -> Option<Filehandle> {
+        let path = if let Some((dev, ino, generation)) = persistent::parse_inode_fh(id) {
+            if !self.persistent_handles {
+                return None;
+            }
+            let stored = self
+                .handle_store
+                .as_ref()
+                .and_then(|store| store.get_path(persistent::inode_key_of(ino, generation)));
+            match stored {
+                Some(path) => path.clone(),
+                None if self.inode_lookup => {
+                    self.backend.lookup_inode(dev, ino, generation).await.ok()?
+                }
+                // unknown inodes are stale, the export is never searched for them
+                None => return None,
             }
+        } else if let Some(handle) = persistent::parse_stored_fh(id) {
+            self.handle_store.as_ref()?.get_path(handle)?.clone()
+        } else {
+            return None;
+        };
+        let meta = self.backend.getattr(&path).await.ok()?;
+        if persistent::parse_inode_fh(id).is_some()
+            && persistent::inode_fh(self.export_id, &meta) != *id
+        {
+            return None;
+        }
+        // a filehandle cached for this path belongs to an object that is gone
+        if let Some(stale) = self.get_filehandle_by_path(&path) {
+            self.fhdb.remove_by_id(&stale.id);
+        }
+        let mut fh = Filehandle::new(path, *id, &meta, self.fsid, self.fsid, 0);
+        fh.attr_change = fh.attr_change.max(self.change_floor(&meta));
+        debug!("Resolved persistent filehandle: {:?}", fh);
+        self.fhdb.insert(fh.clone());
+        Some(fh)
+    }
+
+    pub fn get_filehandle_by_path(&self, path: &str) -> Option<Filehandle> {
+        debug!("get_filehandle_by_path: {}", path);
+        self.fhdb.get_by_path(&path.to_string()).cloned()
+    }
+
+    pub fn get_filehandle(&mut self, path: &str, meta: &Metadata) -> Filehandle {
+        if let Some(fh) = self.get_filehandle_by_path(path) {
+            return fh;
+        }
+        let id = self.get_filehandle_id(path, meta);
+        // hard links share their inode filehandle, keep the one we know
+        if let Some(fh) = self.fhdb.get_by_id(&id) {
+            return fh.clone();
         }
+        let mut fh = Filehandle::new(path.to_string(), id, meta, self.fsid, self.fsid, 0);
+        fh.attr_change = fh.attr_change.max(self.change_floor(meta));
+        debug!("Storing new filehandle: {:?}", fh);
+        self.fhdb.insert(fh.clone());
+        fh
     }
 
-    pub fn root_fh(&mut self) -> Filehandle {
-        self.get_filehandle(&self.root.clone())
+    pub async fn root_fh(&mut self) -> Result<Filehandle, FileManagerError> {
+        let meta = self.backend.getattr("/").await?;
+        Ok(self.get_filehandle("/", &meta))
     }
 
     pub fn attach_locks(&self, mut filehandle: Filehandle) -> Filehandle {
@@ -386,41 +585,39 @@ impl FileManager {
         mut filehandle: Filehandle,
         filemanager: FileManagerHandle,
     ) -> WriteCacheHandle {
-        if self.cachedb.contains_key(&filehandle.id) {
-            self.cachedb.get(&filehandle.id).unwrap().clone()
+        if let Some(handle) = self.cachedb.get(&filehandle.id) {
+            handle.clone()
         } else {
             let handle = WriteCacheHandle::new(filehandle.clone(), filemanager);
             filehandle.write_cache = Some(handle.clone());
-            self.cachedb.insert(filehandle.id.clone(), handle.clone());
+            self.cachedb.insert(filehandle.id, handle.clone());
             self.update_filehandle(filehandle);
             handle
         }
     }
 
-    pub fn drop_cache_handle(&mut self, filehandle_id: &NfsFh4) {
-        if self.cachedb.contains_key(filehandle_id) {
-            self.cachedb.remove(filehandle_id);
-        }
-        let filehandle = self.get_filehandle_by_id(filehandle_id);
-        if let Some(mut filehandle) = filehandle {
+    pub async fn drop_cache_handle(&mut self, filehandle_id: &NfsFh4) {
+        self.cachedb.remove(filehandle_id);
+        let filehandle = self.get_filehandle_by_id(filehandle_id).await;
+        if let Ok(mut filehandle) = filehandle {
             filehandle.write_cache = None;
             self.update_filehandle(filehandle);
         }
     }
 
-    pub fn filehandle_attrs(
+    pub async fn filehandle_attrs(
         &mut self,
-        attr_request: &Vec<FileAttr>,
+        attr_request: &[FileAttr],
         filehandle_id: &NfsFh4,
     ) -> Option<(Vec<FileAttr>, Vec<FileAttrValue>)> {
         let mut answer_attrs = Vec::new();
         let mut attrs = Vec::new();
-        let fh = self.get_filehandle_by_id(filehandle_id);
+        let fh = self.get_filehandle_by_id(filehandle_id).await;
 
         match fh {
-            None => None,
+            Err(_) => None,
 
-            Some(filehandle) => {
+            Ok(filehandle) => {
                 for fileattr in attr_request {
                     match fileattr {
                         FileAttr::SupportedAttrs => {
@@ -452,9 +649,13 @@ impl FileManager {
                             answer_attrs.push(FileAttr::SymlinkSupport);
                         }
                         FileAttr::NamedAttr => {
-                            attrs.push(FileAttrValue::NamedAttr(self.attr_named_attr()));
+                            attrs.push(FileAttrValue::NamedAttr(self.attr_named_attr(&filehandle)));
                             answer_attrs.push(FileAttr::NamedAttr);
                         }
+                        FileAttr::Acl => {
+                            attrs.push(FileAttrValue::Acl(self.attr_acl(&filehandle)));
+                            answer_attrs.push(FileAttr::Acl);
+                        }
                         FileAttr::AclSupport => {
                             attrs.push(FileAttrValue::AclSupport(self.attr_acl_support()));
                             answer_attrs.push(FileAttr::AclSupport);
@@ -480,11 +681,11 @@ impl FileManager {
                             answer_attrs.push(FileAttr::Fileid);
                         }
                         FileAttr::Mode => {
-                            attrs.push(FileAttrValue::Mode(self.attr_mode()));
+                            attrs.push(FileAttrValue::Mode(filehandle.attr_mode));
                             answer_attrs.push(FileAttr::Mode);
                         }
                         FileAttr::Numlinks => {
-                            attrs.push(FileAttrValue::Numlinks(self.attr_numlinks()));
+                            attrs.push(FileAttrValue::Numlinks(filehandle.attr_numlinks));
                             answer_attrs.push(FileAttr::Numlinks);
                         }
                         FileAttr::Owner => {
@@ -513,12 +714,25 @@ impl FileManager {
                             attrs.push(FileAttrValue::TimeModify(filehandle.attr_time_modify));
                             answer_attrs.push(FileAttr::TimeModify);
                         }
-                        // FileAttr::MountedOnFileid => {
-                        //     attrs.push(FileAttrValue::MountedOnFileid(
-                        //         filehandle.attr_mounted_on_fileid,
-                        //     ));
-                        //     answer_attrs.push(FileAttr::MountedOnFileid);
-                        // }
+                        // only reported if the backend records them
+                        FileAttr::TimeCreate => {
+                            if let Some(time_create) = filehandle.attr_time_create {
+                                attrs.push(FileAttrValue::TimeCreate(time_create));
+                                answer_attrs.push(FileAttr::TimeCreate);
+                            }
+                        }
+                        FileAttr::TimeBackup => {
+                            if let Some(time_backup) = filehandle.attr_time_backup {
+                                attrs.push(FileAttrValue::TimeBackup(time_backup));
+                                answer_attrs.push(FileAttr::TimeBackup);
+                            }
+                        }
+                        FileAttr::MountedOnFileid => {
+                            attrs.push(FileAttrValue::MountedOnFileid(
+                                self.attr_mounted_on_fileid(&filehandle),
+                            ));
+                            answer_attrs.push(FileAttr::MountedOnFileid);
+                        }
                         _ => {}
                     }
                 }
@@ -535,7 +749,9 @@ impl FileManager {
         // rdattr_error:
         // The server uses this to specify the behavior of the client when
         // reading attributes.  See Section 4 for additional description.
-        NfsStat4::Nfs4errInval
+        // The attributes of a filehandle are always read successfully,
+        // READDIR reports the errors of entries it can't read itself.
+        NfsStat4::Nfs4Ok
     }
 
     pub fn attr_supported_attrs(&self) -> Attrlist4<FileAttr> {
@@ -568,17 +784,34 @@ impl FileManager {
             FileAttr::OwnerGroup,
             FileAttr::SpaceUsed,
             FileAttr::TimeAccess,
+            FileAttr::TimeBackup,
+            FileAttr::TimeCreate,
             FileAttr::TimeMetadata,
             FileAttr::TimeModify,
-            // FileAttr::MountedOnFileid,
+            FileAttr::MountedOnFileid,
         ]))
     }
 
+    pub fn attr_mounted_on_fileid(&self, filehandle: &Filehandle) -> u64 {
+        // mounted_on_fileid:
+        // Like fileid, but if the target filehandle is the root of a file
+        // system, this attribute represents the fileid of the underlying
+        // directory.
+        match self.mounted_on_fileid {
+            Some(fileid) if filehandle.path == "/" => fileid,
+            _ => filehandle.attr_fileid,
+        }
+    }
+
     pub fn attr_expire_type(&self) -> u32 {
         // fh_expire_type:
         // The server uses this to specify filehandle expiration behavior to the
         // client.  See Section 4 for additional description.
-        FH4_VOLATILE_ANY
+        if self.inode_lookup || self.handle_store.is_some() {
+            FH4_PERSISTENT
+        } else {
+            FH4_VOLATILE_ANY
+        }
     }
 
     pub fn attr_link_support(&self) -> bool {
@@ -593,11 +826,11 @@ impl FileManager {
         self.symlink_support
     }
 
-    pub fn attr_named_attr(&self) -> bool {
+    pub fn attr_named_attr(&self, filehandle: &Filehandle) -> bool {
         // named_attr:
         // TRUE, if the object's has named attributes.  In other words, this
         // object has a non-empty named attribute directory.
-        false
+        filehandle.attr_named_attr
     }
 
     pub fn attr_unique_handles(&self) -> bool {
@@ -607,7 +840,7 @@ impl FileManager {
         self.unique_handles
     }
 
-    pub fn attr_acl(&self) -> bool {
+    pub fn attr_acl(&self, filehandle: &Filehandle) -> Vec<Nfsace4> {
         // acl:
         // The NFSv4.0 ACL attribute contains an array of ACEs that are
         // associated with the file system object.  Although the client can read
@@ -615,13 +848,19 @@ impl FileManager {
         // ACL to perform access control.  The client can use the OPEN or ACCESS
         // operations to check access without modifying or reading data or
         // metadata.
-        false
+        match &filehandle.attr_acl {
+            Some(acl) => acl.clone(),
+            None => acl::from_mode(
+                filehandle.attr_mode,
+                filehandle.attr_type == NfsFtype4::Nf4dir,
+            ),
+        }
     }
 
     pub fn attr_acl_support(&self) -> u32 {
         // acl_support:
         // TRUE, if the object's file system supports Access Control Lists.
-        ACL4_SUPPORT_ALLOW_ACL
+        acl::ACL_SUPPORT
     }
 
     pub fn attr_archive(&self) -> bool {
@@ -629,25 +868,13 @@ impl FileManager {
         // TRUE, if the object's file system supports the archive attribute.
         false
     }
-
-    pub fn attr_mode(&self) -> u32 {
-        // mode:
-        // The NFSv4.0 mode attribute is based on the UNIX mode bits.
-        MODE4_RUSR + MODE4_RGRP + MODE4_ROTH
-    }
-
-    pub fn attr_numlinks(&self) -> u32 {
-        // numlinks:
-        // Number of hard links to this object.
-        1
-    }
 }
 
 // FileManager is run as with the actor pattern
 // learn more: https://ryhl.io/blog/actors-with-tokio/
 async fn run_file_manager(mut actor: FileManager) {
     while let Some(msg) = actor.receiver.recv().await {
-        actor.handle_message(msg);
+        actor.handle_message(msg).await;
     }
 }
 
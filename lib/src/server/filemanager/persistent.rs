@@ -0,0 +1,398 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use bold_proto::nfs4_proto::NfsFh4;
+use tracing::debug;
+
+use crate::storage::Metadata;
+
+// https://datatracker.ietf.org/doc/html/rfc7530#section-4.2.1
+// The first byte of a filehandle tells how it was minted.
+// Volatile filehandles, only valid during the lifetime of this server instance
+pub const FH_KIND_VOLATILE: u8 = 128;
+// Persistent filehandles carrying device, inode and generation of the object
+pub const FH_KIND_INODE: u8 = 1;
+// Persistent filehandles carrying an id of the HandleStore
+pub const FH_KIND_STORED: u8 = 2;
+// The second byte of every filehandle is the id of the export it belongs to.
+pub const FH_EXPORT_BYTE: usize = 1;
+
+pub fn volatile_fh(export_id: u8, boot_time: u64, counter: u128) -> NfsFh4 {
+    let mut id = [0_u8; 26];
+    id[0] = FH_KIND_VOLATILE;
+    id[FH_EXPORT_BYTE] = export_id;
+    id[2..10].copy_from_slice(&boot_time.to_be_bytes());
+    id[10..26].copy_from_slice(&counter.to_be_bytes());
+    id
+}
+
+pub fn inode_fh(export_id: u8, meta: &Metadata) -> NfsFh4 {
+    let mut id = [0_u8; 26];
+    id[0] = FH_KIND_INODE;
+    id[FH_EXPORT_BYTE] = export_id;
+    id[2..10].copy_from_slice(&meta.dev.to_be_bytes());
+    id[10..18].copy_from_slice(&meta.ino.to_be_bytes());
+    id[18..22].copy_from_slice(&meta.generation.to_be_bytes());
+    id
+}
+
+/// Device, inode and generation of a filehandle minted by [`inode_fh`].
+pub fn parse_inode_fh(id: &NfsFh4) -> Option<(u64, u64, u32)> {
+    if id[0] != FH_KIND_INODE {
+        return None;
+    }
+    let dev = u64::from_be_bytes(id[2..10].try_into().ok()?);
+    let ino = u64::from_be_bytes(id[10..18].try_into().ok()?);
+    let generation = u32::from_be_bytes(id[18..22].try_into().ok()?);
+    Some((dev, ino, generation))
+}
+
+/// The HandleStore id under which the path of an inode filehandle is kept.
+pub fn inode_key(meta: &Metadata) -> u128 {
+    inode_key_of(meta.ino, meta.generation)
+}
+
+pub fn inode_key_of(ino: u64, generation: u32) -> u128 {
+    (ino as u128) << 32 | generation as u128
+}
+
+pub fn stored_fh(export_id: u8, handle: u128) -> NfsFh4 {
+    let mut id = [0_u8; 26];
+    id[0] = FH_KIND_STORED;
+    id[FH_EXPORT_BYTE] = export_id;
+    id[2..18].copy_from_slice(&handle.to_be_bytes());
+    id
+}
+
+/// The HandleStore id of a filehandle minted by [`stored_fh`].
+pub fn parse_stored_fh(id: &NfsFh4) -> Option<u128> {
+    if id[0] != FH_KIND_STORED {
+        return None;
+    }
+    Some(u128::from_be_bytes(id[2..18].try_into().ok()?))
+}
+
+const RECORD_ADD: u8 = b'+';
+const RECORD_REMOVE: u8 = b'-';
+// the next id the store allocates, kept when the journal is compacted so the
+// ids of removed entries are never handed out again
+const RECORD_NEXT_ID: u8 = b'#';
+
+/// On-disk map of filehandle ids to paths, so filehandles resolve after a restart.
+///
+/// Backends without stable inode numbers get ids allocated by the store, inode
+/// filehandles are kept under their [`inode_key`].
+///
+/// The map is kept as an append-only journal of add and remove records, which
+/// is compacted every time the store is opened. Changes are only kept in
+/// memory until [`HandleStore::sync`] writes them, once for all the
+/// filehandles a COMPOUND handed out.
+#[derive(Debug)]
+pub struct HandleStore {
+    path: PathBuf,
+    journal: Arc<File>,
+    // the records not written to the journal yet
+    pending: Vec<u8>,
+    // whether there are pending records, shared with the FileManagerHandle
+    unsynced: Arc<AtomicBool>,
+    by_id: HashMap<u128, String>,
+    by_path: HashMap<String, u128>,
+    next_id: u128,
+}
+
+impl HandleStore {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        fs::create_dir_all(&dir)?;
+        let mut by_id = HashMap::new();
+        let mut next_id = 1;
+        match File::open(&path) {
+            Ok(mut file) => {
+                let mut buf = Vec::new();
+                file.read_to_end(&mut buf)?;
+                let mut rest = buf.as_slice();
+                // a truncated last record is ignored, it was never acknowledged
+                while let Some((op, id, record_path, tail)) = Self::parse_record(rest) {
+                    match op {
+                        RECORD_ADD => {
+                            by_id.insert(id, record_path);
+                            next_id = next_id.max(id.saturating_add(1));
+                        }
+                        RECORD_REMOVE => {
+                            by_id.remove(&id);
+                            next_id = next_id.max(id.saturating_add(1));
+                        }
+                        _ => next_id = next_id.max(id),
+                    }
+                    rest = tail;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        // rewrite the journal with the live entries only, and the next id
+        // as the highest id may be one that was removed
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut tmp = File::create(&tmp_path)?;
+            tmp.write_all(&Self::record(RECORD_NEXT_ID, next_id, ""))?;
+            for (id, record_path) in by_id.iter() {
+                tmp.write_all(&Self::record(RECORD_ADD, *id, record_path))?;
+            }
+            tmp.sync_all()?;
+        }
+        fs::rename(&tmp_path, &path)?;
+        // the rename itself is only durable once the directory is synced
+        File::open(&dir)?.sync_all()?;
+        let journal = OpenOptions::new().append(true).open(&path)?;
+
+        let by_path = by_id.iter().map(|(id, p)| (p.clone(), *id)).collect();
+        debug!("Loaded {} filehandles from {:?}", by_id.len(), path);
+        Ok(HandleStore {
+            path,
+            journal: Arc::new(journal),
+            pending: Vec::new(),
+            unsynced: Arc::new(AtomicBool::new(false)),
+            by_id,
+            by_path,
+            next_id,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Set while there are changes [`HandleStore::sync`] has yet to write.
+    pub fn unsynced(&self) -> Arc<AtomicBool> {
+        self.unsynced.clone()
+    }
+
+    pub fn get_path(&self, id: u128) -> Option<&String> {
+        self.by_id.get(&id)
+    }
+
+    /// The id stored for `path`, a new one is allocated if there is none.
+    pub fn get_or_insert(&mut self, path: &str) -> u128 {
+        if let Some(id) = self.by_path.get(path) {
+            return *id;
+        }
+        let id = self.next_id;
+        self.append(RECORD_ADD, id, path);
+        self.next_id += 1;
+        self.by_id.insert(id, path.to_string());
+        self.by_path.insert(path.to_string(), id);
+        id
+    }
+
+    /// Store `path` under a given `id`, e.g. an [`inode_key`].
+    pub fn insert(&mut self, id: u128, path: &str) {
+        if self.by_id.get(&id).is_some_and(|p| p == path) {
+            return;
+        }
+        // the path may have been kept for an object that is gone
+        self.remove(path);
+        self.append(RECORD_ADD, id, path);
+        if let Some(old) = self.by_id.insert(id, path.to_string()) {
+            self.by_path.remove(&old);
+        }
+        self.by_path.insert(path.to_string(), id);
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        if let Some(id) = self.by_path.remove(path) {
+            self.by_id.remove(&id);
+            self.append(RECORD_REMOVE, id, path);
+        }
+    }
+
+    fn append(&mut self, op: u8, id: u128, path: &str) {
+        self.pending.extend(Self::record(op, id, path));
+        self.unsynced.store(true, Ordering::Release);
+    }
+
+    /// Write the changes made since the last sync to the journal, on a
+    /// blocking thread.
+    pub async fn sync(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+        self.unsynced.store(false, Ordering::Release);
+        let journal = self.journal.clone();
+        tokio::task::spawn_blocking(move || {
+            (&*journal).write_all(&pending)?;
+            journal.sync_data()
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+
+    fn record(op: u8, id: u128, path: &str) -> Vec<u8> {
+        let mut record = vec![op];
+        record.extend(id.to_be_bytes());
+        record.extend((path.len() as u32).to_be_bytes());
+        record.extend(path.as_bytes());
+        record
+    }
+
+    fn parse_record(buf: &[u8]) -> Option<(u8, u128, String, &[u8])> {
+        if buf.len() < 21 {
+            return None;
+        }
+        let op = buf[0];
+        if ![RECORD_ADD, RECORD_REMOVE, RECORD_NEXT_ID].contains(&op) {
+            return None;
+        }
+        let id = u128::from_be_bytes(buf[1..17].try_into().ok()?);
+        let len = u32::from_be_bytes(buf[17..21].try_into().ok()?) as usize;
+        let path = buf.get(21..21 + len)?;
+        let path = String::from_utf8(path.to_vec()).ok()?;
+        Some((op, id, path, &buf[21 + len..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::Ordering, Arc};
+
+    use bold_proto::nfs4_proto::{NfsStat4, FH4_PERSISTENT, FH4_VOLATILE_ANY};
+    use tracing_test::traced_test;
+
+    use super::{
+        inode_key_of, parse_stored_fh, stored_fh, HandleStore, FH_KIND_INODE, FH_KIND_STORED,
+    };
+    use crate::{
+        server::filemanager::FileManagerHandle,
+        storage::{MemoryBackend, PhysicalBackend},
+    };
+
+    #[tokio::test]
+    async fn test_handle_store_survives_reopen() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = tmp.path().join("handles.db");
+
+        let mut store = HandleStore::open(&db).unwrap();
+        let file1 = store.get_or_insert("/file1.txt");
+        let dir1 = store.get_or_insert("/dir1");
+        assert_ne!(file1, dir1);
+        assert_eq!(store.get_or_insert("/file1.txt"), file1);
+        store.remove("/dir1");
+        assert!(store.unsynced().load(Ordering::Acquire));
+        store.sync().await.unwrap();
+        assert!(!store.unsynced().load(Ordering::Acquire));
+        drop(store);
+
+        let mut store = HandleStore::open(&db).unwrap();
+        assert_eq!(store.get_path(file1).unwrap(), "/file1.txt");
+        assert_eq!(store.get_path(dir1), None);
+        // ids are never reused
+        let dir1_again = store.get_or_insert("/dir1");
+        assert!(dir1_again > dir1);
+        assert_eq!(parse_stored_fh(&stored_fh(0, file1)), Some(file1));
+
+        // a path kept under an inode key is replaced when a new object takes its place
+        let key = inode_key_of(42, 7);
+        store.insert(key, "/file1.txt");
+        assert_eq!(store.get_path(file1), None);
+        store.insert(inode_key_of(43, 7), "/file1.txt");
+        store.remove("/dir1");
+        store.sync().await.unwrap();
+        drop(store);
+        let store = HandleStore::open(&db).unwrap();
+        assert_eq!(store.get_path(key), None);
+        assert_eq!(store.get_path(inode_key_of(43, 7)).unwrap(), "/file1.txt");
+
+        // the ids of removed paths are not handed out again after the
+        // journal was compacted without them
+        drop(store);
+        let mut store = HandleStore::open(&db).unwrap();
+        assert!(store.get_or_insert("/dir2") > dir1_again);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_inode_filehandles_survive_restart() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("export");
+        let db = tmp.path().join("handles.db");
+        std::fs::create_dir_all(root.join("dir1")).unwrap();
+        std::fs::write(root.join("dir1/file1.txt"), b"hello").unwrap();
+
+        let fm = FileManagerHandle::new(
+            Arc::new(PhysicalBackend::new(&root)),
+            None,
+            Some(db.clone()),
+        );
+        let fh = fm
+            .get_filehandle_for_path("/dir1/file1.txt".to_string())
+            .await
+            .unwrap();
+        assert_eq!(fh.id[0], FH_KIND_INODE);
+        assert_eq!(fm.attr_expire_type(), FH4_PERSISTENT);
+        fm.sync_handles().await;
+
+        // a new filemanager knows nothing about the filehandles of the old one
+        let fm = FileManagerHandle::new(
+            Arc::new(PhysicalBackend::new(&root)),
+            None,
+            Some(db.clone()),
+        );
+        let resolved = fm.get_filehandle_for_id(fh.id).await.unwrap();
+        assert_eq!(resolved.path, "/dir1/file1.txt");
+
+        // without a filehandle db the export is not searched for the inode
+        let fm = FileManagerHandle::new(
+            Arc::new(PhysicalBackend::new(&root).with_handle_db(None)),
+            None,
+            None,
+        );
+        assert_eq!(fm.attr_expire_type(), FH4_VOLATILE_ANY);
+        assert_eq!(
+            fm.get_filehandle_for_id(fh.id).await.unwrap_err().nfs_error,
+            NfsStat4::Nfs4errStale
+        );
+
+        // a removed file stays stale after a restart
+        std::fs::remove_file(root.join("dir1/file1.txt")).unwrap();
+        let fm = FileManagerHandle::new(Arc::new(PhysicalBackend::new(&root)), None, Some(db));
+        assert!(fm.get_filehandle_for_id(fh.id).await.is_err());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_stored_filehandles_survive_restart() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = tmp.path().join("handles.db");
+        let backend = MemoryBackend::new();
+        backend.create_dir_all("/dir1").unwrap();
+        backend.write_file("/dir1/file1.txt", b"hello").unwrap();
+        let backend = Arc::new(backend);
+
+        let fm = FileManagerHandle::new(backend.clone(), None, Some(db.clone()));
+        let fh = fm
+            .get_filehandle_for_path("/dir1/file1.txt".to_string())
+            .await
+            .unwrap();
+        assert_eq!(fh.id[0], FH_KIND_STORED);
+        assert_eq!(fm.attr_expire_type(), FH4_PERSISTENT);
+        fm.sync_handles().await;
+
+        let fm = FileManagerHandle::new(backend, None, Some(db));
+        let resolved = fm.get_filehandle_for_id(fh.id).await.unwrap();
+        assert_eq!(resolved.path, "/dir1/file1.txt");
+    }
+}
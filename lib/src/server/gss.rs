@@ -0,0 +1,1164 @@
+//! RPCSEC_GSS version 1, https://datatracker.ietf.org/doc/html/rfc2203
+//!
+//! The framing of context creation, the sequence window and the three
+//! service levels (krb5, krb5i and krb5p for Kerberos) live here, the
+//! cryptography is left to a [`GssMechanism`].
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bold_proto::nfs4_proto::{Compound4res, NfsStat4};
+use bold_proto::rpc_proto::{
+    call_header_bytes, decode_gss_databody, encode_gss_databody, AcceptBody, AcceptedReply,
+    AuthStat, CallBody, OpaqueAuth, RejectedReply, ReplyBody, RpcGssArgs, RpcGssCred,
+    RpcGssInitRes, RpcGssIntegData, RpcGssPrivData, MAXSEQ, RPCSEC_GSS_CONTINUE_INIT,
+    RPCSEC_GSS_DATA, RPCSEC_GSS_DESTROY, RPCSEC_GSS_INIT, RPCSEC_GSS_VERS_1, RPC_GSS_SVC_INTEGRITY,
+    RPC_GSS_SVC_NONE, RPC_GSS_SVC_PRIVACY,
+};
+use tracing::{debug, error};
+
+use super::access::{Credentials, SecFlavor, ANON_ID};
+
+// GSS-API major status codes
+// https://datatracker.ietf.org/doc/html/rfc2744#section-3.9.1
+pub const GSS_S_COMPLETE: u32 = 0;
+pub const GSS_S_CONTINUE_NEEDED: u32 = 1;
+pub const GSS_S_BAD_SIG: u32 = 6 << 16;
+pub const GSS_S_NO_CONTEXT: u32 = 8 << 16;
+pub const GSS_S_DEFECTIVE_TOKEN: u32 = 9 << 16;
+pub const GSS_S_FAILURE: u32 = 13 << 16;
+
+// the Kerberos 5 mechanism, 1.2.840.113554.1.2.2 in DER
+// https://datatracker.ietf.org/doc/html/rfc1964#section-1
+pub const KRB5_OID: [u8; 11] = [
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02,
+];
+
+// how far sequence numbers may lag behind the highest one seen
+pub const SEQ_WINDOW: u32 = 128;
+
+// contexts kept at most, creating one more pushes out the least recently
+// used, those still being created first
+pub const MAX_CONTEXTS: usize = 4096;
+// how long a client has to finish creating a context
+pub const CONTEXT_INIT_TIMEOUT: Duration = Duration::from_secs(60);
+// how long an established context may sit unused, and live at all
+pub const CONTEXT_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+pub const CONTEXT_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GssError {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl GssError {
+    pub fn new(major: u32) -> Self {
+        GssError { major, minor: 0 }
+    }
+}
+
+impl fmt::Display for GssError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GssError: major {:#x}, minor {}", self.major, self.minor)
+    }
+}
+
+impl std::error::Error for GssError {}
+
+/// The outcome of a context token sent by a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GssAccepted {
+    // the token to send back, may be empty
+    pub token: Vec<u8>,
+    // the context is established, otherwise the client sends another token
+    pub complete: bool,
+}
+
+/// A GSS-API mechanism, e.g. Kerberos 5 through the system GSS-API library.
+///
+/// Its calls may block, e.g. on a KDC or a replay cache, they are made from
+/// the blocking threads of the runtime.
+pub trait GssMechanism: Send + Sync + fmt::Debug {
+    /// Start accepting a new security context.
+    fn accept_context(&self) -> Box<dyn GssContext>;
+
+    /// The local user of `principal`, the anonymous user if it has none.
+    fn credentials(&self, _principal: &str) -> Option<Credentials> {
+        None
+    }
+}
+
+/// A security context between the server and a client principal.
+pub trait GssContext: Send + fmt::Debug {
+    /// Process a context token of the client.
+    fn accept(&mut self, token: &[u8]) -> Result<GssAccepted, GssError>;
+
+    /// The authenticated client, once the context is established.
+    fn principal(&self) -> String;
+
+    fn get_mic(&self, message: &[u8]) -> Result<Vec<u8>, GssError>;
+
+    fn verify_mic(&self, message: &[u8], mic: &[u8]) -> Result<(), GssError>;
+
+    fn wrap(&self, message: &[u8]) -> Result<Vec<u8>, GssError>;
+
+    fn unwrap(&self, token: &[u8]) -> Result<Vec<u8>, GssError>;
+}
+
+// the sequence numbers seen within the window
+// https://datatracker.ietf.org/doc/html/rfc2203#section-5.3.3.1
+#[derive(Debug, Default)]
+struct SequenceWindow {
+    highest: Option<u32>,
+    // bit n is set if highest - n was seen
+    seen: u128,
+}
+
+impl SequenceWindow {
+    // whether seq_num was not seen before and is still inside the window
+    fn accept(&mut self, seq_num: u32) -> bool {
+        let Some(highest) = self.highest else {
+            self.highest = Some(seq_num);
+            self.seen = 1;
+            return true;
+        };
+        if seq_num > highest {
+            let shift = seq_num - highest;
+            self.seen = if shift >= SEQ_WINDOW {
+                0
+            } else {
+                self.seen << shift
+            };
+            self.seen |= 1;
+            self.highest = Some(seq_num);
+            return true;
+        }
+        let behind = highest - seq_num;
+        if behind >= SEQ_WINDOW || self.seen & (1 << behind) != 0 {
+            return false;
+        }
+        self.seen |= 1 << behind;
+        true
+    }
+}
+
+#[derive(Debug)]
+struct ContextEntry {
+    context: Box<dyn GssContext>,
+    window: SequenceWindow,
+}
+
+// A context in the table: the mechanism works on the entry with its own lock
+// held, the table is only locked to find it.
+#[derive(Debug)]
+struct ContextSlot {
+    entry: Arc<Mutex<ContextEntry>>,
+    established: bool,
+    // the end of the lifetime, or of the time to finish creating the context
+    expires: Instant,
+    // moves on every time the context is used
+    idle_until: Instant,
+}
+
+impl ContextSlot {
+    fn new(entry: Arc<Mutex<ContextEntry>>, established: bool, now: Instant) -> Self {
+        let mut slot = ContextSlot {
+            entry,
+            established: false,
+            expires: now + CONTEXT_INIT_TIMEOUT,
+            idle_until: now + CONTEXT_INIT_TIMEOUT,
+        };
+        if established {
+            slot.establish(now);
+        }
+        slot
+    }
+
+    fn establish(&mut self, now: Instant) {
+        self.established = true;
+        self.expires = now + CONTEXT_LIFETIME;
+        self.idle_until = now + CONTEXT_IDLE_TIMEOUT;
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        now >= self.expires || now >= self.idle_until
+    }
+}
+
+/// A call that passed RPCSEC_GSS, its reply is protected with [`GssContexts::seal_reply`].
+#[derive(Debug, Clone)]
+pub struct GssSession {
+    handle: Vec<u8>,
+    seq_num: u32,
+    service: u32,
+    pub principal: String,
+    pub credentials: Credentials,
+}
+
+impl GssSession {
+    /// The security flavor the call was made with.
+    pub fn flavor(&self) -> Option<SecFlavor> {
+        SecFlavor::from_gss_service(self.service)
+    }
+}
+
+#[derive(Debug)]
+pub enum GssOutcome {
+    /// answer the call with this reply, e.g. for context creation or errors
+    Reply(ReplyBody),
+    /// a replayed or outdated call, which gets no reply at all
+    Drop,
+    /// run the call, with its arguments decoded
+    Call(CallBody, GssSession),
+}
+
+/// The RPCSEC_GSS security contexts of all clients.
+#[derive(Debug, Clone)]
+pub struct GssContexts {
+    mechanism: Arc<dyn GssMechanism>,
+    contexts: Arc<Mutex<HashMap<Vec<u8>, ContextSlot>>>,
+}
+
+fn auth_error(stat: AuthStat) -> GssOutcome {
+    GssOutcome::Reply(ReplyBody::MsgDenied(RejectedReply::AuthError(stat)))
+}
+
+fn accepted(verf: OpaqueAuth, reply_data: AcceptBody) -> GssOutcome {
+    GssOutcome::Reply(ReplyBody::MsgAccepted(AcceptedReply { verf, reply_data }))
+}
+
+impl GssContexts {
+    pub fn new(mechanism: Arc<dyn GssMechanism>) -> Self {
+        GssContexts {
+            mechanism,
+            contexts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Check a call with a RPCSEC_GSS credential.
+    pub async fn accept_call(&self, xid: u32, call: CallBody) -> GssOutcome {
+        let contexts = self.clone();
+        match tokio::task::spawn_blocking(move || contexts.check_call(xid, call)).await {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                error!("couldn't check call: {}", e);
+                auth_error(AuthStat::RpcsecGssCtxproblem)
+            }
+        }
+    }
+
+    fn check_call(&self, xid: u32, call: CallBody) -> GssOutcome {
+        let cred = match &call.cred {
+            OpaqueAuth::RpcsecGss(body) => RpcGssCred::from_bytes(body),
+            _ => return auth_error(AuthStat::AuthBadCred),
+        };
+        let cred = match cred {
+            Ok(cred) if cred.version == RPCSEC_GSS_VERS_1 => cred,
+            _ => return auth_error(AuthStat::AuthBadCred),
+        };
+        match cred.gss_proc {
+            RPCSEC_GSS_INIT | RPCSEC_GSS_CONTINUE_INIT => self.create_context(&cred, call),
+            RPCSEC_GSS_DATA | RPCSEC_GSS_DESTROY => self.check_data(xid, &cred, call),
+            _ => auth_error(AuthStat::AuthBadCred),
+        }
+    }
+
+    // https://datatracker.ietf.org/doc/html/rfc2203#section-5.2.2
+    fn create_context(&self, cred: &RpcGssCred, call: CallBody) -> GssOutcome {
+        let token = match call.gss_args {
+            Some(RpcGssArgs::Init(token)) if call.proc == 0 => token,
+            _ => return accepted(OpaqueAuth::AuthNull(Vec::new()), AcceptBody::GarbageArgs),
+        };
+        let now = Instant::now();
+        let (handle, entry) = if cred.gss_proc == RPCSEC_GSS_INIT {
+            let entry = ContextEntry {
+                context: self.mechanism.accept_context(),
+                window: SequenceWindow::default(),
+            };
+            (None, Arc::new(Mutex::new(entry)))
+        } else {
+            let mut contexts = self.contexts.lock().unwrap();
+            match contexts.get(&cred.handle) {
+                Some(slot) if !slot.established && !slot.expired(now) => {
+                    (Some(cred.handle.clone()), slot.entry.clone())
+                }
+                Some(slot) if !slot.established => {
+                    contexts.remove(&cred.handle);
+                    return auth_error(AuthStat::RpcsecGssCtxproblem);
+                }
+                _ => return auth_error(AuthStat::RpcsecGssCredproblem),
+            }
+        };
+
+        // the mechanism may take its time, other contexts stay usable meanwhile
+        let mut locked = entry.lock().unwrap();
+        let accepted_token = locked.context.accept(&token).and_then(|accepted| {
+            // the client checks the window with the verifier
+            let mic = if accepted.complete {
+                Some(locked.context.get_mic(&SEQ_WINDOW.to_be_bytes())?)
+            } else {
+                None
+            };
+            Ok((accepted, mic))
+        });
+        let principal = locked.context.principal();
+        drop(locked);
+
+        match accepted_token {
+            Ok((GssAccepted { token, complete }, mic)) => {
+                let handle = match handle {
+                    Some(handle) => {
+                        let mut contexts = self.contexts.lock().unwrap();
+                        let Some(slot) = contexts.get_mut(&handle) else {
+                            // pushed out while the mechanism was busy
+                            return auth_error(AuthStat::RpcsecGssCtxproblem);
+                        };
+                        if complete {
+                            slot.establish(now);
+                        }
+                        handle
+                    }
+                    None => self.insert(ContextSlot::new(entry, complete, now)),
+                };
+                let mut res = RpcGssInitRes {
+                    handle,
+                    gss_major: GSS_S_CONTINUE_NEEDED,
+                    gss_token: token,
+                    ..Default::default()
+                };
+                let mut verf = OpaqueAuth::AuthNull(Vec::new());
+                if let Some(mic) = mic {
+                    res.gss_major = GSS_S_COMPLETE;
+                    res.seq_window = SEQ_WINDOW;
+                    verf = OpaqueAuth::RpcsecGss(mic);
+                    debug!("Established context for {}", principal);
+                }
+                accepted(verf, AcceptBody::SuccessGssInit(res))
+            }
+            Err(e) => {
+                debug!("Context creation failed: {}", e);
+                if let Some(handle) = handle {
+                    self.contexts.lock().unwrap().remove(&handle);
+                }
+                accepted(
+                    OpaqueAuth::AuthNull(Vec::new()),
+                    AcceptBody::SuccessGssInit(RpcGssInitRes {
+                        gss_major: e.major,
+                        gss_minor: e.minor,
+                        ..Default::default()
+                    }),
+                )
+            }
+        }
+    }
+
+    // add a new context under a random handle, making room for it first
+    fn insert(&self, slot: ContextSlot) -> Vec<u8> {
+        let mut contexts = self.contexts.lock().unwrap();
+        if contexts.len() >= MAX_CONTEXTS {
+            let now = Instant::now();
+            contexts.retain(|_, slot| !slot.expired(now));
+        }
+        if contexts.len() >= MAX_CONTEXTS {
+            let victim = contexts
+                .iter()
+                .min_by_key(|(_, slot)| (slot.established, slot.idle_until))
+                .map(|(handle, _)| handle.clone());
+            if let Some(victim) = victim {
+                debug!("Too many contexts, dropping {:?}", victim);
+                contexts.remove(&victim);
+            }
+        }
+        let mut handle = rand::random::<[u8; 8]>().to_vec();
+        while contexts.contains_key(&handle) {
+            handle = rand::random::<[u8; 8]>().to_vec();
+        }
+        contexts.insert(handle.clone(), slot);
+        handle
+    }
+
+    // the established context of handle, expired ones are removed
+    fn established(&self, handle: &[u8]) -> Result<Arc<Mutex<ContextEntry>>, AuthStat> {
+        let now = Instant::now();
+        let mut contexts = self.contexts.lock().unwrap();
+        match contexts.get(handle) {
+            Some(slot) if slot.established && slot.expired(now) => {
+                debug!("Context {:?} expired", handle);
+                contexts.remove(handle);
+                Err(AuthStat::RpcsecGssCtxproblem)
+            }
+            Some(slot) if slot.established => Ok(slot.entry.clone()),
+            _ => Err(AuthStat::RpcsecGssCredproblem),
+        }
+    }
+
+    // https://datatracker.ietf.org/doc/html/rfc2203#section-5.3.3
+    fn check_data(&self, xid: u32, cred: &RpcGssCred, mut call: CallBody) -> GssOutcome {
+        let entry = match self.established(&cred.handle) {
+            Ok(entry) => entry,
+            Err(stat) => return auth_error(stat),
+        };
+        let mut entry = entry.lock().unwrap();
+        if cred.seq_num >= MAXSEQ {
+            return auth_error(AuthStat::RpcsecGssCtxproblem);
+        }
+        // the verifier signs the header of the call
+        let verified = match (&call.verf, call_header_bytes(xid, &call)) {
+            (OpaqueAuth::RpcsecGss(mic), Ok(header)) => {
+                entry.context.verify_mic(&header, mic).is_ok()
+            }
+            _ => false,
+        };
+        if !verified {
+            return auth_error(AuthStat::RpcsecGssCredproblem);
+        }
+        if !entry.window.accept(cred.seq_num) {
+            debug!("Dropping replayed call {}", cred.seq_num);
+            return GssOutcome::Drop;
+        }
+
+        if cred.gss_proc == RPCSEC_GSS_DESTROY {
+            let mic = entry.context.get_mic(&cred.seq_num.to_be_bytes());
+            self.contexts.lock().unwrap().remove(&cred.handle);
+            return match mic {
+                Ok(mic) => accepted(
+                    OpaqueAuth::RpcsecGss(mic),
+                    AcceptBody::Success(Compound4res {
+                        status: NfsStat4::Nfs4Ok,
+                        tag: "".to_string(),
+                        resarray: Vec::new(),
+                    }),
+                ),
+                Err(_) => auth_error(AuthStat::RpcsecGssCtxproblem),
+            };
+        }
+
+        // unprotect the arguments, which must carry the sequence number of the credential
+        let databody = match (cred.service, call.gss_args.take()) {
+            (RPC_GSS_SVC_NONE, None) => None,
+            (RPC_GSS_SVC_INTEGRITY, Some(RpcGssArgs::Integrity(data))) => {
+                if entry
+                    .context
+                    .verify_mic(&data.databody_integ, &data.checksum)
+                    .is_err()
+                {
+                    return accepted(OpaqueAuth::AuthNull(Vec::new()), AcceptBody::GarbageArgs);
+                }
+                Some(data.databody_integ)
+            }
+            (RPC_GSS_SVC_PRIVACY, Some(RpcGssArgs::Privacy(data))) => {
+                match entry.context.unwrap(&data.databody_priv) {
+                    Ok(databody) => Some(databody),
+                    Err(_) => {
+                        return accepted(OpaqueAuth::AuthNull(Vec::new()), AcceptBody::GarbageArgs)
+                    }
+                }
+            }
+            // NULL calls carry no arguments to protect
+            (RPC_GSS_SVC_INTEGRITY | RPC_GSS_SVC_PRIVACY, None) if call.proc == 0 => None,
+            _ => return auth_error(AuthStat::AuthBadCred),
+        };
+        if let Some(databody) = databody {
+            match decode_gss_databody(&databody) {
+                Ok((seq_num, args)) if seq_num == cred.seq_num => call.args = Some(args),
+                _ => return accepted(OpaqueAuth::AuthNull(Vec::new()), AcceptBody::GarbageArgs),
+            }
+        }
+
+        if let Some(slot) = self.contexts.lock().unwrap().get_mut(&cred.handle) {
+            slot.idle_until = Instant::now() + CONTEXT_IDLE_TIMEOUT;
+        }
+        let principal = entry.context.principal();
+        drop(entry);
+        let credentials = self
+            .mechanism
+            .credentials(&principal)
+            .unwrap_or(Credentials {
+                uid: ANON_ID,
+                gid: ANON_ID,
+                gids: Vec::new(),
+            });
+        GssOutcome::Call(
+            call,
+            GssSession {
+                handle: cred.handle.clone(),
+                seq_num: cred.seq_num,
+                service: cred.service,
+                principal,
+                credentials,
+            },
+        )
+    }
+
+    /// Sign the reply to a call of `session` and protect its results with the
+    /// service of the call.
+    pub async fn seal_reply(&self, session: &GssSession, body: ReplyBody) -> ReplyBody {
+        let contexts = self.clone();
+        let session = session.clone();
+        match tokio::task::spawn_blocking(move || contexts.seal(&session, body)).await {
+            Ok(body) => body,
+            Err(e) => {
+                error!("couldn't protect reply: {}", e);
+                ReplyBody::MsgDenied(RejectedReply::AuthError(AuthStat::RpcsecGssCtxproblem))
+            }
+        }
+    }
+
+    fn seal(&self, session: &GssSession, body: ReplyBody) -> ReplyBody {
+        let ReplyBody::MsgAccepted(reply) = body else {
+            return body;
+        };
+        let Ok(entry) = self.established(&session.handle) else {
+            return ReplyBody::MsgDenied(RejectedReply::AuthError(AuthStat::RpcsecGssCtxproblem));
+        };
+        let entry = entry.lock().unwrap();
+        let context = &entry.context;
+        let sealed = || -> Result<AcceptedReply, GssError> {
+            let verf = OpaqueAuth::RpcsecGss(context.get_mic(&session.seq_num.to_be_bytes())?);
+            let reply_data = match (session.service, reply.reply_data) {
+                (RPC_GSS_SVC_INTEGRITY, AcceptBody::Success(res)) => {
+                    let databody_integ = encode_gss_databody(session.seq_num, &res)
+                        .map_err(|_| GssError::new(GSS_S_FAILURE))?;
+                    AcceptBody::SuccessIntegrity(RpcGssIntegData {
+                        checksum: context.get_mic(&databody_integ)?,
+                        databody_integ,
+                    })
+                }
+                (RPC_GSS_SVC_PRIVACY, AcceptBody::Success(res)) => {
+                    let databody = encode_gss_databody(session.seq_num, &res)
+                        .map_err(|_| GssError::new(GSS_S_FAILURE))?;
+                    AcceptBody::SuccessPrivacy(RpcGssPrivData {
+                        databody_priv: context.wrap(&databody)?,
+                    })
+                }
+                (_, reply_data) => reply_data,
+            };
+            Ok(AcceptedReply { verf, reply_data })
+        };
+        match sealed() {
+            Ok(reply) => ReplyBody::MsgAccepted(reply),
+            Err(e) => {
+                error!("couldn't protect reply: {}", e);
+                ReplyBody::MsgDenied(RejectedReply::AuthError(AuthStat::RpcsecGssCtxproblem))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use bold_proto::{nfs4_proto::*, rpc_proto::*};
+    use tracing_test::traced_test;
+
+    use super::{
+        Credentials, GssAccepted, GssContext, GssContexts, GssError, GssMechanism, SequenceWindow,
+        GSS_S_BAD_SIG, GSS_S_COMPLETE, GSS_S_CONTINUE_NEEDED, GSS_S_DEFECTIVE_TOKEN, MAX_CONTEXTS,
+        SEQ_WINDOW,
+    };
+    use crate::{
+        server::{
+            clientmanager::ClientManagerHandle, exports::Exports, filemanager::FileManagerHandle,
+            nfs40::NFS40Server, request::NfsRequest, NFSService, NfsProtoImpl,
+        },
+        storage::MemoryBackend,
+    };
+
+    // A mechanism for tests: the client sends "hello" and then its principal,
+    // checksums are a sum over the message and wrapping flips the bits. A
+    // "slow" token takes its time like a mechanism waiting for a KDC.
+    #[derive(Debug)]
+    struct MockMechanism;
+
+    #[derive(Debug, Default)]
+    struct MockContext {
+        greeted: bool,
+        principal: Option<String>,
+    }
+
+    fn checksum(message: &[u8]) -> Vec<u8> {
+        let sum = message
+            .iter()
+            .fold(7_u32, |sum, b| sum.wrapping_mul(31).wrapping_add(*b as u32));
+        sum.to_be_bytes().to_vec()
+    }
+
+    impl GssMechanism for MockMechanism {
+        fn accept_context(&self) -> Box<dyn GssContext> {
+            Box::<MockContext>::default()
+        }
+
+        fn credentials(&self, principal: &str) -> Option<Credentials> {
+            (principal == "alice@EXAMPLE.COM").then(|| Credentials {
+                uid: 1000,
+                gid: 1000,
+                gids: Vec::new(),
+            })
+        }
+    }
+
+    impl GssContext for MockContext {
+        fn accept(&mut self, token: &[u8]) -> Result<GssAccepted, GssError> {
+            if token == b"slow" {
+                std::thread::sleep(Duration::from_millis(500));
+            }
+            if !self.greeted {
+                if token != b"hello" {
+                    return Err(GssError::new(GSS_S_DEFECTIVE_TOKEN));
+                }
+                self.greeted = true;
+                return Ok(GssAccepted {
+                    token: b"who".to_vec(),
+                    complete: false,
+                });
+            }
+            self.principal = Some(String::from_utf8_lossy(token).to_string());
+            Ok(GssAccepted {
+                token: Vec::new(),
+                complete: true,
+            })
+        }
+
+        fn principal(&self) -> String {
+            self.principal.clone().unwrap_or_default()
+        }
+
+        fn get_mic(&self, message: &[u8]) -> Result<Vec<u8>, GssError> {
+            Ok(checksum(message))
+        }
+
+        fn verify_mic(&self, message: &[u8], mic: &[u8]) -> Result<(), GssError> {
+            if checksum(message) == mic {
+                Ok(())
+            } else {
+                Err(GssError::new(GSS_S_BAD_SIG))
+            }
+        }
+
+        fn wrap(&self, message: &[u8]) -> Result<Vec<u8>, GssError> {
+            Ok(message.iter().map(|b| !b).collect())
+        }
+
+        fn unwrap(&self, token: &[u8]) -> Result<Vec<u8>, GssError> {
+            Ok(token.iter().map(|b| !b).collect())
+        }
+    }
+
+    fn gss_cred(gss_proc: u32, seq_num: u32, service: u32, handle: &[u8]) -> OpaqueAuth {
+        let cred = RpcGssCred {
+            version: RPCSEC_GSS_VERS_1,
+            gss_proc,
+            seq_num,
+            service,
+            handle: handle.to_vec(),
+        };
+        OpaqueAuth::RpcsecGss(cred.to_bytes().unwrap())
+    }
+
+    fn compound_args(argarray: Vec<NfsArgOp>) -> Compound4args {
+        Compound4args {
+            tag: "".to_string(),
+            minor_version: 0,
+            argarray,
+        }
+    }
+
+    struct Client {
+        service: NFSService<NFS40Server>,
+        gss: GssContexts,
+        exports: Exports,
+        client_manager: ClientManagerHandle,
+        xid: u32,
+    }
+
+    impl Client {
+        fn new() -> Self {
+            let file_manager = FileManagerHandle::new(Arc::new(MemoryBackend::new()), None, None);
+            let gss = GssContexts::new(Arc::new(MockMechanism));
+            Client {
+                service: NFSService::new(NFS40Server::new()).with_gss(Some(gss.clone())),
+                gss,
+                exports: Exports::single(file_manager),
+                client_manager: ClientManagerHandle::new(),
+                xid: 0,
+            }
+        }
+
+        async fn send(&mut self, mut call: CallBody) -> Option<ReplyBody> {
+            self.xid += 1;
+            // sign the header of calls on an established context
+            if let OpaqueAuth::RpcsecGss(body) = &call.cred {
+                let gss_proc = RpcGssCred::from_bytes(body).unwrap().gss_proc;
+                if gss_proc == RPCSEC_GSS_DATA || gss_proc == RPCSEC_GSS_DESTROY {
+                    let header = call_header_bytes(self.xid, &call).unwrap();
+                    call.verf = OpaqueAuth::RpcsecGss(checksum(&header));
+                }
+            }
+            let request = NfsRequest::new(
+                "127.0.0.1:700".to_string(),
+                self.client_manager.clone(),
+                self.exports.clone(),
+                0,
+                None,
+            );
+            let msg = RpcCallMsg {
+                xid: self.xid,
+                body: MsgType::Call(call),
+            };
+            let reply = self.service.call(msg, request).await?;
+            assert_eq!(reply.xid, self.xid);
+            match reply.body {
+                MsgType::Reply(body) => Some(body),
+                _ => panic!("not a reply"),
+            }
+        }
+
+        async fn init(&mut self, gss_proc: u32, handle: &[u8], token: &[u8]) -> ReplyBody {
+            self.send(CallBody {
+                rpcvers: 2,
+                prog: 100003,
+                vers: 4,
+                proc: 0,
+                cred: gss_cred(gss_proc, 0, RPC_GSS_SVC_NONE, handle),
+                verf: OpaqueAuth::AuthNull(Vec::new()),
+                args: None,
+                gss_args: Some(RpcGssArgs::Init(token.to_vec())),
+            })
+            .await
+            .unwrap()
+        }
+
+        async fn establish(&mut self) -> Vec<u8> {
+            let ReplyBody::MsgAccepted(AcceptedReply {
+                reply_data: AcceptBody::SuccessGssInit(res),
+                ..
+            }) = self.init(RPCSEC_GSS_INIT, &[], b"hello").await
+            else {
+                panic!("context creation failed");
+            };
+            assert_eq!(res.gss_major, GSS_S_CONTINUE_NEEDED);
+            assert_eq!(res.gss_token, b"who");
+            let handle = res.handle;
+
+            let ReplyBody::MsgAccepted(AcceptedReply {
+                verf,
+                reply_data: AcceptBody::SuccessGssInit(res),
+            }) = self
+                .init(RPCSEC_GSS_CONTINUE_INIT, &handle, b"alice@EXAMPLE.COM")
+                .await
+            else {
+                panic!("context creation failed");
+            };
+            assert_eq!(res.gss_major, GSS_S_COMPLETE);
+            assert_eq!(res.handle, handle);
+            assert_eq!(res.seq_window, SEQ_WINDOW);
+            assert_eq!(
+                verf,
+                OpaqueAuth::RpcsecGss(checksum(&SEQ_WINDOW.to_be_bytes()))
+            );
+            handle
+        }
+
+        async fn compound(
+            &mut self,
+            handle: &[u8],
+            seq_num: u32,
+            service: u32,
+            argarray: Vec<NfsArgOp>,
+        ) -> Option<ReplyBody> {
+            let args = compound_args(argarray);
+            let databody = serde_xdr::to_bytes(&(seq_num, &args)).unwrap();
+            let (args, gss_args) = match service {
+                RPC_GSS_SVC_INTEGRITY => (
+                    None,
+                    Some(RpcGssArgs::Integrity(RpcGssIntegData {
+                        checksum: checksum(&databody),
+                        databody_integ: databody,
+                    })),
+                ),
+                RPC_GSS_SVC_PRIVACY => (
+                    None,
+                    Some(RpcGssArgs::Privacy(RpcGssPrivData {
+                        databody_priv: databody.iter().map(|b| !b).collect(),
+                    })),
+                ),
+                _ => (Some(args), None),
+            };
+            self.send(CallBody {
+                rpcvers: 2,
+                prog: 100003,
+                vers: 4,
+                proc: 1,
+                cred: gss_cred(RPCSEC_GSS_DATA, seq_num, service, handle),
+                verf: OpaqueAuth::AuthNull(Vec::new()),
+                args,
+                gss_args,
+            })
+            .await
+        }
+    }
+
+    fn getfh_root() -> Vec<NfsArgOp> {
+        vec![NfsArgOp::Opputrootfh(()), NfsArgOp::Opgetfh(())]
+    }
+
+    // the results of a reply, after checking the verifier signs seq_num
+    fn results(reply: Option<ReplyBody>, seq_num: u32) -> Compound4res {
+        let Some(ReplyBody::MsgAccepted(AcceptedReply { verf, reply_data })) = reply else {
+            panic!("call not accepted: {:?}", reply);
+        };
+        assert_eq!(
+            verf,
+            OpaqueAuth::RpcsecGss(checksum(&seq_num.to_be_bytes()))
+        );
+        let databody = match reply_data {
+            AcceptBody::Success(res) => return res,
+            AcceptBody::SuccessIntegrity(data) => {
+                assert_eq!(checksum(&data.databody_integ), data.checksum);
+                data.databody_integ
+            }
+            AcceptBody::SuccessPrivacy(data) => data.databody_priv.iter().map(|b| !b).collect(),
+            reply_data => panic!("unexpected reply {:?}", reply_data),
+        };
+        let (seq, res): (u32, Compound4res) = serde_xdr::from_bytes(&databody).unwrap();
+        assert_eq!(seq, seq_num);
+        res
+    }
+
+    #[test]
+    fn test_sequence_window() {
+        let mut window = SequenceWindow::default();
+        assert!(window.accept(10));
+        assert!(!window.accept(10));
+        assert!(window.accept(8));
+        assert!(window.accept(12));
+        assert!(!window.accept(8));
+        assert!(window.accept(9));
+        assert!(window.accept(12 + SEQ_WINDOW - 1));
+        // 12 is still inside the window, 11 fell out of it
+        assert!(!window.accept(12));
+        assert!(!window.accept(11));
+        assert!(window.accept(1000));
+        assert!(!window.accept(1000 - SEQ_WINDOW));
+        assert!(window.accept(1000 - SEQ_WINDOW + 1));
+    }
+
+    #[test]
+    fn test_wire_format() {
+        let cred = RpcGssCred {
+            version: RPCSEC_GSS_VERS_1,
+            gss_proc: RPCSEC_GSS_INIT,
+            seq_num: 0,
+            service: RPC_GSS_SVC_NONE,
+            handle: Vec::new(),
+        }
+        .to_bytes()
+        .unwrap();
+        let mut bytes = Vec::new();
+        for word in [9_u32, 0, 2, 100003, 4, 0, 6, cred.len() as u32] {
+            bytes.extend(word.to_be_bytes());
+        }
+        bytes.extend(&cred);
+        for word in [0_u32, 0, 5] {
+            bytes.extend(word.to_be_bytes());
+        }
+        bytes.extend(b"hello\0\0\0");
+        let msg = RpcCallMsg::from_bytes(bytes).unwrap();
+        let MsgType::Call(call) = msg.body else {
+            panic!("not a call");
+        };
+        assert!(call.args.is_none());
+        assert!(matches!(call.gss_args, Some(RpcGssArgs::Init(token)) if token == b"hello"));
+
+        let reply = RpcReplyMsg {
+            xid: 9,
+            body: MsgType::Reply(ReplyBody::MsgAccepted(AcceptedReply {
+                verf: OpaqueAuth::RpcsecGss(vec![1, 2, 3, 4]),
+                reply_data: AcceptBody::SuccessGssInit(RpcGssInitRes {
+                    handle: vec![7; 8],
+                    gss_major: GSS_S_COMPLETE,
+                    gss_minor: 0,
+                    seq_window: SEQ_WINDOW,
+                    gss_token: Vec::new(),
+                }),
+            })),
+        };
+        let mut expected = Vec::new();
+        for word in [9_u32, 1, 0, 6, 4, 0x01020304, 0, 8] {
+            expected.extend(word.to_be_bytes());
+        }
+        expected.extend([7; 8]);
+        for word in [GSS_S_COMPLETE, 0, SEQ_WINDOW, 0] {
+            expected.extend(word.to_be_bytes());
+        }
+        assert_eq!(reply.to_bytes().unwrap(), expected);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_context_creation() {
+        let mut client = Client::new();
+        client.establish().await;
+
+        // a bad token fails with the GSS error of the mechanism
+        let ReplyBody::MsgAccepted(AcceptedReply {
+            reply_data: AcceptBody::SuccessGssInit(res),
+            ..
+        }) = client.init(RPCSEC_GSS_INIT, &[], b"hi").await
+        else {
+            panic!("context creation failed");
+        };
+        assert_eq!(res.gss_major, GSS_S_DEFECTIVE_TOKEN);
+        assert!(res.handle.is_empty());
+
+        // continuing an unknown context
+        assert!(matches!(
+            client
+                .init(RPCSEC_GSS_CONTINUE_INIT, b"unknown", b"bob")
+                .await,
+            ReplyBody::MsgDenied(RejectedReply::AuthError(AuthStat::RpcsecGssCredproblem))
+        ));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_context_limits() {
+        let mut client = Client::new();
+        let handle = client.establish().await;
+        // contexts that were never finished fill the table
+        for _ in 0..MAX_CONTEXTS {
+            client.init(RPCSEC_GSS_INIT, &[], b"hello").await;
+        }
+        let len = || client.gss.contexts.lock().unwrap().len();
+        assert_eq!(len(), MAX_CONTEXTS);
+        let reply = client
+            .compound(&handle, 1, RPC_GSS_SVC_NONE, getfh_root())
+            .await;
+        assert_eq!(results(reply, 1).status, NfsStat4::Nfs4Ok);
+
+        // an unfinished context expires
+        let pending = {
+            let mut contexts = client.gss.contexts.lock().unwrap();
+            let (pending, slot) = contexts
+                .iter_mut()
+                .find(|(_, slot)| !slot.established)
+                .unwrap();
+            slot.expires = Instant::now();
+            pending.clone()
+        };
+        assert!(matches!(
+            client
+                .init(RPCSEC_GSS_CONTINUE_INIT, &pending, b"bob")
+                .await,
+            ReplyBody::MsgDenied(RejectedReply::AuthError(AuthStat::RpcsecGssCtxproblem))
+        ));
+
+        // so does an idle one
+        let idle = |handle: &[u8]| {
+            let mut contexts = client.gss.contexts.lock().unwrap();
+            contexts.get_mut(handle).unwrap().idle_until = Instant::now();
+        };
+        idle(&handle);
+        assert!(matches!(
+            client
+                .compound(&handle, 2, RPC_GSS_SVC_NONE, getfh_root())
+                .await,
+            Some(ReplyBody::MsgDenied(RejectedReply::AuthError(
+                AuthStat::RpcsecGssCtxproblem
+            )))
+        ));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_service_levels() {
+        let mut client = Client::new();
+        let handle = client.establish().await;
+
+        for (seq_num, service) in [
+            (1, RPC_GSS_SVC_NONE),
+            (2, RPC_GSS_SVC_INTEGRITY),
+            (3, RPC_GSS_SVC_PRIVACY),
+        ] {
+            let reply = client
+                .compound(&handle, seq_num, service, getfh_root())
+                .await;
+            if service == RPC_GSS_SVC_PRIVACY {
+                // the results are not readable without the context
+                let Some(ReplyBody::MsgAccepted(AcceptedReply {
+                    reply_data: AcceptBody::SuccessPrivacy(data),
+                    ..
+                })) = &reply
+                else {
+                    panic!("results not wrapped");
+                };
+                assert!(
+                    serde_xdr::from_bytes::<_, (u32, Compound4res)>(&data.databody_priv)
+                        .map_or(true, |(seq, _)| seq != seq_num)
+                );
+            }
+            let res = results(reply, seq_num);
+            assert_eq!(res.status, NfsStat4::Nfs4Ok);
+            assert_eq!(res.resarray.len(), 2);
+        }
+
+        // arguments with a bad checksum
+        let args = compound_args(getfh_root());
+        let databody_integ = serde_xdr::to_bytes(&(4_u32, &args)).unwrap();
+        let reply = client
+            .send(CallBody {
+                rpcvers: 2,
+                prog: 100003,
+                vers: 4,
+                proc: 1,
+                cred: gss_cred(RPCSEC_GSS_DATA, 4, RPC_GSS_SVC_INTEGRITY, &handle),
+                verf: OpaqueAuth::AuthNull(Vec::new()),
+                args: None,
+                gss_args: Some(RpcGssArgs::Integrity(RpcGssIntegData {
+                    databody_integ,
+                    checksum: vec![0; 4],
+                })),
+            })
+            .await;
+        assert!(matches!(
+            reply,
+            Some(ReplyBody::MsgAccepted(AcceptedReply {
+                reply_data: AcceptBody::GarbageArgs,
+                ..
+            }))
+        ));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_replays_and_bad_contexts() {
+        let mut client = Client::new();
+        let handle = client.establish().await;
+
+        let reply = client
+            .compound(&handle, 5, RPC_GSS_SVC_NONE, getfh_root())
+            .await;
+        assert_eq!(results(reply, 5).status, NfsStat4::Nfs4Ok);
+        // a replayed sequence number gets no reply
+        assert!(client
+            .compound(&handle, 5, RPC_GSS_SVC_NONE, getfh_root())
+            .await
+            .is_none());
+        // an unknown handle
+        assert!(matches!(
+            client
+                .compound(b"unknown", 6, RPC_GSS_SVC_NONE, getfh_root())
+                .await,
+            Some(ReplyBody::MsgDenied(RejectedReply::AuthError(
+                AuthStat::RpcsecGssCredproblem
+            )))
+        ));
+
+        // the context is gone after RPCSEC_GSS_DESTROY
+        let reply = client
+            .send(CallBody {
+                rpcvers: 2,
+                prog: 100003,
+                vers: 4,
+                proc: 0,
+                cred: gss_cred(RPCSEC_GSS_DESTROY, 7, RPC_GSS_SVC_NONE, &handle),
+                verf: OpaqueAuth::AuthNull(Vec::new()),
+                args: None,
+                gss_args: None,
+            })
+            .await;
+        assert_eq!(results(reply, 7).status, NfsStat4::Nfs4Ok);
+        assert!(matches!(
+            client
+                .compound(&handle, 8, RPC_GSS_SVC_NONE, getfh_root())
+                .await,
+            Some(ReplyBody::MsgDenied(RejectedReply::AuthError(
+                AuthStat::RpcsecGssCredproblem
+            )))
+        ));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_principal_of_client() {
+        let mut client = Client::new();
+        let handle = client.establish().await;
+        let setclientid = |verifier| {
+            NfsArgOp::Opsetclientid(crate::test_utils::create_client(
+                verifier,
+                "Linux NFSv4.0 host".to_string(),
+            ))
+        };
+
+        let res = results(
+            client
+                .compound(&handle, 1, RPC_GSS_SVC_NONE, vec![setclientid([1; 8])])
+                .await,
+            1,
+        );
+        let Some(NfsResOp4::Opsetclientid(SetClientId4res::Resok4(resok))) = res.resarray.first()
+        else {
+            panic!("SETCLIENTID failed: {:?}", res);
+        };
+        let confirm = NfsArgOp::OpsetclientidConfirm(SetClientIdConfirm4args {
+            clientid: resok.clientid,
+            setclientid_confirm: resok.setclientid_confirm,
+        });
+        let res = results(
+            client
+                .compound(&handle, 2, RPC_GSS_SVC_NONE, vec![confirm])
+                .await,
+            2,
+        );
+        assert_eq!(res.status, NfsStat4::Nfs4Ok);
+
+        // the client id is bound to alice, a client with AUTH_SYS can not take it over
+        let reply = client
+            .send(CallBody {
+                rpcvers: 2,
+                prog: 100003,
+                vers: 4,
+                proc: 1,
+                cred: OpaqueAuth::AuthUnix(AuthUnix::default()),
+                verf: OpaqueAuth::AuthNull(Vec::new()),
+                args: Some(compound_args(vec![setclientid([2; 8])])),
+                gss_args: None,
+            })
+            .await;
+        let Some(ReplyBody::MsgAccepted(AcceptedReply {
+            reply_data: AcceptBody::Success(res),
+            ..
+        })) = reply
+        else {
+            panic!("call not accepted");
+        };
+        assert_eq!(res.status, NfsStat4::Nfs4errClidInuse);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_slow_mechanism() {
+        // the runtime goes on while the mechanism works
+        let mut client = Client::new();
+        let others = async {
+            for _ in 0..5 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::select! {
+            reply = client.init(RPCSEC_GSS_INIT, &[], b"slow") => {
+                panic!("other tasks held up until {:?}", reply)
+            }
+            _ = others => {}
+        }
+    }
+}
@@ -1,16 +1,31 @@
+pub mod access;
+pub mod acl;
 pub mod clientmanager;
+pub mod exports;
 pub mod filemanager;
+pub mod gss;
 pub mod nfs40;
 pub mod operation;
 pub mod request;
 pub mod response;
+pub mod rpcbind;
+pub mod transport;
 
 use async_trait::async_trait;
 
+use access::{ClientAccess, Credentials, Peer, SecFlavor};
+use gss::{GssContexts, GssOutcome};
 use request::NfsRequest;
-use tracing::debug;
+use tracing::{debug, error};
 
-use bold_proto::rpc_proto::{CallBody, MsgType, ReplyBody, RpcCallMsg, RpcReplyMsg};
+use bold_proto::{
+    nfs4_proto::{Compound4res, NfsStat4},
+    rpc_proto::{
+        AcceptBody, AcceptedReply, AuthStat, CallBody, MismatchInfo, MsgType, OpaqueAuth,
+        RejectedReply, ReplyBody, RpcCallMsg, RpcReplyMsg, NFS4_PROGRAM, NFSPROC4_COMPOUND,
+        NFSPROC4_NULL, NFS_V4, RPC_VERSION,
+    },
+};
 
 #[async_trait]
 pub trait NfsProtoImpl: Sync {
@@ -36,6 +51,8 @@ pub trait NfsProtoImpl: Sync {
 #[derive(Debug, Clone)]
 pub struct NFSService<Proto> {
     server: Proto,
+    // RPCSEC_GSS contexts, None if RPCSEC_GSS is not offered
+    gss: Option<GssContexts>,
 }
 
 impl<Proto> NFSService<Proto>
@@ -43,39 +60,378 @@ where
     Proto: NfsProtoImpl,
 {
     pub fn new(protocol: Proto) -> Self {
-        NFSService { server: protocol }
+        NFSService {
+            server: protocol,
+            gss: None,
+        }
+    }
+
+    pub fn with_gss(mut self, gss: Option<GssContexts>) -> Self {
+        self.gss = gss;
+        self
     }
 
+    /// Answer a call, `None` if the call is dropped without a reply.
     pub async fn call(
         &self,
         rpc_call_message: RpcCallMsg,
-        request: NfsRequest<'_>,
-    ) -> Box<RpcReplyMsg> {
+        mut request: NfsRequest<'_>,
+    ) -> Option<Box<RpcReplyMsg>> {
         debug!("{:?}", rpc_call_message);
 
+        let xid = rpc_call_message.xid;
+        let reply = |body: ReplyBody| {
+            let rpc_reply_message = RpcReplyMsg {
+                xid,
+                body: MsgType::Reply(body),
+            };
+            debug!("{:?}", rpc_reply_message);
+            Some(Box::new(rpc_reply_message))
+        };
         match rpc_call_message.body {
-            MsgType::Call(call_body) => {
-                // TODO: check nfs protocol version
-                let (request, body) = match call_body.proc {
-                    0 => self.server.null(call_body, request).await,
-                    1 => self.server.compound(call_body, request).await,
-                    _ => {
-                        todo!("Invalid procedure")
+            // https://datatracker.ietf.org/doc/html/rfc5531#section-9
+            // the call can't be understood in any other version of RPC
+            MsgType::Call(call_body) if call_body.rpcvers != RPC_VERSION => {
+                error!("Unsupported RPC version {}", call_body.rpcvers);
+                reply(ReplyBody::MsgDenied(RejectedReply::RpcMismatch(
+                    MismatchInfo {
+                        low: RPC_VERSION,
+                        high: RPC_VERSION,
+                    },
+                )))
+            }
+            MsgType::Call(mut call_body) => {
+                // RPCSEC_GSS calls are checked and unprotected by their security context
+                let mut gss_session = None;
+                if let (OpaqueAuth::RpcsecGss(_), Some(gss)) = (&call_body.cred, &self.gss) {
+                    match gss.accept_call(xid, call_body).await {
+                        GssOutcome::Reply(body) => return reply(body),
+                        GssOutcome::Drop => return None,
+                        GssOutcome::Call(call, session) => {
+                            call_body = call;
+                            gss_session = Some(session);
+                        }
                     }
+                }
+
+                // https://datatracker.ietf.org/doc/html/rfc5531#section-9
+                // only AUTH_NONE, AUTH_SYS and RPCSEC_GSS are understood
+                let credentials = match &gss_session {
+                    Some(session) => Some(session.credentials.clone()),
+                    None => Credentials::from_auth(&call_body.cred),
+                };
+                let Some(credentials) = credentials else {
+                    error!(
+                        "Unsupported credential flavor {} from {}",
+                        call_body.cred.flavor(),
+                        request.client_addr()
+                    );
+                    let stat = match call_body.cred {
+                        OpaqueAuth::Malformed(_) => AuthStat::AuthBadCred,
+                        _ => AuthStat::AuthTooWeak,
+                    };
+                    return reply(ReplyBody::MsgDenied(RejectedReply::AuthError(stat)));
                 };
 
-                // end request
-                request.close().await;
-                let rpc_reply_message = RpcReplyMsg {
-                    xid: rpc_call_message.xid,
-                    body: MsgType::Reply(body),
+                // match the client against the rules of the exports, the
+                // operations are checked against the outcome
+                let client_access = match request.client_addr().parse::<Peer>() {
+                    Ok(peer) => request.exports().client_access(&peer).await,
+                    Err(_) => ClientAccess::denied(),
                 };
-                debug!("{:?}", rpc_reply_message);
-                Box::new(rpc_reply_message)
+                let body = if let Some(reply_data) = unavailable(&call_body) {
+                    ReplyBody::MsgAccepted(AcceptedReply {
+                        verf: OpaqueAuth::AuthNull(Vec::<u8>::new()),
+                        reply_data,
+                    })
+                } else if call_body.proc == NFSPROC4_COMPOUND && !client_access.allowed_anywhere() {
+                    error!(
+                        "Client {} is not allowed on any export",
+                        request.client_addr()
+                    );
+                    ReplyBody::MsgAccepted(AcceptedReply {
+                        verf: OpaqueAuth::AuthNull(Vec::<u8>::new()),
+                        reply_data: AcceptBody::Success(Compound4res {
+                            status: NfsStat4::Nfs4errAccess,
+                            tag: "".to_string(),
+                            resarray: Vec::new(),
+                        }),
+                    })
+                } else {
+                    request.set_client_access(client_access);
+                    request.set_credentials(Some(credentials));
+                    request.set_principal(gss_session.as_ref().map(|s| s.principal.clone()));
+                    request.set_flavor(match &gss_session {
+                        Some(session) => session.flavor(),
+                        None => SecFlavor::from_auth(&call_body.cred),
+                    });
+
+                    let (request, body) = match call_body.proc {
+                        NFSPROC4_NULL => self.server.null(call_body, request).await,
+                        _ => self.server.compound(call_body, request).await,
+                    };
+
+                    // end request
+                    request.close().await;
+                    body
+                };
+
+                match (&self.gss, &gss_session) {
+                    (Some(gss), Some(session)) => reply(gss.seal_reply(session, body).await),
+                    _ => reply(body),
+                }
             }
-            _ => {
-                todo!("Invalid message type")
+            // replies are only expected by clients
+            MsgType::Reply(_) => {
+                error!("Unexpected reply from {}", request.client_addr());
+                None
+            }
+        }
+    }
+}
+
+// the program, version and procedure of a call, None if bold serves them
+fn unavailable(call_body: &CallBody) -> Option<AcceptBody> {
+    if call_body.prog != NFS4_PROGRAM {
+        error!("Unsupported program {}", call_body.prog);
+        return Some(AcceptBody::ProgUnavail);
+    }
+    if call_body.vers != NFS_V4 {
+        error!("Unsupported NFS version {}", call_body.vers);
+        return Some(AcceptBody::ProgMismatch(MismatchInfo {
+            low: NFS_V4,
+            high: NFS_V4,
+        }));
+    }
+    match call_body.proc {
+        NFSPROC4_NULL | NFSPROC4_COMPOUND => None,
+        proc => {
+            error!("Unsupported procedure {}", proc);
+            Some(AcceptBody::ProcUnavail)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bold_proto::{
+        nfs4_proto::{
+            Compound4res, NfsArgOp, NfsResOp4, NfsStat4, Read4res, Read4resok, StableHow4,
+        },
+        rpc_proto::{
+            AcceptBody, AcceptedReply, CallBody, MsgType, OpaqueAuth, RejectedReply, ReplyBody,
+            RpcCallMsg, RpcReplyMsg,
+        },
+        XDRProtoCodec,
+    };
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+    use tracing_test::traced_test;
+
+    use super::{nfs40::NFS40Server, NFSService, NfsProtoImpl};
+    use crate::test_utils::create_nfs40_server;
+
+    fn call(rpcvers: u32, prog: u32, vers: u32, proc: u32) -> RpcCallMsg {
+        RpcCallMsg {
+            xid: 7,
+            body: MsgType::Call(CallBody {
+                rpcvers,
+                prog,
+                vers,
+                proc,
+                cred: OpaqueAuth::AuthNull(Vec::new()),
+                verf: OpaqueAuth::AuthNull(Vec::new()),
+                args: None,
+                gss_args: None,
+            }),
+        }
+    }
+
+    async fn reply_body(msg: RpcCallMsg) -> ReplyBody {
+        let request = create_nfs40_server(None).await;
+        let reply = NFSService::new(NFS40Server::new())
+            .call(msg, request)
+            .await
+            .unwrap();
+        assert_eq!(reply.xid, 7);
+        match reply.body {
+            MsgType::Reply(body) => body,
+            body => panic!("Unexpected message {:?}", body),
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_unavailable_calls() {
+        match reply_body(call(2, 100003, 4, 0)).await {
+            ReplyBody::MsgAccepted(reply) => {
+                assert!(matches!(reply.reply_data, AcceptBody::Success(_)))
             }
+            body => panic!("Unexpected reply {:?}", body),
         }
+        match reply_body(call(3, 100003, 4, 0)).await {
+            ReplyBody::MsgDenied(RejectedReply::RpcMismatch(info)) => {
+                assert_eq!((info.low, info.high), (2, 2))
+            }
+            body => panic!("Unexpected reply {:?}", body),
+        }
+        match reply_body(call(2, 100005, 3, 0)).await {
+            ReplyBody::MsgAccepted(reply) => {
+                assert!(matches!(reply.reply_data, AcceptBody::ProgUnavail))
+            }
+            body => panic!("Unexpected reply {:?}", body),
+        }
+        match reply_body(call(2, 100003, 3, 1)).await {
+            ReplyBody::MsgAccepted(reply) => match reply.reply_data {
+                AcceptBody::ProgMismatch(info) => assert_eq!((info.low, info.high), (4, 4)),
+                data => panic!("Unexpected reply {:?}", data),
+            },
+            body => panic!("Unexpected reply {:?}", body),
+        }
+        match reply_body(call(2, 100003, 4, 2)).await {
+            ReplyBody::MsgAccepted(reply) => {
+                assert!(matches!(reply.reply_data, AcceptBody::ProcUnavail))
+            }
+            body => panic!("Unexpected reply {:?}", body),
+        }
+    }
+
+    // a record of a single fragment
+    fn record(words: &[u32]) -> Vec<u8> {
+        let mut bytes = ((words.len() as u32 * 4) | (1 << 31))
+            .to_be_bytes()
+            .to_vec();
+        for word in words {
+            bytes.extend(word.to_be_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_garbage_calls() {
+        let mut src = BytesMut::new();
+        // a COMPOUND with a tag longer than the message
+        src.extend(record(&[42, 0, 2, 100003, 4, 1, 0, 0, 0, 0, 1000]));
+        // a reply, which the server doesn't wait for
+        src.extend(record(&[43, 1, 7]));
+        // a call of another program, its arguments are not read
+        src.extend(record(&[44, 0, 2, 100005, 3, 1, 0, 0, 0, 0, 1000]));
+        let mut codec = XDRProtoCodec::new();
+
+        let garbage = codec.decode(&mut src).unwrap().unwrap().unwrap_err();
+        assert_eq!(garbage.xid, 42);
+        let msg = codec.decode(&mut src).unwrap().unwrap().unwrap();
+        assert_eq!(msg.xid, 44);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+
+        // a tag of almost 4 GiB is rejected without allocating it
+        src.extend(record(&[45, 0, 2, 100003, 4, 1, 0, 0, 0, 0, 0xffff_fffd]));
+        let garbage = codec.decode(&mut src).unwrap().unwrap().unwrap_err();
+        assert_eq!(garbage.xid, 45);
+
+        // a GETATTR with an attribute bitmap longer than the message
+        src.extend(record(&[
+            46, 0, 2, 100003, 4, 1, 0, 0, 0, 0, 0, 0, 1, 9, 0xffff,
+        ]));
+        let garbage = codec.decode(&mut src).unwrap().unwrap().unwrap_err();
+        assert_eq!(garbage.xid, 46);
+    }
+
+    // the words of a COMPOUND with a FILE_SYNC WRITE of data
+    fn write_call(xid: u32, data: &[u8]) -> Vec<u32> {
+        let mut words = vec![xid, 0, 2, 100003, 4, 1, 0, 0, 0, 0];
+        // tag, minor version, WRITE, stateid, offset, stable
+        words.extend([0, 0, 1, 38, 0, 0, 0, 0, 0, 0, 2]);
+        words.push(data.len() as u32);
+        words.extend(data.chunks(4).map(|chunk| {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u32::from_be_bytes(word)
+        }));
+        words
+    }
+
+    fn write_data(msg: &RpcCallMsg) -> bytes::Bytes {
+        match &msg.body {
+            MsgType::Call(CallBody {
+                args: Some(args), ..
+            }) => match &args.argarray[..] {
+                [NfsArgOp::Opwrite(write)] => {
+                    assert_eq!(write.stable, StableHow4::FileSync4);
+                    write.data.clone()
+                }
+                ops => panic!("Unexpected operations {:?}", ops),
+            },
+            body => panic!("Unexpected message {:?}", body),
+        }
+    }
+
+    #[test]
+    fn test_write_calls() {
+        let data: Vec<u8> = (0..1001).map(|i| i as u8).collect();
+        let mut codec = XDRProtoCodec::new();
+
+        // the data is a slice of the receive buffer, counted by the codec
+        // until the last slice of the record is dropped
+        let record_data = record(&write_call(1, &data));
+        let mut src = BytesMut::from(&record_data[..]);
+        let received = src.as_ptr_range();
+        let msg = codec.decode(&mut src).unwrap().unwrap().unwrap();
+        assert_eq!(msg.xid, 1);
+        let written = write_data(&msg);
+        assert_eq!(written, data);
+        assert!(received.contains(&written.as_ptr()));
+        assert_eq!(codec.retained(), record_data.len());
+        drop(msg);
+        assert_eq!(codec.retained(), record_data.len());
+        drop(written);
+        assert_eq!(codec.retained(), 0);
+
+        // a record of two fragments, arriving in pieces
+        let words = write_call(2, &data);
+        let (first, last) = words.split_at(words.len() / 2);
+        let mut fragments = record(first);
+        fragments[0] &= 0x7f;
+        fragments.extend(record(last));
+        let mut src = BytesMut::new();
+        for piece in fragments.chunks(100) {
+            assert!(src.is_empty() || codec.decode(&mut src).unwrap().is_none());
+            src.extend_from_slice(piece);
+        }
+        let msg = codec.decode(&mut src).unwrap().unwrap().unwrap();
+        assert_eq!(msg.xid, 2);
+        assert_eq!(write_data(&msg), data);
+        assert!(src.is_empty());
+
+        // a record larger than allowed closes the connection
+        let mut codec = XDRProtoCodec::with_max_record(1000);
+        let mut src = BytesMut::from(&fragments[..]);
+        assert!(codec.decode(&mut src).is_err());
+    }
+
+    #[test]
+    fn test_read_reply() {
+        let reply = RpcReplyMsg {
+            xid: 3,
+            body: MsgType::Reply(ReplyBody::MsgAccepted(AcceptedReply {
+                verf: OpaqueAuth::AuthNull(Vec::new()),
+                reply_data: AcceptBody::Success(Compound4res {
+                    status: NfsStat4::Nfs4Ok,
+                    tag: String::new(),
+                    resarray: vec![NfsResOp4::Opread(Read4res::Resok4(Read4resok {
+                        eof: true,
+                        data: vec![7; 1001],
+                    }))],
+                }),
+            })),
+        };
+        let encoded = reply.to_bytes().unwrap();
+        let mut dst = BytesMut::from(&b"sent"[..]);
+        XDRProtoCodec::new()
+            .encode(Box::new(reply), &mut dst)
+            .unwrap();
+        assert_eq!(&dst[..4], b"sent");
+        assert_eq!(dst[4..8], (encoded.len() as u32 | (1 << 31)).to_be_bytes());
+        assert_eq!(dst[8..], encoded[..]);
     }
 }
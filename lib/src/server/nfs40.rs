@@ -3,6 +3,7 @@ use async_trait::async_trait;
 use super::{operation::NfsOperation, request::NfsRequest, response::NfsOpResponse};
 use bold_proto::{nfs4_proto::*, rpc_proto::*};
 
+mod names;
 mod op_access;
 mod op_close;
 mod op_commit;
@@ -10,15 +11,18 @@ mod op_create;
 mod op_getattr;
 mod op_lookup;
 mod op_open;
+mod op_openattr;
 mod op_openconfirm;
 mod op_putfh;
 mod op_read;
 mod op_readdir;
 mod op_remove;
 mod op_renew;
+mod op_secinfo;
 mod op_set_clientid;
 mod op_set_clientid_confirm;
 mod op_setattr;
+mod op_verify;
 mod op_write;
 
 use super::NfsProtoImpl;
@@ -29,7 +33,7 @@ pub struct NFS40Server;
 
 impl NFS40Server {
     async fn put_root_filehandle<'a>(&self, mut request: NfsRequest<'a>) -> NfsOpResponse<'a> {
-        match request.file_manager().get_root_filehandle().await {
+        match request.exports().root().get_root_filehandle().await {
             Ok(filehandle) => {
                 let _ = request.set_filehandle_id(filehandle.id).await;
                 NfsOpResponse {
@@ -51,6 +55,64 @@ impl NFS40Server {
         }
     }
 
+    // the public filehandle is the root, unless configured otherwise, see
+    // https://datatracker.ietf.org/doc/html/rfc7530#section-16.22
+    async fn put_public_filehandle<'a>(&self, mut request: NfsRequest<'a>) -> NfsOpResponse<'a> {
+        let status = match request.exports().public_filehandle().await {
+            Ok(filehandle) => {
+                request.set_filehandle(filehandle);
+                NfsStat4::Nfs4Ok
+            }
+            Err(e) => {
+                error!("Err {:?}", e);
+                e.nfs_error
+            }
+        };
+        NfsOpResponse {
+            request,
+            result: Some(NfsResOp4::Opputpubfh(PutPubFh4res {
+                status: status.clone(),
+            })),
+            status,
+        }
+    }
+
+    async fn lookup_parent<'a>(&self, mut request: NfsRequest<'a>) -> NfsOpResponse<'a> {
+        let filehandle = match request.current_filehandle() {
+            Some(filehandle) => filehandle.clone(),
+            None => {
+                error!("None filehandle");
+                return NfsOpResponse {
+                    request,
+                    result: None,
+                    status: NfsStat4::Nfs4errNofilehandle,
+                };
+            }
+        };
+        // https://datatracker.ietf.org/doc/html/rfc7530#section-16.15.4
+        let status = match filehandle.attr_type {
+            // the parent of a named attribute directory is its object
+            NfsFtype4::Nf4dir | NfsFtype4::Nf4attrdir => {
+                match request.get_parent_filehandle(&filehandle).await {
+                    Ok(parent) => {
+                        request.set_filehandle(parent);
+                        NfsStat4::Nfs4Ok
+                    }
+                    Err(e) => e.nfs_error,
+                }
+            }
+            NfsFtype4::Nf4lnk => NfsStat4::Nfs4errSymlink,
+            _ => NfsStat4::Nfs4errNotdir,
+        };
+        NfsOpResponse {
+            request,
+            result: Some(NfsResOp4::Oplookupp(LookupP4res {
+                status: status.clone(),
+            })),
+            status,
+        }
+    }
+
     fn get_current_filehandle<'a>(&self, request: NfsRequest<'a>) -> NfsOpResponse<'a> {
         let fh = request.current_filehandle_id();
         match fh {
@@ -73,40 +135,7 @@ impl NFS40Server {
         }
     }
 
-    fn operation_not_supported<'a>(&self, request: NfsRequest<'a>) -> NfsOpResponse<'a> {
-        NfsOpResponse {
-            request,
-            result: None,
-            status: NfsStat4::Nfs4errNotsupp,
-        }
-    }
-}
-
-#[async_trait]
-impl NfsProtoImpl for NFS40Server {
-    fn new() -> Self {
-        Self {}
-    }
-
-    fn hash(&self) -> u64 {
-        0
-    }
-
-    async fn null<'a>(&self, _: CallBody, request: NfsRequest<'a>) -> (NfsRequest<'a>, ReplyBody) {
-        (
-            request,
-            ReplyBody::MsgAccepted(AcceptedReply {
-                verf: OpaqueAuth::AuthNull(Vec::<u8>::new()),
-                reply_data: AcceptBody::Success(Compound4res {
-                    status: NfsStat4::Nfs4Ok,
-                    tag: "".to_string(),
-                    resarray: Vec::new(),
-                }),
-            }),
-        )
-    }
-
-    async fn compound<'a>(
+    async fn run_compound<'a>(
         &self,
         msg: CallBody,
         mut request: NfsRequest<'a>,
@@ -118,7 +147,18 @@ impl NfsProtoImpl for NFS40Server {
                 // The server will process the COMPOUND procedure by evaluating each of
                 // the operations within the COMPOUND procedure in order.
                 for arg in args.argarray {
-                    let response = match arg {
+                    // https://datatracker.ietf.org/doc/html/rfc7530#section-3.3.1
+                    // the client has to be allowed on the export it works on
+                    if let Err(status) = request.check_access(&arg) {
+                        error!("Access denied for {:?}: {:?}", arg, status);
+                        last_status = status;
+                        break;
+                    }
+                    let crosses_exports = matches!(
+                        arg,
+                        NfsArgOp::Oplookup(_) | NfsArgOp::Oplookupp(_) | NfsArgOp::Opputpubfh(_)
+                    );
+                    let mut response = match arg {
                         // these should never be called
                         NfsArgOp::OpUndef0 | NfsArgOp::OpUndef1 | NfsArgOp::OpUndef2 => {
                             self.operation_not_supported(request)
@@ -153,14 +193,14 @@ impl NfsProtoImpl for NFS40Server {
                         NfsArgOp::Oplockt(_) => self.operation_not_supported(request),
                         NfsArgOp::Oplocku(_) => self.operation_not_supported(request),
 
-                        NfsArgOp::Oplookupp(_) => self.operation_not_supported(request),
-                        NfsArgOp::Opnverify(_) => self.operation_not_supported(request),
+                        NfsArgOp::Oplookupp(_) => self.lookup_parent(request).await,
+                        NfsArgOp::Opnverify(args) => args.execute(request).await,
 
-                        NfsArgOp::Opopenattr(_) => self.operation_not_supported(request),
+                        NfsArgOp::Opopenattr(args) => args.execute(request).await,
 
                         NfsArgOp::OpopenDowngrade(_) => self.operation_not_supported(request),
 
-                        NfsArgOp::Opputpubfh(_) => self.operation_not_supported(request),
+                        NfsArgOp::Opputpubfh(_) => self.put_public_filehandle(request).await,
 
                         NfsArgOp::Opreadlink(_) => self.operation_not_supported(request),
 
@@ -168,12 +208,20 @@ impl NfsProtoImpl for NFS40Server {
 
                         NfsArgOp::Oprestorefh(_) => self.operation_not_supported(request),
                         NfsArgOp::Opsavefh(_) => self.operation_not_supported(request),
-                        NfsArgOp::OpSecinfo(_) => self.operation_not_supported(request),
+                        NfsArgOp::OpSecinfo(args) => args.execute(request).await,
 
-                        NfsArgOp::Opverify(_) => self.operation_not_supported(request),
+                        NfsArgOp::Opverify(args) => args.execute(request).await,
 
                         NfsArgOp::OpreleaseLockOwner(_) => self.operation_not_supported(request),
                     };
+                    // entering an export the client may not use fails the lookup
+                    if crosses_exports && response.status == NfsStat4::Nfs4Ok {
+                        if let Err(status) = response.request.check_filehandle_access() {
+                            error!("Access denied to export: {:?}", status);
+                            response.result = None;
+                            response.status = status;
+                        }
+                    }
                     // match the result of the operation, pass on success, return on error
                     let res = response.result;
                     last_status = response.status;
@@ -220,7 +268,252 @@ impl NfsProtoImpl for NFS40Server {
         )
     }
 
+    fn operation_not_supported<'a>(&self, request: NfsRequest<'a>) -> NfsOpResponse<'a> {
+        NfsOpResponse {
+            request,
+            result: None,
+            status: NfsStat4::Nfs4errNotsupp,
+        }
+    }
+}
+
+#[async_trait]
+impl NfsProtoImpl for NFS40Server {
+    fn new() -> Self {
+        Self {}
+    }
+
+    fn hash(&self) -> u64 {
+        0
+    }
+
+    async fn null<'a>(&self, _: CallBody, request: NfsRequest<'a>) -> (NfsRequest<'a>, ReplyBody) {
+        (
+            request,
+            ReplyBody::MsgAccepted(AcceptedReply {
+                verf: OpaqueAuth::AuthNull(Vec::<u8>::new()),
+                reply_data: AcceptBody::Success(Compound4res {
+                    status: NfsStat4::Nfs4Ok,
+                    tag: "".to_string(),
+                    resarray: Vec::new(),
+                }),
+            }),
+        )
+    }
+
+    async fn compound<'a>(
+        &self,
+        msg: CallBody,
+        request: NfsRequest<'a>,
+    ) -> (NfsRequest<'a>, ReplyBody) {
+        let (request, reply) = self.run_compound(msg, request).await;
+        // the filehandles handed out must survive a restart, write them once
+        // per compound instead of once per handle
+        request.exports().sync_handles().await;
+        (request, reply)
+    }
+
     fn minor_version(&self) -> u32 {
         0
     }
 }
+
+#[cfg(all(test, unix))]
+mod integration_tests {
+    use std::{collections::HashSet, os::unix::fs::MetadataExt, path::Path, sync::Arc};
+
+    use bold_proto::{nfs4_proto::*, rpc_proto::*};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use tracing_test::traced_test;
+
+    use super::NFS40Server;
+    use crate::{
+        server::NfsProtoImpl, storage::PhysicalBackend,
+        test_utils::create_nfs40_server_with_backend,
+    };
+
+    const SECRET: &[u8] = b"SECRET outside of the export";
+
+    fn random_name(rng: &mut StdRng) -> Vec<u8> {
+        let names: [&[u8]; 16] = [
+            b"dir1",
+            b"file1.txt",
+            b"up",
+            b"abs",
+            b"escape",
+            b"loop",
+            b"outside",
+            b"secret.txt",
+            b".",
+            b"..",
+            b"../outside",
+            b"/etc",
+            b"dir1/../..",
+            b"",
+            &[0x2e, 0x2e, 0xff],
+            &[b'a'; 300],
+        ];
+        names[rng.gen_range(0..names.len())].to_vec()
+    }
+
+    fn random_op(rng: &mut StdRng) -> NfsArgOp {
+        // most of the attacks need a couple of lookups in a row
+        match rng.gen_range(0..12) {
+            0 => NfsArgOp::Opputrootfh(()),
+            1 => NfsArgOp::Opread(Read4args {
+                stateid: Stateid4 {
+                    seqid: 0,
+                    other: [0; 12],
+                },
+                offset: 0,
+                count: 1024,
+            }),
+            2 => NfsArgOp::Opreaddir(Readdir4args {
+                cookie: 0,
+                cookieverf: [0; 8],
+                dircount: 4096,
+                maxcount: 8192,
+                attr_request: Attrlist4::<FileAttr>::new(Some(vec![FileAttr::Fileid])),
+            }),
+            3 => NfsArgOp::Opopen(Open4args {
+                seqid: 0,
+                share_access: 1,
+                share_deny: 0,
+                owner: OpenOwner4 {
+                    clientid: 0,
+                    owner: b"fuzz".to_vec(),
+                },
+                openhow: OpenFlag4::Open4Nocreate,
+                claim: OpenClaim4::ClaimNull(random_name(rng)),
+            }),
+            4 => NfsArgOp::Opcreate(Create4args {
+                objtype: Createtype4::Nf4dir,
+                objname: random_name(rng),
+                createattrs: Fattr4 {
+                    attrmask: Attrlist4::<FileAttr>::new(None),
+                    attr_vals: Attrlist4::<FileAttrValue>::new(None),
+                    decode_error: None,
+                },
+            }),
+            5 => NfsArgOp::Opremove(Remove4args {
+                target: random_name(rng),
+            }),
+            _ => NfsArgOp::Oplookup(Lookup4args {
+                objname: random_name(rng),
+            }),
+        }
+    }
+
+    fn inodes(paths: &[&Path]) -> HashSet<u64> {
+        paths
+            .iter()
+            .map(|path| std::fs::symlink_metadata(path).unwrap().ino())
+            .collect()
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_compounds_stay_in_export() {
+        let tmp = tempfile::tempdir().unwrap();
+        let outside = tmp.path().join("outside");
+        let export = tmp.path().join("export");
+        std::fs::create_dir(&outside).unwrap();
+        std::fs::write(outside.join("secret.txt"), SECRET).unwrap();
+        std::fs::create_dir_all(export.join("dir1")).unwrap();
+        std::fs::write(export.join("dir1/file1.txt"), b"public").unwrap();
+        std::os::unix::fs::symlink("..", export.join("up")).unwrap();
+        std::os::unix::fs::symlink(&outside, export.join("abs")).unwrap();
+        std::os::unix::fs::symlink("../../outside/secret.txt", export.join("dir1/escape")).unwrap();
+        std::os::unix::fs::symlink("loop", export.join("loop")).unwrap();
+        let forbidden = inodes(&[tmp.path(), &outside, &outside.join("secret.txt")]);
+
+        let server = NFS40Server::new();
+        let mut request = create_nfs40_server_with_backend(Arc::new(
+            PhysicalBackend::new(&export).with_handle_db(None),
+        ))
+        .await;
+        let mut rng = StdRng::seed_from_u64(7530);
+        for _ in 0..2000 {
+            let mut argarray = vec![NfsArgOp::Opputrootfh(())];
+            for _ in 0..rng.gen_range(1..8) {
+                argarray.push(random_op(&mut rng));
+            }
+            argarray.push(NfsArgOp::Opgetattr(Getattr4args {
+                attr_request: Attrlist4::<FileAttr>::new(Some(vec![FileAttr::Fileid])),
+            }));
+            let call = CallBody {
+                rpcvers: 2,
+                prog: 100003,
+                vers: 4,
+                proc: 1,
+                cred: OpaqueAuth::AuthNull(Vec::new()),
+                verf: OpaqueAuth::AuthNull(Vec::new()),
+                gss_args: None,
+                args: Some(Compound4args {
+                    tag: "fuzz".to_string(),
+                    minor_version: 0,
+                    argarray: argarray.clone(),
+                }),
+            };
+            let (mut response_request, reply) = server.compound(call, request).await;
+
+            if let Some(fh) = response_request.current_filehandle() {
+                assert!(
+                    !forbidden.contains(&fh.attr_fileid),
+                    "{:?} reached {:?} outside of the export",
+                    argarray,
+                    fh.path
+                );
+            }
+            let ReplyBody::MsgAccepted(AcceptedReply {
+                reply_data: AcceptBody::Success(res),
+                ..
+            }) = reply
+            else {
+                panic!("Unexpected reply {:?}", reply);
+            };
+            for res in res.resarray {
+                match res {
+                    NfsResOp4::Opread(Read4res::Resok4(read)) => {
+                        assert_ne!(read.data, SECRET, "{:?} read the secret", argarray);
+                    }
+                    NfsResOp4::Opgetattr(getattr) => {
+                        for value in getattr
+                            .obj_attributes
+                            .iter()
+                            .flat_map(|a| a.attr_vals.iter())
+                        {
+                            if let FileAttrValue::Fileid(fileid) = value {
+                                assert!(!forbidden.contains(fileid), "{:?}", argarray);
+                            }
+                        }
+                    }
+                    NfsResOp4::Opreaddir(ReadDir4res::Resok4(readdir)) => {
+                        let mut entry = readdir.reply.entries;
+                        while let Some(e) = entry {
+                            for value in e.attrs.attr_vals.iter() {
+                                if let FileAttrValue::Fileid(fileid) = value {
+                                    assert!(!forbidden.contains(fileid), "{:?}", argarray);
+                                }
+                            }
+                            entry = e.nextentry.map(|next| *next);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            response_request.unset_filehandle();
+            request = response_request;
+        }
+
+        // nothing outside of the export was changed
+        let mut names: Vec<_> = std::fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["export", "outside"]);
+        assert_eq!(std::fs::read_dir(&outside).unwrap().count(), 1);
+        assert_eq!(std::fs::read(outside.join("secret.txt")).unwrap(), SECRET);
+    }
+}
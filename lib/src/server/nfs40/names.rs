@@ -0,0 +1,71 @@
+use bold_proto::nfs4_proto::{NfsFtype4, NfsStat4};
+
+use crate::{server::filemanager::Filehandle, storage::join_path};
+
+// the longest component name accepted, like NAME_MAX on most UNIX systems
+pub const NFS4_MAXNAMLEN: usize = 255;
+
+/// Check a component name sent by a client.
+///
+/// https://datatracker.ietf.org/doc/html/rfc7530#section-12.7
+/// Every operation taking a component name has to go through this check, it
+/// ensures a name always refers to an entry of the directory it is looked up in.
+pub fn validate_name(name: &[u8]) -> Result<&str, NfsStat4> {
+    // If the component is of zero length, NFS4ERR_INVAL will be returned.
+    if name.is_empty() {
+        return Err(NfsStat4::Nfs4errInval);
+    }
+    if name.len() > NFS4_MAXNAMLEN {
+        return Err(NfsStat4::Nfs4errNametoolong);
+    }
+    // names that are not valid UTF-8 are rejected with NFS4ERR_INVAL
+    let name = std::str::from_utf8(name).map_err(|_| NfsStat4::Nfs4errInval)?;
+    // the path separator and NUL can not be part of a name on the backends
+    if name.contains(['/', '\0']) {
+        return Err(NfsStat4::Nfs4errBadchar);
+    }
+    // "." and ".." are not names of directory entries, LOOKUPP is used to go up
+    if name == "." || name == ".." {
+        return Err(NfsStat4::Nfs4errBadname);
+    }
+    Ok(name)
+}
+
+/// The path of the entry `name` in the directory `dir`.
+pub fn entry_path(dir: &Filehandle, name: &[u8]) -> Result<String, NfsStat4> {
+    match dir.attr_type {
+        NfsFtype4::Nf4dir | NfsFtype4::Nf4attrdir => {}
+        // https://datatracker.ietf.org/doc/html/rfc7530#section-16.15.5
+        // a symbolic link is never followed by the server
+        NfsFtype4::Nf4lnk => return Err(NfsStat4::Nfs4errSymlink),
+        _ => return Err(NfsStat4::Nfs4errNotdir),
+    }
+    let name = validate_name(name)?;
+    Ok(join_path(&dir.path, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use bold_proto::nfs4_proto::NfsStat4;
+
+    use super::{validate_name, NFS4_MAXNAMLEN};
+
+    #[test]
+    fn test_validate_name() {
+        assert_eq!(validate_name(b"file1.txt"), Ok("file1.txt"));
+        assert_eq!(validate_name("fïlé".as_bytes()), Ok("fïlé"));
+        assert_eq!(validate_name(b"..."), Ok("..."));
+        assert_eq!(validate_name(b""), Err(NfsStat4::Nfs4errInval));
+        assert_eq!(validate_name(&[0x66, 0xff]), Err(NfsStat4::Nfs4errInval));
+        assert_eq!(validate_name(b"."), Err(NfsStat4::Nfs4errBadname));
+        assert_eq!(validate_name(b".."), Err(NfsStat4::Nfs4errBadname));
+        assert_eq!(validate_name(b"../etc"), Err(NfsStat4::Nfs4errBadchar));
+        assert_eq!(validate_name(b"dir1/file2"), Err(NfsStat4::Nfs4errBadchar));
+        assert_eq!(validate_name(b"file\0"), Err(NfsStat4::Nfs4errBadchar));
+        assert!(validate_name(&[b'a'; NFS4_MAXNAMLEN]).is_ok());
+        assert_eq!(
+            validate_name(&[b'a'; NFS4_MAXNAMLEN + 1]),
+            Err(NfsStat4::Nfs4errNametoolong)
+        );
+    }
+}
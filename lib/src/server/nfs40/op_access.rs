@@ -15,16 +15,31 @@ impl NfsOperation for Access4args {
             "Operation 3: ACCESS - Check Access Rights {:?}, with request {:?}",
             self, request
         );
+        let mut supported = ACCESS4_READ
+            | ACCESS4_LOOKUP
+            | ACCESS4_MODIFY
+            | ACCESS4_EXTEND
+            | ACCESS4_DELETE
+            | ACCESS4_EXECUTE;
+        // nothing can be changed on a read-only export
+        if request.read_only() {
+            supported &= !(ACCESS4_MODIFY | ACCESS4_EXTEND | ACCESS4_DELETE);
+        }
+        let granted = match request.current_filehandle() {
+            Some(filehandle) => request.granted_access(filehandle),
+            None => {
+                return NfsOpResponse {
+                    request,
+                    result: None,
+                    status: NfsStat4::Nfs4errNofilehandle,
+                };
+            }
+        };
         NfsOpResponse {
             request,
             result: Some(NfsResOp4::OpAccess(Access4res::Resok4(Access4resok {
-                supported: ACCESS4_READ
-                    | ACCESS4_LOOKUP
-                    | ACCESS4_MODIFY
-                    | ACCESS4_EXTEND
-                    | ACCESS4_DELETE
-                    | ACCESS4_EXECUTE,
-                access: self.access,
+                supported,
+                access: self.access & supported & granted,
             }))),
             status: NfsStat4::Nfs4Ok,
         }
@@ -33,22 +48,36 @@ impl NfsOperation for Access4args {
 
 #[cfg(test)]
 mod integration_tests {
+    use std::sync::Arc;
+
     use crate::{
         server::{
+            access::Credentials,
             nfs40::{
-                Access4args, Access4res, NfsResOp4, NfsStat4, ACCESS4_DELETE, ACCESS4_EXECUTE,
-                ACCESS4_EXTEND, ACCESS4_LOOKUP, ACCESS4_MODIFY, ACCESS4_READ,
+                Access4args, Access4res, NfsResOp4, NfsStat4, PutFh4args, ACCESS4_DELETE,
+                ACCESS4_EXECUTE, ACCESS4_EXTEND, ACCESS4_LOOKUP, ACCESS4_MODIFY, ACCESS4_READ,
             },
             operation::NfsOperation,
+            request::NfsRequest,
         },
-        test_utils::create_nfs40_server,
+        storage::MemoryBackend,
+        test_utils::{create_nfs40_server, create_nfs40_server_with_backend},
     };
     use tracing_test::traced_test;
 
+    async fn put_path(request: NfsRequest<'static>, path: &str) -> NfsRequest<'static> {
+        let fh = request
+            .file_manager()
+            .get_filehandle_for_path(path.to_string())
+            .await
+            .unwrap();
+        PutFh4args { object: fh.id }.execute(request).await.request
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn test_check_access() {
-        let request = create_nfs40_server(None).await;
+        let request = put_path(create_nfs40_server(None).await, "/").await;
         let args = Access4args {
             access: ACCESS4_READ
                 | ACCESS4_LOOKUP
@@ -76,4 +105,49 @@ mod integration_tests {
             panic!("Unexpected response: {:?}", response);
         }
     }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_check_access_by_mode() {
+        let backend = MemoryBackend::new();
+        backend.write_file("/file1.txt", b"data").unwrap();
+        let all = ACCESS4_READ
+            | ACCESS4_LOOKUP
+            | ACCESS4_MODIFY
+            | ACCESS4_EXTEND
+            | ACCESS4_DELETE
+            | ACCESS4_EXECUTE;
+        let granted = |response: crate::server::response::NfsOpResponse<'static>| match response
+            .result
+        {
+            Some(NfsResOp4::OpAccess(Access4res::Resok4(res))) => (res.access, response.request),
+            _ => panic!("Unexpected response: {:?}", response),
+        };
+
+        // a user may read the file and the directory of root, but change neither
+        let mut request = create_nfs40_server_with_backend(Arc::new(backend)).await;
+        request.set_credentials(Some(Credentials {
+            uid: 1000,
+            gid: 1000,
+            gids: vec![],
+        }));
+        let request = put_path(request, "/file1.txt").await;
+        let (access, request) = granted(Access4args { access: all }.execute(request).await);
+        assert_eq!(access, ACCESS4_READ);
+        let request = put_path(request, "/").await;
+        let (access, mut request) = granted(Access4args { access: all }.execute(request).await);
+        assert_eq!(access, ACCESS4_READ | ACCESS4_LOOKUP);
+
+        // root may do anything but executing a file without execute bits
+        request.set_credentials(Some(Credentials {
+            uid: 0,
+            gid: 0,
+            gids: vec![],
+        }));
+        let (access, request) = granted(Access4args { access: all }.execute(request).await);
+        assert_eq!(access, all & !ACCESS4_EXECUTE);
+        let request = put_path(request, "/file1.txt").await;
+        let (access, _) = granted(Access4args { access: all }.execute(request).await);
+        assert_eq!(access, ACCESS4_READ | ACCESS4_MODIFY | ACCESS4_EXTEND);
+    }
 }
@@ -33,20 +33,27 @@ impl NfsOperation for Commit4args {
 
         // unlock write cache & write file
 
-        let write_cache = &request
+        let committed = match request
             .file_manager()
             .get_write_cache_handle(filehandle.clone())
             .await
-            .unwrap();
-        // // TODO: this commits the whole cache, we should only commit the data up to the offset
-        write_cache.commit().await;
+        {
+            // // TODO: this commits the whole cache, we should only commit the data up to the offset
+            Ok(write_cache) => write_cache.commit().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = committed {
+            error!("FileManagerError {:?}", e);
+            return NfsOpResponse {
+                request,
+                result: None,
+                status: e.nfs_error,
+            };
+        }
 
-        request
-            .file_manager()
-            .touch_file(filehandle.id.clone())
-            .await;
+        request.file_manager().touch_file(filehandle.id).await;
 
-        request.drop_filehandle_from_cache(filehandle.id.clone());
+        request.drop_filehandle_from_cache(filehandle.id);
         let boot_time = request.boot_time;
         NfsOpResponse {
             request,
@@ -1,11 +1,13 @@
 use async_trait::async_trait;
 use tracing::{debug, error};
 
-use crate::server::{operation::NfsOperation, request::NfsRequest, response::NfsOpResponse};
+use crate::server::{
+    nfs40::names::entry_path, operation::NfsOperation, request::NfsRequest, response::NfsOpResponse,
+};
 
 use bold_proto::nfs4_proto::{
-    Attrlist4, ChangeInfo4, Create4args, Create4res, Create4resok, Createtype4, FileAttr,
-    NfsResOp4, NfsStat4,
+    Attrlist4, Create4args, Create4res, Create4resok, Createtype4, FileAttr, NfsResOp4, NfsStat4,
+    ACCESS4_EXTEND, ACCESS4_LOOKUP,
 };
 
 #[async_trait]
@@ -29,14 +31,36 @@ impl NfsOperation for Create4args {
             }
         };
 
-        if self.objname.len() == 0 {
-            // If the objname is of zero length, NFS4ERR_INVAL will be returned.
-            // The objname is also subject to the normal UTF-8, character support,
-            // and name checks.  See Section 12.7 for further discussion.
+        if let Some(error) = &self.createattrs.decode_error {
             return NfsOpResponse {
                 request,
                 result: None,
-                status: NfsStat4::Nfs4errInval,
+                status: error.into(),
+            };
+        }
+
+        // If the current filehandle is not a directory, the error
+        // NFS4ERR_NOTDIR will be returned.
+        // If the objname is of zero length, NFS4ERR_INVAL will be returned.
+        // The objname is also subject to the normal UTF-8, character support,
+        // and name checks.  See Section 12.7 for further discussion.
+        let path = match entry_path(filehandle, &self.objname) {
+            Ok(path) => path,
+            Err(status) => {
+                return NfsOpResponse {
+                    request,
+                    result: None,
+                    status,
+                };
+            }
+        };
+
+        // adding an entry needs write and execute permission on the directory
+        if let Err(status) = request.check_permission(filehandle, ACCESS4_EXTEND | ACCESS4_LOOKUP) {
+            return NfsOpResponse {
+                request,
+                result: None,
+                status,
             };
         }
 
@@ -44,27 +68,11 @@ impl NfsOperation for Create4args {
             // TODO support links
             // LinkData(vec) => todo!(),
             Createtype4::Nf4dir => {
-                let current_dir = if filehandle.file.is_file().unwrap() {
-                    &filehandle.file.parent()
-                } else {
-                    &filehandle.file
-                };
-                let new_dir = current_dir
-                    .join(std::str::from_utf8(&self.objname).unwrap())
-                    .unwrap();
-                let _ = new_dir.create_dir();
-
-                request.file_manager().touch_file(filehandle.id).await;
-
-                let resp = request
-                    .file_manager()
-                    .get_filehandle_for_path(new_dir.as_str().to_string())
-                    .await;
-                let filehandle = match resp {
-                    Ok(filehandle) => filehandle,
+                let resp = request.file_manager().create_dir(path).await;
+                let (filehandle, cinfo) = match resp {
+                    Ok(created) => created,
                     Err(e) => {
                         debug!("FileManagerError {:?}", e);
-                        request.unset_filehandle();
                         return NfsOpResponse {
                             request,
                             result: None,
@@ -72,16 +80,10 @@ impl NfsOperation for Create4args {
                         };
                     }
                 };
-                request.set_filehandle(filehandle.clone());
+                request.set_filehandle(filehandle);
+                request.set_owner_to_caller().await;
 
-                (
-                    ChangeInfo4 {
-                        atomic: true,
-                        before: filehandle.attr_change,
-                        after: filehandle.attr_change,
-                    },
-                    Attrlist4::<FileAttr>::new(None),
-                )
+                (cinfo, Attrlist4::<FileAttr>::new(None))
             }
             _ => {
                 // https://datatracker.ietf.org/doc/html/rfc7530#section-16.4.2
@@ -103,3 +105,55 @@ impl NfsOperation for Create4args {
         }
     }
 }
+
+#[cfg(test)]
+mod integration_tests {
+    use crate::{
+        server::{
+            nfs40::{NfsStat4, PutFh4args},
+            operation::NfsOperation,
+        },
+        test_utils::{create_fake_fs, create_nfs40_server},
+    };
+    use bold_proto::nfs4_proto::{
+        Attrlist4, Create4args, Create4res, Createtype4, Fattr4, FileAttr, FileAttrValue, NfsResOp4,
+    };
+    use tracing_test::traced_test;
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_create_dir_change_info() {
+        let request = create_nfs40_server(Some(create_fake_fs())).await;
+        let root = request.file_manager().get_root_filehandle().await.unwrap();
+
+        let putfh_args = PutFh4args { object: root.id };
+        let putfh_response = putfh_args.execute(request).await;
+        let args = Create4args {
+            objtype: Createtype4::Nf4dir,
+            objname: "dir2".as_bytes().to_vec(),
+            createattrs: Fattr4 {
+                attrmask: Attrlist4::<FileAttr>::new(None),
+                attr_vals: Attrlist4::<FileAttrValue>::new(None),
+                decode_error: None,
+            },
+        };
+        let response = args.execute(putfh_response.request).await;
+        assert_eq!(response.status, NfsStat4::Nfs4Ok);
+        let cinfo = match response.result {
+            Some(NfsResOp4::Opcreate(Create4res::Resok4(res))) => res.cinfo,
+            _ => panic!("Unexpected result"),
+        };
+        assert_eq!(cinfo.before, root.attr_change);
+        assert!(cinfo.after > cinfo.before);
+        // the vfs backend might be changed by others
+        assert!(!cinfo.atomic);
+
+        let root = response
+            .request
+            .file_manager()
+            .get_root_filehandle()
+            .await
+            .unwrap();
+        assert_eq!(root.attr_change, cinfo.after);
+    }
+}
@@ -22,15 +22,15 @@ impl NfsOperation for Getattr4args {
                     request,
                     result: Some(NfsResOp4::Opgetattr(Getattr4resok {
                         obj_attributes: None,
-                        status: NfsStat4::Nfs4errStale,
+                        status: NfsStat4::Nfs4errNofilehandle,
                     })),
-                    status: NfsStat4::Nfs4errStale,
+                    status: NfsStat4::Nfs4errNofilehandle,
                 };
             }
             Some(filehandle) => {
                 let resp = request
                     .file_manager()
-                    .filehandle_attrs(&self.attr_request, &filehandle);
+                    .filehandle_attrs(&self.attr_request, filehandle);
 
                 let (answer_attrs, attrs) = match resp {
                     Some(inner) => inner,
@@ -53,6 +53,7 @@ impl NfsOperation for Getattr4args {
                         obj_attributes: Some(Fattr4 {
                             attrmask: answer_attrs,
                             attr_vals: attrs,
+                            decode_error: None,
                         }),
                     })),
                     status: NfsStat4::Nfs4Ok,
@@ -2,13 +2,13 @@ use async_trait::async_trait;
 use tracing::{debug, error};
 
 use crate::server::{
-    nfs40::{Lookup4res, NfsResOp4},
+    nfs40::{names::entry_path, Lookup4res, NfsResOp4},
     operation::NfsOperation,
     request::NfsRequest,
     response::NfsOpResponse,
 };
 
-use bold_proto::nfs4_proto::{Lookup4args, NfsStat4};
+use bold_proto::nfs4_proto::{Lookup4args, NfsStat4, ACCESS4_LOOKUP};
 
 #[async_trait]
 impl NfsOperation for Lookup4args {
@@ -30,17 +30,33 @@ impl NfsOperation for Lookup4args {
             }
         };
 
-        let mut path = filehandle.path.clone();
-        if path == "/" {
-            path.push_str(std::str::from_utf8(&self.objname).unwrap());
-        } else {
-            path.push('/');
-            path.push_str(std::str::from_utf8(&self.objname).unwrap());
+        let path = match entry_path(filehandle, &self.objname) {
+            Ok(path) => path,
+            Err(status) => {
+                return NfsOpResponse {
+                    request,
+                    result: Some(NfsResOp4::Oplookup(Lookup4res {
+                        status: status.clone(),
+                    })),
+                    status,
+                };
+            }
+        };
+
+        // searching a directory needs execute permission on it
+        if let Err(status) = request.check_permission(filehandle, ACCESS4_LOOKUP) {
+            return NfsOpResponse {
+                request,
+                result: Some(NfsResOp4::Oplookup(Lookup4res {
+                    status: status.clone(),
+                })),
+                status,
+            };
         }
 
         debug!("lookup {:?}", path);
 
-        let resp = request.file_manager().get_filehandle_for_path(path).await;
+        let resp = request.get_filehandle_for_path(path).await;
         let filehandle = match resp {
             Ok(filehandle) => filehandle,
             Err(e) => {
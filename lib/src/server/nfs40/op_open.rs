@@ -2,8 +2,10 @@ use async_trait::async_trait;
 use tracing::{debug, error};
 
 use crate::server::{
-    filemanager::Filehandle,
-    nfs40::{ChangeInfo4, Open4res, Open4resok, OpenDelegation4, OPEN4_RESULT_CONFIRM},
+    access::share_access_mask,
+    nfs40::{
+        names::entry_path, ChangeInfo4, Open4res, Open4resok, OpenDelegation4, OPEN4_RESULT_CONFIRM,
+    },
     operation::NfsOperation,
     request::NfsRequest,
     response::NfsOpResponse,
@@ -11,25 +13,15 @@ use crate::server::{
 
 use bold_proto::nfs4_proto::{
     Attrlist4, CreateHow4, FileAttr, NfsResOp4, NfsStat4, Open4args, OpenClaim4, OpenFlag4,
-    Stateid4,
+    Stateid4, ACCESS4_EXTEND, ACCESS4_LOOKUP,
 };
 
 async fn open_for_reading<'a>(
     args: &Open4args,
-    file: &Vec<u8>,
+    fh_path: String,
+    dir_change: u64,
     mut request: NfsRequest<'a>,
 ) -> NfsOpResponse<'a> {
-    let current_filehandle = request.current_filehandle().unwrap();
-    let path = &current_filehandle.path;
-
-    let fh_path = {
-        if path == "/" {
-            format!("{}{}", path, std::str::from_utf8(file).unwrap())
-        } else {
-            format!("{}/{}", path, std::str::from_utf8(file).unwrap())
-        }
-    };
-
     debug!("open_for_reading {:?}", fh_path);
     let filehandle = match request
         .file_manager()
@@ -47,6 +39,15 @@ async fn open_for_reading<'a>(
         }
     };
 
+    if let Err(status) = request.check_permission(&filehandle, share_access_mask(args.share_access))
+    {
+        return NfsOpResponse {
+            request,
+            result: None,
+            status,
+        };
+    }
+
     // Create a new lock state for the file
     let lock_filehandle = match request
         .file_manager()
@@ -80,10 +81,11 @@ async fn open_for_reading<'a>(
                 seqid: lock.seqid,
                 other: lock.stateid,
             },
+            // nothing was changed, the directory is reported as it is
             cinfo: ChangeInfo4 {
-                atomic: false,
-                before: 0,
-                after: 0,
+                atomic: true,
+                before: dir_change,
+                after: dir_change,
             },
             rflags: OPEN4_RESULT_CONFIRM,
             attrset: Attrlist4::<FileAttr>::new(None),
@@ -95,31 +97,56 @@ async fn open_for_reading<'a>(
 
 async fn open_for_writing<'a>(
     args: &Open4args,
-    filehandle: &Filehandle,
-    file: &Vec<u8>,
+    fh_path: String,
     how: &CreateHow4,
     mut request: NfsRequest<'a>,
 ) -> NfsOpResponse<'a> {
-    let path = &filehandle.path;
+    debug!("open_for_writing {:?}", fh_path);
 
-    let fh_path = {
-        if path == "/" {
-            format!("{}{}", path, std::str::from_utf8(file).unwrap())
-        } else {
-            format!("{}/{}", path, std::str::from_utf8(file).unwrap())
+    if let CreateHow4::UNCHECKED4(createattrs) | CreateHow4::GUARDED4(createattrs) = how {
+        if let Some(error) = &createattrs.decode_error {
+            return NfsOpResponse {
+                request,
+                result: None,
+                status: error.into(),
+            };
         }
-    };
-
-    debug!("open_for_writing {:?}", fh_path);
+    }
 
-    let newfile_op = filehandle.file.join(std::str::from_utf8(file).unwrap());
+    // an existing file is opened with the permissions it grants, a new one
+    // needs write permission on the directory
+    let created = match request.get_filehandle_for_path(fh_path.clone()).await {
+        Ok(existing) => {
+            if let Err(status) =
+                request.check_permission(&existing, share_access_mask(args.share_access))
+            {
+                return NfsOpResponse {
+                    request,
+                    result: None,
+                    status,
+                };
+            }
+            false
+        }
+        Err(_) => {
+            let dir = request.current_filehandle().unwrap();
+            if let Err(status) = request.check_permission(dir, ACCESS4_EXTEND) {
+                return NfsOpResponse {
+                    request,
+                    result: None,
+                    status,
+                };
+            }
+            true
+        }
+    };
 
     let filehandle = match how {
         CreateHow4::UNCHECKED4(_fattr) => {
             match request
                 .file_manager()
                 .create_file(
-                    newfile_op.unwrap(),
+                    fh_path.clone(),
                     args.owner.clientid,
                     args.owner.owner.clone(),
                     args.share_access,
@@ -134,7 +161,7 @@ async fn open_for_writing<'a>(
                     return NfsOpResponse {
                         request,
                         result: None,
-                        status: NfsStat4::Nfs4errServerfault,
+                        status: e.nfs_error,
                     };
                 }
             }
@@ -143,7 +170,7 @@ async fn open_for_writing<'a>(
             match request
                 .file_manager()
                 .create_file(
-                    newfile_op.unwrap(),
+                    fh_path.clone(),
                     args.owner.clientid,
                     args.owner.owner.clone(),
                     args.share_access,
@@ -158,7 +185,7 @@ async fn open_for_writing<'a>(
                     return NfsOpResponse {
                         request,
                         result: None,
-                        status: NfsStat4::Nfs4errServerfault,
+                        status: e.nfs_error,
                     };
                 }
             }
@@ -173,8 +200,11 @@ async fn open_for_writing<'a>(
         }
     };
 
-    let (lock_filehandle, _) = filehandle;
+    let (lock_filehandle, cinfo) = filehandle;
     request.set_filehandle(lock_filehandle.clone());
+    if created {
+        request.set_owner_to_caller().await;
+    }
     // we expect this filehandle to have one lock (for the shared reservation)
     let lock = &lock_filehandle.locks[0];
 
@@ -185,11 +215,7 @@ async fn open_for_writing<'a>(
                 seqid: lock.seqid,
                 other: lock.stateid,
             },
-            cinfo: ChangeInfo4 {
-                atomic: false,
-                before: 0,
-                after: 0,
-            },
+            cinfo,
             // OPEN4_RESULT_CONFIRM indicates that the client MUST execute an
             // OPEN_CONFIRM operation before using the open file.
             rflags: OPEN4_RESULT_CONFIRM,
@@ -222,17 +248,6 @@ impl NfsOperation for Open4args {
             }
         };
 
-        // If the current filehandle is not a directory, the error
-        // NFS4ERR_NOTDIR will be returned.
-        if !filehandle.file.is_dir().unwrap() {
-            error!("Not a directory");
-            return NfsOpResponse {
-                request,
-                result: None,
-                status: NfsStat4::Nfs4errNotdir,
-            };
-        }
-
         let file = match &self.claim {
             // CLAIM_NULL:  For the client, this is a new OPEN request, and there is
             // no previous state associated with the file for the client.
@@ -249,26 +264,41 @@ impl NfsOperation for Open4args {
             }
         };
 
+        // If the current filehandle is not a directory, the error
+        // NFS4ERR_NOTDIR will be returned.
         // If the component is of zero length, NFS4ERR_INVAL will be returned.
         // The component is also subject to the normal UTF-8, character support,
         // and name checks.  See Section 12.7 for further discussion.
-        if file.is_empty() {
-            error!("Empty file name");
+        let fh_path = match entry_path(filehandle, file) {
+            Ok(fh_path) => fh_path,
+            Err(status) => {
+                error!("Invalid open target: {:?}", status);
+                return NfsOpResponse {
+                    request,
+                    result: None,
+                    status,
+                };
+            }
+        };
+        let dir_change = filehandle.attr_change;
+
+        // the file is looked up in the directory
+        if let Err(status) = request.check_permission(filehandle, ACCESS4_LOOKUP) {
             return NfsOpResponse {
                 request,
                 result: None,
-                status: NfsStat4::Nfs4errInval,
+                status,
             };
         }
 
         match &self.openhow {
             OpenFlag4::Open4Nocreate => {
                 // Open a file for reading
-                open_for_reading(self, file, request).await
+                open_for_reading(self, fh_path, dir_change, request).await
             }
             OpenFlag4::How(how) => {
                 // Open a file for writing
-                open_for_writing(self, &filehandle.clone(), file, how, request).await
+                open_for_writing(self, fh_path, how, request).await
             }
         }
     }
@@ -0,0 +1,192 @@
+use async_trait::async_trait;
+use tracing::{debug, error};
+
+use crate::{
+    server::{operation::NfsOperation, request::NfsRequest, response::NfsOpResponse},
+    storage::{join_path, NAMED_ATTR_DIR},
+};
+
+use bold_proto::nfs4_proto::{
+    NfsFtype4, NfsResOp4, NfsStat4, OpenAttr4args, OpenAttr4res, ACCESS4_READ,
+};
+
+#[async_trait]
+impl NfsOperation for OpenAttr4args {
+    /// The OPENATTR operation is used to obtain the filehandle of the named
+    /// attribute directory associated with the current filehandle.
+    ///
+    /// Named attributes are the extended attributes of the object, see
+    /// [`crate::storage::NamedAttrBackend`]. The directory of an object
+    /// without attributes only exists if `createdir` is set.
+    ///
+    /// Please read: [RFC 7530](https://datatracker.ietf.org/doc/html/rfc7530#section-16.17)
+    async fn execute<'a>(&self, mut request: NfsRequest<'a>) -> NfsOpResponse<'a> {
+        debug!(
+            "Operation 19: OPENATTR - Open Named Attribute Directory {:?}, with request {:?}",
+            self, request
+        );
+        let error = |request, status: NfsStat4| NfsOpResponse {
+            request,
+            result: Some(NfsResOp4::Opopenattr(OpenAttr4res {
+                status: status.clone(),
+            })),
+            status,
+        };
+        let Some(filehandle) = request.current_filehandle() else {
+            error!("None filehandle");
+            return error(request, NfsStat4::Nfs4errNofilehandle);
+        };
+        // named attributes have no named attributes of their own
+        if matches!(
+            filehandle.attr_type,
+            NfsFtype4::Nf4attrdir | NfsFtype4::Nf4namedattr
+        ) {
+            return error(request, NfsStat4::Nfs4errInval);
+        }
+        if !filehandle.attr_named_attr && !self.createdir {
+            return error(request, NfsStat4::Nfs4errNoent);
+        }
+        // reading the attributes of an object needs read permission on it
+        if let Err(status) = request.check_permission(filehandle, ACCESS4_READ) {
+            return error(request, status);
+        }
+
+        let path = join_path(&filehandle.path, NAMED_ATTR_DIR);
+        match request.file_manager().get_filehandle_for_path(path).await {
+            Ok(attr_dir) => {
+                request.set_filehandle(attr_dir);
+                NfsOpResponse {
+                    request,
+                    result: Some(NfsResOp4::Opopenattr(OpenAttr4res {
+                        status: NfsStat4::Nfs4Ok,
+                    })),
+                    status: NfsStat4::Nfs4Ok,
+                }
+            }
+            // the backend has no extended attributes
+            Err(e) if e.nfs_error == NfsStat4::Nfs4errNoent => {
+                error(request, NfsStat4::Nfs4errNotsupp)
+            }
+            Err(e) => error(request, e.nfs_error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod integration_tests {
+    use std::sync::Arc;
+
+    use bold_proto::nfs4_proto::*;
+    use tracing_test::traced_test;
+
+    use crate::{
+        server::{nfs40::PutFh4args, operation::NfsOperation, request::NfsRequest},
+        storage::{join_path, MemoryBackend, VfsBackend},
+        test_utils::create_nfs40_server_with_backend,
+    };
+
+    async fn put_path(request: NfsRequest<'static>, path: &str) -> NfsRequest<'static> {
+        let fh = request
+            .file_manager()
+            .get_filehandle_for_path(path.to_string())
+            .await
+            .unwrap();
+        PutFh4args { object: fh.id }.execute(request).await.request
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_named_attributes() {
+        let backend = MemoryBackend::new();
+        backend.write_file("/file1.txt", b"data").unwrap();
+        let request = create_nfs40_server_with_backend(Arc::new(backend)).await;
+        let request = put_path(request, "/file1.txt").await;
+
+        // the file has no attributes yet
+        let response = OpenAttr4args { createdir: false }.execute(request).await;
+        assert_eq!(response.status, NfsStat4::Nfs4errNoent);
+        let response = OpenAttr4args { createdir: true }
+            .execute(response.request)
+            .await;
+        assert_eq!(response.status, NfsStat4::Nfs4Ok);
+        let attr_dir = response.request.current_filehandle().unwrap().clone();
+        assert_eq!(attr_dir.attr_type, NfsFtype4::Nf4attrdir);
+        let response = OpenAttr4args { createdir: true }
+            .execute(response.request)
+            .await;
+        assert_eq!(response.status, NfsStat4::Nfs4errInval);
+
+        // create an attribute, write and read it
+        let file_manager = response.request.file_manager();
+        let (attr, _) = file_manager
+            .create_file(
+                join_path(&attr_dir.path, "tag"),
+                0,
+                vec![],
+                OPEN4_SHARE_ACCESS_BOTH,
+                0,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(attr.attr_type, NfsFtype4::Nf4namedattr);
+        file_manager
+            .backend()
+            .write(&attr.path, 0, b"important")
+            .await
+            .unwrap();
+        let request = put_path(response.request, &attr_dir.path).await;
+        let response = Lookup4args {
+            objname: b"tag".to_vec(),
+        }
+        .execute(request)
+        .await;
+        assert_eq!(response.status, NfsStat4::Nfs4Ok);
+        let response = Read4args {
+            stateid: Stateid4 {
+                seqid: 0,
+                other: [0; 12],
+            },
+            offset: 0,
+            count: 100,
+        }
+        .execute(response.request)
+        .await;
+        match response.result {
+            Some(NfsResOp4::Opread(Read4res::Resok4(res))) => {
+                assert_eq!(res.data, b"important".to_vec());
+                assert!(res.eof);
+            }
+            _ => panic!("Unexpected response: {:?}", response),
+        }
+
+        // the object now reports its attributes until the last one is removed
+        let fh = file_manager
+            .get_filehandle_for_path("/file1.txt".to_string())
+            .await
+            .unwrap();
+        assert!(fh.attr_named_attr);
+        let request = put_path(response.request, &attr_dir.path).await;
+        let response = Remove4args {
+            target: b"tag".to_vec(),
+        }
+        .execute(request)
+        .await;
+        assert_eq!(response.status, NfsStat4::Nfs4Ok);
+        let fh = file_manager
+            .get_filehandle_for_path("/file1.txt".to_string())
+            .await
+            .unwrap();
+        assert!(!fh.attr_named_attr);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_named_attributes_not_supported() {
+        let backend = VfsBackend::new(vfs::VfsPath::new(vfs::MemoryFS::new()));
+        let request = create_nfs40_server_with_backend(Arc::new(backend)).await;
+        let request = put_path(request, "/").await;
+        let response = OpenAttr4args { createdir: true }.execute(request).await;
+        assert_eq!(response.status, NfsStat4::Nfs4errNotsupp);
+    }
+}
@@ -12,21 +12,18 @@ impl NfsOperation for PutFh4args {
             self, request
         );
 
-        match request.get_filehandle_from_cache(self.object.clone()) {
-            Some(fh) => {
-                request.set_filehandle(fh);
-                return NfsOpResponse {
-                    request,
-                    result: Some(NfsResOp4::Opputfh(PutFh4res {
-                        status: NfsStat4::Nfs4Ok,
-                    })),
+        if let Some(fh) = request.get_filehandle_from_cache(self.object) {
+            request.set_filehandle(fh);
+            return NfsOpResponse {
+                request,
+                result: Some(NfsResOp4::Opputfh(PutFh4res {
                     status: NfsStat4::Nfs4Ok,
-                };
-            }
-            None => {}
+                })),
+                status: NfsStat4::Nfs4Ok,
+            };
         }
 
-        match request.set_filehandle_id(self.object.clone()).await {
+        match request.set_filehandle_id(self.object).await {
             Ok(fh) => {
                 request.cache_filehandle(fh);
                 return NfsOpResponse {
@@ -1,10 +1,10 @@
-use std::io::SeekFrom;
-
 use async_trait::async_trait;
 use tracing::{debug, error};
 
 use crate::server::{operation::NfsOperation, request::NfsRequest, response::NfsOpResponse};
-use bold_proto::nfs4_proto::{NfsResOp4, NfsStat4, Read4args, Read4res, Read4resok};
+use bold_proto::nfs4_proto::{
+    NfsResOp4, NfsStat4, Read4args, Read4res, Read4resok, ACCESS4_EXECUTE, ACCESS4_READ,
+};
 
 #[async_trait]
 impl NfsOperation for Read4args {
@@ -26,16 +26,39 @@ impl NfsOperation for Read4args {
             }
         };
 
-        let mut buffer: Vec<u8> = vec![0; self.count as usize];
-        let mut rfile = filehandle.file.open_file().unwrap();
-        rfile.seek(SeekFrom::Start(self.offset)).unwrap();
-        let _ = rfile.read_exact(&mut buffer);
+        // the owner may read what it opened even after dropping its read
+        // permission, executables may be read to be run
+        if !request.is_owner(filehandle)
+            && request.granted_access(filehandle) & (ACCESS4_READ | ACCESS4_EXECUTE) == 0
+        {
+            return NfsOpResponse {
+                request,
+                result: None,
+                status: NfsStat4::Nfs4errAccess,
+            };
+        }
+
+        let (data, eof) = match request
+            .file_manager()
+            .read(filehandle, self.offset, self.count)
+            .await
+        {
+            Ok(res) => res,
+            Err(e) => {
+                error!("FileManagerError {:?}", e);
+                return NfsOpResponse {
+                    request,
+                    result: None,
+                    status: e.nfs_error,
+                };
+            }
+        };
 
         NfsOpResponse {
             request,
             result: Some(NfsResOp4::Opread(Read4res::Resok4(Read4resok {
-                eof: true,
-                data: buffer,
+                eof,
+                data,
             }))),
             status: NfsStat4::Nfs4Ok,
         }
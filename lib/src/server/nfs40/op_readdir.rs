@@ -1,176 +1,229 @@
 use async_trait::async_trait;
 use tracing::{debug, error};
 
-use crate::server::{operation::NfsOperation, request::NfsRequest, response::NfsOpResponse};
+use crate::{
+    server::{operation::NfsOperation, request::NfsRequest, response::NfsOpResponse},
+    storage::join_path,
+};
 
 use bold_proto::nfs4_proto::{
-    DirList4, Entry4, Fattr4, NfsResOp4, NfsStat4, ReadDir4res, ReadDir4resok, Readdir4args,
+    Attrlist4, DirList4, Entry4, Fattr4, FileAttr, FileAttrValue, NfsResOp4, NfsStat4, ReadDir4res,
+    ReadDir4resok, Readdir4args,
 };
 
+// https://datatracker.ietf.org/doc/html/rfc7530#section-16.24.4
+// the cookies 1 and 2 are reserved, the cookies of the backend are moved past them
+const RESERVED_COOKIES: u64 = 2;
+
+// how many entries are asked from the backend at once
+const BATCH_SIZE: usize = 128;
+
+// the READDIR4resok without entries: cookieverf, the end of the entry list and eof
+const RESOK_SIZE: usize = 8 + 4 + 4;
+
+// the XDR size of a variable length opaque
+fn opaque_size(len: usize) -> usize {
+    4 + len.div_ceil(4) * 4
+}
+
 #[async_trait]
 impl NfsOperation for Readdir4args {
+    /// The READDIR operation retrieves a variable number of entries from a
+    /// file system directory and returns client-requested attributes for
+    /// each entry along with information to allow the client to request
+    /// additional directory entries in a subsequent READDIR.
+    ///
+    /// Please read: [RFC 7530](https://datatracker.ietf.org/doc/html/rfc7530#section-16.24)
     async fn execute<'a>(&self, request: NfsRequest<'a>) -> NfsOpResponse<'a> {
         debug!(
             "Operation 26: READDIR - Read Directory {:?}, with request {:?}",
             self, request
         );
-        let current_fh = request.current_filehandle();
-        let dir_fh = match current_fh {
+        let error = |request, status| NfsOpResponse {
+            request,
+            result: None,
+            status,
+        };
+        let dir_fh = match request.current_filehandle() {
             Some(filehandle) => filehandle,
             None => {
                 error!("None filehandle");
-                return NfsOpResponse {
-                    request,
-                    result: None,
-                    status: NfsStat4::Nfs4errFhexpired,
-                };
+                return error(request, NfsStat4::Nfs4errNofilehandle);
             }
         };
-        request.file_manager().touch_file(dir_fh.id.clone()).await;
-        let dir = dir_fh.file.read_dir().unwrap();
-
-        let mut fnames = Vec::new();
-        let mut filehandles = Vec::new();
-        let dircount: usize = self.dircount as usize;
-        let maxcount: usize = self.maxcount as usize;
-        let mut dircount_actual = 0;
-        // get a list of filenames and filehandles
-        for (i, entry) in dir.enumerate() {
-            let name = entry.filename().into_bytes();
-            fnames.push(name.clone());
-            // if the cookie value is progressed, we add only subsequent filehandles
-            // https://datatracker.ietf.org/doc/html/rfc7530#section-16.24.4
-            // To enable some client environments, the cookie values of 0, 1, and 2 are to be considered reserved.
-            if (i + 2) >= self.cookie as usize {
-                // this is a poor man's estimation of the XRD outputs bytes, must be improved
-                // we need to know the definitive size of the output of the XDR message here, but how?
-                dircount_actual = dircount_actual + 8 + name.len() + 5;
-                if (dircount == 0 || dircount > filehandles.len()) && (maxcount == 0 || maxcount > dircount_actual) {
-                    let filehandle = request
-                        .file_manager()
-                        .get_filehandle_for_path(entry.as_str().to_string())
-                        .await;
-                    match filehandle {
-                        Err(_e) => {
-                            error!("None filehandle");
-                            return NfsOpResponse {
-                                request,
-                                result: None,
-                                status: NfsStat4::Nfs4errFhexpired,
-                            };
-                        }
-                        Ok(filehandle) => {
-                            // https://datatracker.ietf.org/doc/html/rfc7530#section-16.24.4
-                            // To enable some client environments, the cookie values of 0, 1, and 2 are to be considered reserved.
-                            filehandles.push((i + 3, filehandle));
-                        }
-                    }
-                }
-            }
-        }
+        let mut cookie = match self.cookie {
+            0 => 0,
+            1..=RESERVED_COOKIES => return error(request, NfsStat4::Nfs4errBadCookie),
+            cookie => cookie - RESERVED_COOKIES,
+        };
+        // https://datatracker.ietf.org/doc/html/rfc7530#section-16.24.4
+        // every backend keeps its cookies valid while entries are added or
+        // removed (see StorageBackend::readdir), so there is nothing for a
+        // verifier to catch: it is always zero and the one of the client
+        // isn't checked.
+        let cookieverf = [0u8; 8];
 
-        // get a seed of this directory, concat all files names
-        let seed: Vec<u8> = fnames.iter().flatten().cloned().collect();
-        // take only every nth char to create a cookie verifier
-        let mut cookieverf = seed
-            .iter()
-            .step_by(seed.len() / 8 + 1)
-            .copied()
-            .collect::<Vec<_>>();
-        if self.cookie != 0 && cookieverf != self.cookieverf {
-            error!("Nfs4errNotSame");
-            return NfsOpResponse {
-                request,
-                result: None,
-                status: NfsStat4::Nfs4errNotSame,
-            };
+        let maxcount = self.maxcount as usize;
+        let dircount = self.dircount as usize;
+        if maxcount < RESOK_SIZE {
+            return error(request, NfsStat4::Nfs4errToosmall);
         }
-
-        // if this directory is empty, we can't create a cookie verifier based on the dir contents
-        // setting it to a default value
-        if cookieverf.is_empty() {
-            cookieverf = [0u8; 8].to_vec();
-        } else if cookieverf.len() < 8 {
-            let mut diff = 8 - cookieverf.len();
-            while diff > 0 {
-                cookieverf.push(0);
-                diff -= 1;
-            }
-        }
-
-        let mut tnextentry = None;
-        let mut added_entries = 0;
-        for (cookie, fh) in filehandles.into_iter().rev() {
-            let resp = request
+        let mut size = RESOK_SIZE;
+        let mut dirsize = 0;
+        let mut entries = Vec::new();
+        let eof = 'listing: loop {
+            let page = match request
                 .file_manager()
-                .filehandle_attrs(&self.attr_request, &fh);
-            let (answer_attrs, attrs) = match resp {
-                Some(inner) => inner,
-                None => {
-                    return NfsOpResponse {
-                        request,
-                        result: None,
-                        status: NfsStat4::Nfs4errServerfault,
-                    };
+                .read_dir_page(dir_fh, cookie, BATCH_SIZE)
+                .await
+            {
+                Ok(page) => page,
+                Err(e) => {
+                    error!("FileManagerError {:?}", e);
+                    return error(request, e.nfs_error);
                 }
             };
-
-            let entry = Entry4 {
-                name: fh.file.filename().into_bytes(),
-                cookie: cookie as u64,
-                attrs: Fattr4 {
-                    attrmask: answer_attrs,
-                    attr_vals: attrs,
-                },
-                nextentry: if tnextentry.is_some() {
-                    Some(Box::new(tnextentry.unwrap()))
-                } else {
-                    None
-                },
-            };
-            added_entries += 1;
-            tnextentry = Some(entry);
-        }
-        let eof = {
-            if tnextentry.is_some()
-                && (tnextentry.clone().unwrap().cookie + added_entries) >= fnames.len() as u64
-            {
-                true
-            } else {
-                tnextentry.is_none()
+            for entry in page.entries {
+                // paths are UTF-8, no operation could look up, open or
+                // remove an entry with another name, so it isn't listed
+                let Ok(name) = String::from_utf8(entry.name) else {
+                    debug!("Entry with a name that is not UTF-8 skipped");
+                    cookie = entry.cookie;
+                    continue;
+                };
+                let Some(entry_cookie) = entry.cookie.checked_add(RESERVED_COOKIES) else {
+                    error!("Cookie {} of the backend out of range", entry.cookie);
+                    return error(request, NfsStat4::Nfs4errServerfault);
+                };
+                // dircount only counts the cookie and the name of the entries
+                let entry_dirsize = 8 + opaque_size(name.len());
+                if dircount != 0 && dirsize + entry_dirsize > dircount && !entries.is_empty() {
+                    break 'listing false;
+                }
+                let attrs =
+                    match entry_attrs(&request, &self.attr_request, &dir_fh.path, &name).await {
+                        Ok(Some(attrs)) => attrs,
+                        // removed since the directory was read
+                        Ok(None) => {
+                            cookie = entry.cookie;
+                            continue;
+                        }
+                        Err(status) => return error(request, status),
+                    };
+                // the entry is preceded by the discriminant of its list link
+                let entry_size = 4
+                    + entry_dirsize
+                    + serde_xdr::to_bytes(&attrs).map_or(usize::MAX, |bytes| bytes.len());
+                if size + entry_size > maxcount {
+                    if entries.is_empty() {
+                        return error(request, NfsStat4::Nfs4errToosmall);
+                    }
+                    break 'listing false;
+                }
+                size += entry_size;
+                dirsize += entry_dirsize;
+                entries.push(Entry4 {
+                    name: name.into_bytes(),
+                    cookie: entry_cookie,
+                    attrs,
+                    nextentry: None,
+                });
+                cookie = entry.cookie;
+            }
+            if page.eof {
+                break true;
             }
         };
 
+        // link the entries, from the last one on
+        let entries = entries
+            .into_iter()
+            .rev()
+            .fold(None, |nextentry, mut entry| {
+                entry.nextentry = nextentry.map(Box::new);
+                Some(entry)
+            });
         NfsOpResponse {
             request,
             result: Some(NfsResOp4::Opreaddir(ReadDir4res::Resok4(ReadDir4resok {
-                reply: DirList4 {
-                    // len: if tnextentry.is_some() { 1 } else { 0 },
-                    entries: tnextentry.clone(),
-                    eof,
-                },
-                cookieverf: cookieverf.as_slice().try_into().unwrap(),
+                reply: DirList4 { entries, eof },
+                cookieverf,
             }))),
             status: NfsStat4::Nfs4Ok,
         }
     }
 }
 
+// the requested attributes of the entry name, None if it doesn't exist
+async fn entry_attrs(
+    request: &NfsRequest<'_>,
+    attr_request: &Attrlist4<FileAttr>,
+    dir: &str,
+    name: &str,
+) -> Result<Option<Fattr4>, NfsStat4> {
+    // only entries with attributes to report need a filehandle
+    if attr_request.is_empty() {
+        return Ok(Some(Fattr4 {
+            attrmask: Attrlist4::<FileAttr>::new(None),
+            attr_vals: Attrlist4::<FileAttrValue>::new(None),
+            decode_error: None,
+        }));
+    }
+    let attrs = match request.get_filehandle_for_path(join_path(dir, name)).await {
+        // entries that are junctions to exports are reported with the
+        // attributes of the export root
+        Ok(fh) => request
+            .file_manager_for(&fh.id)
+            .filehandle_attrs(attr_request, &fh)
+            .ok_or(NfsStat4::Nfs4errServerfault),
+        Err(e) if e.nfs_error == NfsStat4::Nfs4errNoent => return Ok(None),
+        Err(e) => Err(e.nfs_error),
+    };
+    match attrs {
+        Ok((attrmask, attr_vals)) => Ok(Some(Fattr4 {
+            attrmask,
+            attr_vals,
+            decode_error: None,
+        })),
+        // https://datatracker.ietf.org/doc/html/rfc7530#section-16.24.4
+        // the error is reported for the entry if the client asked for
+        // rdattr_error, otherwise the whole READDIR fails
+        Err(status) if attr_request.contains(&FileAttr::RdattrError) => {
+            error!("Attributes of entry {:?} not readable: {:?}", name, status);
+            Ok(Some(Fattr4 {
+                attrmask: Attrlist4::<FileAttr>::new(Some(vec![FileAttr::RdattrError])),
+                attr_vals: Attrlist4::<FileAttrValue>::new(Some(vec![FileAttrValue::RdattrError(
+                    status,
+                )])),
+                decode_error: None,
+            }))
+        }
+        Err(status) => Err(status),
+    }
+}
+
 #[cfg(test)]
 mod integration_tests {
+    use std::{collections::BTreeSet, sync::Arc};
 
     use bold_proto::nfs4_proto::Attrlist4;
+    use proptest::prelude::*;
     use tracing_test::traced_test;
+    use vfs::{MemoryFS, VfsPath};
 
     use crate::{
         server::{
             nfs40::{
-                DirList4, FileAttr, FileAttrValue, NfsFtype4, NfsResOp4, NfsStat4, PutFh4args,
-                ReadDir4res, ReadDir4resok, Readdir4args,
+                DirList4, FileAttr, FileAttrValue, Getattr4args, NfsFtype4, NfsResOp4, NfsStat4,
+                PutFh4args, ReadDir4res, ReadDir4resok, Readdir4args,
             },
             operation::NfsOperation,
+            request::NfsRequest,
+        },
+        storage::{MemoryBackend, StorageError},
+        test_utils::{
+            create_fake_fs, create_nfs40_server, create_nfs40_server_with_backend, FaultyBackend,
         },
-        test_utils::{create_fake_fs, create_nfs40_server},
     };
 
     #[tokio::test]
@@ -178,11 +231,9 @@ mod integration_tests {
     async fn test_read_directory() {
         // dummy fs, empty
         let request = create_nfs40_server(None).await;
-        let fh = request.file_manager().get_root_filehandle().await;
+        let fh = request.file_manager().get_root_filehandle().await.unwrap();
 
-        let putfh_args = PutFh4args {
-            object: fh.unwrap().id,
-        };
+        let putfh_args = PutFh4args { object: fh.id };
         let putfh_request = putfh_args.execute(request).await;
 
         let readdir_args = Readdir4args {
@@ -216,7 +267,7 @@ mod integration_tests {
         assert_eq!(
             readdir_response.result,
             Some(NfsResOp4::Opreaddir(ReadDir4res::Resok4(ReadDir4resok {
-                cookieverf: [0, 0, 0, 0, 0, 0, 0, 0],
+                cookieverf: [0u8; 8],
                 reply: DirList4 {
                     entries: None,
                     eof: true
@@ -267,17 +318,17 @@ mod integration_tests {
             NfsResOp4::Opreaddir(ReadDir4res::Resok4(res)) => {
                 assert_eq!(res.cookieverf.len(), 8);
                 let entries = res.reply.entries.unwrap();
-                assert_eq!(entries.cookie, 3);
+                assert!(entries.cookie > 2);
                 if entries.name == "file1.txt".as_bytes() {
-                    assert_eq!(entries.attrs.attrmask.len(), 14);
-                    assert_eq!(entries.attrs.attr_vals.len(), 14);
+                    assert_eq!(entries.attrs.attrmask.len(), 15);
+                    assert_eq!(entries.attrs.attr_vals.len(), 15);
                     assert_eq!(
                         entries.attrs.attr_vals[0],
                         FileAttrValue::Type(NfsFtype4::Nf4reg)
                     );
                 } else if entries.name == "dir1".as_bytes() {
-                    assert_eq!(entries.attrs.attrmask.len(), 14);
-                    assert_eq!(entries.attrs.attr_vals.len(), 14);
+                    assert_eq!(entries.attrs.attrmask.len(), 15);
+                    assert_eq!(entries.attrs.attr_vals.len(), 15);
                     assert_eq!(
                         entries.attrs.attr_vals[0],
                         FileAttrValue::Type(NfsFtype4::Nf4dir)
@@ -286,17 +337,17 @@ mod integration_tests {
                     panic!("Unexpected entry");
                 }
                 let next = entries.nextentry.unwrap();
-                assert_eq!(next.cookie, 4);
+                assert!(next.cookie > entries.cookie);
                 if next.name == "file1.txt".as_bytes() {
-                    assert_eq!(next.attrs.attrmask.len(), 14);
-                    assert_eq!(next.attrs.attr_vals.len(), 14);
+                    assert_eq!(next.attrs.attrmask.len(), 15);
+                    assert_eq!(next.attrs.attr_vals.len(), 15);
                     assert_eq!(
                         next.attrs.attr_vals[0],
                         FileAttrValue::Type(NfsFtype4::Nf4reg)
                     );
                 } else if next.name == "dir1".as_bytes() {
-                    assert_eq!(next.attrs.attrmask.len(), 14);
-                    assert_eq!(next.attrs.attr_vals.len(), 14);
+                    assert_eq!(next.attrs.attrmask.len(), 15);
+                    assert_eq!(next.attrs.attr_vals.len(), 15);
                     assert_eq!(
                         next.attrs.attr_vals[0],
                         FileAttrValue::Type(NfsFtype4::Nf4dir)
@@ -310,4 +361,297 @@ mod integration_tests {
             _ => panic!("Expected Resok4"),
         }
     }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_read_directory_pages() {
+        let backend = Arc::new(MemoryBackend::new());
+        for i in 0..300 {
+            backend.write_file(&format!("/file{i}"), b"").unwrap();
+        }
+        let mut request = create_nfs40_server_with_backend(backend.clone()).await;
+        let fh = request.file_manager().get_root_filehandle().await.unwrap();
+        request = PutFh4args { object: fh.id }.execute(request).await.request;
+
+        let readdir = |cookie, maxcount| Readdir4args {
+            cookie,
+            cookieverf: [0u8; 8],
+            dircount: 0,
+            maxcount,
+            attr_request: Attrlist4::<FileAttr>::new(None),
+        };
+        // too small for the reply itself or for the first entry
+        for maxcount in [8, 20] {
+            let response = readdir(0, maxcount).execute(request).await;
+            assert_eq!(response.status, NfsStat4::Nfs4errToosmall);
+            request = response.request;
+        }
+        // the reserved cookies
+        let response = readdir(1, 1024).execute(request).await;
+        assert_eq!(response.status, NfsStat4::Nfs4errBadCookie);
+        request = response.request;
+
+        let mut names = BTreeSet::new();
+        let mut cookie = 0;
+        let mut pages = 0;
+        loop {
+            let response = readdir(cookie, 1024).execute(request).await;
+            request = response.request;
+            let Some(NfsResOp4::Opreaddir(ReadDir4res::Resok4(res))) = response.result else {
+                panic!("Unexpected response: {:?}", response.status);
+            };
+            pages += 1;
+            // a file created while listing doesn't disturb the listing
+            if pages == 2 {
+                backend.write_file("/late", b"").unwrap();
+            }
+            let mut entry = res.reply.entries.as_ref();
+            while let Some(e) = entry {
+                assert!(e.cookie > cookie);
+                cookie = e.cookie;
+                assert!(names.insert(String::from_utf8(e.name.clone()).unwrap()));
+                entry = e.nextentry.as_deref();
+            }
+            if res.reply.eof {
+                break;
+            }
+        }
+        assert!(pages > 2);
+        assert!(names.remove("late"));
+        assert_eq!(
+            names,
+            (0..300)
+                .map(|i| format!("file{i}"))
+                .collect::<BTreeSet<_>>()
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_read_directory_rdattr_error() {
+        let memory = MemoryBackend::new();
+        memory.write_file("/good", b"").unwrap();
+        memory.write_file("/bad", b"").unwrap();
+        let backend = FaultyBackend::new(Arc::new(memory));
+        backend.fail("/bad", StorageError::Io("disk on fire".to_string()));
+        let mut request = create_nfs40_server_with_backend(Arc::new(backend)).await;
+        let fh = request.file_manager().get_root_filehandle().await.unwrap();
+        request = PutFh4args { object: fh.id }.execute(request).await.request;
+
+        let readdir = |attr_request: Vec<FileAttr>| Readdir4args {
+            cookie: 0,
+            cookieverf: [0u8; 8],
+            dircount: 0,
+            maxcount: 4096,
+            attr_request: Attrlist4::<FileAttr>::new(Some(attr_request)),
+        };
+        // without rdattr_error the whole listing fails
+        let response = readdir(vec![FileAttr::Type]).execute(request).await;
+        assert_eq!(response.status, NfsStat4::Nfs4errIo);
+
+        // with it, the error is reported for the entry
+        let response = readdir(vec![FileAttr::Type, FileAttr::RdattrError])
+            .execute(response.request)
+            .await;
+        assert_eq!(response.status, NfsStat4::Nfs4Ok);
+        let Some(NfsResOp4::Opreaddir(ReadDir4res::Resok4(res))) = response.result else {
+            panic!("Unexpected response: {:?}", response.status);
+        };
+        assert!(res.reply.eof);
+        let mut entry = res.reply.entries.as_ref();
+        let mut seen = 0;
+        while let Some(e) = entry {
+            match e.name.as_slice() {
+                b"good" => assert_eq!(
+                    e.attrs.attr_vals.to_vec(),
+                    vec![
+                        FileAttrValue::Type(NfsFtype4::Nf4reg),
+                        FileAttrValue::RdattrError(NfsStat4::Nfs4Ok)
+                    ]
+                ),
+                b"bad" => assert_eq!(
+                    e.attrs.attr_vals.to_vec(),
+                    vec![FileAttrValue::RdattrError(NfsStat4::Nfs4errIo)]
+                ),
+                _ => panic!("Unexpected entry {:?}", e.name),
+            }
+            seen += 1;
+            entry = e.nextentry.as_deref();
+        }
+        assert_eq!(seen, 2);
+
+        // GETATTR reads the attributes of the filehandle it has
+        let response = Getattr4args {
+            attr_request: Attrlist4::<FileAttr>::new(Some(vec![FileAttr::RdattrError])),
+        }
+        .execute(response.request)
+        .await;
+        assert_eq!(response.status, NfsStat4::Nfs4Ok);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_read_directory_vanished_entries() {
+        let memory = MemoryBackend::new();
+        for i in 0..300 {
+            memory.write_file(&format!("/file{i}"), b"").unwrap();
+        }
+        // all but the last entry are gone by the time their attributes are read
+        let backend = FaultyBackend::new(Arc::new(memory));
+        for i in 0..299 {
+            backend.fail(&format!("/file{i}"), StorageError::NotFound);
+        }
+        let (_, names) = read_pages(
+            create_nfs40_server_with_backend(Arc::new(backend)).await,
+            "/",
+            4096,
+            &[FileAttr::Type],
+        )
+        .await;
+        assert_eq!(names, vec!["file299".to_string()]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[traced_test]
+    async fn test_read_directory_non_utf8_names() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join(OsStr::from_bytes(b"caf\xe9")), b"").unwrap();
+        std::fs::write(tmp.path().join("file1.txt"), b"").unwrap();
+        let backend = crate::storage::PhysicalBackend::new(tmp.path()).with_handle_db(None);
+        let mut request = create_nfs40_server_with_backend(Arc::new(backend)).await;
+        let fh = request.file_manager().get_root_filehandle().await.unwrap();
+        request = PutFh4args { object: fh.id }.execute(request).await.request;
+
+        let readdir = |attr_request: Vec<FileAttr>| Readdir4args {
+            cookie: 0,
+            cookieverf: [0u8; 8],
+            dircount: 0,
+            maxcount: 4096,
+            attr_request: Attrlist4::<FileAttr>::new(Some(attr_request)),
+        };
+        for attr_request in [
+            vec![FileAttr::Type],
+            vec![FileAttr::Type, FileAttr::RdattrError],
+        ] {
+            let response = readdir(attr_request.clone()).execute(request).await;
+            request = response.request;
+            let Some(NfsResOp4::Opreaddir(ReadDir4res::Resok4(res))) = response.result else {
+                panic!("Unexpected response: {:?}", response.status);
+            };
+            // the name can't be looked up, the entry is left out
+            let entry = res.reply.entries.unwrap();
+            assert_eq!(entry.name, b"file1.txt");
+            assert_eq!(entry.attrs.attrmask.len(), attr_request.len());
+            assert!(entry.nextentry.is_none());
+            assert!(res.reply.eof);
+        }
+    }
+
+    // reads the directory page by page, returns the names in the order of the pages
+    async fn read_pages(
+        mut request: NfsRequest<'static>,
+        path: &str,
+        maxcount: u32,
+        attr_request: &[FileAttr],
+    ) -> (NfsRequest<'static>, Vec<String>) {
+        let fh = request
+            .file_manager()
+            .get_filehandle_for_path(path.to_string())
+            .await
+            .unwrap();
+        request = PutFh4args { object: fh.id }.execute(request).await.request;
+        let mut names = Vec::new();
+        let mut cookie = 0;
+        loop {
+            let response = Readdir4args {
+                cookie,
+                cookieverf: [0u8; 8],
+                dircount: maxcount / 2,
+                maxcount,
+                attr_request: Attrlist4::<FileAttr>::new(Some(attr_request.to_vec())),
+            }
+            .execute(request)
+            .await;
+            request = response.request;
+            let Some(NfsResOp4::Opreaddir(ReadDir4res::Resok4(res))) = response.result else {
+                panic!("Unexpected response: {:?}", response.status);
+            };
+            let mut entry = res.reply.entries.as_ref();
+            // only the last page may be empty
+            assert!(entry.is_some() || res.reply.eof);
+            while let Some(e) = entry {
+                assert!(e.cookie > cookie);
+                cookie = e.cookie;
+                assert_eq!(e.attrs.attrmask.len(), attr_request.len());
+                names.push(String::from_utf8(e.name.clone()).unwrap());
+                entry = e.nextentry.as_deref();
+            }
+            if res.reply.eof {
+                return (request, names);
+            }
+        }
+    }
+
+    // subdirectories of the root with the names of their files
+    fn directory_shape() -> impl Strategy<Value = Vec<(String, Vec<String>)>> {
+        let names = || prop::collection::btree_set("[a-zA-Z0-9_]{1,40}", 0..40);
+        prop::collection::btree_map("[a-z]{1,8}", names(), 0..5).prop_map(|dirs| {
+            dirs.into_iter()
+                .map(|(dir, names)| (dir, names.into_iter().collect()))
+                .collect()
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn test_read_directory_pages_match_read_dir(
+            shape in directory_shape(),
+            root_files in prop::collection::btree_set("[a-zA-Z0-9_.]{1,60}", 0..60),
+            // the largest entry, a name of 60 bytes with attributes, takes 112
+            // bytes, smaller pages are rightly too small for it
+            maxcount in 128u32..2000,
+            with_attrs in any::<bool>(),
+        ) {
+            let root: VfsPath = MemoryFS::new().into();
+            for name in root_files.iter().filter(|name| !name.starts_with('.')) {
+                root.join(name).unwrap().create_file().unwrap();
+            }
+            for (dir, names) in shape.iter() {
+                let dir = root.join(format!("d_{dir}")).unwrap();
+                dir.create_dir_all().unwrap();
+                for name in names {
+                    dir.join(name).unwrap().create_file().unwrap();
+                }
+            }
+            let attr_request = match with_attrs {
+                true => vec![FileAttr::Type, FileAttr::Size, FileAttr::Owner],
+                false => vec![],
+            };
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            runtime.block_on(async {
+                let mut request = create_nfs40_server(Some(root.clone())).await;
+                let mut dirs = vec![root.clone()];
+                dirs.extend(root.read_dir().unwrap().filter(|path| path.is_dir().unwrap()));
+                for dir in dirs {
+                    let path = match dir.as_str() {
+                        "" => "/",
+                        path => path,
+                    };
+                    let (next_request, mut names) =
+                        read_pages(request, path, maxcount, &attr_request).await;
+                    request = next_request;
+                    let mut expected: Vec<String> =
+                        dir.read_dir().unwrap().map(|entry| entry.filename()).collect();
+                    names.sort();
+                    expected.sort();
+                    assert_eq!(names, expected);
+                }
+            });
+        }
+    }
 }
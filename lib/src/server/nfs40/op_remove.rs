@@ -2,13 +2,13 @@ use async_trait::async_trait;
 use tracing::{debug, error};
 
 use crate::server::{
-    nfs40::{ChangeInfo4, NfsStat4},
+    nfs40::{names::entry_path, ChangeInfo4, NfsStat4},
     operation::NfsOperation,
     request::NfsRequest,
     response::NfsOpResponse,
 };
 
-use bold_proto::nfs4_proto::{NfsResOp4, Remove4args, Remove4res};
+use bold_proto::nfs4_proto::{NfsResOp4, Remove4args, Remove4res, ACCESS4_DELETE, ACCESS4_LOOKUP};
 
 #[async_trait]
 impl NfsOperation for Remove4args {
@@ -35,10 +35,39 @@ impl NfsOperation for Remove4args {
                 };
             }
             Some(filehandle) => {
-                let path = filehandle
-                    .file
-                    .join(std::str::from_utf8(&self.target).unwrap())
-                    .unwrap();
+                let path = match entry_path(filehandle, &self.target) {
+                    Ok(path) => path,
+                    Err(status) => {
+                        return NfsOpResponse {
+                            request,
+                            result: None,
+                            status,
+                        };
+                    }
+                };
+                // removing an entry needs write and execute permission on the directory
+                if let Err(status) =
+                    request.check_permission(filehandle, ACCESS4_DELETE | ACCESS4_LOOKUP)
+                {
+                    return NfsOpResponse {
+                        request,
+                        result: None,
+                        status,
+                    };
+                }
+                // in a sticky directory only the owners of the entry or the
+                // directory may remove it
+                if filehandle.attr_mode & 0o1000 != 0 && !request.is_owner(filehandle) {
+                    if let Ok(target) = request.get_filehandle_for_path(path.clone()).await {
+                        if !request.is_owner(&target) {
+                            return NfsOpResponse {
+                                request,
+                                result: None,
+                                status: NfsStat4::Nfs4errPerm,
+                            };
+                        }
+                    }
+                }
                 let res = request.file_manager().remove_file(path).await;
                 match res {
                     Ok(cinfo) => NfsOpResponse {
@@ -0,0 +1,218 @@
+use async_trait::async_trait;
+use tracing::{debug, error};
+
+use crate::server::{
+    nfs40::names::entry_path, operation::NfsOperation, request::NfsRequest, response::NfsOpResponse,
+};
+
+use bold_proto::nfs4_proto::{NfsResOp4, NfsStat4, SecInfo4args, SecInfo4res, ACCESS4_LOOKUP};
+
+#[async_trait]
+impl NfsOperation for SecInfo4args {
+    /// The SECINFO operation is used by the client to obtain a list of valid
+    /// RPC authentication flavors for a specific directory filehandle, file
+    /// name pair, ordered by the preference of the server.
+    ///
+    /// Please read: [RFC 7530](https://datatracker.ietf.org/doc/html/rfc7530#section-16.31)
+    async fn execute<'a>(&self, request: NfsRequest<'a>) -> NfsOpResponse<'a> {
+        debug!(
+            "Operation 33: SECINFO - Obtain Available Security {:?}, with request {:?}",
+            self, request
+        );
+        let error = |request, status| NfsOpResponse {
+            request,
+            result: None,
+            status,
+        };
+        let Some(dir) = request.current_filehandle() else {
+            error!("None filehandle");
+            return error(request, NfsStat4::Nfs4errNofilehandle);
+        };
+        let path = match entry_path(dir, &self.name) {
+            Ok(path) => path,
+            Err(status) => return error(request, status),
+        };
+        if let Err(status) = request.check_permission(dir, ACCESS4_LOOKUP) {
+            return error(request, status);
+        }
+
+        // the flavors of the export the object is on, which differ from
+        // those of the directory for the mount point of an export
+        let filehandle = match request.get_filehandle_for_path(path).await {
+            Ok(filehandle) => filehandle,
+            Err(e) => return error(request, e.nfs_error),
+        };
+        let Some(export) = request.exports().get(&filehandle.id) else {
+            return error(request, NfsStat4::Nfs4errStale);
+        };
+        let flavors = export
+            .flavors()
+            .iter()
+            .map(|flavor| flavor.secinfo())
+            .collect();
+
+        NfsOpResponse {
+            request,
+            result: Some(NfsResOp4::OpSecinfo(SecInfo4res::Resok4(flavors))),
+            status: NfsStat4::Nfs4Ok,
+        }
+    }
+}
+
+#[cfg(test)]
+mod integration_tests {
+    use std::sync::Arc;
+
+    use bold_proto::{
+        nfs4_proto::*,
+        rpc_proto::{AUTH_NONE, AUTH_SYS, RPC_GSS_SVC_PRIVACY},
+    };
+    use tracing_test::traced_test;
+
+    use crate::{
+        server::{
+            access::SecFlavor,
+            clientmanager::ClientManagerHandle,
+            exports::{ExportConfig, Exports},
+            gss::KRB5_OID,
+            operation::NfsOperation,
+            request::NfsRequest,
+        },
+        storage::MemoryBackend,
+    };
+
+    fn create_request() -> NfsRequest<'static> {
+        let data = MemoryBackend::new();
+        data.write_file("/file1.txt", b"data").unwrap();
+        let mut secure = ExportConfig::new("/secure", Arc::new(MemoryBackend::new()));
+        secure.sec = vec![SecFlavor::Krb5p, SecFlavor::AuthSys];
+        let exports = Exports::new(
+            vec![ExportConfig::new("/data", Arc::new(data)), secure],
+            None,
+        );
+        NfsRequest::new(
+            "127.0.0.1:700".to_string(),
+            ClientManagerHandle::new(),
+            exports,
+            0,
+            None,
+        )
+    }
+
+    async fn secinfo(
+        request: NfsRequest<'static>,
+        name: &str,
+    ) -> (NfsRequest<'static>, Result<Vec<SeCinfo4>, NfsStat4>) {
+        let args = SecInfo4args {
+            name: name.as_bytes().to_vec(),
+        };
+        let response = args.execute(request).await;
+        let result = match response.result {
+            Some(NfsResOp4::OpSecinfo(SecInfo4res::Resok4(flavors))) => Ok(flavors),
+            _ => Err(response.status),
+        };
+        (response.request, result)
+    }
+
+    async fn enter(mut request: NfsRequest<'static>, path: &str) -> NfsRequest<'static> {
+        let filehandle = request.get_filehandle_for_path(path.to_string()).await;
+        request.set_filehandle(filehandle.unwrap());
+        request
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_secinfo() {
+        let mut request = create_request();
+        let root = request
+            .exports()
+            .root()
+            .get_root_filehandle()
+            .await
+            .unwrap();
+        request.set_filehandle(root);
+
+        let (request, flavors) = secinfo(request, "secure").await;
+        assert_eq!(
+            flavors,
+            Ok(vec![
+                SeCinfo4::FlavorInfo(RpcSecGssInfo {
+                    oid: KRB5_OID.to_vec(),
+                    qop: 0,
+                    service: RPC_GSS_SVC_PRIVACY,
+                }),
+                SeCinfo4::Flavor(AUTH_SYS),
+            ])
+        );
+        // an export without flavors can be used with any of them
+        let (request, flavors) = secinfo(request, "data").await;
+        assert_eq!(
+            flavors,
+            Ok(vec![
+                SeCinfo4::Flavor(AUTH_SYS),
+                SeCinfo4::Flavor(AUTH_NONE)
+            ])
+        );
+        let (request, flavors) = secinfo(request, "missing").await;
+        assert_eq!(flavors, Err(NfsStat4::Nfs4errNoent));
+        let (request, flavors) = secinfo(request, "..").await;
+        assert_eq!(flavors, Err(NfsStat4::Nfs4errBadname));
+
+        // objects inside an export
+        let request = enter(request, "/data").await;
+        let (request, flavors) = secinfo(request, "file1.txt").await;
+        assert_eq!(
+            flavors,
+            Ok(vec![
+                SeCinfo4::Flavor(AUTH_SYS),
+                SeCinfo4::Flavor(AUTH_NONE)
+            ])
+        );
+        let request = enter(request, "/file1.txt").await;
+        let (_, flavors) = secinfo(request, "file1.txt").await;
+        assert_eq!(flavors, Err(NfsStat4::Nfs4errNotdir));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_wrong_security_flavor() {
+        let mut request = create_request();
+        request.set_flavor(Some(SecFlavor::AuthNone));
+        let root = request
+            .exports()
+            .root()
+            .get_root_filehandle()
+            .await
+            .unwrap();
+        request.set_filehandle(root);
+
+        // the pseudo file system takes the flavors of all exports
+        assert_eq!(request.check_filehandle_access(), Ok(()));
+        let mut request = enter(request, "/data").await;
+        assert_eq!(request.check_filehandle_access(), Ok(()));
+        let root = request
+            .exports()
+            .root()
+            .get_root_filehandle()
+            .await
+            .unwrap();
+        request.set_filehandle(root);
+        let mut request = enter(request, "/secure").await;
+        assert_eq!(
+            request.check_filehandle_access(),
+            Err(NfsStat4::Nfs4errWrongsec)
+        );
+        // SECINFO is answered whatever the flavor
+        let getfh = NfsArgOp::Opgetfh(());
+        let secinfo = NfsArgOp::OpSecinfo(SecInfo4args {
+            name: b"dir".to_vec(),
+        });
+        assert_eq!(request.check_access(&getfh), Err(NfsStat4::Nfs4errWrongsec));
+        assert_eq!(request.check_access(&secinfo), Ok(()));
+
+        request.set_flavor(Some(SecFlavor::AuthSys));
+        assert_eq!(request.check_access(&getfh), Ok(()));
+        request.set_flavor(Some(SecFlavor::Krb5i));
+        assert_eq!(request.check_access(&getfh), Err(NfsStat4::Nfs4errWrongsec));
+    }
+}
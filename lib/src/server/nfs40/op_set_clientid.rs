@@ -29,10 +29,23 @@ impl NfsOperation for SetClientId4args {
             raddr: self.callback.cb_location.raddr.clone(),
             callback_ident: self.callback_ident,
         };
+        // the callback path is only needed for delegations, a client is
+        // accepted without one
+        if callback.addr().is_none() {
+            debug!(
+                "Unusable callback address {:?} of netid {:?}",
+                callback.raddr, callback.rnetid
+            );
+        }
 
         let res = request
             .client_manager()
-            .upsert_client(self.client.verifier, self.client.id.clone(), callback, None)
+            .upsert_client(
+                self.client.verifier,
+                self.client.id.clone(),
+                callback,
+                request.principal(),
+            )
             .await;
         match res {
             Ok(client) => NfsOpResponse {
@@ -45,10 +58,10 @@ impl NfsOperation for SetClientId4args {
                 ))),
                 status: NfsStat4::Nfs4Ok,
             },
-            Err(_e) => NfsOpResponse {
+            Err(e) => NfsOpResponse {
                 request,
                 result: None,
-                status: NfsStat4::Nfs4errServerfault,
+                status: e.nfs_error,
             },
         }
     }
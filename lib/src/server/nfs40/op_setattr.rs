@@ -5,7 +5,9 @@ use crate::server::{
     nfs40::NfsStat4, operation::NfsOperation, request::NfsRequest, response::NfsOpResponse,
 };
 
-use bold_proto::nfs4_proto::{Attrlist4, FileAttr, NfsResOp4, SetAttr4args, SetAttr4res};
+use bold_proto::nfs4_proto::{
+    Attrlist4, FileAttr, FileAttrValue, NfsResOp4, SetAttr4args, SetAttr4res,
+};
 
 #[async_trait]
 impl NfsOperation for SetAttr4args {
@@ -28,17 +30,55 @@ impl NfsOperation for SetAttr4args {
                 }
             }
             Some(filehandle) => {
+                if let Some(error) = &self.obj_attributes.decode_error {
+                    let status = NfsStat4::from(error);
+                    return NfsOpResponse {
+                        request,
+                        result: Some(NfsResOp4::Opsetattr(SetAttr4res {
+                            status: status.clone(),
+                            attrsset: Attrlist4::<FileAttr>::new(None),
+                        })),
+                        status,
+                    };
+                }
+                // only the owner may change who has access to the object
+                let changes_access = self
+                    .obj_attributes
+                    .attr_vals
+                    .iter()
+                    .any(|attr| matches!(attr, FileAttrValue::Mode(_) | FileAttrValue::Acl(_)));
+                if changes_access && !request.is_owner(filehandle) {
+                    return NfsOpResponse {
+                        request,
+                        result: Some(NfsResOp4::Opsetattr(SetAttr4res {
+                            status: NfsStat4::Nfs4errPerm,
+                            attrsset: Attrlist4::<FileAttr>::new(None),
+                        })),
+                        status: NfsStat4::Nfs4errPerm,
+                    };
+                }
                 let attrsset = if !self.obj_attributes.attrmask.is_empty() {
-                    let attrsset = request
+                    let attrsset = match request
                         .file_manager()
-                        .set_attr(&filehandle, &self.obj_attributes.attr_vals);
+                        .set_attr(filehandle, &self.obj_attributes.attr_vals)
+                        .await
+                    {
+                        Ok(attrsset) => attrsset,
+                        Err(e) => {
+                            return NfsOpResponse {
+                                request,
+                                result: Some(NfsResOp4::Opsetattr(SetAttr4res {
+                                    status: e.nfs_error.clone(),
+                                    attrsset: Attrlist4::<FileAttr>::new(None),
+                                })),
+                                status: e.nfs_error,
+                            };
+                        }
+                    };
 
-                    request
-                        .file_manager()
-                        .touch_file(filehandle.id.clone())
-                        .await;
+                    request.file_manager().touch_file(filehandle.id).await;
 
-                    match request.set_filehandle_id(filehandle.id.clone()).await {
+                    match request.set_filehandle_id(filehandle.id).await {
                         Ok(fh) => {
                             request.cache_filehandle(fh);
                         }
@@ -68,3 +108,195 @@ impl NfsOperation for SetAttr4args {
         }
     }
 }
+
+#[cfg(test)]
+mod integration_tests {
+    use std::sync::Arc;
+
+    use bold_proto::nfs4_proto::{
+        Attrlist4, Fattr4, FileAttr, FileAttrValue, NfsStat4, Nfsace4, SetAttr4args, Stateid4,
+        ACCESS4_EXTEND, ACCESS4_MODIFY, ACCESS4_READ, ACE4_ACCESS_ALLOWED_ACE_TYPE,
+        ACE4_ACCESS_DENIED_ACE_TYPE, ACE4_READ_DATA, ACE4_WRITE_DATA,
+    };
+    use tracing_test::traced_test;
+
+    use crate::{
+        server::{
+            access::Credentials, acl, nfs40::PutFh4args, operation::NfsOperation,
+            request::NfsRequest,
+        },
+        storage::MemoryBackend,
+        test_utils::create_nfs40_server_with_backend,
+    };
+
+    fn user(uid: u32) -> Option<Credentials> {
+        Some(Credentials {
+            uid,
+            gid: uid,
+            gids: vec![],
+        })
+    }
+
+    async fn setattr(
+        request: NfsRequest<'static>,
+        attrs: Vec<FileAttrValue>,
+    ) -> (NfsRequest<'static>, NfsStat4) {
+        let attrmask = attrs
+            .iter()
+            .map(|attr| match attr {
+                FileAttrValue::Mode(_) => FileAttr::Mode,
+                FileAttrValue::Acl(_) => FileAttr::Acl,
+                _ => unimplemented!(),
+            })
+            .collect();
+        let args = SetAttr4args {
+            stateid: Stateid4 {
+                seqid: 0,
+                other: [0; 12],
+            },
+            obj_attributes: Fattr4 {
+                attrmask: Attrlist4::<FileAttr>::new(Some(attrmask)),
+                attr_vals: Attrlist4::<FileAttrValue>::new(Some(attrs)),
+                decode_error: None,
+            },
+        };
+        let response = args.execute(request).await;
+        (response.request, response.status)
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_set_acl() {
+        let backend = MemoryBackend::new();
+        backend.write_file("/file1.txt", b"data").unwrap();
+        let request = create_nfs40_server_with_backend(Arc::new(backend)).await;
+        let fh = request
+            .file_manager()
+            .get_filehandle_for_path("/file1.txt".to_string())
+            .await
+            .unwrap();
+        let mut request = PutFh4args { object: fh.id }.execute(request).await.request;
+        // without an acl the mode decides
+        request.set_credentials(user(1000));
+        let fh = request.current_filehandle().unwrap().clone();
+        assert_eq!(request.granted_access(&fh), ACCESS4_READ);
+
+        // user 1000 may write, user 1001 is denied reading what everyone may read
+        let acl = vec![
+            Nfsace4 {
+                acetype: ACE4_ACCESS_ALLOWED_ACE_TYPE,
+                flag: 0,
+                access_mask: ACE4_READ_DATA | ACE4_WRITE_DATA,
+                who: b"1000".to_vec(),
+            },
+            Nfsace4 {
+                acetype: ACE4_ACCESS_DENIED_ACE_TYPE,
+                flag: 0,
+                access_mask: ACE4_READ_DATA,
+                who: b"1001".to_vec(),
+            },
+            Nfsace4 {
+                acetype: ACE4_ACCESS_ALLOWED_ACE_TYPE,
+                flag: 0,
+                access_mask: ACE4_READ_DATA,
+                who: acl::EVERYONE.to_vec(),
+            },
+        ];
+        // only the owner may set it
+        let (mut request, status) = setattr(request, vec![FileAttrValue::Acl(acl.clone())]).await;
+        assert_eq!(status, NfsStat4::Nfs4errPerm);
+        request.set_credentials(user(0));
+        let (mut request, status) = setattr(request, vec![FileAttrValue::Acl(acl.clone())]).await;
+        assert_eq!(status, NfsStat4::Nfs4Ok);
+
+        let fh = request.current_filehandle().unwrap().clone();
+        assert_eq!(fh.attr_acl, Some(acl));
+        // the mode follows the acl
+        assert_eq!(fh.attr_mode, 0o444);
+        request.set_credentials(user(1000));
+        assert_eq!(request.granted_access(&fh), ACCESS4_READ | ACCESS4_MODIFY);
+        request.set_credentials(user(1001));
+        assert_eq!(request.granted_access(&fh), 0);
+        request.set_credentials(user(1002));
+        assert_eq!(request.granted_access(&fh), ACCESS4_READ);
+
+        // and the acl follows the mode, keeping the named entries
+        request.set_credentials(user(0));
+        let (mut request, status) = setattr(request, vec![FileAttrValue::Mode(0o666)]).await;
+        assert_eq!(status, NfsStat4::Nfs4Ok);
+        let fh = request.current_filehandle().unwrap().clone();
+        assert_eq!(fh.attr_mode, 0o666);
+        request.set_credentials(user(1001));
+        assert_eq!(request.granted_access(&fh), ACCESS4_MODIFY | ACCESS4_EXTEND);
+        request.set_credentials(user(1002));
+        assert_eq!(
+            request.granted_access(&fh),
+            ACCESS4_READ | ACCESS4_MODIFY | ACCESS4_EXTEND
+        );
+
+        // audit entries are not supported
+        request.set_credentials(user(0));
+        let audit = vec![Nfsace4 {
+            acetype: 2,
+            flag: 0,
+            access_mask: ACE4_READ_DATA,
+            who: acl::EVERYONE.to_vec(),
+        }];
+        let (_, status) = setattr(request, vec![FileAttrValue::Acl(audit)]).await;
+        assert_eq!(status, NfsStat4::Nfs4errAttrnotsupp);
+    }
+
+    // encodes an attrmask of the given words and the raw attribute values
+    fn raw_fattr4(attrmask: &[u32], attr_vals: &[u8]) -> Fattr4 {
+        let mut bytes = Vec::new();
+        bytes.extend((attrmask.len() as u32).to_be_bytes());
+        for word in attrmask {
+            bytes.extend(word.to_be_bytes());
+        }
+        bytes.extend((attr_vals.len() as u32).to_be_bytes());
+        bytes.extend(attr_vals);
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+        serde_xdr::from_bytes(bytes).unwrap()
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_set_undecodable_attributes() {
+        let backend = MemoryBackend::new();
+        backend.write_file("/file1.txt", b"data").unwrap();
+        let request = create_nfs40_server_with_backend(Arc::new(backend)).await;
+        let fh = request
+            .file_manager()
+            .get_filehandle_for_path("/file1.txt".to_string())
+            .await
+            .unwrap();
+        let mut request = PutFh4args { object: fh.id }.execute(request).await.request;
+        request.set_credentials(user(0));
+
+        // hidden (25) has no decoder, bit 60 is no attribute at all, and the
+        // mode (33) is missing its value
+        let cases = [
+            (
+                raw_fattr4(&[1 << 25], &[0, 0, 0, 1]),
+                NfsStat4::Nfs4errAttrnotsupp,
+            ),
+            (raw_fattr4(&[0, 1 << 28], &[]), NfsStat4::Nfs4errAttrnotsupp),
+            (raw_fattr4(&[0, 1 << 1], &[]), NfsStat4::Nfs4errInval),
+        ];
+        for (obj_attributes, expected) in cases {
+            assert!(obj_attributes.attr_vals.is_empty());
+            let args = SetAttr4args {
+                stateid: Stateid4 {
+                    seqid: 0,
+                    other: [0; 12],
+                },
+                obj_attributes,
+            };
+            let response = args.execute(request).await;
+            assert_eq!(response.status, expected);
+            request = response.request;
+        }
+        let fh = request.current_filehandle().unwrap();
+        assert_eq!(fh.attr_mode, 0o644);
+    }
+}
@@ -0,0 +1,210 @@
+use async_trait::async_trait;
+use tracing::{debug, error};
+
+use crate::server::{operation::NfsOperation, request::NfsRequest, response::NfsOpResponse};
+
+use bold_proto::nfs4_proto::{
+    Fattr4, FileAttr, NfsResOp4, NfsStat4, Nverify4args, Nverify4res, Verify4args, Verify4res,
+};
+
+// compares the attributes sent by the client with the ones of the current
+// filehandle, VERIFY and NVERIFY only differ in what they make of the result
+fn attributes_equal(request: &NfsRequest, obj_attributes: &Fattr4) -> Result<bool, NfsStat4> {
+    let Some(filehandle) = request.current_filehandle() else {
+        error!("None filehandle");
+        return Err(NfsStat4::Nfs4errNofilehandle);
+    };
+    if let Some(error) = &obj_attributes.decode_error {
+        return Err(error.into());
+    }
+    // rdattr_error is not an attribute of the object
+    if obj_attributes.attrmask.contains(&FileAttr::RdattrError) {
+        return Err(NfsStat4::Nfs4errInval);
+    }
+    let (answer_attrs, attr_vals) = request
+        .file_manager()
+        .filehandle_attrs(&obj_attributes.attrmask, filehandle)
+        .ok_or(NfsStat4::Nfs4errServerfault)?;
+    // attributes bold doesn't report for this object can't be compared
+    if answer_attrs != obj_attributes.attrmask {
+        return Err(NfsStat4::Nfs4errAttrnotsupp);
+    }
+    // https://datatracker.ietf.org/doc/html/rfc7530#section-16.35.4
+    // the comparison is made on the encoded values
+    Ok(attr_vals.to_bytes() == obj_attributes.attr_vals.to_bytes())
+}
+
+#[async_trait]
+impl NfsOperation for Verify4args {
+    /// The VERIFY operation is used to verify that attributes have the
+    /// values specified, the COMPOUND stops with NFS4ERR_NOT_SAME if they
+    /// don't.
+    ///
+    /// Please read: [RFC 7530](https://datatracker.ietf.org/doc/html/rfc7530#section-16.35)
+    async fn execute<'a>(&self, request: NfsRequest<'a>) -> NfsOpResponse<'a> {
+        debug!(
+            "Operation 37: VERIFY - Verify Same Attributes {:?}, with request {:?}",
+            self, request
+        );
+        let status = match attributes_equal(&request, &self.obj_attributes) {
+            Ok(true) => NfsStat4::Nfs4Ok,
+            Ok(false) => NfsStat4::Nfs4errNotSame,
+            Err(status) => status,
+        };
+        NfsOpResponse {
+            request,
+            result: Some(NfsResOp4::Opverify(Verify4res {
+                status: status.clone(),
+            })),
+            status,
+        }
+    }
+}
+
+#[async_trait]
+impl NfsOperation for Nverify4args {
+    /// The NVERIFY operation is the opposite of VERIFY, the COMPOUND stops
+    /// with NFS4ERR_SAME if the attributes have the values specified.
+    ///
+    /// Please read: [RFC 7530](https://datatracker.ietf.org/doc/html/rfc7530#section-16.15)
+    async fn execute<'a>(&self, request: NfsRequest<'a>) -> NfsOpResponse<'a> {
+        debug!(
+            "Operation 17: NVERIFY - Verify Difference in Attributes {:?}, with request {:?}",
+            self, request
+        );
+        let status = match attributes_equal(&request, &self.obj_attributes) {
+            Ok(true) => NfsStat4::Nfs4errSame,
+            Ok(false) => NfsStat4::Nfs4Ok,
+            Err(status) => status,
+        };
+        NfsOpResponse {
+            request,
+            result: Some(NfsResOp4::Opnverify(Nverify4res {
+                status: status.clone(),
+            })),
+            status,
+        }
+    }
+}
+
+#[cfg(test)]
+mod integration_tests {
+    use std::sync::Arc;
+
+    use bold_proto::nfs4_proto::*;
+    use tracing_test::traced_test;
+
+    use crate::{
+        server::{nfs40::PutFh4args, operation::NfsOperation, request::NfsRequest},
+        storage::MemoryBackend,
+        test_utils::create_nfs40_server_with_backend,
+    };
+
+    async fn put_path(request: NfsRequest<'static>, path: &str) -> NfsRequest<'static> {
+        let fh = request
+            .file_manager()
+            .get_filehandle_for_path(path.to_string())
+            .await
+            .unwrap();
+        PutFh4args { object: fh.id }.execute(request).await.request
+    }
+
+    // encodes and decodes attributes like they travel between client and server
+    fn fattr4(attr_vals: Vec<FileAttrValue>) -> Fattr4 {
+        let attrmask = attr_vals
+            .iter()
+            .map(|value| match value {
+                FileAttrValue::Type(_) => FileAttr::Type,
+                FileAttrValue::Change(_) => FileAttr::Change,
+                FileAttrValue::Size(_) => FileAttr::Size,
+                FileAttrValue::Mode(_) => FileAttr::Mode,
+                FileAttrValue::Owner(_) => FileAttr::Owner,
+                FileAttrValue::RdattrError(_) => FileAttr::RdattrError,
+                FileAttrValue::TimeModify(_) => FileAttr::TimeModify,
+                _ => unimplemented!(),
+            })
+            .collect();
+        let fattr = Fattr4 {
+            attrmask: Attrlist4::<FileAttr>::new(Some(attrmask)),
+            attr_vals: Attrlist4::<FileAttrValue>::new(Some(attr_vals)),
+            decode_error: None,
+        };
+        serde_xdr::from_bytes(serde_xdr::to_bytes(&fattr).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_verify() {
+        let backend = MemoryBackend::new();
+        backend.write_file("/file1.txt", b"Hello").unwrap();
+        let request = create_nfs40_server_with_backend(Arc::new(backend)).await;
+        let request = put_path(request, "/file1.txt").await;
+        let fh = request.current_filehandle().unwrap().clone();
+
+        let same = fattr4(vec![
+            FileAttrValue::Type(NfsFtype4::Nf4reg),
+            FileAttrValue::Change(fh.attr_change),
+            FileAttrValue::Size(5),
+            FileAttrValue::Owner(fh.attr_owner.clone()),
+            FileAttrValue::TimeModify(fh.attr_time_modify),
+        ]);
+        let different = fattr4(vec![
+            FileAttrValue::Size(5),
+            FileAttrValue::Mode(fh.attr_mode ^ 0o1),
+        ]);
+
+        let response = Verify4args {
+            obj_attributes: same.clone(),
+        }
+        .execute(request)
+        .await;
+        assert_eq!(response.status, NfsStat4::Nfs4Ok);
+        let response = Verify4args {
+            obj_attributes: different.clone(),
+        }
+        .execute(response.request)
+        .await;
+        assert_eq!(response.status, NfsStat4::Nfs4errNotSame);
+        let response = Nverify4args {
+            obj_attributes: same,
+        }
+        .execute(response.request)
+        .await;
+        assert_eq!(response.status, NfsStat4::Nfs4errSame);
+        let response = Nverify4args {
+            obj_attributes: different,
+        }
+        .execute(response.request)
+        .await;
+        assert_eq!(response.status, NfsStat4::Nfs4Ok);
+
+        let response = Verify4args {
+            obj_attributes: fattr4(vec![FileAttrValue::RdattrError(NfsStat4::Nfs4Ok)]),
+        }
+        .execute(response.request)
+        .await;
+        assert_eq!(response.status, NfsStat4::Nfs4errInval);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_verify_unsupported_attribute() {
+        let backend = MemoryBackend::new();
+        backend.write_file("/file1.txt", b"Hello").unwrap();
+        let request = create_nfs40_server_with_backend(Arc::new(backend)).await;
+        let request = put_path(request, "/file1.txt").await;
+
+        // hidden has no value bold could decode
+        let obj_attributes: Fattr4 = serde_xdr::from_bytes(
+            serde_xdr::to_bytes(&Fattr4 {
+                attrmask: Attrlist4::<FileAttr>::new(Some(vec![FileAttr::Size, FileAttr::Hidden])),
+                attr_vals: Attrlist4::<FileAttrValue>::new(Some(vec![FileAttrValue::Size(5)])),
+                decode_error: None,
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        let response = Nverify4args { obj_attributes }.execute(request).await;
+        assert_eq!(response.status, NfsStat4::Nfs4errAttrnotsupp);
+    }
+}
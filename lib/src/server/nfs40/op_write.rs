@@ -1,11 +1,11 @@
-use std::io::Write;
-
 use async_trait::async_trait;
 use tracing::{debug, error};
 
 use crate::server::{operation::NfsOperation, request::NfsRequest, response::NfsOpResponse};
 
-use bold_proto::nfs4_proto::{NfsResOp4, NfsStat4, StableHow4, Write4args, Write4res, Write4resok};
+use bold_proto::nfs4_proto::{
+    NfsResOp4, NfsStat4, StableHow4, Write4args, Write4res, Write4resok, ACCESS4_MODIFY,
+};
 
 fn verifier_from_boot(boot_time: &u64) -> [u8; 8] {
     let mut verifier = [0; 8];
@@ -34,50 +34,77 @@ impl NfsOperation for Write4args {
             }
         };
 
+        // the owner may write to what it opened even after dropping its write permission
+        if !request.is_owner(filehandle) {
+            if let Err(status) = request.check_permission(filehandle, ACCESS4_MODIFY) {
+                return NfsOpResponse {
+                    request,
+                    result: None,
+                    status,
+                };
+            }
+        }
+
         let mut stable = StableHow4::Unstable4;
         let mut count: u32 = self.data.len() as u32;
-        if self.stable == StableHow4::Unstable4 {
+        // the data of unstable writes is cached until COMMIT, unless the
+        // connection keeps too much in memory already
+        if self.stable == StableHow4::Unstable4 && !request.write_through() {
             // write to cache
             let write_cache = match &filehandle.write_cache {
-                Some(write_cache) => write_cache,
+                Some(write_cache) => Ok(write_cache.clone()),
                 None => {
                     let write_cache = request
                         .file_manager()
                         .get_write_cache_handle(filehandle.clone())
+                        .await;
+                    request.drop_filehandle_from_cache(filehandle.id);
+                    write_cache
+                }
+            };
+            let written = match write_cache {
+                Ok(write_cache) => {
+                    write_cache
+                        .write_bytes(self.offset, self.data.clone())
                         .await
-                        .unwrap();
-                    request.drop_filehandle_from_cache(filehandle.id.clone());
-                    &write_cache.clone()
                 }
+                Err(e) => Err(e),
             };
-
-            write_cache
-                .write_bytes(self.offset, self.data.clone())
-                .await;
+            if let Err(e) = written {
+                error!("FileManagerError {:?}", e);
+                return NfsOpResponse {
+                    request,
+                    result: None,
+                    status: e.nfs_error,
+                };
+            }
         } else {
             // write to file
-            // Read-modify-write approach
-            let mut contents = Vec::new();
-            if filehandle.file.exists().unwrap() {
-                let mut file = filehandle.file.open_file().unwrap();
-                use std::io::Read;
-                file.read_to_end(&mut contents).unwrap();
-            }
-
-            let offset = self.offset as usize;
-            let data_len = self.data.len();
-            let new_len = offset + data_len;
-
-            if new_len > contents.len() {
-                contents.resize(new_len, 0);
+            let file_manager = request.file_manager();
+            let written = match file_manager
+                .write(filehandle, self.offset, &self.data)
+                .await
+            {
+                Ok(written) => written,
+                Err(e) => {
+                    error!("FileManagerError {:?}", e);
+                    return NfsOpResponse {
+                        request,
+                        result: None,
+                        status: e.nfs_error,
+                    };
+                }
+            };
+            if let Err(e) = file_manager.commit(filehandle).await {
+                error!("FileManagerError {:?}", e);
+                return NfsOpResponse {
+                    request,
+                    result: None,
+                    status: e.nfs_error,
+                };
             }
 
-            contents[offset..offset + data_len].copy_from_slice(&self.data);
-
-            let mut file = filehandle.file.create_file().unwrap();
-            file.write_all(&contents).unwrap();
-            
-            count = self.data.len() as u32;
+            count = written;
             stable = StableHow4::FileSync4;
 
             if count > 0 {
@@ -89,7 +116,7 @@ impl NfsOperation for Write4args {
         NfsOpResponse {
             request,
             result: Some(NfsResOp4::Opwrite(Write4res::Resok4(Write4resok {
-                count: count,
+                count,
                 committed: stable,
                 writeverf: verifier_from_boot(&boot_time),
             }))),
@@ -97,3 +124,107 @@ impl NfsOperation for Write4args {
         }
     }
 }
+
+#[cfg(test)]
+mod integration_tests {
+    use crate::{
+        server::{
+            nfs40::{Lookup4args, NfsStat4, PutFh4args},
+            operation::NfsOperation,
+        },
+        test_utils::{create_fake_fs, create_nfs40_server},
+    };
+    use bold_proto::nfs4_proto::{NfsResOp4, StableHow4, Stateid4, Write4args, Write4res};
+    use bytes::Bytes;
+    use tracing_test::traced_test;
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_write_increases_change() {
+        let request = create_nfs40_server(Some(create_fake_fs())).await;
+        let fh = request.file_manager().get_root_filehandle().await;
+
+        let putfh_args = PutFh4args {
+            object: fh.unwrap().id,
+        };
+        let putfh_response = putfh_args.execute(request).await;
+        let args = Lookup4args {
+            objname: "file1.txt".as_bytes().to_vec(),
+        };
+        let mut response = args.execute(putfh_response.request).await;
+        assert_eq!(response.status, NfsStat4::Nfs4Ok);
+
+        let file_id = response.request.current_filehandle_id().unwrap();
+        let mut change = response.request.current_filehandle().unwrap().attr_change;
+        // writes within the same second must be visible to clients
+        for offset in 0..3 {
+            let args = Write4args {
+                stateid: Stateid4 {
+                    seqid: 0,
+                    other: [0; 12],
+                },
+                offset,
+                stable: StableHow4::FileSync4,
+                data: Bytes::from_static(b"x"),
+            };
+            response = args.execute(response.request).await;
+            assert_eq!(response.status, NfsStat4::Nfs4Ok);
+
+            let fh = response
+                .request
+                .file_manager()
+                .get_filehandle_for_id(file_id)
+                .await
+                .unwrap();
+            assert!(fh.attr_change > change);
+            change = fh.attr_change;
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_write_through() {
+        let request = create_nfs40_server(Some(create_fake_fs())).await;
+        let fh = request.file_manager().get_root_filehandle().await;
+
+        let putfh_args = PutFh4args {
+            object: fh.unwrap().id,
+        };
+        let putfh_response = putfh_args.execute(request).await;
+        let args = Lookup4args {
+            objname: "file1.txt".as_bytes().to_vec(),
+        };
+        let mut response = args.execute(putfh_response.request).await;
+        assert_eq!(response.status, NfsStat4::Nfs4Ok);
+
+        // a connection keeping too much in memory doesn't cache its writes
+        response.request.set_write_through(true);
+        let args = Write4args {
+            stateid: Stateid4 {
+                seqid: 0,
+                other: [0; 12],
+            },
+            offset: 0,
+            stable: StableHow4::Unstable4,
+            data: Bytes::from_static(b"through"),
+        };
+        let response = args.execute(response.request).await;
+        assert_eq!(response.status, NfsStat4::Nfs4Ok);
+        match response.result {
+            Some(NfsResOp4::Opwrite(Write4res::Resok4(res))) => {
+                assert_eq!(res.count, 7);
+                assert_eq!(res.committed, StableHow4::FileSync4);
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+        let filehandle = response.request.current_filehandle().unwrap();
+        assert!(filehandle.write_cache.is_none());
+        let (data, _) = response
+            .request
+            .file_manager()
+            .read(filehandle, 0, 7)
+            .await
+            .unwrap();
+        assert_eq!(data, b"through");
+    }
+}
@@ -1,12 +1,15 @@
 use std::{collections::HashMap, time::SystemTime};
 
-use bold_proto::nfs4_proto::{NfsFh4, NfsStat4};
-use tracing::error;
+use bold_proto::nfs4_proto::{NfsArgOp, NfsFh4, NfsStat4};
+use tracing::{debug, error};
 
 use super::{
+    access::{self, ClientAccess, Credentials, SecFlavor},
     clientmanager::ClientManagerHandle,
-    filemanager::{FileManagerHandle, Filehandle},
+    exports::Exports,
+    filemanager::{FileManagerError, FileManagerHandle, Filehandle, FH_EXPORT_BYTE},
 };
+use crate::storage::{split_path, SetAttrs};
 
 #[derive(Debug)]
 pub struct NfsRequest<'a> {
@@ -14,8 +17,16 @@ pub struct NfsRequest<'a> {
     filehandle: Option<Filehandle>,
     // shared state for client manager between connections
     cmanager: ClientManagerHandle,
-    // local filehandle manager
-    fmanager: FileManagerHandle,
+    // the file systems served, each with its filehandle manager
+    exports: Exports,
+    // what the client may do on the exports, set by NFSService
+    client_access: Option<ClientAccess>,
+    // the user sending the request, before squashing
+    credentials: Option<Credentials>,
+    // the principal authenticated by RPCSEC_GSS
+    principal: Option<String>,
+    // the security flavor the request was made with
+    flavor: Option<SecFlavor>,
     // time the server was booted
     pub boot_time: u64,
     // time the request was received
@@ -23,13 +34,15 @@ pub struct NfsRequest<'a> {
     // locally cached filehandles for this client
     pub filehandle_cache: Option<&'a mut HashMap<NfsFh4, (SystemTime, Filehandle)>>,
     cache_ttl: u64,
+    // whether the connection keeps too much in memory to cache writes
+    write_through: bool,
 }
 
 impl<'a> NfsRequest<'a> {
     pub fn new(
         client_addr: String,
         cmanager: ClientManagerHandle,
-        fmanager: FileManagerHandle,
+        exports: Exports,
         boot_time: u64,
         // cache ttl + filehandle
         filehandle_cache: Option<&'a mut HashMap<NfsFh4, (SystemTime, Filehandle)>>,
@@ -40,12 +53,17 @@ impl<'a> NfsRequest<'a> {
             client_addr,
             filehandle: None,
             cmanager,
-            fmanager,
+            exports,
+            client_access: None,
+            credentials: None,
+            principal: None,
+            flavor: None,
             boot_time,
             request_time,
             filehandle_cache,
             // set filehandle cache ttl to 10 seconds
             cache_ttl: 10,
+            write_through: false,
         }
     }
 
@@ -54,26 +72,238 @@ impl<'a> NfsRequest<'a> {
     }
 
     pub fn current_filehandle_id(&self) -> Option<NfsFh4> {
-        match self.filehandle {
-            Some(ref fh) => Some(fh.id.clone()),
-            None => None,
-        }
+        self.filehandle.as_ref().map(|fh| fh.id)
     }
 
     pub fn current_filehandle(&self) -> Option<&Filehandle> {
         // TODO handle None
-        match self.filehandle {
-            Some(ref fh) => Some(fh),
-            None => None,
-        }
+        self.filehandle.as_ref()
     }
 
     pub fn client_manager(&self) -> ClientManagerHandle {
         self.cmanager.clone()
     }
 
+    pub fn exports(&self) -> &Exports {
+        &self.exports
+    }
+
+    pub fn set_client_access(&mut self, client_access: ClientAccess) {
+        self.client_access = Some(client_access);
+    }
+
+    pub fn set_credentials(&mut self, credentials: Option<Credentials>) {
+        self.credentials = credentials;
+    }
+
+    pub fn set_principal(&mut self, principal: Option<String>) {
+        self.principal = principal;
+    }
+
+    pub fn principal(&self) -> Option<String> {
+        self.principal.clone()
+    }
+
+    pub fn set_flavor(&mut self, flavor: Option<SecFlavor>) {
+        self.flavor = flavor;
+    }
+
+    /// Write unstable WRITEs through, the connection of the request keeps
+    /// more in memory than it may already.
+    pub fn set_write_through(&mut self, write_through: bool) {
+        self.write_through = write_through;
+    }
+
+    pub fn write_through(&self) -> bool {
+        self.write_through
+    }
+
+    /// The user the request is made for on the export of the current
+    /// filehandle, with the squashing of the export applied.
+    pub fn credentials(&self) -> Option<Credentials> {
+        let export_id = self
+            .current_filehandle_id()
+            .map_or(0, |id| id[FH_EXPORT_BYTE]);
+        self.credentials_on(export_id)
+    }
+
+    fn credentials_on(&self, export_id: u8) -> Option<Credentials> {
+        let credentials = self.credentials.as_ref()?;
+        match self
+            .client_access
+            .as_ref()
+            .and_then(|access| access.options(export_id))
+        {
+            Some(options) => Some(credentials.squash(options)),
+            None => Some(credentials.clone()),
+        }
+    }
+
+    /// The ACCESS4 bits the mode of `filehandle` grants to the caller.
+    ///
+    /// Requests without credentials did not pass through NFSService and
+    /// are granted everything.
+    pub fn granted_access(&self, filehandle: &Filehandle) -> u32 {
+        match self.credentials_on(filehandle.id[FH_EXPORT_BYTE]) {
+            Some(credentials) => credentials.access(filehandle),
+            None => u32::MAX,
+        }
+    }
+
+    /// Check that the caller is granted all ACCESS4 bits of `access` on `filehandle`.
+    pub fn check_permission(&self, filehandle: &Filehandle, access: u32) -> Result<(), NfsStat4> {
+        if self.granted_access(filehandle) & access == access {
+            Ok(())
+        } else {
+            Err(NfsStat4::Nfs4errAccess)
+        }
+    }
+
+    /// Whether the caller owns `filehandle`, or is root.
+    pub fn is_owner(&self, filehandle: &Filehandle) -> bool {
+        match self.credentials_on(filehandle.id[FH_EXPORT_BYTE]) {
+            Some(credentials) => credentials.uid == 0 || credentials.uid == filehandle.attr_uid,
+            None => true,
+        }
+    }
+
+    /// Hand the object of the current filehandle, which the caller just
+    /// created, over to the caller. Backends that can not change the owner
+    /// keep their own.
+    pub async fn set_owner_to_caller(&mut self) {
+        let (Some(credentials), Some(filehandle)) = (self.credentials(), self.filehandle.clone())
+        else {
+            return;
+        };
+        if filehandle.attr_uid == credentials.uid && filehandle.attr_gid == credentials.gid {
+            return;
+        }
+        let file_manager = self.file_manager();
+        let attrs = SetAttrs {
+            uid: Some(credentials.uid),
+            gid: Some(credentials.gid),
+            ..Default::default()
+        };
+        if let Err(e) = file_manager
+            .backend()
+            .setattr(&filehandle.path, &attrs)
+            .await
+        {
+            debug!("couldn't change owner of {:?}: {:?}", filehandle.path, e);
+            return;
+        }
+        file_manager.touch_file(filehandle.id).await;
+        if let Ok(filehandle) = self.set_filehandle_id(filehandle.id).await {
+            self.cache_filehandle(filehandle);
+        }
+    }
+
+    /// Check that the client may run `op` on the export of the current filehandle.
+    ///
+    /// Requests that did not pass through NFSService carry no client access
+    /// and are not restricted.
+    pub fn check_access(&self, op: &NfsArgOp) -> Result<(), NfsStat4> {
+        if !access::uses_current_filehandle(op) {
+            return Ok(());
+        }
+        // SECINFO tells the client which flavor to use, whatever flavor it uses
+        let check_flavor = !matches!(op, NfsArgOp::OpSecinfo(_));
+        self.check_export_access(access::modifies(op), check_flavor)
+    }
+
+    /// Check that the client may use the export of the current filehandle.
+    pub fn check_filehandle_access(&self) -> Result<(), NfsStat4> {
+        self.check_export_access(false, true)
+    }
+
+    fn check_export_access(&self, modify: bool, check_flavor: bool) -> Result<(), NfsStat4> {
+        let Some(id) = self.current_filehandle_id() else {
+            return Ok(());
+        };
+        if let Some(client_access) = &self.client_access {
+            client_access.check(id[FH_EXPORT_BYTE], modify)?;
+        }
+        // https://datatracker.ietf.org/doc/html/rfc7530#section-3.3.1.1
+        if let (true, Some(flavor)) = (check_flavor, self.flavor) {
+            if self
+                .exports
+                .get(&id)
+                .is_some_and(|export| !export.allows(flavor))
+            {
+                return Err(NfsStat4::Nfs4errWrongsec);
+            }
+        }
+        if modify && self.read_only() {
+            return Err(NfsStat4::Nfs4errRofs);
+        }
+        Ok(())
+    }
+
+    /// Whether the client may not change the export of the current filehandle,
+    /// because the export or the rule of the client is read-only.
+    pub fn read_only(&self) -> bool {
+        let Some(id) = self.current_filehandle_id() else {
+            return false;
+        };
+        let export_id = id[FH_EXPORT_BYTE];
+        self.exports.get(&id).is_some_and(|export| export.read_only)
+            || self
+                .client_access
+                .as_ref()
+                .and_then(|access| access.options(export_id))
+                .is_some_and(|options| options.read_only)
+    }
+
+    // the file manager of the export the current filehandle belongs to
     pub fn file_manager(&self) -> FileManagerHandle {
-        self.fmanager.clone()
+        match &self.filehandle {
+            Some(fh) => self.file_manager_for(&fh.id),
+            None => self.exports.root(),
+        }
+    }
+
+    pub fn file_manager_for(&self, filehandle_id: &NfsFh4) -> FileManagerHandle {
+        match self.exports.get(filehandle_id) {
+            Some(export) => export.file_manager.clone(),
+            None => self.exports.root(),
+        }
+    }
+
+    /// The filehandle of `path` in the export of the current filehandle.
+    /// An export mounted at `path` in the pseudo file system is entered at its root.
+    pub async fn get_filehandle_for_path(
+        &self,
+        path: String,
+    ) -> Result<Filehandle, FileManagerError> {
+        if let Some(fh) = &self.filehandle {
+            if let Some(export) = self.exports.mounted_at(&fh.id, &path) {
+                return export.file_manager.get_root_filehandle().await;
+            }
+        }
+        self.file_manager().get_filehandle_for_path(path).await
+    }
+
+    /// The parent directory of `filehandle`, for the root of an export this is
+    /// the directory it is mounted on in the pseudo file system.
+    pub async fn get_parent_filehandle(
+        &self,
+        filehandle: &Filehandle,
+    ) -> Result<Filehandle, FileManagerError> {
+        let export = self.exports.get(&filehandle.id).ok_or(FileManagerError {
+            nfs_error: NfsStat4::Nfs4errStale,
+        })?;
+        if filehandle.path != "/" {
+            let (parent, _) = split_path(&filehandle.path);
+            return export.file_manager.get_filehandle_for_path(parent).await;
+        }
+        // the root of the namespace has no parent
+        if export.id == 0 {
+            return Err(FileManagerError {
+                nfs_error: NfsStat4::Nfs4errNoent,
+            });
+        }
+        let (parent, _) = split_path(&export.path);
+        self.exports.root().get_filehandle_for_path(parent).await
     }
 
     pub fn set_filehandle(&mut self, filehandle: Filehandle) {
@@ -83,10 +313,10 @@ impl<'a> NfsRequest<'a> {
     pub fn cache_filehandle(&mut self, filehandle: Filehandle) {
         let cache = self.filehandle_cache.as_mut();
         match cache {
-            None => return,
+            None => (),
             Some(cache) => {
                 let now: SystemTime = SystemTime::now();
-                cache.insert(filehandle.id.clone(), (now, filehandle));
+                cache.insert(filehandle.id, (now, filehandle));
             }
         }
     }
@@ -94,7 +324,7 @@ impl<'a> NfsRequest<'a> {
     pub fn drop_filehandle_from_cache(&mut self, filehandle_id: NfsFh4) {
         let cache = self.filehandle_cache.as_mut();
         match cache {
-            None => return,
+            None => (),
             Some(cache) => {
                 cache.remove(&filehandle_id);
             }
@@ -113,7 +343,7 @@ impl<'a> NfsRequest<'a> {
                         let (time, filehandle) = fh;
                         // if cache is expired since 10 seconds, remove it
                         if now.duration_since(*time).unwrap().as_secs() > self.cache_ttl {
-                            self.drop_filehandle_from_cache(filehandle.id.clone());
+                            self.drop_filehandle_from_cache(filehandle.id);
                             None
                         } else {
                             Some(filehandle.clone())
@@ -129,7 +359,18 @@ impl<'a> NfsRequest<'a> {
         &mut self,
         filehandle_id: NfsFh4,
     ) -> Result<Filehandle, NfsStat4> {
-        let res = self.fmanager.get_filehandle_for_id(filehandle_id).await;
+        let res = match self.exports.get(&filehandle_id) {
+            Some(export) => {
+                export
+                    .file_manager
+                    .get_filehandle_for_id(filehandle_id)
+                    .await
+            }
+            // the filehandle belongs to an export that is no longer served
+            None => Err(FileManagerError {
+                nfs_error: NfsStat4::Nfs4errStale,
+            }),
+        };
         match res {
             Ok(ref fh) => {
                 self.filehandle = Some(fh.clone());
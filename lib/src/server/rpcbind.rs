@@ -0,0 +1,688 @@
+//! Registration of the NFS service with rpcbind, and a minimal rpcbind for
+//! hosts without one.
+//!
+//! Clients that don't know the port of the server ask rpcbind on port 111,
+//! e.g. Linux with `mount -o port=0`. The server registers NFSv4 over the
+//! local socket of rpcbind, or with PMAPPROC_SET over TCP with a portmapper
+//! that has no local socket, and removes the registration on shutdown.
+//!
+//! Please read: [RFC 1833](https://datatracker.ietf.org/doc/html/rfc1833)
+
+use std::{
+    future::pending,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+
+use bold_proto::{
+    rpc_proto::{
+        call_header_bytes, format_uaddr, parse_uaddr, AcceptBody, AcceptedReply, CallBody,
+        MismatchInfo, MsgType, OpaqueAuth, RejectedReply, ReplyBody, RpcCallMsg, RpcReplyMsg,
+        NFS4_PROGRAM, NFS_V4, RPC_VERSION,
+    },
+    rpcb_proto::{
+        Pmap, Rpcb, RpcbCall, RpcbList, RpcbReply, IPPROTO_TCP, IPPROTO_UDP, PMAPPROC_DUMP,
+        PMAPPROC_GETPORT, PMAPPROC_NULL, PMAPPROC_SET, PMAPPROC_UNSET, PMAP_V2, RPCBIND_PORT,
+        RPCBIND_PROGRAM, RPCBIND_V3, RPCBIND_V4, RPCBPROC_DUMP, RPCBPROC_GETADDR,
+        RPCBPROC_GETVERSADDR, RPCBPROC_SET, RPCBPROC_UNSET,
+    },
+    xdr::{self, Opaque},
+    XDRProtoCodec,
+};
+use bytes::{Bytes, BytesMut};
+use futures::{future::join_all, stream::FuturesUnordered, SinkExt};
+use serde::{de::DeserializeOwned, Serialize};
+use socket2::Type;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream, UdpSocket, UnixStream},
+};
+use tokio_stream::StreamExt;
+use tokio_util::codec::{Decoder, Encoder, Framed};
+use tracing::{error, trace};
+
+use super::transport::{bind, Connection};
+
+// where libtirpc looks for the local socket of rpcbind
+const RPCBIND_SOCKETS: [&str; 2] = ["/run/rpcbind.sock", "/var/run/rpcbind.sock"];
+
+// rpcbind takes the owner of a mapping from the credentials of its local
+// socket, this one is only informational
+const OWNER: &str = "bold";
+
+// calls of rpcbind are small, no record gets larger than this
+const MAX_RECORD: usize = 64 * 1024;
+
+// the header of a successful reply, whose results depend on the procedure,
+// see RpcbReply
+// https://datatracker.ietf.org/doc/html/rfc5531#section-9
+const MSG_REPLY: u32 = 1;
+const MSG_ACCEPTED: u32 = 0;
+const SUCCESS: u32 = 0;
+
+// the records of rpcbind are framed like the ones of NFS, but decoded by
+// the procedure they belong to
+struct Records(XDRProtoCodec);
+
+impl Records {
+    fn new() -> Self {
+        Records(XDRProtoCodec::with_max_record(MAX_RECORD))
+    }
+}
+
+impl Decoder for Records {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Bytes>> {
+        self.0.decode_record(src)
+    }
+}
+
+impl Encoder<Vec<u8>> for Records {
+    type Error = io::Error;
+
+    fn encode(&mut self, message: Vec<u8>, dst: &mut BytesMut) -> io::Result<()> {
+        self.0.encode_record(&message, dst);
+        Ok(())
+    }
+}
+
+/// The mappings of NFSv4 listening on TCP at `addrs`, see
+/// [`super::transport::Listener::tcp`] for `only_v6`. rpcbind keeps one
+/// address for every netid, the first one of `addrs` is registered.
+pub fn mappings(addrs: &[SocketAddr], only_v6: bool) -> Vec<Rpcb> {
+    let mut mappings: Vec<Rpcb> = Vec::new();
+    for addr in addrs {
+        let mut addrs = vec![*addr];
+        // a dual-stack socket takes IPv4 clients as well
+        if addr.is_ipv6() && addr.ip().is_unspecified() && !only_v6 {
+            addrs.push(SocketAddr::from((Ipv4Addr::UNSPECIFIED, addr.port())));
+        }
+        for addr in addrs {
+            let netid = netid("tcp", &addr);
+            if mappings.iter().all(|mapping| mapping.r_netid != netid) {
+                mappings.push(Rpcb {
+                    r_prog: NFS4_PROGRAM,
+                    r_vers: NFS_V4,
+                    r_netid: netid,
+                    r_addr: format_uaddr(&addr),
+                    r_owner: OWNER.to_string(),
+                });
+            }
+        }
+    }
+    mappings
+}
+
+// the netid of `protocol` over the address family of `addr`, e.g. tcp6
+fn netid(protocol: &str, addr: &SocketAddr) -> String {
+    match addr.ip().to_canonical() {
+        IpAddr::V4(_) => protocol.to_string(),
+        IpAddr::V6(_) => format!("{}6", protocol),
+    }
+}
+
+// the mapping of the portmapper, which only knows about IPv4
+fn pmap(mapping: &Rpcb) -> Option<Pmap> {
+    let prot = match mapping.r_netid.as_str() {
+        "tcp" => IPPROTO_TCP,
+        "udp" => IPPROTO_UDP,
+        _ => return None,
+    };
+    let addr = parse_uaddr(&mapping.r_addr)?;
+    Some(Pmap {
+        prog: mapping.r_prog,
+        vers: mapping.r_vers,
+        prot,
+        port: addr.port() as u32,
+    })
+}
+
+/// Registers `mappings` with the rpcbind of this host, replacing the ones a
+/// server before left behind.
+pub async fn register(mappings: &[Rpcb]) -> io::Result<()> {
+    Client::connect().await?.register(mappings).await
+}
+
+/// Removes the registration of `mappings` from the rpcbind of this host.
+pub async fn unregister(mappings: &[Rpcb]) -> io::Result<()> {
+    Client::connect().await?.unregister(mappings).await
+}
+
+// a client of rpcbind, or of a portmapper with version 2
+struct Client {
+    stream: Framed<Box<dyn Connection>, Records>,
+    version: u32,
+    xid: u32,
+}
+
+impl Client {
+    fn new(stream: Box<dyn Connection>, version: u32) -> Self {
+        Client {
+            stream: Framed::new(stream, Records::new()),
+            version,
+            xid: 0,
+        }
+    }
+
+    async fn connect() -> io::Result<Self> {
+        for path in RPCBIND_SOCKETS {
+            if let Ok(stream) = UnixStream::connect(path).await {
+                return Ok(Client::new(Box::new(stream), RPCBIND_V4));
+            }
+        }
+        let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, RPCBIND_PORT)).await?;
+        Ok(Client::new(Box::new(stream), PMAP_V2))
+    }
+
+    async fn register(&mut self, mappings: &[Rpcb]) -> io::Result<()> {
+        for mapping in mappings {
+            self.change(RPCBPROC_UNSET, mapping).await?;
+            if !self.change(RPCBPROC_SET, mapping).await? {
+                return Err(io::Error::other(format!(
+                    "rpcbind refused {} {}",
+                    mapping.r_netid, mapping.r_addr
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    async fn unregister(&mut self, mappings: &[Rpcb]) -> io::Result<()> {
+        for mapping in mappings {
+            self.change(RPCBPROC_UNSET, mapping).await?;
+        }
+        Ok(())
+    }
+
+    // SET or UNSET `mapping`, the procedures are the same in every version
+    async fn change(&mut self, proc: u32, mapping: &Rpcb) -> io::Result<bool> {
+        if self.version != PMAP_V2 {
+            return self.call(proc, mapping).await;
+        }
+        match pmap(mapping) {
+            Some(pmap) => self.call(proc, pmap).await,
+            // IPv6 is left out with a portmapper
+            None => Ok(true),
+        }
+    }
+
+    async fn call<A: Serialize, R: DeserializeOwned>(
+        &mut self,
+        proc: u32,
+        args: A,
+    ) -> io::Result<R> {
+        self.xid += 1;
+        let call = CallBody {
+            rpcvers: RPC_VERSION,
+            prog: RPCBIND_PROGRAM,
+            vers: self.version,
+            proc,
+            cred: OpaqueAuth::AuthNull(Vec::new()),
+            verf: OpaqueAuth::AuthNull(Vec::new()),
+            args: None,
+            gss_args: None,
+        };
+        let mut message = call_header_bytes(self.xid, &call).map_err(io::Error::other)?;
+        serde_xdr::to_writer(&mut message, &(&call.verf, args))
+            .map_err(|e| io::Error::other(format!("Error serializing call: {:?}", e)))?;
+        self.stream.send(message).await?;
+        let reply = match self.stream.next().await {
+            Some(reply) => reply?,
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        };
+
+        // a rejected reply has no verifier, the header is checked first
+        let (xid, msg_type, reply_stat): (u32, u32, u32) = decode(&reply)?;
+        if xid != self.xid || msg_type != MSG_REPLY {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "rpcbind didn't reply to the call",
+            ));
+        }
+        if reply_stat != MSG_ACCEPTED {
+            let (_, rejected): (u32, MsgType) = decode(&reply)?;
+            return Err(io::Error::other(format!(
+                "rpcbind rejected the call: {:?}",
+                rejected
+            )));
+        }
+        let accepted: RpcbReply<()> = decode(&reply)?;
+        if accepted.accept_stat != SUCCESS {
+            return Err(io::Error::other(format!(
+                "rpcbind version {} failed procedure {} with {}",
+                self.version, proc, accepted.accept_stat
+            )));
+        }
+        Ok(decode::<RpcbReply<R>>(&reply)?.result)
+    }
+}
+
+fn decode<T: DeserializeOwned>(data: &[u8]) -> io::Result<T> {
+    xdr::from_bytes(data).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Error deserializing message: {:?}", e),
+        )
+    })
+}
+
+/// The sockets of a minimal rpcbind, which tells clients where NFSv4 is and
+/// refuses registrations.
+pub struct Responder {
+    tcp: Vec<TcpListener>,
+    udp: Vec<UdpSocket>,
+}
+
+impl Responder {
+    /// Listen on TCP and UDP at `addrs`, port 111 for clients to find it.
+    /// IPv6 addresses are dual-stack like the ones of
+    /// [`crate::ServerBuilder::listen`].
+    pub fn bind(addrs: &[SocketAddr]) -> io::Result<Self> {
+        let only_v6 = addrs.iter().any(SocketAddr::is_ipv4);
+        let mut tcp = Vec::new();
+        let mut udp = Vec::new();
+        for addr in addrs {
+            let socket = bind(*addr, Type::STREAM, only_v6)?;
+            socket.listen(128)?;
+            tcp.push(TcpListener::from_std(socket.into())?);
+            let socket = bind(*addr, Type::DGRAM, only_v6)?;
+            udp.push(UdpSocket::from_std(socket.into())?);
+        }
+        Ok(Responder { tcp, udp })
+    }
+
+    /// Answer the queries for `mappings`, serve forever.
+    pub async fn serve(&self, mappings: &[Rpcb]) {
+        let tcp = self
+            .tcp
+            .iter()
+            .map(|listener| serve_tcp(listener, mappings));
+        let udp = self.udp.iter().map(|socket| serve_udp(socket, mappings));
+        tokio::join!(join_all(tcp), join_all(udp));
+        // without sockets there is nothing to serve
+        pending::<()>().await
+    }
+}
+
+async fn serve_tcp(listener: &TcpListener, mappings: &[Rpcb]) {
+    let mut connections = FuturesUnordered::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    let local = stream
+                        .local_addr()
+                        .ok()
+                        .map(|addr| SocketAddr::new(addr.ip().to_canonical(), addr.port()));
+                    connections.push(serve_stream(stream, netid("tcp", &peer), local, mappings));
+                }
+                Err(e) => error!("couldn't accept rpcbind connection: {:?}", e),
+            },
+            Some(()) = connections.next() => {}
+        }
+    }
+}
+
+// answer the calls on `stream` until the client disconnects, `local` is the
+// address the client connected to
+async fn serve_stream(
+    stream: impl AsyncRead + AsyncWrite + Unpin,
+    netid: String,
+    local: Option<SocketAddr>,
+    mappings: &[Rpcb],
+) {
+    let mut stream = Framed::new(stream, Records::new());
+    while let Some(Ok(call)) = stream.next().await {
+        if let Some(reply) = answer(call, &netid, local, mappings) {
+            if stream.send(reply).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+async fn serve_udp(socket: &UdpSocket, mappings: &[Rpcb]) {
+    let mut buffer = vec![0; MAX_RECORD];
+    loop {
+        let (length, peer) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(e) => {
+                error!("couldn't receive rpcbind call: {:?}", e);
+                continue;
+            }
+        };
+        // the address a datagram was sent to is unknown, an unspecified
+        // address is answered as it is
+        let call = Bytes::copy_from_slice(&buffer[..length]);
+        if let Some(reply) = answer(call, &netid("udp", &peer), None, mappings) {
+            let _ = socket.send_to(&reply, peer).await;
+        }
+    }
+}
+
+// the reply to `call`, which came in over `netid`, None if it isn't a call
+fn answer(
+    call: Bytes,
+    netid: &str,
+    local: Option<SocketAddr>,
+    mappings: &[Rpcb],
+) -> Option<Vec<u8>> {
+    // the header is the one of every call, the arguments depend on the
+    // procedure and are decoded from `call` again
+    let RpcCallMsg {
+        xid,
+        body: MsgType::Call(header),
+    } = RpcCallMsg::from_bytes(call.clone()).ok()?
+    else {
+        return None;
+    };
+    trace!(xid, vers = header.vers, proc = header.proc, "rpcbind call");
+    if header.rpcvers != RPC_VERSION {
+        let mismatch = MismatchInfo {
+            low: RPC_VERSION,
+            high: RPC_VERSION,
+        };
+        return error_reply(
+            xid,
+            ReplyBody::MsgDenied(RejectedReply::RpcMismatch(mismatch)),
+        );
+    }
+    if header.prog != RPCBIND_PROGRAM {
+        return failure(xid, AcceptBody::ProgUnavail);
+    }
+
+    match (header.vers, header.proc) {
+        (PMAP_V2..=RPCBIND_V4, PMAPPROC_NULL) => reply(xid, ()),
+        // only NFSv4 is known, nothing else can register
+        (PMAP_V2..=RPCBIND_V4, PMAPPROC_SET | PMAPPROC_UNSET) => reply(xid, false),
+        (PMAP_V2, PMAPPROC_GETPORT) => match decode::<RpcbCall<Pmap>>(&call) {
+            Ok(RpcbCall { args: query, .. }) => {
+                let port = mappings
+                    .iter()
+                    .filter_map(pmap)
+                    .find(|pmap| {
+                        (pmap.prog, pmap.vers, pmap.prot) == (query.prog, query.vers, query.prot)
+                    })
+                    .map_or(0, |pmap| pmap.port);
+                reply(xid, port)
+            }
+            Err(_) => failure(xid, AcceptBody::GarbageArgs),
+        },
+        (PMAP_V2, PMAPPROC_DUMP) => {
+            reply(xid, RpcbList(mappings.iter().filter_map(pmap).collect()))
+        }
+        (RPCBIND_V3 | RPCBIND_V4, RPCBPROC_GETADDR) | (RPCBIND_V4, RPCBPROC_GETVERSADDR) => {
+            match decode::<RpcbCall<Rpcb>>(&call) {
+                Ok(RpcbCall { args: query, .. }) => {
+                    // GETADDR answers with any version of the program
+                    let any_version = header.proc == RPCBPROC_GETADDR;
+                    // without a netid, the one the call came in over
+                    let netid = match query.r_netid.as_str() {
+                        "" => netid,
+                        r_netid => r_netid,
+                    };
+                    let r_addr = mappings
+                        .iter()
+                        .find(|mapping| {
+                            mapping.r_prog == query.r_prog
+                                && (any_version || mapping.r_vers == query.r_vers)
+                                && mapping.r_netid == netid
+                        })
+                        .map_or(String::new(), |mapping| merged(&mapping.r_addr, local));
+                    reply(xid, Opaque(r_addr))
+                }
+                Err(_) => failure(xid, AcceptBody::GarbageArgs),
+            }
+        }
+        (RPCBIND_V3 | RPCBIND_V4, RPCBPROC_DUMP) => reply(xid, RpcbList(mappings.to_vec())),
+        (PMAP_V2..=RPCBIND_V4, _) => failure(xid, AcceptBody::ProcUnavail),
+        _ => {
+            let mismatch = MismatchInfo {
+                low: PMAP_V2,
+                high: RPCBIND_V4,
+            };
+            failure(xid, AcceptBody::ProgMismatch(mismatch))
+        }
+    }
+}
+
+// an unspecified address is answered with the address the client connected
+// to, like rpcbind does, for clients connecting to the address they get
+fn merged(r_addr: &str, local: Option<SocketAddr>) -> String {
+    match (parse_uaddr(r_addr), local) {
+        (Some(addr), Some(local))
+            if addr.ip().is_unspecified() && addr.is_ipv4() == local.is_ipv4() =>
+        {
+            format_uaddr(&SocketAddr::new(local.ip(), addr.port()))
+        }
+        _ => r_addr.to_string(),
+    }
+}
+
+// the reply to the call `xid` with the results of its procedure
+fn reply<T: Serialize>(xid: u32, result: T) -> Option<Vec<u8>> {
+    let reply = RpcbReply {
+        xid,
+        msg_type: MSG_REPLY,
+        reply_stat: MSG_ACCEPTED,
+        verf: OpaqueAuth::AuthNull(Vec::new()),
+        accept_stat: SUCCESS,
+        result,
+    };
+    serde_xdr::to_bytes(&reply).ok()
+}
+
+// the reply to the call `xid` that was accepted but failed
+fn failure(xid: u32, reply_data: AcceptBody) -> Option<Vec<u8>> {
+    error_reply(
+        xid,
+        ReplyBody::MsgAccepted(AcceptedReply {
+            verf: OpaqueAuth::AuthNull(Vec::new()),
+            reply_data,
+        }),
+    )
+}
+
+fn error_reply(xid: u32, body: ReplyBody) -> Option<Vec<u8>> {
+    RpcReplyMsg {
+        xid,
+        body: MsgType::Reply(body),
+    }
+    .to_bytes()
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use bold_proto::{
+        rpc_proto::OpaqueAuth,
+        rpcb_proto::{
+            Pmap, Rpcb, RpcbCall, RpcbReply, IPPROTO_TCP, IPPROTO_UDP, PMAPPROC_GETPORT,
+            PMAPPROC_SET, PMAP_V2, RPCBIND_PROGRAM, RPCBIND_V3, RPCBIND_V4, RPCBPROC_DUMP,
+            RPCBPROC_GETADDR, RPCBPROC_GETVERSADDR,
+        },
+        xdr::Opaque,
+    };
+    use futures::SinkExt;
+    use tokio::net::{TcpStream, UdpSocket, UnixListener, UnixStream};
+    use tokio_stream::StreamExt;
+    use tokio_util::codec::Framed;
+    use tracing_test::traced_test;
+
+    use super::{decode, mappings, reply, Client, Records, Responder};
+
+    fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    fn query(r_vers: u32, r_netid: &str) -> Rpcb {
+        Rpcb {
+            r_prog: 100003,
+            r_vers,
+            r_netid: r_netid.to_string(),
+            r_addr: String::new(),
+            r_owner: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_mappings() {
+        let registered = |listen: &[&str], only_v6| {
+            mappings(&addrs(listen), only_v6)
+                .into_iter()
+                .map(|mapping| (mapping.r_netid, mapping.r_addr))
+                .collect::<Vec<_>>()
+        };
+        let mapping = |netid: &str, addr: &str| (netid.to_string(), addr.to_string());
+
+        assert_eq!(
+            registered(&["[::]:2049"], false),
+            [mapping("tcp6", "::.8.1"), mapping("tcp", "0.0.0.0.8.1")]
+        );
+        // one address for every netid
+        assert_eq!(
+            registered(&["127.0.0.1:2049", "10.0.0.1:2049", "[::1]:11112"], true),
+            [
+                mapping("tcp", "127.0.0.1.8.1"),
+                mapping("tcp6", "::1.43.104")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_register() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rpcbind.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let mappings = mappings(&addrs(&["[::]:2049"]), false);
+
+        // an rpcbind taking every registration
+        let rpcbind = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = Framed::new(stream, Records::new());
+            let mut calls = Vec::new();
+            while let Some(Ok(call)) = stream.next().await {
+                let call: RpcbCall<Rpcb> = decode(&call).unwrap();
+                assert_eq!((call.prog, call.vers), (RPCBIND_PROGRAM, RPCBIND_V4));
+                calls.push((call.proc, call.args.r_netid, call.args.r_addr));
+                let reply = reply(call.xid, true).unwrap();
+                stream.send(reply).await.unwrap();
+            }
+            calls
+        };
+        let server = async {
+            let stream = UnixStream::connect(&path).await.unwrap();
+            let mut client = Client::new(Box::new(stream), RPCBIND_V4);
+            client.register(&mappings).await.unwrap();
+            client.unregister(&mappings).await.unwrap();
+        };
+        let (calls, ()) = tokio::join!(rpcbind, server);
+
+        let call = |proc: u32, netid: &str, addr: &str| (proc, netid.to_string(), addr.to_string());
+        // a registration left behind is removed first
+        assert_eq!(
+            calls,
+            [
+                call(2, "tcp6", "::.8.1"),
+                call(1, "tcp6", "::.8.1"),
+                call(2, "tcp", "0.0.0.0.8.1"),
+                call(1, "tcp", "0.0.0.0.8.1"),
+                call(2, "tcp6", "::.8.1"),
+                call(2, "tcp", "0.0.0.0.8.1"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_responder() {
+        let responder = Responder::bind(&addrs(&["127.0.0.1:0"])).unwrap();
+        let tcp = responder.tcp[0].local_addr().unwrap();
+        let udp = responder.udp[0].local_addr().unwrap();
+        let mappings = mappings(&addrs(&["[::]:2049"]), false);
+
+        let client = async {
+            let stream = TcpStream::connect(tcp).await.unwrap();
+            let mut client = Client::new(Box::new(stream), PMAP_V2);
+            let getport = |prot| Pmap {
+                prog: 100003,
+                vers: 4,
+                prot,
+                port: 0,
+            };
+            let port: u32 = client
+                .call(PMAPPROC_GETPORT, getport(IPPROTO_TCP))
+                .await
+                .unwrap();
+            assert_eq!(port, 2049);
+            let port: u32 = client
+                .call(PMAPPROC_GETPORT, getport(IPPROTO_UDP))
+                .await
+                .unwrap();
+            assert_eq!(port, 0);
+            // registrations are refused, IPv6 is left out with version 2
+            let set: bool = client
+                .call(PMAPPROC_SET, getport(IPPROTO_TCP))
+                .await
+                .unwrap();
+            assert!(!set);
+            assert!(client.register(&mappings).await.is_err());
+
+            client.version = RPCBIND_V4;
+            let mut getaddr = async |proc, vers, netid| {
+                let r_addr: Opaque<String> = client.call(proc, query(vers, netid)).await.unwrap();
+                r_addr.0
+            };
+            // the unspecified address is the one the client connected to
+            assert_eq!(getaddr(RPCBPROC_GETADDR, 4, "tcp").await, "127.0.0.1.8.1");
+            assert_eq!(getaddr(RPCBPROC_GETADDR, 4, "tcp6").await, "::.8.1");
+            // the netid of the transport, any version with GETADDR
+            assert_eq!(getaddr(RPCBPROC_GETADDR, 3, "").await, "127.0.0.1.8.1");
+            assert_eq!(getaddr(RPCBPROC_GETVERSADDR, 3, "tcp").await, "");
+            assert_eq!(getaddr(RPCBPROC_GETADDR, 4, "udp").await, "");
+
+            client.version = RPCBIND_V3;
+            let dump: (bool, Rpcb, bool, Rpcb, bool) =
+                client.call(RPCBPROC_DUMP, ()).await.unwrap();
+            assert_eq!((dump.0, dump.2, dump.4), (true, true, false));
+            assert_eq!((dump.1, dump.3), (mappings[0].clone(), mappings[1].clone()));
+            // version 5 doesn't exist
+            client.version = 5;
+            assert!(client.call::<_, ()>(0, ()).await.is_err());
+        };
+        let datagram = async {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let call = RpcbCall {
+                xid: 7,
+                msg_type: 0,
+                rpcvers: 2,
+                prog: RPCBIND_PROGRAM,
+                vers: PMAP_V2,
+                proc: PMAPPROC_GETPORT,
+                cred: OpaqueAuth::AuthNull(Vec::new()),
+                verf: OpaqueAuth::AuthNull(Vec::new()),
+                args: Pmap {
+                    prog: 100003,
+                    vers: 4,
+                    prot: IPPROTO_TCP,
+                    port: 0,
+                },
+            };
+            let call = serde_xdr::to_bytes(&call).unwrap();
+            socket.send_to(&call, udp).await.unwrap();
+            let mut buffer = [0; 1024];
+            let length = socket.recv(&mut buffer).await.unwrap();
+            let reply: RpcbReply<u32> = decode(&buffer[..length]).unwrap();
+            assert_eq!((reply.xid, reply.accept_stat, reply.result), (7, 0, 2049));
+        };
+        tokio::select! {
+            _ = responder.serve(&mappings) => unreachable!(),
+            _ = async { tokio::join!(client, datagram) } => {}
+        }
+    }
+}
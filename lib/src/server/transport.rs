@@ -0,0 +1,617 @@
+//! The transports calls arrive on: TCP, Unix domain sockets and RPC-over-TLS.
+//!
+//! Every connection is served by the same loop over a [`Connection`], a TCP
+//! or Unix stream or the TLS session started on one of them.
+//!
+//! Please read: [RFC 9289](https://datatracker.ietf.org/doc/html/rfc9289)
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    net::SocketAddr,
+    os::unix::fs::FileTypeExt,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use bold_proto::{
+    nfs4_proto::{Compound4res, NfsFh4, NfsStat4},
+    rpc_proto::{
+        AcceptBody, AcceptedReply, AuthStat, CallBody, MsgType, OpaqueAuth, RejectedReply,
+        ReplyBody, RpcCallMsg, RpcReplyMsg, AUTH_TLS, NFS4_PROGRAM, NFSPROC4_NULL, NFS_V4,
+    },
+    XDRProtoCodec,
+};
+use futures::SinkExt;
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig,
+};
+use socket2::{Domain, Socket, Type};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener},
+    task::JoinSet,
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+use tracing::{error, info, span, trace, Instrument, Level};
+
+use super::{
+    access::Peer, clientmanager::ClientManagerHandle, exports::Exports, filemanager::Filehandle,
+    gss::GssContexts, nfs40::NFS40Server, request::NfsRequest, NFSService,
+};
+use crate::NFSServer;
+
+/// A stream calls are read from and replies are written to.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+type Transport = Framed<Box<dyn Connection>, XDRProtoCodec>;
+
+// https://datatracker.ietf.org/doc/html/rfc9289#section-4.1
+// the verifier of the reply to an AUTH_TLS probe
+const STARTTLS: &[u8] = b"STARTTLS";
+// https://datatracker.ietf.org/doc/html/rfc9289#section-5.1
+const ALPN_SUNRPC: &[u8] = b"sunrpc";
+
+/// A socket clients connect to.
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Listen on TCP at `addr`. An IPv6 address also takes IPv4 clients
+    /// unless `only_v6`, e.g. `[::]` is dual-stack without `0.0.0.0`.
+    pub fn tcp(addr: SocketAddr, only_v6: bool) -> io::Result<Self> {
+        let socket = bind(addr, Type::STREAM, only_v6)?;
+        socket.listen(1024)?;
+        Ok(Listener::Tcp(TcpListener::from_std(socket.into())?))
+    }
+
+    /// The address of a TCP listener.
+    pub fn tcp_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().ok(),
+            Listener::Unix(_) => None,
+        }
+    }
+
+    /// Listen on a Unix domain socket at `path`, replacing the socket an
+    /// earlier server left behind.
+    pub fn unix(path: &Path) -> io::Result<Self> {
+        if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        Ok(Listener::Unix(UnixListener::bind(path)?))
+    }
+
+    /// The next client and the address it is known by.
+    pub async fn accept(&self) -> io::Result<(Box<dyn Connection>, String)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                let _ = stream.set_nodelay(true);
+                // IPv4 clients of a dual-stack socket are matched against
+                // the client rules by their IPv4 address
+                let addr = SocketAddr::new(addr.ip().to_canonical(), addr.port());
+                Ok((Box::new(stream), addr.to_string()))
+            }
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                let uid = stream.peer_cred().ok().map(|cred| cred.uid());
+                Ok((Box::new(stream), unix_peer(uid).to_string()))
+            }
+        }
+    }
+}
+
+/// A socket of `socket_type` bound to `addr`, see [`Listener::tcp`] for
+/// `only_v6`.
+pub(crate) fn bind(addr: SocketAddr, socket_type: Type, only_v6: bool) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), socket_type, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    if socket_type == Type::STREAM {
+        socket.set_reuse_address(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    Ok(socket)
+}
+
+impl std::fmt::Display for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let addr = match self {
+            Listener::Tcp(listener) => listener.local_addr().map(|addr| addr.to_string()),
+            Listener::Unix(listener) => listener
+                .local_addr()
+                .map(|addr| format!("{:?}", addr.as_pathname().unwrap_or(Path::new("")))),
+        };
+        match addr {
+            Ok(addr) => write!(f, "{}", addr),
+            Err(_) => write!(f, "?"),
+        }
+    }
+}
+
+// connections on a Unix domain socket have no address, they are numbered
+// as clients are told apart by the address they are known by
+fn unix_peer(uid: Option<u32>) -> Peer {
+    static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(1);
+    Peer::Unix {
+        connection: NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed),
+        uid,
+    }
+}
+
+/// The TLS configuration of RPC-over-TLS with the certificate chain `certs`
+/// and its private key, TLS 1.3 only as required by RFC 9289.
+pub fn tls_config(
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<Arc<ServerConfig>, rustls::Error> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![ALPN_SUNRPC.to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// Read a certificate chain and its private key from PEM files, e.g. for
+/// [`crate::ServerBuilder::tls`].
+pub fn read_pem(
+    cert: &Path,
+    key: &Path,
+) -> io::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let invalid = |e: rustls::pki_types::pem::Error| {
+        io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+    };
+    let certs = CertificateDer::pem_file_iter(cert)
+        .map_err(invalid)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid)?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(invalid)?;
+    Ok((certs, key))
+}
+
+// a NULL call of NFSv4 with an AUTH_TLS credential asks whether the server
+// offers TLS
+fn is_tls_probe(msg: &RpcCallMsg) -> bool {
+    matches!(
+        &msg.body,
+        MsgType::Call(CallBody {
+            prog: NFS4_PROGRAM,
+            vers: NFS_V4,
+            proc: NFSPROC4_NULL,
+            cred: OpaqueAuth::Unsupported(AUTH_TLS),
+            ..
+        })
+    )
+}
+
+fn tls_probe_reply(xid: u32, offered: bool) -> Box<RpcReplyMsg> {
+    let body = if offered {
+        ReplyBody::MsgAccepted(AcceptedReply {
+            verf: OpaqueAuth::AuthNull(STARTTLS.to_vec()),
+            reply_data: AcceptBody::Success(Compound4res {
+                status: NfsStat4::Nfs4Ok,
+                tag: "".to_string(),
+                resarray: Vec::new(),
+            }),
+        })
+    } else {
+        // servers without TLS reject the credential
+        ReplyBody::MsgDenied(RejectedReply::AuthError(AuthStat::AuthBadCred))
+    };
+    Box::new(RpcReplyMsg {
+        xid,
+        body: MsgType::Reply(body),
+    })
+}
+
+async fn send(transport: &mut Transport, reply: Box<RpcReplyMsg>) -> bool {
+    match transport.send(reply).await {
+        Ok(_) => {
+            trace!("response sent");
+            true
+        }
+        Err(e) => {
+            error!("couldn't send response: {:?}", e);
+            false
+        }
+    }
+}
+
+/// The state the connections of a server share, every connection is served
+/// by a task of its own with a clone of it.
+#[derive(Clone)]
+pub(crate) struct Connections {
+    service: NFS40Server,
+    max_record_size: usize,
+    max_connection_memory: usize,
+    boot_time: u64,
+    client_manager: ClientManagerHandle,
+    exports: Exports,
+    // security contexts are shared between connections
+    gss: Option<GssContexts>,
+    tls: Option<TlsAcceptor>,
+}
+
+impl Connections {
+    /// Fails if the TLS certificate of the server can't be used.
+    pub(crate) fn new(server: &NFSServer) -> Result<Self, rustls::Error> {
+        let tls = match &server.tls {
+            Some(tls) => Some(TlsAcceptor::from(tls_config(
+                tls.certs.clone(),
+                tls.key.clone_key(),
+            )?)),
+            None => None,
+        };
+        Ok(Connections {
+            // ToDo implement and select correct version of NFS protocol, this services all with minor version 0
+            service: server.service_0.clone().unwrap(),
+            max_record_size: server.max_record_size,
+            max_connection_memory: server.max_connection_memory,
+            boot_time: server.boot_time,
+            client_manager: ClientManagerHandle::new(),
+            exports: Exports::new(server.exports.clone(), server.handle_db.clone())
+                .with_public(&server.public),
+            gss: server.gss_mechanism.clone().map(GssContexts::new),
+            tls,
+        })
+    }
+
+    /// Serve the clients of every listener, forever, the connections are
+    /// closed when this is dropped.
+    pub(crate) async fn serve(&self, listeners: &[Listener]) {
+        let mut connections = JoinSet::new();
+        loop {
+            let accept = futures::future::select_all(
+                listeners.iter().map(|listener| Box::pin(listener.accept())),
+            );
+            tokio::select! {
+                (accepted, _, _) = accept => match accepted {
+                    Ok((stream, addr)) => {
+                        let span = span!(Level::TRACE, "client", %addr);
+                        let this = self.clone();
+                        connections.spawn(
+                            async move { this.serve_connection(stream, addr).await }
+                                .instrument(span),
+                        );
+                    }
+                    Err(e) => error!("couldn't get client: {:?}", e),
+                },
+                Some(served) = connections.join_next() => {
+                    if let Err(e) = served {
+                        error!("connection task failed: {:?}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Serve the calls of a client until it disconnects.
+    pub(crate) async fn serve_connection(&self, stream: Box<dyn Connection>, addr: String) {
+        info!(%addr, "Client connected");
+        // a per-client based filehandle cache
+        let mut filehandle_cache = HashMap::new();
+        let mut stream = stream;
+        let mut secure = false;
+        while let Some(io) = self
+            .serve_calls(stream, &addr, secure, &mut filehandle_cache)
+            .await
+        {
+            let Some(tls) = &self.tls else {
+                break;
+            };
+            match tls.accept(io).await {
+                Ok(tls_stream) => {
+                    info!(%addr, "TLS session established");
+                    stream = Box::new(tls_stream);
+                    secure = true;
+                }
+                Err(e) => {
+                    error!("couldn't establish TLS session: {:?}", e);
+                    break;
+                }
+            }
+        }
+        info!(%addr, "Client disconnected");
+    }
+
+    // serve calls until the client disconnects, or returns the stream once
+    // the client was told to start TLS
+    async fn serve_calls(
+        &self,
+        stream: Box<dyn Connection>,
+        addr: &str,
+        secure: bool,
+        filehandle_cache: &mut HashMap<NfsFh4, (SystemTime, Filehandle)>,
+    ) -> Option<Box<dyn Connection>> {
+        // Reading NFS RPC messages over record marking codec
+        let mut transport =
+            Framed::new(stream, XDRProtoCodec::with_max_record(self.max_record_size));
+        loop {
+            match transport.next().await {
+                Some(Ok(Ok(msg))) if is_tls_probe(&msg) => {
+                    let offered = self.tls.is_some() && !secure;
+                    if !send(&mut transport, tls_probe_reply(msg.xid, offered)).await {
+                        return None;
+                    }
+                    if offered {
+                        let parts = transport.into_parts();
+                        // the client waits for the reply before its handshake
+                        if !parts.read_buf.is_empty() {
+                            error!("Client {} sent data before the TLS handshake", addr);
+                            return None;
+                        }
+                        return Some(parts.io);
+                    }
+                }
+                Some(Ok(Ok(msg))) => {
+                    // create a NFS request
+                    let mut request = NfsRequest::new(
+                        addr.to_string(),
+                        self.client_manager.clone(),
+                        self.exports.clone(),
+                        self.boot_time,
+                        Some(filehandle_cache),
+                    );
+                    request.set_write_through(
+                        transport.codec().retained() > self.max_connection_memory,
+                    );
+                    let service = NFSService::new(self.service.clone()).with_gss(self.gss.clone());
+
+                    let Some(resp) = service.call(msg, request).await else {
+                        trace!("call dropped");
+                        continue;
+                    };
+                    if !send(&mut transport, resp).await {
+                        return None;
+                    }
+                }
+                Some(Ok(Err(garbage))) => {
+                    error!("couldn't decode call: {:?}", garbage.error);
+                    let resp = Box::new(RpcReplyMsg {
+                        xid: garbage.xid,
+                        body: MsgType::Reply(ReplyBody::MsgAccepted(AcceptedReply {
+                            verf: OpaqueAuth::AuthNull(Vec::<u8>::new()),
+                            reply_data: AcceptBody::GarbageArgs,
+                        })),
+                    });
+                    if !send(&mut transport, resp).await {
+                        return None;
+                    }
+                }
+                Some(Err(e)) => {
+                    // the stream can't be framed anymore
+                    error!("couldn't get message: {:?}", e);
+                    return None;
+                }
+                // client closed connection
+                None => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+        net::{TcpStream, UnixStream},
+    };
+    use tokio_rustls::TlsConnector;
+    use tracing_test::traced_test;
+
+    use super::{Connections, Listener, Peer, ALPN_SUNRPC};
+    use crate::{storage::MemoryBackend, ServerBuilder};
+
+    // a record with a NULL call with a credential of flavor
+    fn null_call(xid: u32, flavor: u32) -> Vec<u8> {
+        program_null_call(xid, 100003, flavor)
+    }
+
+    // a record with a NULL call of version 4 of program
+    fn program_null_call(xid: u32, program: u32, flavor: u32) -> Vec<u8> {
+        let words = [xid, 0, 2, program, 4, 0, flavor, 0, 0, 0];
+        let mut record = (40_u32 | (1 << 31)).to_be_bytes().to_vec();
+        record.extend(words.iter().flat_map(|word| word.to_be_bytes()));
+        record
+    }
+
+    // the words of the next reply
+    async fn reply(stream: &mut (impl AsyncRead + Unpin)) -> Vec<u32> {
+        let header = stream.read_u32().await.unwrap();
+        let mut body = vec![0; (header & !(1 << 31)) as usize];
+        stream.read_exact(&mut body).await.unwrap();
+        body.chunks(4)
+            .map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    fn certificate() -> (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>) {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+        (vec![certified.cert.der().clone()], key.into())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_starttls() {
+        let (certs, key) = certificate();
+        let server = ServerBuilder::new(MemoryBackend::new())
+            .tls(certs.clone(), key)
+            .build();
+        let connections = Connections::new(&server).unwrap();
+        let (mut client, stream) = tokio::io::duplex(64 * 1024);
+
+        let client = async move {
+            // a NULL call of another program isn't a probe, its credential is
+            // rejected as any other
+            client
+                .write_all(&program_null_call(4, 100005, 7))
+                .await
+                .unwrap();
+            assert_eq!(reply(&mut client).await, [4, 1, 1, 1, 5]);
+            client.write_all(&null_call(1, 7)).await.unwrap();
+            let words = reply(&mut client).await;
+            // accepted with STARTTLS in the verifier
+            assert_eq!(words[..5], [1, 1, 0, 0, 8]);
+            assert_eq!(words[5..8], [0x53544152, 0x54544c53, 0]);
+
+            let mut roots = rustls::RootCertStore::empty();
+            roots.add(certs[0].clone()).unwrap();
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+            let mut config = rustls::ClientConfig::builder_with_provider(provider)
+                .with_protocol_versions(&[&rustls::version::TLS13])
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+            config.alpn_protocols = vec![ALPN_SUNRPC.to_vec()];
+            let mut tls = TlsConnector::from(Arc::new(config))
+                .connect(ServerName::try_from("localhost").unwrap(), client)
+                .await
+                .unwrap();
+            assert_eq!(tls.get_ref().1.alpn_protocol(), Some(ALPN_SUNRPC));
+
+            tls.write_all(&null_call(2, 0)).await.unwrap();
+            assert_eq!(reply(&mut tls).await[..6], [2, 1, 0, 0, 0, 0]);
+            // TLS isn't started twice, the probe is rejected with AUTH_BADCRED
+            tls.write_all(&null_call(3, 7)).await.unwrap();
+            assert_eq!(reply(&mut tls).await, [3, 1, 1, 1, 1]);
+        };
+        tokio::join!(
+            connections.serve_connection(Box::new(stream), "127.0.0.1:1000".to_string()),
+            client
+        );
+    }
+
+    #[test]
+    fn test_start_errors() {
+        // an address in use
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap();
+        let error = ServerBuilder::new(MemoryBackend::new())
+            .listen(&[addr])
+            .build()
+            .start()
+            .unwrap_err();
+        assert_eq!(error.to_string(), format!("couldn't listen on {}", addr));
+
+        // a key that doesn't go with the certificate
+        let (certs, _) = certificate();
+        let (_, key) = certificate();
+        let error = ServerBuilder::new(MemoryBackend::new())
+            .listen(&[addr])
+            .tls(certs, key)
+            .build()
+            .start()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "couldn't set up TLS");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nfs.sock");
+        // the socket of an earlier server is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let listeners = [Listener::unix(&path).unwrap()];
+        let server = ServerBuilder::new(MemoryBackend::new()).build();
+        let connections = Connections::new(&server).unwrap();
+
+        let client = async {
+            // a client doesn't hold up the others
+            let _idle = UnixStream::connect(&path).await.unwrap();
+            let mut stream = UnixStream::connect(&path).await.unwrap();
+            stream.write_all(&null_call(1, 0)).await.unwrap();
+            assert_eq!(reply(&mut stream).await[..6], [1, 1, 0, 0, 0, 0]);
+            // without a certificate TLS is not offered
+            stream.write_all(&null_call(2, 7)).await.unwrap();
+            assert_eq!(reply(&mut stream).await, [2, 1, 1, 1, 1]);
+        };
+        tokio::select! {
+            _ = connections.serve(&listeners) => unreachable!(),
+            _ = client => {}
+        }
+
+        // every connection is a client of its own, of the user of the
+        // client process
+        let uid = unsafe { libc::getuid() };
+        let mut peers = Vec::new();
+        for _ in 0..2 {
+            let _client = UnixStream::connect(&path).await.unwrap();
+            let (_, addr) = listeners[0].accept().await.unwrap();
+            match addr.parse().unwrap() {
+                Peer::Unix {
+                    connection,
+                    uid: Some(peer_uid),
+                } if peer_uid == uid => peers.push(connection),
+                peer => panic!("unexpected peer {}", peer),
+            }
+        }
+        assert_ne!(peers[0], peers[1]);
+    }
+
+    fn local_port(listener: &Listener) -> u16 {
+        match listener {
+            Listener::Tcp(listener) => listener.local_addr().unwrap().port(),
+            Listener::Unix(_) => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_dual_stack() {
+        // alone, an IPv6 socket takes IPv4 clients as well, by their IPv4
+        // address
+        let listener = Listener::tcp("[::]:0".parse().unwrap(), false).unwrap();
+        let port = local_port(&listener);
+        for server in ["127.0.0.1", "[::1]"] {
+            let client = TcpStream::connect(format!("{}:{}", server, port))
+                .await
+                .unwrap();
+            let (_, addr) = listener.accept().await.unwrap();
+            assert_eq!(addr, client.local_addr().unwrap().to_string());
+        }
+
+        // next to an IPv4 socket on the same port, it takes IPv6 clients only
+        let v4 = Listener::tcp("127.0.0.1:0".parse().unwrap(), true).unwrap();
+        let port = local_port(&v4);
+        assert!(Listener::tcp(format!("[::]:{}", port).parse().unwrap(), false).is_err());
+        let v6 = Listener::tcp(format!("[::]:{}", port).parse().unwrap(), true).unwrap();
+        let listeners = [v4, v6];
+        let server = ServerBuilder::new(MemoryBackend::new()).build();
+        let connections = Connections::new(&server).unwrap();
+
+        let client = async {
+            for (xid, server) in [(1, "127.0.0.1"), (2, "[::1]")] {
+                let mut stream = TcpStream::connect(format!("{}:{}", server, port))
+                    .await
+                    .unwrap();
+                stream.write_all(&null_call(xid, 0)).await.unwrap();
+                assert_eq!(reply(&mut stream).await[..6], [xid, 1, 0, 0, 0, 0]);
+            }
+        };
+        tokio::select! {
+            _ = connections.serve(&listeners) => unreachable!(),
+            _ = client => {}
+        }
+    }
+}
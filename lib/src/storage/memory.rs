@@ -0,0 +1,587 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use async_trait::async_trait;
+use bold_proto::nfs4_proto::NfsFtype4;
+
+use super::{
+    split_path, Capabilities, DirEntry, Metadata, ReadDirPage, SetAttrs, StatFs, StorageBackend,
+    StorageError, StorageResult,
+};
+
+const ROOT_INO: u64 = 1;
+
+#[derive(Debug)]
+struct Inode {
+    meta: Metadata,
+    // file contents of regular files
+    data: Vec<u8>,
+    // directory entries
+    entries: Entries,
+    // target of symbolic links
+    target: String,
+    // extended attributes, name to value
+    xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+impl Inode {
+    fn new(ino: u64, file_type: NfsFtype4, mode: u32) -> Self {
+        let now = SystemTime::now();
+        Inode {
+            meta: Metadata {
+                file_type,
+                size: 0,
+                space_used: 0,
+                mode: mode & 0o7777,
+                nlink: if file_type == NfsFtype4::Nf4dir { 2 } else { 1 },
+                uid: 0,
+                gid: 0,
+                dev: 0,
+                ino,
+                generation: 0,
+                atime: Some(now),
+                mtime: Some(now),
+                ctime: Some(now),
+                btime: Some(now),
+                backup_time: None,
+                acl: None,
+                named_attrs: false,
+            },
+            data: Vec::new(),
+            entries: Entries::default(),
+            target: String::new(),
+            xattrs: BTreeMap::new(),
+        }
+    }
+
+    fn touch(&mut self) {
+        let now = SystemTime::now();
+        self.meta.mtime = Some(now);
+        self.meta.ctime = Some(now);
+    }
+}
+
+// the entries of a directory, listed in the order they were added: every
+// entry gets the next cookie of its directory, which keeps the cookies of
+// the other entries valid while the directory changes
+#[derive(Debug, Default)]
+struct Entries {
+    // name to inode number and cookie
+    by_name: BTreeMap<String, (u64, u64)>,
+    // cookie to name
+    by_cookie: BTreeMap<u64, String>,
+    last_cookie: u64,
+}
+
+impl Entries {
+    fn get(&self, name: &str) -> Option<&u64> {
+        self.by_name.get(name).map(|(ino, _)| ino)
+    }
+
+    fn contains_key(&self, name: &str) -> bool {
+        self.by_name.contains_key(name)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    fn insert(&mut self, name: String, ino: u64) {
+        self.remove(&name);
+        self.last_cookie += 1;
+        self.by_cookie.insert(self.last_cookie, name.clone());
+        self.by_name.insert(name, (ino, self.last_cookie));
+    }
+
+    fn remove(&mut self, name: &str) -> Option<u64> {
+        let (ino, cookie) = self.by_name.remove(name)?;
+        self.by_cookie.remove(&cookie);
+        Some(ino)
+    }
+
+    fn page(&self, cookie: u64, max_entries: usize) -> ReadDirPage {
+        let mut rest = self
+            .by_cookie
+            .range(cookie.saturating_add(1)..)
+            .map(|(cookie, name)| DirEntry {
+                name: name.clone().into_bytes(),
+                cookie: *cookie,
+            });
+        let entries: Vec<DirEntry> = rest.by_ref().take(max_entries).collect();
+        let eof = rest.next().is_none();
+        ReadDirPage { entries, eof }
+    }
+}
+
+#[derive(Debug)]
+struct MemoryState {
+    inodes: HashMap<u64, Inode>,
+    next_ino: u64,
+}
+
+impl MemoryState {
+    fn inode(&self, ino: u64) -> StorageResult<&Inode> {
+        self.inodes.get(&ino).ok_or(StorageError::NotFound)
+    }
+
+    fn inode_mut(&mut self, ino: u64) -> StorageResult<&mut Inode> {
+        self.inodes.get_mut(&ino).ok_or(StorageError::NotFound)
+    }
+
+    fn resolve(&self, path: &str) -> StorageResult<u64> {
+        let mut ino = ROOT_INO;
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            let inode = self.inode(ino)?;
+            if inode.meta.file_type != NfsFtype4::Nf4dir {
+                return Err(StorageError::NotDir);
+            }
+            ino = *inode.entries.get(component).ok_or(StorageError::NotFound)?;
+        }
+        Ok(ino)
+    }
+
+    fn resolve_dir(&self, path: &str) -> StorageResult<u64> {
+        let ino = self.resolve(path)?;
+        if self.inode(ino)?.meta.file_type != NfsFtype4::Nf4dir {
+            return Err(StorageError::NotDir);
+        }
+        Ok(ino)
+    }
+
+    fn add_entry(&mut self, dir: u64, name: &str, inode: Inode) -> StorageResult<Metadata> {
+        if name.is_empty() {
+            return Err(StorageError::InvalidArgument);
+        }
+        let parent = self.inode_mut(dir)?;
+        if parent.entries.contains_key(name) {
+            return Err(StorageError::Exists);
+        }
+        parent.entries.insert(name.to_string(), inode.meta.ino);
+        parent.touch();
+        let meta = inode.meta.clone();
+        self.inodes.insert(inode.meta.ino, inode);
+        Ok(meta)
+    }
+
+    fn new_inode(&mut self, file_type: NfsFtype4, mode: u32) -> Inode {
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        Inode::new(ino, file_type, mode)
+    }
+
+    fn unlink(&mut self, dir: u64, name: &str) -> StorageResult<()> {
+        let ino = *self
+            .inode(dir)?
+            .entries
+            .get(name)
+            .ok_or(StorageError::NotFound)?;
+        let inode = self.inode_mut(ino)?;
+        if inode.meta.file_type == NfsFtype4::Nf4dir {
+            if !inode.entries.is_empty() {
+                return Err(StorageError::NotEmpty);
+            }
+            inode.meta.nlink = 0;
+        } else {
+            inode.meta.nlink = inode.meta.nlink.saturating_sub(1);
+            inode.meta.ctime = Some(SystemTime::now());
+        }
+        if inode.meta.nlink == 0 {
+            self.inodes.remove(&ino);
+        }
+        let parent = self.inode_mut(dir)?;
+        parent.entries.remove(name);
+        parent.touch();
+        Ok(())
+    }
+}
+
+/// An in-memory file system with inode numbers, hard links and symbolic links.
+#[derive(Debug)]
+pub struct MemoryBackend {
+    state: Mutex<MemoryState>,
+    capacity: u64,
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        let mut inodes = HashMap::new();
+        inodes.insert(ROOT_INO, Inode::new(ROOT_INO, NfsFtype4::Nf4dir, 0o755));
+        MemoryBackend {
+            state: Mutex::new(MemoryState {
+                inodes,
+                next_ino: ROOT_INO + 1,
+            }),
+            // report 1 GiB of space by default
+            capacity: 1 << 30,
+        }
+    }
+
+    /// Set the size reported as total space by statfs.
+    pub fn with_capacity(mut self, capacity: u64) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Create a directory and all its missing parents.
+    pub fn create_dir_all(&self, path: &str) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let mut ino = ROOT_INO;
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            let existing = state.inode(ino)?.entries.get(component).copied();
+            ino = match existing {
+                Some(child) => {
+                    if state.inode(child)?.meta.file_type != NfsFtype4::Nf4dir {
+                        return Err(StorageError::NotDir);
+                    }
+                    child
+                }
+                None => {
+                    let inode = state.new_inode(NfsFtype4::Nf4dir, 0o755);
+                    state.add_entry(ino, component, inode)?.ino
+                }
+            };
+        }
+        Ok(())
+    }
+
+    /// Create or replace the regular file at `path` with `contents`.
+    pub fn write_file(&self, path: &str, contents: &[u8]) -> StorageResult<()> {
+        let (dir, name) = split_path(path);
+        let mut state = self.state.lock().unwrap();
+        let dir_ino = state.resolve_dir(&dir)?;
+        let existing = state.inode(dir_ino)?.entries.get(&name).copied();
+        let ino = match existing {
+            Some(ino) => ino,
+            None => {
+                let inode = state.new_inode(NfsFtype4::Nf4reg, 0o644);
+                state.add_entry(dir_ino, &name, inode)?.ino
+            }
+        };
+        let inode = state.inode_mut(ino)?;
+        if inode.meta.file_type != NfsFtype4::Nf4reg {
+            return Err(StorageError::IsDir);
+        }
+        inode.data = contents.to_vec();
+        inode.meta.size = contents.len() as u64;
+        inode.meta.space_used = contents.len() as u64;
+        inode.touch();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryBackend {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            hard_links: true,
+            symlinks: true,
+            unique_handles: false,
+            persistent_handles: false,
+            inode_lookup: false,
+            exclusive_access: true,
+        }
+    }
+
+    async fn getattr(&self, path: &str) -> StorageResult<Metadata> {
+        let state = self.state.lock().unwrap();
+        let ino = state.resolve(path)?;
+        Ok(state.inode(ino)?.meta.clone())
+    }
+
+    async fn setattr(&self, path: &str, attrs: &SetAttrs) -> StorageResult<Metadata> {
+        let mut state = self.state.lock().unwrap();
+        let ino = state.resolve(path)?;
+        let inode = state.inode_mut(ino)?;
+        if let Some(size) = attrs.size {
+            if inode.meta.file_type == NfsFtype4::Nf4dir {
+                return Err(StorageError::IsDir);
+            }
+            inode.data.resize(size as usize, 0);
+            inode.meta.size = size;
+            inode.meta.space_used = size;
+            inode.meta.mtime = Some(SystemTime::now());
+        }
+        if let Some(mode) = attrs.mode {
+            inode.meta.mode = mode & 0o7777;
+        }
+        if let Some(uid) = attrs.uid {
+            inode.meta.uid = uid;
+        }
+        if let Some(gid) = attrs.gid {
+            inode.meta.gid = gid;
+        }
+        if let Some(atime) = attrs.atime {
+            inode.meta.atime = Some(atime);
+        }
+        if let Some(mtime) = attrs.mtime {
+            inode.meta.mtime = Some(mtime);
+        }
+        if let Some(acl) = &attrs.acl {
+            inode.meta.acl = Some(acl.clone());
+        }
+        inode.meta.ctime = Some(SystemTime::now());
+        Ok(inode.meta.clone())
+    }
+
+    async fn read(&self, path: &str, offset: u64, count: u32) -> StorageResult<(Vec<u8>, bool)> {
+        let state = self.state.lock().unwrap();
+        let inode = state.inode(state.resolve(path)?)?;
+        if inode.meta.file_type == NfsFtype4::Nf4dir {
+            return Err(StorageError::IsDir);
+        }
+        let size = inode.data.len() as u64;
+        if offset >= size {
+            return Ok((Vec::new(), true));
+        }
+        let end = std::cmp::min(size, offset + count as u64);
+        Ok((
+            inode.data[offset as usize..end as usize].to_vec(),
+            end == size,
+        ))
+    }
+
+    async fn write(&self, path: &str, offset: u64, data: &[u8]) -> StorageResult<u32> {
+        let mut state = self.state.lock().unwrap();
+        let ino = state.resolve(path)?;
+        let inode = state.inode_mut(ino)?;
+        if inode.meta.file_type == NfsFtype4::Nf4dir {
+            return Err(StorageError::IsDir);
+        }
+        let offset = offset as usize;
+        let end = offset + data.len();
+        if end > inode.data.len() {
+            inode.data.resize(end, 0);
+        }
+        inode.data[offset..end].copy_from_slice(data);
+        inode.meta.size = inode.data.len() as u64;
+        inode.meta.space_used = inode.meta.size;
+        inode.touch();
+        Ok(data.len() as u32)
+    }
+
+    async fn create(
+        &self,
+        dir: &str,
+        name: &str,
+        file_type: NfsFtype4,
+        mode: u32,
+    ) -> StorageResult<Metadata> {
+        if file_type != NfsFtype4::Nf4reg && file_type != NfsFtype4::Nf4dir {
+            return Err(StorageError::NotSupported);
+        }
+        let mut state = self.state.lock().unwrap();
+        let dir_ino = state.resolve_dir(dir)?;
+        let inode = state.new_inode(file_type, mode);
+        state.add_entry(dir_ino, name, inode)
+    }
+
+    async fn remove(&self, dir: &str, name: &str) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let dir_ino = state.resolve_dir(dir)?;
+        state.unlink(dir_ino, name)
+    }
+
+    async fn rename(
+        &self,
+        from_dir: &str,
+        from_name: &str,
+        to_dir: &str,
+        to_name: &str,
+    ) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let from_ino = state.resolve_dir(from_dir)?;
+        let to_ino = state.resolve_dir(to_dir)?;
+        let ino = *state
+            .inode(from_ino)?
+            .entries
+            .get(from_name)
+            .ok_or(StorageError::NotFound)?;
+        if let Some(existing) = state.inode(to_ino)?.entries.get(to_name).copied() {
+            if existing == ino {
+                return Ok(());
+            }
+            let source_is_dir = state.inode(ino)?.meta.is_dir();
+            if state.inode(existing)?.meta.is_dir() != source_is_dir {
+                return Err(if source_is_dir {
+                    StorageError::NotDir
+                } else {
+                    StorageError::IsDir
+                });
+            }
+            state.unlink(to_ino, to_name)?;
+        }
+        let from = state.inode_mut(from_ino)?;
+        from.entries.remove(from_name);
+        from.touch();
+        let to = state.inode_mut(to_ino)?;
+        to.entries.insert(to_name.to_string(), ino);
+        to.touch();
+        state.inode_mut(ino)?.meta.ctime = Some(SystemTime::now());
+        Ok(())
+    }
+
+    async fn link(&self, path: &str, dir: &str, name: &str) -> StorageResult<Metadata> {
+        let mut state = self.state.lock().unwrap();
+        let ino = state.resolve(path)?;
+        if state.inode(ino)?.meta.is_dir() {
+            return Err(StorageError::IsDir);
+        }
+        let dir_ino = state.resolve_dir(dir)?;
+        let parent = state.inode_mut(dir_ino)?;
+        if parent.entries.contains_key(name) {
+            return Err(StorageError::Exists);
+        }
+        parent.entries.insert(name.to_string(), ino);
+        parent.touch();
+        let inode = state.inode_mut(ino)?;
+        inode.meta.nlink += 1;
+        inode.meta.ctime = Some(SystemTime::now());
+        Ok(inode.meta.clone())
+    }
+
+    async fn symlink(&self, dir: &str, name: &str, target: &str) -> StorageResult<Metadata> {
+        let mut state = self.state.lock().unwrap();
+        let dir_ino = state.resolve_dir(dir)?;
+        let mut inode = state.new_inode(NfsFtype4::Nf4lnk, 0o777);
+        inode.target = target.to_string();
+        inode.meta.size = target.len() as u64;
+        state.add_entry(dir_ino, name, inode)
+    }
+
+    async fn readlink(&self, path: &str) -> StorageResult<String> {
+        let state = self.state.lock().unwrap();
+        let inode = state.inode(state.resolve(path)?)?;
+        if inode.meta.file_type != NfsFtype4::Nf4lnk {
+            return Err(StorageError::InvalidArgument);
+        }
+        Ok(inode.target.clone())
+    }
+
+    async fn readdir(
+        &self,
+        path: &str,
+        cookie: u64,
+        max_entries: usize,
+    ) -> StorageResult<ReadDirPage> {
+        let state = self.state.lock().unwrap();
+        let inode = state.inode(state.resolve_dir(path)?)?;
+        Ok(inode.entries.page(cookie, max_entries))
+    }
+
+    async fn statfs(&self, _path: &str) -> StorageResult<StatFs> {
+        let state = self.state.lock().unwrap();
+        let used: u64 = state.inodes.values().map(|i| i.meta.space_used).sum();
+        let free = self.capacity.saturating_sub(used);
+        let files = state.inodes.len() as u64;
+        Ok(StatFs {
+            total_bytes: self.capacity,
+            free_bytes: free,
+            avail_bytes: free,
+            total_files: u32::MAX as u64,
+            free_files: u32::MAX as u64 - files,
+            avail_files: u32::MAX as u64 - files,
+        })
+    }
+
+    async fn list_xattrs(&self, path: &str) -> StorageResult<Vec<String>> {
+        let state = self.state.lock().unwrap();
+        let inode = state.inode(state.resolve(path)?)?;
+        Ok(inode.xattrs.keys().cloned().collect())
+    }
+
+    async fn get_xattr(&self, path: &str, name: &str) -> StorageResult<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        let inode = state.inode(state.resolve(path)?)?;
+        inode
+            .xattrs
+            .get(name)
+            .cloned()
+            .ok_or(StorageError::NotFound)
+    }
+
+    async fn set_xattr(&self, path: &str, name: &str, value: &[u8]) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let ino = state.resolve(path)?;
+        let inode = state.inode_mut(ino)?;
+        inode.xattrs.insert(name.to_string(), value.to_vec());
+        inode.meta.ctime = Some(SystemTime::now());
+        Ok(())
+    }
+
+    async fn remove_xattr(&self, path: &str, name: &str) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let ino = state.resolve(path)?;
+        let inode = state.inode_mut(ino)?;
+        inode.xattrs.remove(name).ok_or(StorageError::NotFound)?;
+        inode.meta.ctime = Some(SystemTime::now());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bold_proto::nfs4_proto::NfsFtype4;
+
+    use super::MemoryBackend;
+    use crate::storage::{StorageBackend, StorageError};
+
+    #[tokio::test]
+    async fn test_links_and_readdir() {
+        let backend = MemoryBackend::new();
+        backend.create_dir_all("/dir1").unwrap();
+        backend.write_file("/dir1/file1.txt", b"Hello").unwrap();
+
+        let meta = backend
+            .link("/dir1/file1.txt", "/", "hardlink")
+            .await
+            .unwrap();
+        assert_eq!(meta.nlink, 2);
+        backend
+            .symlink("/", "symlink", "dir1/file1.txt")
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.readlink("/symlink").await.unwrap(),
+            "dir1/file1.txt"
+        );
+
+        backend.write("/hardlink", 5, b", World!").await.unwrap();
+        let (data, eof) = backend.read("/dir1/file1.txt", 0, 100).await.unwrap();
+        assert_eq!(data, b"Hello, World!");
+        assert!(eof);
+
+        let page = backend.readdir("/", 0, 2).await.unwrap();
+        let names: Vec<_> = page.entries.iter().map(|e| e.name.as_slice()).collect();
+        assert_eq!(names, vec![b"dir1".as_slice(), b"hardlink"]);
+        assert!(!page.eof);
+        let page = backend
+            .readdir("/", page.entries[1].cookie, 2)
+            .await
+            .unwrap();
+        assert_eq!(page.entries.len(), 1);
+        assert_eq!(page.entries[0].name, b"symlink");
+        assert!(page.eof);
+
+        assert_eq!(
+            backend.remove("/", "dir1").await.unwrap_err(),
+            StorageError::NotEmpty
+        );
+        backend
+            .rename("/dir1", "file1.txt", "/", "moved")
+            .await
+            .unwrap();
+        backend.remove("/", "dir1").await.unwrap();
+        let meta = backend.getattr("/moved").await.unwrap();
+        assert_eq!(meta.file_type, NfsFtype4::Nf4reg);
+        assert_eq!(meta.nlink, 2);
+    }
+}
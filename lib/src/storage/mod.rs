@@ -0,0 +1,509 @@
+//! Storage backends for bold.
+//!
+//! The [`StorageBackend`] trait is the only interface the `FileManager` uses
+//! to reach the exported file system. Paths handed to a backend are always
+//! absolute within the export, use `/` as separator and `/` denotes the
+//! export root (the same convention as `Filehandle::path`).
+//!
+//! bold ships three implementations:
+//! - [`PhysicalBackend`]: a directory of the host file system
+//! - [`MemoryBackend`]: an in-memory file system with inodes, links and symlinks
+//! - [`VfsBackend`]: an adapter for any [`vfs::FileSystem`]
+//!
+//! [`NamedAttrBackend`] wraps any of them to present extended attributes as
+//! NFSv4 named attributes.
+
+use std::{fmt, io, path::PathBuf, time::SystemTime};
+
+use async_trait::async_trait;
+use bold_proto::nfs4_proto::{NfsFtype4, NfsStat4, Nfsace4};
+
+mod memory;
+mod named_attrs;
+mod physical;
+mod vfs_adapter;
+
+pub use memory::MemoryBackend;
+pub use named_attrs::{NamedAttrBackend, NAMED_ATTR_DIR};
+pub use physical::PhysicalBackend;
+pub use vfs_adapter::VfsBackend;
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+/// Errors a backend can report, mapped to NFS errors by [`StorageError::nfs_error`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageError {
+    NotFound,
+    Exists,
+    NotDir,
+    IsDir,
+    NotEmpty,
+    AccessDenied,
+    PermissionDenied,
+    NoSpace,
+    QuotaExceeded,
+    ReadOnly,
+    NameTooLong,
+    InvalidArgument,
+    CrossDevice,
+    NotSupported,
+    // the object is a symbolic link, which is never followed
+    Symlink,
+    Io(String),
+}
+
+impl StorageError {
+    pub fn nfs_error(&self) -> NfsStat4 {
+        match self {
+            StorageError::NotFound => NfsStat4::Nfs4errNoent,
+            StorageError::Exists => NfsStat4::Nfs4errExist,
+            StorageError::NotDir => NfsStat4::Nfs4errNotdir,
+            StorageError::IsDir => NfsStat4::Nfs4errIsdir,
+            StorageError::NotEmpty => NfsStat4::Nfs4errNotempty,
+            StorageError::AccessDenied => NfsStat4::Nfs4errAccess,
+            StorageError::PermissionDenied => NfsStat4::Nfs4errPerm,
+            StorageError::NoSpace => NfsStat4::Nfs4errNospc,
+            StorageError::QuotaExceeded => NfsStat4::Nfs4errDquot,
+            StorageError::ReadOnly => NfsStat4::Nfs4errRofs,
+            StorageError::NameTooLong => NfsStat4::Nfs4errNametoolong,
+            StorageError::InvalidArgument => NfsStat4::Nfs4errInval,
+            StorageError::CrossDevice => NfsStat4::Nfs4errXdev,
+            StorageError::NotSupported => NfsStat4::Nfs4errNotsupp,
+            StorageError::Symlink => NfsStat4::Nfs4errSymlink,
+            StorageError::Io(_) => NfsStat4::Nfs4errIo,
+        }
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StorageError: {:?}", self)
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> Self {
+        #[cfg(unix)]
+        if let Some(code) = e.raw_os_error() {
+            match code {
+                libc::ENOENT => return StorageError::NotFound,
+                libc::EEXIST => return StorageError::Exists,
+                libc::ENOTDIR => return StorageError::NotDir,
+                libc::EISDIR => return StorageError::IsDir,
+                libc::ENOTEMPTY => return StorageError::NotEmpty,
+                libc::EACCES => return StorageError::AccessDenied,
+                libc::EPERM => return StorageError::PermissionDenied,
+                libc::ENOSPC => return StorageError::NoSpace,
+                libc::EDQUOT => return StorageError::QuotaExceeded,
+                libc::EROFS => return StorageError::ReadOnly,
+                libc::ENAMETOOLONG => return StorageError::NameTooLong,
+                libc::EINVAL => return StorageError::InvalidArgument,
+                libc::EXDEV => return StorageError::CrossDevice,
+                libc::ENOTSUP => return StorageError::NotSupported,
+                libc::ELOOP => return StorageError::Symlink,
+                _ => {}
+            }
+        }
+        match e.kind() {
+            io::ErrorKind::NotFound => StorageError::NotFound,
+            io::ErrorKind::AlreadyExists => StorageError::Exists,
+            io::ErrorKind::PermissionDenied => StorageError::AccessDenied,
+            io::ErrorKind::InvalidInput => StorageError::InvalidArgument,
+            io::ErrorKind::Unsupported => StorageError::NotSupported,
+            _ => StorageError::Io(e.to_string()),
+        }
+    }
+}
+
+impl From<vfs::VfsError> for StorageError {
+    fn from(e: vfs::VfsError) -> Self {
+        match e.kind() {
+            vfs::error::VfsErrorKind::FileNotFound => StorageError::NotFound,
+            vfs::error::VfsErrorKind::InvalidPath => StorageError::InvalidArgument,
+            vfs::error::VfsErrorKind::DirectoryExists => StorageError::Exists,
+            vfs::error::VfsErrorKind::FileExists => StorageError::Exists,
+            vfs::error::VfsErrorKind::NotSupported => StorageError::NotSupported,
+            // keep the os error, it tells a full disk from an exceeded quota
+            vfs::error::VfsErrorKind::IoError(io) => match io.raw_os_error() {
+                Some(code) => StorageError::from(io::Error::from_raw_os_error(code)),
+                None => StorageError::from(io::Error::new(io.kind(), io.to_string())),
+            },
+            _ => StorageError::Io(e.to_string()),
+        }
+    }
+}
+
+/// Attributes of a file system object as reported by a backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub file_type: NfsFtype4,
+    // size in bytes, 0 for directories
+    pub size: u64,
+    // bytes allocated on the backing store
+    pub space_used: u64,
+    // UNIX mode bits, without the file type
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    // device and inode number, the inode number is reported as fileid and
+    // must be unique within the export and stay the same across renames
+    pub dev: u64,
+    pub ino: u64,
+    // distinguishes objects that reuse the inode number of a removed one
+    pub generation: u32,
+    pub atime: Option<SystemTime>,
+    pub mtime: Option<SystemTime>,
+    pub ctime: Option<SystemTime>,
+    // creation time
+    pub btime: Option<SystemTime>,
+    // time of the last backup, only recorded by few file systems
+    pub backup_time: Option<SystemTime>,
+    // the NFSv4 ACL stored for the object, None if only its mode applies
+    pub acl: Option<Vec<Nfsace4>>,
+    // the object has extended attributes, filled in by NamedAttrBackend
+    pub named_attrs: bool,
+}
+
+impl Metadata {
+    pub fn is_dir(&self) -> bool {
+        self.file_type == NfsFtype4::Nf4dir
+    }
+}
+
+/// Attributes to change with [`StorageBackend::setattr`], `None` leaves a value untouched.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SetAttrs {
+    pub size: Option<u64>,
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub atime: Option<SystemTime>,
+    pub mtime: Option<SystemTime>,
+    // replaces the stored ACL, the caller keeps it in sync with the mode
+    pub acl: Option<Vec<Nfsace4>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    // the name as stored by the file system, not necessarily UTF-8
+    pub name: Vec<u8>,
+    // passing this cookie to readdir continues the listing after this entry
+    pub cookie: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadDirPage {
+    pub entries: Vec<DirEntry>,
+    pub eof: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatFs {
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    pub avail_bytes: u64,
+    pub total_files: u64,
+    pub free_files: u64,
+    pub avail_files: u64,
+}
+
+/// Features a backend supports, reported to clients as file system attributes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    pub hard_links: bool,
+    pub symlinks: bool,
+    pub unique_handles: bool,
+    // objects keep their device, inode and generation for their lifetime,
+    // so filehandles can be built from them
+    pub persistent_handles: bool,
+    // [`StorageBackend::lookup_inode`] finds objects without scanning, so
+    // inode filehandles resolve after a restart even without a filehandle db
+    pub inode_lookup: bool,
+    // the storage is only modified through this backend, so directory
+    // changes made by bold are reported to clients as atomic
+    pub exclusive_access: bool,
+}
+
+#[async_trait]
+pub trait StorageBackend: Send + Sync + fmt::Debug {
+    fn capabilities(&self) -> Capabilities;
+
+    /// Look up `name` in the directory `dir`.
+    async fn lookup(&self, dir: &str, name: &str) -> StorageResult<Metadata> {
+        self.getattr(&join_path(dir, name)).await
+    }
+
+    /// Attributes of `path`; symbolic links are not followed.
+    async fn getattr(&self, path: &str) -> StorageResult<Metadata>;
+
+    async fn setattr(&self, path: &str, attrs: &SetAttrs) -> StorageResult<Metadata>;
+
+    /// Read up to `count` bytes at `offset`, returns the data and whether the end of file was reached.
+    async fn read(&self, path: &str, offset: u64, count: u32) -> StorageResult<(Vec<u8>, bool)>;
+
+    /// Write `data` at `offset`, extending the file if needed. Returns the number of bytes written.
+    async fn write(&self, path: &str, offset: u64, data: &[u8]) -> StorageResult<u32>;
+
+    /// Flush written data of `path` to stable storage.
+    async fn commit(&self, _path: &str) -> StorageResult<()> {
+        Ok(())
+    }
+
+    /// Create a regular file or a directory, fails with [`StorageError::Exists`] if `name` is taken.
+    async fn create(
+        &self,
+        dir: &str,
+        name: &str,
+        file_type: NfsFtype4,
+        mode: u32,
+    ) -> StorageResult<Metadata>;
+
+    /// Remove a file, symbolic link or empty directory.
+    async fn remove(&self, dir: &str, name: &str) -> StorageResult<()>;
+
+    async fn rename(
+        &self,
+        from_dir: &str,
+        from_name: &str,
+        to_dir: &str,
+        to_name: &str,
+    ) -> StorageResult<()>;
+
+    /// Create a hard link `dir/name` to `path`.
+    async fn link(&self, path: &str, dir: &str, name: &str) -> StorageResult<Metadata>;
+
+    async fn symlink(&self, dir: &str, name: &str, target: &str) -> StorageResult<Metadata>;
+
+    async fn readlink(&self, path: &str) -> StorageResult<String>;
+
+    /// List up to `max_entries` entries of the directory `path`, starting after `cookie`.
+    /// A cookie of 0 starts at the beginning of the directory.
+    ///
+    /// Cookies have to stay valid while entries are added to or removed from
+    /// the directory, clients page through large directories with them.
+    /// They are never 0 and stay below `u64::MAX - 2`.
+    async fn readdir(
+        &self,
+        path: &str,
+        cookie: u64,
+        max_entries: usize,
+    ) -> StorageResult<ReadDirPage>;
+
+    async fn statfs(&self, path: &str) -> StorageResult<StatFs>;
+
+    /// Find the path of the object with the given device, inode and generation,
+    /// only used by backends reporting [`Capabilities::inode_lookup`].
+    async fn lookup_inode(&self, _dev: u64, _ino: u64, _generation: u32) -> StorageResult<String> {
+        Err(StorageError::NotSupported)
+    }
+
+    /// Where the filehandles handed out are kept when the server is given no
+    /// filehandle db, for backends whose filehandles can't be resolved after
+    /// a restart without one.
+    fn default_handle_db(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Names of the extended attributes of `path`.
+    async fn list_xattrs(&self, _path: &str) -> StorageResult<Vec<String>> {
+        Err(StorageError::NotSupported)
+    }
+
+    /// Value of the extended attribute `name` of `path`, [`StorageError::NotFound`] if it is not set.
+    async fn get_xattr(&self, _path: &str, _name: &str) -> StorageResult<Vec<u8>> {
+        Err(StorageError::NotSupported)
+    }
+
+    /// Create or replace the extended attribute `name` of `path`.
+    async fn set_xattr(&self, _path: &str, _name: &str, _value: &[u8]) -> StorageResult<()> {
+        Err(StorageError::NotSupported)
+    }
+
+    async fn remove_xattr(&self, _path: &str, _name: &str) -> StorageResult<()> {
+        Err(StorageError::NotSupported)
+    }
+}
+
+/// Join a directory path and a component name.
+pub fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() || dir == "/" {
+        format!("/{}", name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Split a path into its parent directory and the last component.
+pub fn split_path(path: &str) -> (String, String) {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(0) => ("/".to_string(), path[1..].to_string()),
+        Some(idx) => (path[..idx].to_string(), path[idx + 1..].to_string()),
+        None => ("/".to_string(), path.to_string()),
+    }
+}
+
+/// A page of a directory for backends that can only list all of its
+/// entries at once.
+///
+/// The entries are ordered by a hash of their name, which makes up their
+/// cookie, so cookies don't depend on the other entries of the directory.
+/// Names sharing a hash are ranked by name in the low bits of the cookie,
+/// only adding or removing one of them moves the others. The whole
+/// directory is still listed, but only the entries of the page are sorted.
+pub fn hashed_page(
+    names: impl IntoIterator<Item = Vec<u8>>,
+    cookie: u64,
+    max_entries: usize,
+) -> ReadDirPage {
+    ranked_page(names, cookie, max_entries, name_bucket)
+}
+
+fn ranked_page(
+    names: impl IntoIterator<Item = Vec<u8>>,
+    cookie: u64,
+    max_entries: usize,
+    bucket_of: impl Fn(&[u8]) -> u64,
+) -> ReadDirPage {
+    // the bucket of the entry the last page ended with
+    let resume = cookie.checked_sub(1).map(|last| last >> RANK_BITS);
+    let mut rest: Vec<(u64, Vec<u8>)> = names
+        .into_iter()
+        .map(|name| (bucket_of(&name), name))
+        .filter(|(bucket, _)| resume.is_none_or(|resume| *bucket >= resume))
+        .collect();
+    // the ranks of the resume bucket the client has seen come first at most
+    let needed = max_entries + (RANK_MASK as usize + 1);
+    let mut more = false;
+    if rest.len() > needed {
+        rest.select_nth_unstable(needed);
+        rest.truncate(needed);
+        more = true;
+    }
+    rest.sort_unstable();
+
+    let mut entries = Vec::new();
+    let mut previous = None;
+    let mut rank = 0;
+    for (bucket, name) in rest {
+        rank = match previous {
+            Some(previous) if previous == bucket => (rank + 1).min(RANK_MASK),
+            _ => 0,
+        };
+        previous = Some(bucket);
+        let entry_cookie = (bucket << RANK_BITS | rank) + 1;
+        if entry_cookie <= cookie {
+            continue;
+        }
+        if entries.len() == max_entries {
+            more = true;
+            break;
+        }
+        entries.push(DirEntry {
+            name,
+            cookie: entry_cookie,
+        });
+    }
+    ReadDirPage {
+        entries,
+        eof: !more,
+    }
+}
+
+// the low bits of a hashed cookie rank the names sharing a bucket, more
+// than 256 names would have to share 54 bits of the hash to overflow them
+const RANK_BITS: u32 = 8;
+const RANK_MASK: u64 = (1 << RANK_BITS) - 1;
+
+// 54 bits of the FNV-1a hash of the name, a fixed hash so cookies survive
+// restarts and rebuilds of the server
+fn name_bucket(name: &[u8]) -> u64 {
+    fnv1a(name) >> (64 - 54)
+}
+
+// the FNV-1a hash, the same in every build of the server
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+    bytes.iter().fold(FNV_OFFSET, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hashed_page, join_path, name_bucket, ranked_page, split_path, RANK_BITS};
+
+    #[test]
+    fn test_path_helpers() {
+        assert_eq!(join_path("/", "file1.txt"), "/file1.txt");
+        assert_eq!(join_path("", "dir1"), "/dir1");
+        assert_eq!(join_path("/dir1", "file2.txt"), "/dir1/file2.txt");
+        assert_eq!(
+            split_path("/dir1/file2.txt"),
+            ("/dir1".to_string(), "file2.txt".to_string())
+        );
+        assert_eq!(
+            split_path("/file1.txt"),
+            ("/".to_string(), "file1.txt".to_string())
+        );
+    }
+
+    #[test]
+    fn test_hashed_page() {
+        let names = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.as_bytes().to_vec())
+                .collect::<Vec<_>>()
+        };
+        let page = hashed_page(names(&["a", "b", "c"]), 0, 2);
+        assert_eq!(page.entries.len(), 2);
+        assert!(!page.eof);
+        // an entry added before the cookie doesn't move the rest of the listing
+        let added = names(&["a", "b", "c", "d", "e", "f"]);
+        let first = page.entries[0].clone();
+        let rest = hashed_page(added.clone(), page.entries[1].cookie, 10);
+        assert!(rest.eof);
+        let all = hashed_page(added, 0, 10);
+        let after = all
+            .entries
+            .iter()
+            .skip_while(|entry| entry.cookie <= page.entries[1].cookie);
+        assert!(after.eq(rest.entries.iter()));
+        assert!(!rest.entries.contains(&first));
+    }
+
+    #[test]
+    fn test_hashed_page_collisions() {
+        let names: Vec<Vec<u8>> = vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()];
+        // every name in one bucket, paging one entry at a time loses none
+        let mut cookie = 0;
+        let mut listed = Vec::new();
+        loop {
+            let page = ranked_page(names.clone(), cookie, 1, |_| 7);
+            listed.extend(page.entries.iter().map(|entry| entry.name.clone()));
+            match page.entries.last() {
+                Some(last) if !page.eof => cookie = last.cookie,
+                _ => break,
+            }
+        }
+        assert_eq!(listed, names);
+        // the cookies are distinct and keep the bucket
+        let page = ranked_page(names, 0, 10, |_| 7);
+        let cookies: Vec<u64> = page.entries.iter().map(|entry| entry.cookie).collect();
+        assert_eq!(
+            cookies,
+            vec![
+                (7 << RANK_BITS) + 1,
+                (7 << RANK_BITS) + 2,
+                (7 << RANK_BITS) + 3
+            ]
+        );
+        // the hash doesn't change between builds of the server
+        assert_eq!(name_bucket(b"file1.txt"), 10957962832636903);
+    }
+}
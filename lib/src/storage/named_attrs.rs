@@ -0,0 +1,425 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use bold_proto::nfs4_proto::NfsFtype4;
+
+use super::{
+    hashed_page, Capabilities, Metadata, ReadDirPage, SetAttrs, StatFs, StorageBackend,
+    StorageError, StorageResult,
+};
+
+/// The path component of the named attribute directory of an object.
+///
+/// Component names sent by clients never contain NUL, so this can't
+/// collide with a directory entry.
+pub const NAMED_ATTR_DIR: &str = "\0named_attrs";
+
+// what a path passed to NamedAttrBackend refers to
+#[derive(Debug, PartialEq, Eq)]
+enum Target<'a> {
+    Object(&'a str),
+    // the named attribute directory of an object
+    AttrDir(&'a str),
+    // a named attribute of an object
+    Attr(&'a str, &'a str),
+}
+
+fn target(path: &str) -> StorageResult<Target<'_>> {
+    let marker = format!("/{}", NAMED_ATTR_DIR);
+    let Some(idx) = path.find(&marker) else {
+        return Ok(Target::Object(path));
+    };
+    let object = if idx == 0 { "/" } else { &path[..idx] };
+    match &path[idx + marker.len()..] {
+        "" => Ok(Target::AttrDir(object)),
+        rest => match rest.strip_prefix('/') {
+            // named attributes have no named attributes or entries of their own
+            Some(name) if !name.is_empty() && !name.contains('/') => Ok(Target::Attr(object, name)),
+            _ => Err(StorageError::NotDir),
+        },
+    }
+}
+
+// named attributes have no inode numbers, they get one derived from the
+// object and the name with the top bit set, which inode numbers rarely use
+fn attr_ino(ino: u64, name: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (ino, name).hash(&mut hasher);
+    hasher.finish() | 1 << 63
+}
+
+/// Presents the extended attributes of the objects of a backend as NFSv4
+/// named attributes.
+///
+/// The named attribute directory of `path` is `path/`[`NAMED_ATTR_DIR`] and
+/// each extended attribute a regular file in it. All other paths are passed
+/// on to the wrapped backend.
+#[derive(Debug, Clone)]
+pub struct NamedAttrBackend {
+    inner: Arc<dyn StorageBackend>,
+}
+
+impl NamedAttrBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>) -> Self {
+        NamedAttrBackend { inner }
+    }
+
+    fn attr_dir_meta(object: &Metadata) -> Metadata {
+        Metadata {
+            file_type: NfsFtype4::Nf4attrdir,
+            size: 0,
+            space_used: 0,
+            // who may read the object may list its attributes
+            mode: (object.mode & 0o666) | ((object.mode & 0o444) >> 2),
+            nlink: 2,
+            ino: attr_ino(object.ino, ""),
+            acl: None,
+            named_attrs: false,
+            ..object.clone()
+        }
+    }
+
+    fn attr_meta(object: &Metadata, name: &str, size: u64) -> Metadata {
+        Metadata {
+            file_type: NfsFtype4::Nf4namedattr,
+            size,
+            space_used: size,
+            mode: object.mode & 0o666,
+            nlink: 1,
+            ino: attr_ino(object.ino, name),
+            acl: None,
+            named_attrs: false,
+            ..object.clone()
+        }
+    }
+
+    async fn object_meta(&self, path: &str) -> StorageResult<Metadata> {
+        let mut meta = self.inner.getattr(path).await?;
+        meta.named_attrs = self
+            .inner
+            .list_xattrs(path)
+            .await
+            .is_ok_and(|names| !names.is_empty());
+        Ok(meta)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for NamedAttrBackend {
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn getattr(&self, path: &str) -> StorageResult<Metadata> {
+        match target(path)? {
+            Target::Object(path) => self.object_meta(path).await,
+            Target::AttrDir(object) => {
+                // only objects that can have attributes have an attribute directory
+                self.inner.list_xattrs(object).await?;
+                Ok(Self::attr_dir_meta(&self.inner.getattr(object).await?))
+            }
+            Target::Attr(object, name) => {
+                let size = self.inner.get_xattr(object, name).await?.len() as u64;
+                Ok(Self::attr_meta(
+                    &self.inner.getattr(object).await?,
+                    name,
+                    size,
+                ))
+            }
+        }
+    }
+
+    async fn setattr(&self, path: &str, attrs: &SetAttrs) -> StorageResult<Metadata> {
+        match target(path)? {
+            Target::Object(path) => {
+                self.inner.setattr(path, attrs).await?;
+                self.object_meta(path).await
+            }
+            // the attributes of the object apply, only the size can be set
+            Target::AttrDir(_) => self.getattr(path).await,
+            Target::Attr(object, name) => {
+                if let Some(size) = attrs.size {
+                    let mut value = self.inner.get_xattr(object, name).await?;
+                    value.resize(size as usize, 0);
+                    self.inner.set_xattr(object, name, &value).await?;
+                }
+                self.getattr(path).await
+            }
+        }
+    }
+
+    async fn read(&self, path: &str, offset: u64, count: u32) -> StorageResult<(Vec<u8>, bool)> {
+        match target(path)? {
+            Target::Object(path) => self.inner.read(path, offset, count).await,
+            Target::AttrDir(_) => Err(StorageError::IsDir),
+            Target::Attr(object, name) => {
+                let value = self.inner.get_xattr(object, name).await?;
+                let start = std::cmp::min(offset, value.len() as u64) as usize;
+                let end = std::cmp::min(start + count as usize, value.len());
+                Ok((value[start..end].to_vec(), end == value.len()))
+            }
+        }
+    }
+
+    async fn write(&self, path: &str, offset: u64, data: &[u8]) -> StorageResult<u32> {
+        match target(path)? {
+            Target::Object(path) => self.inner.write(path, offset, data).await,
+            Target::AttrDir(_) => Err(StorageError::IsDir),
+            Target::Attr(object, name) => {
+                let mut value = self.inner.get_xattr(object, name).await?;
+                let offset = offset as usize;
+                let end = offset + data.len();
+                if end > value.len() {
+                    value.resize(end, 0);
+                }
+                value[offset..end].copy_from_slice(data);
+                self.inner.set_xattr(object, name, &value).await?;
+                Ok(data.len() as u32)
+            }
+        }
+    }
+
+    async fn commit(&self, path: &str) -> StorageResult<()> {
+        match target(path)? {
+            Target::Object(path) => self.inner.commit(path).await,
+            // attributes are written as a whole
+            _ => Ok(()),
+        }
+    }
+
+    async fn create(
+        &self,
+        dir: &str,
+        name: &str,
+        file_type: NfsFtype4,
+        mode: u32,
+    ) -> StorageResult<Metadata> {
+        match target(dir)? {
+            Target::Object(dir) => self.inner.create(dir, name, file_type, mode).await,
+            Target::AttrDir(object) => {
+                if file_type != NfsFtype4::Nf4reg {
+                    return Err(StorageError::NotSupported);
+                }
+                match self.inner.get_xattr(object, name).await {
+                    Ok(_) => return Err(StorageError::Exists),
+                    Err(StorageError::NotFound) => {}
+                    Err(e) => return Err(e),
+                }
+                self.inner.set_xattr(object, name, &[]).await?;
+                Ok(Self::attr_meta(&self.inner.getattr(object).await?, name, 0))
+            }
+            Target::Attr(..) => Err(StorageError::NotDir),
+        }
+    }
+
+    async fn remove(&self, dir: &str, name: &str) -> StorageResult<()> {
+        match target(dir)? {
+            Target::Object(dir) => self.inner.remove(dir, name).await,
+            Target::AttrDir(object) => self.inner.remove_xattr(object, name).await,
+            Target::Attr(..) => Err(StorageError::NotDir),
+        }
+    }
+
+    async fn rename(
+        &self,
+        from_dir: &str,
+        from_name: &str,
+        to_dir: &str,
+        to_name: &str,
+    ) -> StorageResult<()> {
+        match (target(from_dir)?, target(to_dir)?) {
+            (Target::Object(from_dir), Target::Object(to_dir)) => {
+                self.inner
+                    .rename(from_dir, from_name, to_dir, to_name)
+                    .await
+            }
+            // attributes only move within the attribute directory of their object
+            (Target::AttrDir(from), Target::AttrDir(to)) if from == to => {
+                let value = self.inner.get_xattr(from, from_name).await?;
+                self.inner.set_xattr(from, to_name, &value).await?;
+                if from_name != to_name {
+                    self.inner.remove_xattr(from, from_name).await?;
+                }
+                Ok(())
+            }
+            _ => Err(StorageError::CrossDevice),
+        }
+    }
+
+    async fn link(&self, path: &str, dir: &str, name: &str) -> StorageResult<Metadata> {
+        match (target(path)?, target(dir)?) {
+            (Target::Object(path), Target::Object(dir)) => self.inner.link(path, dir, name).await,
+            _ => Err(StorageError::CrossDevice),
+        }
+    }
+
+    async fn symlink(&self, dir: &str, name: &str, target_path: &str) -> StorageResult<Metadata> {
+        match target(dir)? {
+            Target::Object(dir) => self.inner.symlink(dir, name, target_path).await,
+            _ => Err(StorageError::NotSupported),
+        }
+    }
+
+    async fn readlink(&self, path: &str) -> StorageResult<String> {
+        match target(path)? {
+            Target::Object(path) => self.inner.readlink(path).await,
+            _ => Err(StorageError::InvalidArgument),
+        }
+    }
+
+    async fn readdir(
+        &self,
+        path: &str,
+        cookie: u64,
+        max_entries: usize,
+    ) -> StorageResult<ReadDirPage> {
+        match target(path)? {
+            Target::Object(path) => self.inner.readdir(path, cookie, max_entries).await,
+            Target::AttrDir(object) => {
+                let names = self.inner.list_xattrs(object).await?;
+                let names = names.into_iter().map(String::into_bytes);
+                Ok(hashed_page(names, cookie, max_entries))
+            }
+            Target::Attr(..) => Err(StorageError::NotDir),
+        }
+    }
+
+    async fn statfs(&self, path: &str) -> StorageResult<StatFs> {
+        match target(path)? {
+            Target::Object(path) | Target::AttrDir(path) | Target::Attr(path, _) => {
+                self.inner.statfs(path).await
+            }
+        }
+    }
+
+    async fn lookup_inode(&self, dev: u64, ino: u64, generation: u32) -> StorageResult<String> {
+        self.inner.lookup_inode(dev, ino, generation).await
+    }
+
+    fn default_handle_db(&self) -> Option<PathBuf> {
+        self.inner.default_handle_db()
+    }
+
+    async fn list_xattrs(&self, path: &str) -> StorageResult<Vec<String>> {
+        match target(path)? {
+            Target::Object(path) => self.inner.list_xattrs(path).await,
+            _ => Err(StorageError::NotSupported),
+        }
+    }
+
+    async fn get_xattr(&self, path: &str, name: &str) -> StorageResult<Vec<u8>> {
+        match target(path)? {
+            Target::Object(path) => self.inner.get_xattr(path, name).await,
+            _ => Err(StorageError::NotSupported),
+        }
+    }
+
+    async fn set_xattr(&self, path: &str, name: &str, value: &[u8]) -> StorageResult<()> {
+        match target(path)? {
+            Target::Object(path) => self.inner.set_xattr(path, name, value).await,
+            _ => Err(StorageError::NotSupported),
+        }
+    }
+
+    async fn remove_xattr(&self, path: &str, name: &str) -> StorageResult<()> {
+        match target(path)? {
+            Target::Object(path) => self.inner.remove_xattr(path, name).await,
+            _ => Err(StorageError::NotSupported),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bold_proto::nfs4_proto::NfsFtype4;
+
+    use super::{target, NamedAttrBackend, Target};
+    use crate::storage::{join_path, MemoryBackend, StorageBackend, StorageError, NAMED_ATTR_DIR};
+
+    #[test]
+    fn test_target() {
+        let dir = join_path("/dir1/file1.txt", NAMED_ATTR_DIR);
+        assert_eq!(
+            target("/dir1/file1.txt"),
+            Ok(Target::Object("/dir1/file1.txt"))
+        );
+        assert_eq!(target(&dir), Ok(Target::AttrDir("/dir1/file1.txt")));
+        assert_eq!(
+            target(&join_path(&dir, "user.tag")),
+            Ok(Target::Attr("/dir1/file1.txt", "user.tag"))
+        );
+        assert_eq!(
+            target(&join_path("/", NAMED_ATTR_DIR)),
+            Ok(Target::AttrDir("/"))
+        );
+        assert_eq!(
+            target(&join_path(&join_path(&dir, "tag"), "x")),
+            Err(StorageError::NotDir)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_named_attrs() {
+        let memory = MemoryBackend::new();
+        memory.write_file("/file1.txt", b"data").unwrap();
+        let backend = NamedAttrBackend::new(Arc::new(memory));
+        let dir = join_path("/file1.txt", NAMED_ATTR_DIR);
+
+        let meta = backend.getattr(&dir).await.unwrap();
+        assert_eq!(meta.file_type, NfsFtype4::Nf4attrdir);
+        assert!(!backend.getattr("/file1.txt").await.unwrap().named_attrs);
+        backend
+            .create(&dir, "tag", NfsFtype4::Nf4reg, 0o644)
+            .await
+            .unwrap();
+        assert_eq!(
+            backend
+                .create(&dir, "tag", NfsFtype4::Nf4reg, 0o644)
+                .await
+                .unwrap_err(),
+            StorageError::Exists
+        );
+        let attr = join_path(&dir, "tag");
+        backend.write(&attr, 0, b"Hello").await.unwrap();
+        backend.write(&attr, 5, b", World!").await.unwrap();
+        assert_eq!(
+            backend.read(&attr, 7, 100).await.unwrap(),
+            (b"World!".to_vec(), true)
+        );
+        let meta = backend.getattr(&attr).await.unwrap();
+        assert_eq!(meta.file_type, NfsFtype4::Nf4namedattr);
+        assert_eq!(meta.size, 13);
+        assert_ne!(meta.ino, backend.getattr("/file1.txt").await.unwrap().ino);
+        assert!(backend.getattr("/file1.txt").await.unwrap().named_attrs);
+
+        backend.rename(&dir, "tag", &dir, "label").await.unwrap();
+        let page = backend.readdir(&dir, 0, 10).await.unwrap();
+        assert_eq!(page.entries.len(), 1);
+        assert_eq!(page.entries[0].name, b"label");
+        assert!(page.eof);
+        assert_eq!(
+            backend
+                .rename(&dir, "label", "/", "label")
+                .await
+                .unwrap_err(),
+            StorageError::CrossDevice
+        );
+        backend.remove(&dir, "label").await.unwrap();
+        assert_eq!(
+            backend.getattr(&attr).await.unwrap_err(),
+            StorageError::NotFound
+        );
+        // the file itself is untouched
+        assert_eq!(
+            backend.read("/file1.txt", 0, 100).await.unwrap(),
+            (b"data".to_vec(), true)
+        );
+    }
+}
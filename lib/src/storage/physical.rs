@@ -0,0 +1,1130 @@
+use std::{
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use bold_proto::nfs4_proto::{NfsFtype4, Nfsace4};
+
+use super::{
+    join_path, Capabilities, Metadata, ReadDirPage, SetAttrs, StatFs, StorageBackend, StorageError,
+    StorageResult,
+};
+
+/// Shares a directory of the host file system.
+#[derive(Debug, Clone)]
+pub struct PhysicalBackend {
+    root: PathBuf,
+    // where filehandles are kept without a filehandle db of the server
+    handle_db: Option<PathBuf>,
+}
+
+impl PhysicalBackend {
+    /// Share the directory `root`. Its filehandles are kept in a file of
+    /// the state directory of the user, see [`PhysicalBackend::with_handle_db`].
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref().to_path_buf();
+        PhysicalBackend {
+            handle_db: default_handle_db(&root),
+            root,
+        }
+    }
+
+    /// Keep the filehandles handed out in the file at `path` when the
+    /// server is given no filehandle db, `None` to let them go stale on a
+    /// restart. By default they are kept in
+    /// `$XDG_STATE_HOME/bold/handles-<hash of the directory>.db`, in
+    /// `~/.local/state` without `XDG_STATE_HOME` and `/var/lib` for root.
+    pub fn with_handle_db(mut self, path: Option<PathBuf>) -> Self {
+        self.handle_db = path;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // File system calls block, and the file descriptors a resolved path goes
+    // through must stay open until they are done, so every operation runs on
+    // a blocking thread.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&PhysicalBackend) -> StorageResult<T> + Send + 'static,
+    ) -> StorageResult<T> {
+        let backend = self.clone();
+        tokio::task::spawn_blocking(move || f(&backend))
+            .await
+            .map_err(|e| StorageError::Io(e.to_string()))?
+    }
+
+    // Walk a path of the export from the root directory one component at a
+    // time, without following symbolic links, they could point anywhere.
+    // Returns the directory holding the last component and its name, which
+    // is None for the root itself.
+    #[cfg(target_os = "linux")]
+    fn open_parent<'a>(
+        &self,
+        path: &'a str,
+    ) -> StorageResult<(std::os::fd::OwnedFd, Option<&'a str>)> {
+        let mut dir = open_fd(None, &c_path(&self.root)?, libc::O_DIRECTORY)?;
+        let mut components = path.split('/').filter(|c| !c.is_empty()).peekable();
+        while let Some(component) = components.next() {
+            if component == "." || component == ".." {
+                return Err(StorageError::InvalidArgument);
+            }
+            if components.peek().is_none() {
+                return Ok((dir, Some(component)));
+            }
+            let next = open_fd(Some(&dir), &c_name(component)?, libc::O_NOFOLLOW)?;
+            let file = std::fs::File::from(next);
+            let file_type = file.metadata()?.file_type();
+            if file_type.is_symlink() {
+                return Err(StorageError::Symlink);
+            }
+            if !file_type.is_dir() {
+                return Err(StorageError::NotDir);
+            }
+            dir = file.into();
+        }
+        Ok((dir, None))
+    }
+
+    // Map a path of the export to the host file system. The directories on
+    // the way are opened without following symbolic links and the result
+    // goes through the last of them, so a directory replaced with a link in
+    // the meantime is never followed. The last component isn't looked at,
+    // for operations that don't follow a symbolic link at the end of path.
+    #[cfg(target_os = "linux")]
+    fn resolve(&self, path: &str) -> StorageResult<RealPath> {
+        let (dir, name) = self.open_parent(path)?;
+        Ok(RealPath::new(dir, Some(name.unwrap_or("."))))
+    }
+
+    // like resolve, for operations that would follow a symbolic link at the
+    // end of path: the object itself is opened and used through its descriptor
+    #[cfg(target_os = "linux")]
+    fn resolve_nofollow(&self, path: &str) -> StorageResult<RealPath> {
+        let (dir, name) = self.open_parent(path)?;
+        let Some(name) = name else {
+            return Ok(RealPath::new(dir, Some(".")));
+        };
+        let file = std::fs::File::from(open_fd(Some(&dir), &c_name(name)?, libc::O_NOFOLLOW)?);
+        if file.metadata()?.file_type().is_symlink() {
+            return Err(StorageError::Symlink);
+        }
+        Ok(RealPath::new(file.into(), None))
+    }
+
+    // Without file descriptors to walk a path with, symbolic links are
+    // checked for before the path is used.
+    #[cfg(not(target_os = "linux"))]
+    fn resolve(&self, path: &str) -> StorageResult<RealPath> {
+        let mut real_path = self.root.clone();
+        let mut components = path.split('/').filter(|c| !c.is_empty()).peekable();
+        while let Some(component) = components.next() {
+            if component == "." || component == ".." {
+                return Err(StorageError::InvalidArgument);
+            }
+            real_path.push(component);
+            if components.peek().is_some() {
+                let meta = std::fs::symlink_metadata(&real_path)?;
+                if meta.file_type().is_symlink() {
+                    return Err(StorageError::Symlink);
+                }
+                if !meta.is_dir() {
+                    return Err(StorageError::NotDir);
+                }
+            }
+        }
+        Ok(RealPath { path: real_path })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn resolve_nofollow(&self, path: &str) -> StorageResult<RealPath> {
+        let real_path = self.resolve(path)?;
+        if std::fs::symlink_metadata(&real_path)?
+            .file_type()
+            .is_symlink()
+        {
+            return Err(StorageError::Symlink);
+        }
+        Ok(real_path)
+    }
+
+    fn stat(&self, path: &str) -> StorageResult<Metadata> {
+        let real_path = self.resolve(path)?;
+        let mut meta = metadata_from_std(&std::fs::symlink_metadata(&real_path)?);
+        meta.acl = read_acl(&real_path);
+        Ok(meta)
+    }
+}
+
+// A path of the host file system, on Linux it goes through
+// /proc/self/fd to a file descriptor that is kept open as long as the path is used.
+#[derive(Debug)]
+struct RealPath {
+    path: PathBuf,
+    #[cfg(target_os = "linux")]
+    _fd: std::os::fd::OwnedFd,
+}
+
+#[cfg(target_os = "linux")]
+impl RealPath {
+    fn new(fd: std::os::fd::OwnedFd, name: Option<&str>) -> Self {
+        use std::os::fd::AsRawFd;
+
+        let mut path = PathBuf::from(format!("/proc/self/fd/{}", fd.as_raw_fd()));
+        if let Some(name) = name {
+            path.push(name);
+        }
+        RealPath { path, _fd: fd }
+    }
+}
+
+impl std::ops::Deref for RealPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for RealPath {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+// open an object for its descriptor only, relative to dir or the working directory
+#[cfg(target_os = "linux")]
+fn open_fd(
+    dir: Option<&std::os::fd::OwnedFd>,
+    name: &std::ffi::CStr,
+    flags: libc::c_int,
+) -> StorageResult<std::os::fd::OwnedFd> {
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    let dir = dir.map_or(libc::AT_FDCWD, |dir| dir.as_raw_fd());
+    // SAFETY: name is NUL-terminated and dir an open directory or AT_FDCWD
+    let fd = unsafe { libc::openat(dir, name.as_ptr(), flags | libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(StorageError::from(std::io::Error::last_os_error()));
+    }
+    // SAFETY: fd was just opened and belongs to nothing else
+    Ok(unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) })
+}
+
+#[cfg(target_os = "linux")]
+fn c_name(name: &str) -> StorageResult<std::ffi::CString> {
+    std::ffi::CString::new(name).map_err(|_| StorageError::InvalidArgument)
+}
+
+// the file in the state directory the filehandles of root are kept in, one
+// for every shared directory
+#[cfg(unix)]
+fn default_handle_db(root: &Path) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let state = match std::env::var_os("XDG_STATE_HOME") {
+        Some(state) => PathBuf::from(state),
+        None if unsafe { libc::geteuid() } == 0 => PathBuf::from("/var/lib"),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    let root = std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let hash = super::fnv1a(root.as_os_str().as_bytes());
+    Some(state.join("bold").join(format!("handles-{:016x}.db", hash)))
+}
+
+// filehandles are built from inode numbers on Unix only
+#[cfg(not(unix))]
+fn default_handle_db(_root: &Path) -> Option<PathBuf> {
+    None
+}
+
+// The inode generation number is only available through an ioctl on an open
+// file, the birth time serves the same purpose: a new object reusing an inode
+// number has a different one.
+fn generation(meta: &std::fs::Metadata) -> u32 {
+    match meta
+        .created()
+        .ok()
+        .and_then(|btime| btime.duration_since(std::time::UNIX_EPOCH).ok())
+    {
+        Some(since_epoch) => (since_epoch.as_secs() as u32) ^ since_epoch.subsec_nanos(),
+        None => 0,
+    }
+}
+
+// The host file system knows nothing about NFSv4 ACLs, they are kept XDR
+// encoded in an extended attribute next to the mode bits.
+#[cfg(target_os = "linux")]
+const ACL_XATTR: &std::ffi::CStr = c"user.nfs4_acl";
+
+#[cfg(unix)]
+fn c_path(real_path: &Path) -> StorageResult<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(real_path.as_os_str().as_bytes())
+        .map_err(|_| StorageError::InvalidArgument)
+}
+
+// objects without the attribute, or whose attribute can't be read, only have
+// their mode bits
+#[cfg(target_os = "linux")]
+fn read_acl(real_path: &Path) -> Option<Vec<Nfsace4>> {
+    let c_path = c_path(real_path).ok()?;
+    // SAFETY: both strings are NUL-terminated, a NULL buffer asks for the size
+    let size =
+        unsafe { libc::lgetxattr(c_path.as_ptr(), ACL_XATTR.as_ptr(), std::ptr::null_mut(), 0) };
+    if size <= 0 {
+        return None;
+    }
+    let mut buffer = vec![0_u8; size as usize];
+    // SAFETY: buffer is valid for buffer.len() bytes
+    let size = unsafe {
+        libc::lgetxattr(
+            c_path.as_ptr(),
+            ACL_XATTR.as_ptr(),
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            buffer.len(),
+        )
+    };
+    if size < 0 {
+        return None;
+    }
+    buffer.truncate(size as usize);
+    bold_proto::xdr::from_bytes(&buffer).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_acl(_real_path: &Path) -> Option<Vec<Nfsace4>> {
+    None
+}
+
+// real_path is never a symbolic link, see resolve_nofollow
+#[cfg(target_os = "linux")]
+fn write_acl(real_path: &Path, acl: &[Nfsace4]) -> StorageResult<()> {
+    let c_path = c_path(real_path)?;
+    let value = serde_xdr::to_bytes(&acl.to_vec()).map_err(|e| StorageError::Io(e.to_string()))?;
+    // SAFETY: both strings are NUL-terminated and value is valid for value.len() bytes
+    let res = unsafe {
+        libc::setxattr(
+            c_path.as_ptr(),
+            ACL_XATTR.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    if res != 0 {
+        return Err(StorageError::from(std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn write_acl(_real_path: &Path, _acl: &[Nfsace4]) -> StorageResult<()> {
+    Err(StorageError::NotSupported)
+}
+
+// Named attributes are the extended attributes of the user namespace, the
+// one holding the ACL is not one of them.
+#[cfg(target_os = "linux")]
+fn xattr_name(name: &str) -> StorageResult<std::ffi::CString> {
+    let name = std::ffi::CString::new(format!("user.{}", name))
+        .map_err(|_| StorageError::InvalidArgument)?;
+    if name.as_c_str() == ACL_XATTR {
+        return Err(StorageError::AccessDenied);
+    }
+    Ok(name)
+}
+
+#[cfg(target_os = "linux")]
+fn list_xattrs(real_path: &Path) -> StorageResult<Vec<String>> {
+    let c_path = c_path(real_path)?;
+    // the list can grow between asking for its size and reading it
+    loop {
+        // SAFETY: c_path is NUL-terminated, a NULL buffer asks for the size
+        let size = unsafe { libc::llistxattr(c_path.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return Err(StorageError::from(std::io::Error::last_os_error()));
+        }
+        let mut buffer = vec![0_u8; size as usize];
+        // SAFETY: buffer is valid for buffer.len() bytes
+        let size = unsafe {
+            libc::llistxattr(
+                c_path.as_ptr(),
+                buffer.as_mut_ptr() as *mut libc::c_char,
+                buffer.len(),
+            )
+        };
+        if size < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.raw_os_error() == Some(libc::ERANGE) {
+                continue;
+            }
+            return Err(StorageError::from(e));
+        }
+        buffer.truncate(size as usize);
+        let acl = ACL_XATTR.to_bytes();
+        return Ok(buffer
+            .split(|b| *b == 0)
+            .filter(|name| *name != acl)
+            .filter_map(|name| name.strip_prefix(b"user."))
+            .map(|name| String::from_utf8_lossy(name).to_string())
+            .collect());
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn get_xattr(real_path: &Path, name: &str) -> StorageResult<Vec<u8>> {
+    let (c_path, c_name) = (c_path(real_path)?, xattr_name(name)?);
+    loop {
+        // SAFETY: both strings are NUL-terminated, a NULL buffer asks for the size
+        let size =
+            unsafe { libc::lgetxattr(c_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return Err(xattr_error());
+        }
+        let mut buffer = vec![0_u8; size as usize];
+        // SAFETY: buffer is valid for buffer.len() bytes
+        let size = unsafe {
+            libc::lgetxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        };
+        if size < 0 {
+            if std::io::Error::last_os_error().raw_os_error() == Some(libc::ERANGE) {
+                continue;
+            }
+            return Err(xattr_error());
+        }
+        buffer.truncate(size as usize);
+        return Ok(buffer);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_xattr(real_path: &Path, name: &str, value: &[u8]) -> StorageResult<()> {
+    let (c_path, c_name) = (c_path(real_path)?, xattr_name(name)?);
+    // SAFETY: both strings are NUL-terminated and value is valid for value.len() bytes
+    let res = unsafe {
+        libc::lsetxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    if res != 0 {
+        return Err(xattr_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn remove_xattr(real_path: &Path, name: &str) -> StorageResult<()> {
+    let (c_path, c_name) = (c_path(real_path)?, xattr_name(name)?);
+    // SAFETY: both strings are NUL-terminated
+    let res = unsafe { libc::lremovexattr(c_path.as_ptr(), c_name.as_ptr()) };
+    if res != 0 {
+        return Err(xattr_error());
+    }
+    Ok(())
+}
+
+// a missing attribute is reported as ENODATA
+#[cfg(target_os = "linux")]
+fn xattr_error() -> StorageError {
+    let e = std::io::Error::last_os_error();
+    match e.raw_os_error() {
+        Some(libc::ENODATA) => StorageError::NotFound,
+        _ => StorageError::from(e),
+    }
+}
+
+#[cfg(unix)]
+fn metadata_from_std(meta: &std::fs::Metadata) -> Metadata {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // seconds are rounded down and nanoseconds count forward, also before the epoch
+    let to_time = |secs: i64, nsecs: i64| {
+        let nanos = secs as i128 * 1_000_000_000 + nsecs as i128;
+        let since_epoch = |nanos: u128| {
+            Duration::new(
+                (nanos / 1_000_000_000) as u64,
+                (nanos % 1_000_000_000) as u32,
+            )
+        };
+        if nanos >= 0 {
+            UNIX_EPOCH.checked_add(since_epoch(nanos as u128))
+        } else {
+            UNIX_EPOCH.checked_sub(since_epoch(nanos.unsigned_abs()))
+        }
+    };
+    let ft = meta.file_type();
+    let file_type = if ft.is_dir() {
+        NfsFtype4::Nf4dir
+    } else if ft.is_file() {
+        NfsFtype4::Nf4reg
+    } else if ft.is_symlink() {
+        NfsFtype4::Nf4lnk
+    } else if ft.is_block_device() {
+        NfsFtype4::Nf4blk
+    } else if ft.is_char_device() {
+        NfsFtype4::Nf4chr
+    } else if ft.is_fifo() {
+        NfsFtype4::Nf4fifo
+    } else if ft.is_socket() {
+        NfsFtype4::Nf4sock
+    } else {
+        NfsFtype4::Nf4Undef
+    };
+
+    Metadata {
+        file_type,
+        size: meta.size(),
+        space_used: meta.blocks() * 512,
+        mode: meta.mode() & 0o7777,
+        nlink: meta.nlink() as u32,
+        uid: meta.uid(),
+        gid: meta.gid(),
+        dev: meta.dev(),
+        ino: meta.ino(),
+        generation: generation(meta),
+        atime: to_time(meta.atime(), meta.atime_nsec()),
+        mtime: to_time(meta.mtime(), meta.mtime_nsec()),
+        ctime: to_time(meta.ctime(), meta.ctime_nsec()),
+        btime: meta.created().ok(),
+        backup_time: None,
+        acl: None,
+        named_attrs: false,
+    }
+}
+
+#[cfg(not(unix))]
+fn metadata_from_std(meta: &std::fs::Metadata) -> Metadata {
+    let ft = meta.file_type();
+    let file_type = if ft.is_dir() {
+        NfsFtype4::Nf4dir
+    } else if ft.is_file() {
+        NfsFtype4::Nf4reg
+    } else if ft.is_symlink() {
+        NfsFtype4::Nf4lnk
+    } else {
+        NfsFtype4::Nf4Undef
+    };
+    let mode = if meta.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    };
+
+    Metadata {
+        file_type,
+        size: meta.len(),
+        space_used: meta.len(),
+        mode: if ft.is_dir() { mode | 0o111 } else { mode },
+        nlink: 1,
+        uid: 0,
+        gid: 0,
+        dev: 0,
+        ino: 0,
+        generation: 0,
+        atime: meta.accessed().ok(),
+        mtime: meta.modified().ok(),
+        ctime: meta.modified().ok(),
+        btime: meta.created().ok(),
+        backup_time: None,
+        acl: None,
+        named_attrs: false,
+    }
+}
+
+#[async_trait]
+impl StorageBackend for PhysicalBackend {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            hard_links: true,
+            symlinks: cfg!(unix),
+            unique_handles: false,
+            persistent_handles: cfg!(unix),
+            inode_lookup: false,
+            exclusive_access: false,
+        }
+    }
+
+    fn default_handle_db(&self) -> Option<PathBuf> {
+        self.handle_db.clone()
+    }
+
+    async fn getattr(&self, path: &str) -> StorageResult<Metadata> {
+        let path = path.to_string();
+        self.blocking(move |backend| backend.stat(&path)).await
+    }
+
+    async fn setattr(&self, path: &str, attrs: &SetAttrs) -> StorageResult<Metadata> {
+        let (path, attrs) = (path.to_string(), attrs.clone());
+        self.blocking(move |backend| {
+            let real_path = backend.resolve_nofollow(&path)?;
+            if let Some(size) = attrs.size {
+                let file = std::fs::OpenOptions::new().write(true).open(&real_path)?;
+                file.set_len(size)?;
+            }
+            if let Some(mode) = attrs.mode {
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    std::fs::set_permissions(
+                        &real_path,
+                        std::fs::Permissions::from_mode(mode & 0o7777),
+                    )?;
+                }
+                #[cfg(not(unix))]
+                {
+                    let mut perms = std::fs::metadata(&real_path)?.permissions();
+                    perms.set_readonly(mode & 0o222 == 0);
+                    std::fs::set_permissions(&real_path, perms)?;
+                }
+            }
+            if attrs.uid.is_some() || attrs.gid.is_some() {
+                // real_path is no symbolic link, there is nothing chown could follow
+                #[cfg(unix)]
+                std::os::unix::fs::chown(&real_path, attrs.uid, attrs.gid)?;
+                #[cfg(not(unix))]
+                return Err(StorageError::NotSupported);
+            }
+            if attrs.atime.is_some() || attrs.mtime.is_some() {
+                let mut times = std::fs::FileTimes::new();
+                if let Some(atime) = attrs.atime {
+                    times = times.set_accessed(atime);
+                }
+                if let Some(mtime) = attrs.mtime {
+                    times = times.set_modified(mtime);
+                }
+                let file = std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&real_path)
+                    .or_else(|_| std::fs::File::open(&real_path))?;
+                file.set_times(times)?;
+            }
+            if let Some(acl) = &attrs.acl {
+                write_acl(&real_path, acl)?;
+            }
+            backend.stat(&path)
+        })
+        .await
+    }
+
+    async fn read(&self, path: &str, offset: u64, count: u32) -> StorageResult<(Vec<u8>, bool)> {
+        let path = path.to_string();
+        self.blocking(move |backend| {
+            let mut file = std::fs::File::open(backend.resolve_nofollow(&path)?)?;
+            let size = file.metadata()?.len();
+            if offset >= size {
+                return Ok((Vec::new(), true));
+            }
+            file.seek(SeekFrom::Start(offset))?;
+            let len = std::cmp::min(count as u64, size - offset) as usize;
+            let mut buffer = vec![0_u8; len];
+            let mut read = 0;
+            while read < len {
+                let n = file.read(&mut buffer[read..])?;
+                if n == 0 {
+                    break;
+                }
+                read += n;
+            }
+            buffer.truncate(read);
+            let eof = offset + read as u64 >= size;
+            Ok((buffer, eof))
+        })
+        .await
+    }
+
+    async fn write(&self, path: &str, offset: u64, data: &[u8]) -> StorageResult<u32> {
+        let (path, data) = (path.to_string(), data.to_vec());
+        self.blocking(move |backend| {
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(backend.resolve_nofollow(&path)?)?;
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(&data)?;
+            Ok(data.len() as u32)
+        })
+        .await
+    }
+
+    async fn commit(&self, path: &str) -> StorageResult<()> {
+        let path = path.to_string();
+        self.blocking(move |backend| {
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(backend.resolve_nofollow(&path)?)?;
+            file.sync_all()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn create(
+        &self,
+        dir: &str,
+        name: &str,
+        file_type: NfsFtype4,
+        mode: u32,
+    ) -> StorageResult<Metadata> {
+        let path = join_path(dir, name);
+        self.blocking(move |backend| {
+            let real_path = backend.resolve(&path)?;
+            match file_type {
+                NfsFtype4::Nf4reg => {
+                    let mut options = std::fs::OpenOptions::new();
+                    options.write(true).create_new(true);
+                    #[cfg(unix)]
+                    {
+                        use std::os::unix::fs::OpenOptionsExt;
+                        options.mode(mode & 0o7777);
+                    }
+                    options.open(&real_path)?;
+                }
+                NfsFtype4::Nf4dir => {
+                    let mut builder = std::fs::DirBuilder::new();
+                    #[cfg(unix)]
+                    {
+                        use std::os::unix::fs::DirBuilderExt;
+                        builder.mode(mode & 0o7777);
+                    }
+                    builder.create(&real_path)?;
+                }
+                _ => return Err(StorageError::NotSupported),
+            }
+            #[cfg(not(unix))]
+            let _ = mode;
+            backend.stat(&path)
+        })
+        .await
+    }
+
+    async fn remove(&self, dir: &str, name: &str) -> StorageResult<()> {
+        let path = join_path(dir, name);
+        self.blocking(move |backend| {
+            let real_path = backend.resolve(&path)?;
+            let meta = std::fs::symlink_metadata(&real_path)?;
+            if meta.is_dir() {
+                std::fs::remove_dir(&real_path)?;
+            } else {
+                std::fs::remove_file(&real_path)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn rename(
+        &self,
+        from_dir: &str,
+        from_name: &str,
+        to_dir: &str,
+        to_name: &str,
+    ) -> StorageResult<()> {
+        let (from, to) = (join_path(from_dir, from_name), join_path(to_dir, to_name));
+        self.blocking(move |backend| {
+            std::fs::rename(backend.resolve(&from)?, backend.resolve(&to)?)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn link(&self, path: &str, dir: &str, name: &str) -> StorageResult<Metadata> {
+        let (path, new_path) = (path.to_string(), join_path(dir, name));
+        self.blocking(move |backend| {
+            std::fs::hard_link(backend.resolve(&path)?, backend.resolve(&new_path)?)?;
+            backend.stat(&new_path)
+        })
+        .await
+    }
+
+    async fn symlink(&self, dir: &str, name: &str, target: &str) -> StorageResult<Metadata> {
+        let new_path = join_path(dir, name);
+        #[cfg(unix)]
+        {
+            let target = target.to_string();
+            self.blocking(move |backend| {
+                std::os::unix::fs::symlink(target, backend.resolve(&new_path)?)?;
+                backend.stat(&new_path)
+            })
+            .await
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (new_path, target);
+            Err(StorageError::NotSupported)
+        }
+    }
+
+    async fn readlink(&self, path: &str) -> StorageResult<String> {
+        let path = path.to_string();
+        self.blocking(move |backend| {
+            let target = std::fs::read_link(backend.resolve(&path)?)?;
+            Ok(target.to_string_lossy().to_string())
+        })
+        .await
+    }
+
+    // the offsets of the host file system serve as cookies, it keeps them
+    // valid while the directory changes
+    #[cfg(target_os = "linux")]
+    async fn readdir(
+        &self,
+        path: &str,
+        cookie: u64,
+        max_entries: usize,
+    ) -> StorageResult<ReadDirPage> {
+        let path = path.to_string();
+        self.blocking(move |backend| {
+            let real_path = backend.resolve_nofollow(&path)?;
+            read_dir_at(&c_path(&real_path)?, cookie, max_entries)
+        })
+        .await
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn readdir(
+        &self,
+        path: &str,
+        cookie: u64,
+        max_entries: usize,
+    ) -> StorageResult<ReadDirPage> {
+        let path = path.to_string();
+        self.blocking(move |backend| {
+            let mut names = Vec::new();
+            for entry in std::fs::read_dir(backend.resolve_nofollow(&path)?)? {
+                names.push(entry?.file_name().into_encoded_bytes());
+            }
+            Ok(super::hashed_page(names, cookie, max_entries))
+        })
+        .await
+    }
+
+    #[cfg(unix)]
+    async fn statfs(&self, path: &str) -> StorageResult<StatFs> {
+        let path = path.to_string();
+        self.blocking(move |backend| {
+            let real_path = backend.resolve_nofollow(&path)?;
+            let c_path = c_path(&real_path)?;
+            let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+            // SAFETY: c_path is a valid NUL-terminated string and stat a valid out pointer
+            let res = unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) };
+            if res != 0 {
+                return Err(StorageError::from(std::io::Error::last_os_error()));
+            }
+            let frsize = stat.f_frsize as u64;
+            Ok(StatFs {
+                total_bytes: stat.f_blocks as u64 * frsize,
+                free_bytes: stat.f_bfree as u64 * frsize,
+                avail_bytes: stat.f_bavail as u64 * frsize,
+                total_files: stat.f_files as u64,
+                free_files: stat.f_ffree as u64,
+                avail_files: stat.f_favail as u64,
+            })
+        })
+        .await
+    }
+
+    #[cfg(not(unix))]
+    async fn statfs(&self, _path: &str) -> StorageResult<StatFs> {
+        Err(StorageError::NotSupported)
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn list_xattrs(&self, path: &str) -> StorageResult<Vec<String>> {
+        let path = path.to_string();
+        self.blocking(move |backend| list_xattrs(&backend.resolve(&path)?))
+            .await
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn get_xattr(&self, path: &str, name: &str) -> StorageResult<Vec<u8>> {
+        let (path, name) = (path.to_string(), name.to_string());
+        self.blocking(move |backend| get_xattr(&backend.resolve(&path)?, &name))
+            .await
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_xattr(&self, path: &str, name: &str, value: &[u8]) -> StorageResult<()> {
+        let (path, name, value) = (path.to_string(), name.to_string(), value.to_vec());
+        self.blocking(move |backend| set_xattr(&backend.resolve(&path)?, &name, &value))
+            .await
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn remove_xattr(&self, path: &str, name: &str) -> StorageResult<()> {
+        let (path, name) = (path.to_string(), name.to_string());
+        self.blocking(move |backend| remove_xattr(&backend.resolve(&path)?, &name))
+            .await
+    }
+}
+
+// list a directory from the offset cookie on
+#[cfg(target_os = "linux")]
+fn read_dir_at(
+    c_path: &std::ffi::CStr,
+    cookie: u64,
+    max_entries: usize,
+) -> StorageResult<ReadDirPage> {
+    // closes the directory stream on every return
+    struct Dir(*mut libc::DIR);
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            // SAFETY: the stream was opened by opendir and is closed only here
+            unsafe { libc::closedir(self.0) };
+        }
+    }
+
+    let offset = libc::c_long::try_from(cookie).map_err(|_| StorageError::InvalidArgument)?;
+    // SAFETY: c_path is a valid NUL-terminated string
+    let dir = unsafe { libc::opendir(c_path.as_ptr()) };
+    if dir.is_null() {
+        return Err(StorageError::from(std::io::Error::last_os_error()));
+    }
+    let dir = Dir(dir);
+    if offset != 0 {
+        // SAFETY: dir is an open directory stream
+        unsafe { libc::seekdir(dir.0, offset) };
+    }
+    let mut entries = Vec::new();
+    loop {
+        // the end of the directory and errors can only be told apart by errno
+        // SAFETY: errno is thread local
+        unsafe { *libc::__errno_location() = 0 };
+        // SAFETY: dir is an open directory stream
+        let entry = unsafe { libc::readdir64(dir.0) };
+        if entry.is_null() {
+            let e = std::io::Error::last_os_error();
+            return match e.raw_os_error() {
+                Some(0) => Ok(ReadDirPage { entries, eof: true }),
+                _ => Err(StorageError::from(e)),
+            };
+        }
+        // SAFETY: the entry returned by readdir is valid until the next call
+        let (name, next) = unsafe {
+            (
+                std::ffi::CStr::from_ptr((*entry).d_name.as_ptr()),
+                (*entry).d_off,
+            )
+        };
+        if name == c"." || name == c".." {
+            continue;
+        }
+        if entries.len() == max_entries {
+            return Ok(ReadDirPage {
+                entries,
+                eof: false,
+            });
+        }
+        entries.push(super::DirEntry {
+            name: name.to_bytes().to_vec(),
+            // d_off is the offset of the next entry
+            cookie: next as u64,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bold_proto::nfs4_proto::{NfsFtype4, Nfstime4};
+
+    use super::PhysicalBackend;
+    use crate::{
+        server::{acl, filemanager::Filehandle},
+        storage::{SetAttrs, StorageBackend, StorageError},
+    };
+
+    #[tokio::test]
+    async fn test_ranged_read_write() {
+        let tmp = tempfile::tempdir().unwrap();
+        let backend = PhysicalBackend::new(tmp.path());
+
+        let meta = backend
+            .create("/", "file1.txt", NfsFtype4::Nf4reg, 0o644)
+            .await
+            .unwrap();
+        assert_eq!(meta.file_type, NfsFtype4::Nf4reg);
+        assert_eq!(meta.size, 0);
+
+        backend
+            .write("/file1.txt", 0, b"Hello, World!")
+            .await
+            .unwrap();
+        backend.write("/file1.txt", 7, b"Bold!").await.unwrap();
+        let (data, eof) = backend.read("/file1.txt", 0, 1024).await.unwrap();
+        assert_eq!(data, b"Hello, Bold!!");
+        assert!(eof);
+
+        let (data, eof) = backend.read("/file1.txt", 7, 4).await.unwrap();
+        assert_eq!(data, b"Bold");
+        assert!(!eof);
+
+        let meta = backend
+            .setattr(
+                "/file1.txt",
+                &SetAttrs {
+                    size: Some(5),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(meta.size, 5);
+
+        assert_eq!(
+            backend
+                .create("/", "file1.txt", NfsFtype4::Nf4reg, 0o644)
+                .await
+                .unwrap_err(),
+            StorageError::Exists
+        );
+        backend.remove("/", "file1.txt").await.unwrap();
+        assert_eq!(
+            backend.getattr("/file1.txt").await.unwrap_err(),
+            StorageError::NotFound
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlinks_not_followed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let outside = tmp.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+        std::fs::write(outside.join("secret.txt"), b"secret").unwrap();
+        let backend = PhysicalBackend::new(tmp.path().join("export"));
+        std::fs::create_dir(backend.root()).unwrap();
+        std::os::unix::fs::symlink(&outside, backend.root().join("link")).unwrap();
+        std::fs::write(backend.root().join("file1.txt"), b"hello").unwrap();
+
+        // the link itself can be looked at, but never walked through
+        let meta = backend.getattr("/link").await.unwrap();
+        assert_eq!(meta.file_type, NfsFtype4::Nf4lnk);
+        assert_eq!(
+            backend.getattr("/link/secret.txt").await.unwrap_err(),
+            StorageError::Symlink
+        );
+        assert_eq!(
+            backend.readdir("/link", 0, 10).await.unwrap_err(),
+            StorageError::Symlink
+        );
+        assert_eq!(
+            backend.getattr("/file1.txt/x").await.unwrap_err(),
+            StorageError::NotDir
+        );
+        assert_eq!(
+            backend.getattr("/../outside").await.unwrap_err(),
+            StorageError::InvalidArgument
+        );
+        assert_eq!(
+            backend.getattr("/").await.unwrap().file_type,
+            NfsFtype4::Nf4dir
+        );
+        assert_eq!(backend.read("/file1.txt", 0, 10).await.unwrap().0, b"hello");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_pre_epoch_mtime() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let backend = PhysicalBackend::new(tmp.path());
+        backend
+            .create("/", "file1.txt", NfsFtype4::Nf4reg, 0o644)
+            .await
+            .unwrap();
+        let mtime = UNIX_EPOCH - Duration::new(1, 250_000_000);
+        let meta = backend
+            .setattr(
+                "/file1.txt",
+                &SetAttrs {
+                    mtime: Some(mtime),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(meta.mtime, Some(mtime));
+        assert_eq!(
+            Filehandle::nfstime(meta.mtime),
+            Nfstime4 {
+                seconds: -2,
+                nseconds: 750_000_000
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_acl_xattr() {
+        let tmp = tempfile::tempdir().unwrap();
+        let backend = PhysicalBackend::new(tmp.path());
+        let meta = backend
+            .create("/", "file1.txt", NfsFtype4::Nf4reg, 0o644)
+            .await
+            .unwrap();
+        assert_eq!(meta.acl, None);
+
+        let attrs = SetAttrs {
+            acl: Some(acl::from_mode(0o600, false)),
+            ..Default::default()
+        };
+        match backend.setattr("/file1.txt", &attrs).await {
+            Ok(meta) => assert_eq!(meta.acl, attrs.acl),
+            // the file system of the temporary directory has no user xattrs
+            Err(StorageError::NotSupported) => {}
+            Err(e) => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_user_xattrs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let backend = PhysicalBackend::new(tmp.path());
+        backend
+            .create("/", "file1.txt", NfsFtype4::Nf4reg, 0o644)
+            .await
+            .unwrap();
+        match backend.set_xattr("/file1.txt", "tag", b"value").await {
+            Ok(()) => {}
+            Err(StorageError::NotSupported) => return,
+            Err(e) => panic!("Unexpected error: {:?}", e),
+        }
+        assert_eq!(
+            backend.get_xattr("/file1.txt", "tag").await.unwrap(),
+            b"value".to_vec()
+        );
+        // the stored ACL is not a named attribute
+        backend
+            .setattr(
+                "/file1.txt",
+                &SetAttrs {
+                    acl: Some(acl::from_mode(0o600, false)),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.list_xattrs("/file1.txt").await.unwrap(),
+            vec!["tag".to_string()]
+        );
+        backend.remove_xattr("/file1.txt", "tag").await.unwrap();
+        assert_eq!(
+            backend.get_xattr("/file1.txt", "tag").await.unwrap_err(),
+            StorageError::NotFound
+        );
+    }
+}
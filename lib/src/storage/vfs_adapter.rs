@@ -0,0 +1,390 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, SeekFrom, Write},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use bold_proto::nfs4_proto::{NfsFtype4, Nfsace4};
+use vfs::{VfsFileType, VfsPath};
+
+use super::{
+    hashed_page, Capabilities, Metadata, ReadDirPage, SetAttrs, StatFs, StorageBackend,
+    StorageError, StorageResult,
+};
+
+/// Adapter exporting any [`vfs::FileSystem`] wrapped in a [`VfsPath`].
+///
+/// vfs has no notion of links, ownership or permissions, so those are
+/// reported with fixed values and link operations are not supported.
+/// Neither has it inode numbers, they are allocated on first use and
+/// follow the object when it is renamed. ACLs are kept in memory with
+/// the inode number of their object.
+#[derive(Debug, Clone)]
+pub struct VfsBackend {
+    root: VfsPath,
+    inodes: Arc<Mutex<InodeAllocator>>,
+}
+
+#[derive(Debug)]
+struct InodeAllocator {
+    by_path: HashMap<String, u64>,
+    next_ino: u64,
+    acls: HashMap<u64, Vec<Nfsace4>>,
+}
+
+impl InodeAllocator {
+    fn get(&mut self, path: &str) -> u64 {
+        if let Some(ino) = self.by_path.get(path) {
+            return *ino;
+        }
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        self.by_path.insert(path.to_string(), ino);
+        ino
+    }
+
+    // forget the ACL of the object at path, as it is removed or replaced
+    fn drop_acl(&mut self, path: &str) {
+        if let Some(ino) = self.by_path.get(path) {
+            self.acls.remove(ino);
+        }
+    }
+
+    // forget path and everything below it
+    fn remove(&mut self, path: &str) {
+        let prefix = format!("{}/", path);
+        self.by_path
+            .retain(|p, _| p != path && !p.starts_with(&prefix));
+    }
+
+    // move the inode numbers of from and everything below it to to
+    fn rename(&mut self, from: &str, to: &str) {
+        self.drop_acl(to);
+        self.remove(to);
+        let prefix = format!("{}/", from);
+        let moved: Vec<(String, u64)> = self
+            .by_path
+            .iter()
+            .filter(|(p, _)| *p == from || p.starts_with(&prefix))
+            .map(|(p, ino)| (format!("{}{}", to, &p[from.len()..]), *ino))
+            .collect();
+        self.remove(from);
+        self.by_path.extend(moved);
+    }
+}
+
+impl VfsBackend {
+    pub fn new(root: VfsPath) -> Self {
+        VfsBackend {
+            root,
+            inodes: Arc::new(Mutex::new(InodeAllocator {
+                by_path: HashMap::new(),
+                next_ino: 1,
+                acls: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn root(&self) -> &VfsPath {
+        &self.root
+    }
+
+    fn resolve(&self, path: &str) -> StorageResult<VfsPath> {
+        // join treats a leading slash as the root of the vfs, which might not be our root
+        Ok(self.root.join(path.trim_start_matches('/'))?)
+    }
+
+    fn metadata(&self, path: &str) -> StorageResult<Metadata> {
+        let file = self.resolve(path)?;
+        let meta = file.metadata()?;
+        let file_type = match meta.file_type {
+            VfsFileType::File => NfsFtype4::Nf4reg,
+            VfsFileType::Directory => NfsFtype4::Nf4dir,
+        };
+        let (ino, acl) = {
+            let mut inodes = self.inodes.lock().unwrap();
+            let ino = inodes.get(file.as_str());
+            (ino, inodes.acls.get(&ino).cloned())
+        };
+        Ok(Metadata {
+            file_type,
+            size: meta.len,
+            space_used: meta.len,
+            mode: if file_type == NfsFtype4::Nf4dir {
+                0o755
+            } else {
+                0o644
+            },
+            nlink: 1,
+            // vfs has no ownership, report the uid/gid of a typical first user
+            uid: 1000,
+            gid: 1000,
+            dev: 0,
+            ino,
+            generation: 0,
+            atime: meta.accessed,
+            mtime: meta.modified,
+            ctime: meta.modified,
+            btime: meta.created,
+            backup_time: None,
+            acl,
+            named_attrs: false,
+        })
+    }
+
+    fn names(&self, path: &str) -> StorageResult<Vec<String>> {
+        let dir = self.resolve(path)?;
+        if !dir.is_dir()? {
+            return Err(StorageError::NotDir);
+        }
+        Ok(dir.read_dir()?.map(|entry| entry.filename()).collect())
+    }
+}
+
+#[async_trait]
+impl StorageBackend for VfsBackend {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            hard_links: false,
+            symlinks: false,
+            unique_handles: false,
+            persistent_handles: false,
+            inode_lookup: false,
+            exclusive_access: false,
+        }
+    }
+
+    async fn getattr(&self, path: &str) -> StorageResult<Metadata> {
+        let file = self.resolve(path)?;
+        if !file.exists()? {
+            return Err(StorageError::NotFound);
+        }
+        self.metadata(path)
+    }
+
+    async fn setattr(&self, path: &str, attrs: &SetAttrs) -> StorageResult<Metadata> {
+        let file = self.resolve(path)?;
+        if let Some(size) = attrs.size {
+            if file.is_dir()? {
+                return Err(StorageError::IsDir);
+            }
+            let mut buf = Vec::new();
+            file.open_file()?.read_to_end(&mut buf)?;
+            buf.resize(size as usize, 0);
+            file.create_file()?.write_all(&buf)?;
+        }
+        // setting times is optional in vfs, ignore backends that do not support it
+        if let Some(atime) = attrs.atime {
+            let _ = file.set_access_time(atime);
+        }
+        if let Some(mtime) = attrs.mtime {
+            let _ = file.set_modification_time(mtime);
+        }
+        if let Some(acl) = &attrs.acl {
+            let mut inodes = self.inodes.lock().unwrap();
+            let ino = inodes.get(file.as_str());
+            inodes.acls.insert(ino, acl.clone());
+        }
+        self.metadata(path)
+    }
+
+    async fn read(&self, path: &str, offset: u64, count: u32) -> StorageResult<(Vec<u8>, bool)> {
+        let file = self.resolve(path)?;
+        if file.is_dir()? {
+            return Err(StorageError::IsDir);
+        }
+        let size = file.metadata()?.len;
+        if offset >= size {
+            return Ok((Vec::new(), true));
+        }
+        let mut reader = file.open_file()?;
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut buf = Vec::new();
+        reader.take(count as u64).read_to_end(&mut buf)?;
+        let eof = offset + buf.len() as u64 >= size;
+        Ok((buf, eof))
+    }
+
+    async fn write(&self, path: &str, offset: u64, data: &[u8]) -> StorageResult<u32> {
+        let file = self.resolve(path)?;
+        if file.is_dir()? {
+            return Err(StorageError::IsDir);
+        }
+        let size = file.metadata()?.len;
+        if offset == size {
+            file.append_file()?.write_all(data)?;
+        } else {
+            // vfs can not open a file for writing without truncating it
+            let mut buf = Vec::new();
+            file.open_file()?.read_to_end(&mut buf)?;
+            let end = offset as usize + data.len();
+            if end > buf.len() {
+                buf.resize(end, 0);
+            }
+            buf[offset as usize..end].copy_from_slice(data);
+            file.create_file()?.write_all(&buf)?;
+        }
+        Ok(data.len() as u32)
+    }
+
+    async fn create(
+        &self,
+        dir: &str,
+        name: &str,
+        file_type: NfsFtype4,
+        _mode: u32,
+    ) -> StorageResult<Metadata> {
+        let parent = self.resolve(dir)?;
+        if !parent.is_dir()? {
+            return Err(StorageError::NotDir);
+        }
+        let file = parent.join(name)?;
+        if file.exists()? {
+            return Err(StorageError::Exists);
+        }
+        match file_type {
+            NfsFtype4::Nf4reg => {
+                file.create_file()?;
+            }
+            NfsFtype4::Nf4dir => file.create_dir()?,
+            _ => return Err(StorageError::NotSupported),
+        }
+        self.metadata(&super::join_path(dir, name))
+    }
+
+    async fn remove(&self, dir: &str, name: &str) -> StorageResult<()> {
+        let file = self.resolve(dir)?.join(name)?;
+        if !file.exists()? {
+            return Err(StorageError::NotFound);
+        }
+        if file.is_dir()? {
+            if file.read_dir()?.next().is_some() {
+                return Err(StorageError::NotEmpty);
+            }
+            file.remove_dir()?;
+        } else {
+            file.remove_file()?;
+        }
+        let mut inodes = self.inodes.lock().unwrap();
+        inodes.drop_acl(file.as_str());
+        inodes.remove(file.as_str());
+        Ok(())
+    }
+
+    async fn rename(
+        &self,
+        from_dir: &str,
+        from_name: &str,
+        to_dir: &str,
+        to_name: &str,
+    ) -> StorageResult<()> {
+        let from = self.resolve(from_dir)?.join(from_name)?;
+        let to = self.resolve(to_dir)?.join(to_name)?;
+        if !from.exists()? {
+            return Err(StorageError::NotFound);
+        }
+        if from == to {
+            return Ok(());
+        }
+        if to.exists()? {
+            if from.is_dir()? != to.is_dir()? {
+                return Err(if from.is_dir()? {
+                    StorageError::NotDir
+                } else {
+                    StorageError::IsDir
+                });
+            }
+            if to.is_dir()? {
+                if to.read_dir()?.next().is_some() {
+                    return Err(StorageError::Exists);
+                }
+                to.remove_dir()?;
+            } else {
+                to.remove_file()?;
+            }
+        }
+        if from.is_dir()? {
+            from.move_dir(&to)?;
+        } else {
+            from.move_file(&to)?;
+        }
+        self.inodes
+            .lock()
+            .unwrap()
+            .rename(from.as_str(), to.as_str());
+        Ok(())
+    }
+
+    async fn link(&self, _path: &str, _dir: &str, _name: &str) -> StorageResult<Metadata> {
+        Err(StorageError::NotSupported)
+    }
+
+    async fn symlink(&self, _dir: &str, _name: &str, _target: &str) -> StorageResult<Metadata> {
+        Err(StorageError::NotSupported)
+    }
+
+    async fn readlink(&self, _path: &str) -> StorageResult<String> {
+        Err(StorageError::NotSupported)
+    }
+
+    async fn readdir(
+        &self,
+        path: &str,
+        cookie: u64,
+        max_entries: usize,
+    ) -> StorageResult<ReadDirPage> {
+        // vfs can only list a whole directory
+        let names = self.names(path)?.into_iter().map(String::into_bytes);
+        Ok(hashed_page(names, cookie, max_entries))
+    }
+
+    async fn statfs(&self, _path: &str) -> StorageResult<StatFs> {
+        Err(StorageError::NotSupported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bold_proto::nfs4_proto::NfsFtype4;
+    use vfs::{MemoryFS, VfsPath};
+
+    use super::VfsBackend;
+    use crate::storage::StorageBackend;
+
+    #[tokio::test]
+    async fn test_fileid_stable_across_rename() {
+        let backend = VfsBackend::new(VfsPath::new(MemoryFS::new()));
+        backend
+            .create("/", "dir1", NfsFtype4::Nf4dir, 0o755)
+            .await
+            .unwrap();
+        let file = backend
+            .create("/dir1", "file1.txt", NfsFtype4::Nf4reg, 0o644)
+            .await
+            .unwrap();
+        let dir = backend.getattr("/dir1").await.unwrap();
+        let root = backend.getattr("/").await.unwrap();
+        assert_ne!(file.ino, dir.ino);
+        assert_ne!(dir.ino, root.ino);
+
+        backend.rename("/", "dir1", "/", "dir2").await.unwrap();
+        assert_eq!(backend.getattr("/dir2").await.unwrap().ino, dir.ino);
+        assert_eq!(
+            backend.getattr("/dir2/file1.txt").await.unwrap().ino,
+            file.ino
+        );
+        backend
+            .rename("/dir2", "file1.txt", "/", "file2.txt")
+            .await
+            .unwrap();
+        assert_eq!(backend.getattr("/file2.txt").await.unwrap().ino, file.ino);
+
+        // a new object never reuses the number of an existing one
+        let other = backend
+            .create("/", "dir1", NfsFtype4::Nf4dir, 0o755)
+            .await
+            .unwrap();
+        assert_ne!(other.ino, dir.ino);
+    }
+}
@@ -0,0 +1,142 @@
+//! Decoding a 1 MiB WRITE and encoding a 1 MiB READ reply with
+//! XDRProtoCodec, against copying the record into a `Vec` first the way
+//! the codec used to. The codec hands out the data of the WRITE as a slice
+//! of the received record, the copying decoder copies it twice.
+
+use std::io::Cursor;
+
+use bold_proto::{
+    nfs4_proto::{Compound4res, NfsArgOp, NfsResOp4, NfsStat4, Read4res, Read4resok},
+    rpc_proto::{AcceptBody, AcceptedReply, CallBody, MsgType, OpaqueAuth, ReplyBody, RpcReplyMsg},
+    XDRProtoCodec,
+};
+use bytes::{Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use serde::Deserialize;
+use tokio_util::codec::{Decoder, Encoder};
+
+const SIZE: usize = 1024 * 1024;
+
+// a COMPOUND with a single WRITE of SIZE bytes, in a record of one fragment
+fn write_record() -> Vec<u8> {
+    let mut words = vec![1, 0, 2, 100003, 4, 1, 0, 0, 0, 0];
+    // tag, minor version, WRITE, stateid, offset, stable
+    words.extend([0, 0, 1, 38, 0, 0, 0, 0, 0, 0, 0, SIZE as u32]);
+    let mut message: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+    message.extend((0..SIZE).map(|i| i as u8));
+
+    let mut record = (message.len() as u32 | (1 << 31)).to_be_bytes().to_vec();
+    record.extend(message);
+    record
+}
+
+// the same call, decoded with serde_bytes
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct CopiedWrite {
+    header: [u32; 10],
+    tag: String,
+    minor_version: u32,
+    ops: u32,
+    op: u32,
+    stateid: [u32; 4],
+    offset: u64,
+    stable: u32,
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+}
+
+fn write_data(msg: &bold_proto::rpc_proto::RpcCallMsg) -> Bytes {
+    match &msg.body {
+        MsgType::Call(CallBody {
+            args: Some(args), ..
+        }) => match &args.argarray[..] {
+            [NfsArgOp::Opwrite(write)] => write.data.clone(),
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    }
+}
+
+fn decode_write(c: &mut Criterion) {
+    let record = write_record();
+    let mut group = c.benchmark_group("write_1mib");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.bench_function("codec", |b| {
+        b.iter_batched(
+            || BytesMut::from(&record[..]),
+            |mut src| {
+                let received = src.as_ptr_range();
+                let msg = XDRProtoCodec::new().decode(&mut src).unwrap().unwrap();
+                let data = write_data(&msg.unwrap());
+                assert!(received.contains(&data.as_ptr()));
+                black_box(data)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("copying", |b| {
+        b.iter_batched(
+            || BytesMut::from(&record[..]),
+            |src| {
+                let fragment = src[4..].to_vec();
+                let write: CopiedWrite =
+                    serde_xdr::from_reader(&mut Cursor::new(fragment)).unwrap();
+                // the WRITE operation cloned the data into the write cache
+                black_box(write.data.clone())
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn read_reply() -> Box<RpcReplyMsg> {
+    Box::new(RpcReplyMsg {
+        xid: 1,
+        body: MsgType::Reply(ReplyBody::MsgAccepted(AcceptedReply {
+            verf: OpaqueAuth::AuthNull(Vec::new()),
+            reply_data: AcceptBody::Success(Compound4res {
+                status: NfsStat4::Nfs4Ok,
+                tag: String::new(),
+                resarray: vec![NfsResOp4::Opread(Read4res::Resok4(Read4resok {
+                    eof: true,
+                    data: vec![7; SIZE],
+                }))],
+            }),
+        })),
+    })
+}
+
+fn encode_read(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read_1mib");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.bench_function("codec", |b| {
+        b.iter_batched(
+            read_reply,
+            |reply| {
+                let mut dst = BytesMut::new();
+                XDRProtoCodec::new().encode(reply, &mut dst).unwrap();
+                black_box(dst)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("copying", |b| {
+        b.iter_batched(
+            read_reply,
+            |reply| {
+                let message = reply.to_bytes().unwrap();
+                let mut dst = BytesMut::with_capacity(4 + message.len());
+                dst.extend_from_slice(&(message.len() as u32 | (1 << 31)).to_be_bytes());
+                dst.extend_from_slice(&message);
+                black_box(dst)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode_write, encode_read);
+criterion_main!(benches);
@@ -1,19 +1,77 @@
 pub mod nfs4_proto;
 pub mod rpc_proto;
+pub mod rpcb_proto;
 pub mod utils;
-
-use bytes::{Buf, BytesMut};
-use serde_xdr::{from_reader, to_writer, CompatDeserializationError};
-use std::io::Cursor;
+pub mod xdr;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use serde_xdr::{to_writer, CompatDeserializationError};
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 use tokio_util::codec::{Decoder, Encoder};
 // use tracing::trace;
 
 use self::rpc_proto::{RpcCallMsg, RpcReplyMsg};
 
+/// Frames RPC messages with record marking and decodes them straight out of
+/// the receive buffer, see [`xdr`]. The opaque data of a call in a record of
+/// one fragment is a slice of the receive buffer, only the fragments of
+/// larger records are copied together.
+///
+/// The codec counts the bytes of the records it decoded that are still in
+/// use, see [`XDRProtoCodec::retained`].
 #[derive(Debug)]
-pub struct XDRProtoCodec {}
+pub struct XDRProtoCodec {
+    // the largest record a connection may send, the receive buffer of a
+    // connection doesn't grow any larger than this
+    max_record: usize,
+    // the bytes of the decoded records still in use
+    retained: Arc<AtomicUsize>,
+}
+
+// a decoded record, counted by its codec until the last slice of it is
+// dropped
+struct Retained {
+    record: Bytes,
+    retained: Arc<AtomicUsize>,
+}
 
-const MAX: usize = 8 * 1024 * 1024;
+impl AsRef<[u8]> for Retained {
+    fn as_ref(&self) -> &[u8] {
+        &self.record
+    }
+}
+
+impl Drop for Retained {
+    fn drop(&mut self) {
+        self.retained
+            .fetch_sub(self.record.len(), Ordering::Relaxed);
+    }
+}
+
+/// A call that was received completely but couldn't be decoded, it is
+/// answered with GARBAGE_ARGS.
+#[derive(Debug)]
+pub struct GarbageCall {
+    pub xid: u32,
+    pub error: anyhow::Error,
+}
+
+/// The largest record accepted by default, a 1 MiB WRITE with room to spare.
+pub const MAX_RECORD: usize = 8 * 1024 * 1024;
+
+// the capacity the receive buffer starts over with once a record took all
+// of it, like the initial capacity of tokio-util
+const RECEIVE_BUFFER: usize = 8 * 1024;
+
+// the last fragment of a record has the highest bit of its header set
+// https://datatracker.ietf.org/doc/html/rfc5531#section-11
+const LAST_FRAGMENT: u32 = 1 << 31;
 
 impl Default for XDRProtoCodec {
     fn default() -> Self {
@@ -23,60 +81,136 @@ impl Default for XDRProtoCodec {
 
 impl XDRProtoCodec {
     pub fn new() -> XDRProtoCodec {
-        XDRProtoCodec {}
+        Self::with_max_record(MAX_RECORD)
     }
-}
 
-impl Decoder for XDRProtoCodec {
-    type Item = RpcCallMsg;
-    type Error = std::io::Error;
+    /// A codec rejecting records larger than `max_record` bytes.
+    pub fn with_max_record(max_record: usize) -> XDRProtoCodec {
+        XDRProtoCodec {
+            max_record,
+            retained: Arc::new(AtomicUsize::new(0)),
+        }
+    }
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let mut message_data = Vec::new();
-        let mut is_last = false;
-        while !is_last {
-            if src.len() < 4 {
-                // Not enough data to read length marker.
-                return Ok(None);
+    /// The bytes of the records decoded by this codec that are still in
+    /// use, e.g. as the data of an unstable WRITE waiting for its COMMIT.
+    ///
+    /// The receive buffer is shared with the records decoded from it. Once a
+    /// record took all of it, the codec reads into a new buffer, so a record
+    /// keeps only the buffer it was received in and the records received
+    /// along with it in memory.
+    pub fn retained(&self) -> usize {
+        self.retained.load(Ordering::Relaxed)
+    }
+
+    /// Takes the record at the start of `src` out of it, with its fragments
+    /// joined, None if some of it is still to arrive. This is the framing of
+    /// the codec without decoding the record, for other RPC programs than
+    /// NFS.
+    pub fn decode_record(&mut self, src: &mut BytesMut) -> std::io::Result<Option<Bytes>> {
+        let Some(fragments) = self.record_fragments(src)? else {
+            return Ok(None);
+        };
+        // the record is only taken from the buffer once it is complete
+        let end = fragments.last().map_or(0, |fragment| fragment.end);
+        let record = src.split_to(end).freeze();
+        if src.is_empty() {
+            *src = BytesMut::with_capacity(RECEIVE_BUFFER);
+        }
+        self.retained.fetch_add(record.len(), Ordering::Relaxed);
+        let record = Bytes::from_owner(Retained {
+            record,
+            retained: self.retained.clone(),
+        });
+        let message_data = match &fragments[..] {
+            [fragment] => record.slice(fragment.clone()),
+            _ => {
+                let mut message_data = BytesMut::with_capacity(record.len());
+                for fragment in fragments {
+                    message_data.extend_from_slice(&record[fragment]);
+                }
+                message_data.freeze()
             }
+        };
+        Ok(Some(message_data))
+    }
 
-            // Read the frame: https://datatracker.ietf.org/doc/html/rfc1057#section-10
-            let mut header_bytes = [0u8; 4];
-            header_bytes.copy_from_slice(&src[..4]);
+    /// Appends `message` to `dst` as a record of one fragment.
+    pub fn encode_record(&mut self, message: &[u8], dst: &mut BytesMut) {
+        dst.reserve(4 + message.len());
+        dst.put_u32(message.len() as u32 | LAST_FRAGMENT);
+        dst.extend_from_slice(message);
+    }
 
-            let fragment_header = u32::from_be_bytes(header_bytes) as usize;
-            is_last = (fragment_header & (1 << 31)) > 0;
-            let length = fragment_header & ((1 << 31) - 1);
+    // the fragments of the record at the start of src, None if some of it
+    // is still to arrive
+    fn record_fragments(&self, src: &mut BytesMut) -> std::io::Result<Option<Vec<Range<usize>>>> {
+        let mut fragments = Vec::new();
+        let mut offset = 0;
+        let mut record = 0;
+        loop {
+            let Some(header) = src.get(offset..offset + 4) else {
+                // Not enough data to read length marker.
+                return Ok(None);
+            };
+            let header = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+            let length = (header & !LAST_FRAGMENT) as usize;
 
-            // Check that the length is not too large to avoid a denial of
+            // Check that the record is not too large to avoid a denial of
             // service attack where the server runs out of memory.
-            if length > MAX {
+            record += length;
+            if record > self.max_record {
                 return Err(std::io::Error::new(
                     std::io::ErrorKind::InvalidData,
-                    format!("Frame of length {} is too large.", length),
+                    format!("Record of length {} is too large.", record),
                 ));
             }
 
-            if src.len() < 4 + length {
-                // The full string has not yet arrived.
-                src.reserve(4 + length - src.len());
+            let end = offset + 4 + length;
+            if src.len() < end {
+                // The full record has not yet arrived.
+                src.reserve(end - src.len());
                 return Ok(None);
             }
-            let fragment = src[4..4 + length].to_vec();
-            src.advance(4 + length);
-
-            message_data.extend_from_slice(&fragment[..]);
-            // TODO remove due to performance reasons
-            // trace!(
-            //     length = length,
-            //     is_last = is_last,
-            //     "Finishing Reading fragment"
-            // );
+            fragments.push(offset + 4..end);
+            offset = end;
+            if header & LAST_FRAGMENT != 0 {
+                return Ok(Some(fragments));
+            }
         }
+    }
+}
+
+impl Decoder for XDRProtoCodec {
+    type Item = Result<RpcCallMsg, GarbageCall>;
+    type Error = std::io::Error;
 
-        RpcCallMsg::from_bytes(message_data)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
-            .map(Some)
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            let Some(message_data) = self.decode_record(src)? else {
+                return Ok(None);
+            };
+
+            // xid and message type, which can be read from any message
+            let header = message_data.get(..8).map(|header| {
+                let (xid, msg_type) = header.split_at(4);
+                (
+                    u32::from_be_bytes([xid[0], xid[1], xid[2], xid[3]]),
+                    u32::from_be_bytes([msg_type[0], msg_type[1], msg_type[2], msg_type[3]]),
+                )
+            });
+            match RpcCallMsg::from_bytes(message_data) {
+                Ok(msg) => return Ok(Some(Ok(msg))),
+                // https://datatracker.ietf.org/doc/html/rfc5531#section-9
+                Err(error) => {
+                    if let Some((xid, 0)) = header {
+                        return Ok(Some(Err(GarbageCall { xid, error })));
+                    }
+                    // anything but a call has no one waiting for an answer,
+                    // go on with the next message
+                }
+            }
+        }
     }
 }
 
@@ -84,23 +218,24 @@ impl Encoder<Box<RpcReplyMsg>> for XDRProtoCodec {
     type Error = std::io::Error;
 
     fn encode(&mut self, message: Box<RpcReplyMsg>, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let buffer_message = message
-            .to_bytes()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-        let buffer_header = u32::to_be_bytes(buffer_message.len() as u32 + (1 << 31));
-        // Reserve space in the buffer.
-        dst.reserve(4 + buffer_message.len());
-
-        // Write the length and string to the buffer.
-        dst.extend_from_slice(&buffer_header);
-        dst.extend_from_slice(&buffer_message);
+        // the reply is encoded in place, behind room for its header
+        let start = dst.len();
+        dst.put_u32(0);
+        if let Err(e) = to_writer(&mut (&mut *dst).writer(), &*message) {
+            dst.truncate(start);
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Error serializing message: {:?}", e),
+            ));
+        }
+        let length = (dst.len() - start - 4) as u32;
+        dst[start..start + 4].copy_from_slice(&(length | LAST_FRAGMENT).to_be_bytes());
         Ok(())
     }
 }
 
-pub fn from_bytes(buffer: Vec<u8>) -> Result<RpcCallMsg, anyhow::Error> {
-    let mut cursor = Cursor::new(buffer);
-    let result: Result<RpcCallMsg, CompatDeserializationError> = from_reader(&mut cursor);
+pub fn from_bytes(buffer: Bytes) -> Result<RpcCallMsg, anyhow::Error> {
+    let result: Result<RpcCallMsg, CompatDeserializationError> = xdr::from_shared(&buffer);
     // todo add proper logging
     match result {
         Ok(msg) => Ok(msg),
@@ -1,7 +1,7 @@
-extern crate serde_bytes;
 extern crate serde_xdr;
 use super::utils::write_argarray;
 
+use bytes::Bytes;
 use num_derive::{FromPrimitive, ToPrimitive};
 
 use serde_derive::{Deserialize, Serialize};
@@ -26,7 +26,7 @@ const NFS4_OTHER_SIZE: usize = 12;
 /*
  * File types
  */
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, ToPrimitive)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, FromPrimitive, ToPrimitive)]
 #[repr(u32)]
 pub enum NfsFtype4 {
     Nf4Undef = 0,     /* undefined */
@@ -44,7 +44,7 @@ pub enum NfsFtype4 {
 /*
  * Error status
  */
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, ToPrimitive)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, FromPrimitive, ToPrimitive)]
 #[repr(u32)]
 pub enum NfsStat4 {
     Nfs4Ok = 0,         /* everything is okay       */
@@ -133,12 +133,12 @@ pub type NfsFh4 = [u8; 26];
 pub type NfsLease4 = u32;
 type Offset4 = u64;
 type Qop4 = u32;
-type SecOid4 = Vec<u64>;
+type SecOid4 = Vec<u8>;
 type Seqid4 = u32;
 // type opaque  String<>;
 type Utf8strCis = String;
 type Utf8strCs = Vec<u8>;
-type Utf8strMixed = String;
+// type Utf8strMixed = String;
 type Component4 = Utf8strCs;
 type Linktext4 = Vec<u64>;
 // type AsciiRequired4 = String;
@@ -207,58 +207,58 @@ pub const ACL4_SUPPORT_DENY_ACL: u32 = 0x00000002;
 pub const ACL4_SUPPORT_AUDIT_ACL: u32 = 0x00000004;
 pub const ACL4_SUPPORT_ALARM_ACL: u32 = 0x00000008;
 
-type Acetype4 = u32;
+pub type Acetype4 = u32;
 
 /*
  * Acetype4 values; others can be added as needed.
  */
-// const ACE4_ACCESS_ALLOWED_ACE_TYPE: u32 = 0x00000000;
-// const ACE4_ACCESS_DENIED_ACE_TYPE: u32 = 0x00000001;
-// const ACE4_SYSTEM_AUDIT_ACE_TYPE: u32 = 0x00000002;
-// const ACE4_SYSTEM_ALARM_ACE_TYPE: u32 = 0x00000003;
+pub const ACE4_ACCESS_ALLOWED_ACE_TYPE: u32 = 0x00000000;
+pub const ACE4_ACCESS_DENIED_ACE_TYPE: u32 = 0x00000001;
+pub const ACE4_SYSTEM_AUDIT_ACE_TYPE: u32 = 0x00000002;
+pub const ACE4_SYSTEM_ALARM_ACE_TYPE: u32 = 0x00000003;
 
 /*
  * ACE flag
  */
-type Aceflag4 = u32;
+pub type Aceflag4 = u32;
 
 /*
  * ACE flag values
  */
-// const ACE4_FILE_INHERIT_ACE: u32 = 0x00000001;
-// const ACE4_DIRECTORY_INHERIT_ACE: u32 = 0x00000002;
-// const ACE4_NO_PROPAGATE_INHERIT_ACE: u32 = 0x00000004;
-// const ACE4_INHERIT_ONLY_ACE: u32 = 0x00000008;
-// const ACE4_SUCCESSFUL_ACCESS_ACE_FLAG: u32 = 0x00000010;
-// const ACE4_FAILED_ACCESS_ACE_FLAG: u32 = 0x00000020;
-// const ACE4_IDENTIFIER_GROUP: u32 = 0x00000040;
+pub const ACE4_FILE_INHERIT_ACE: u32 = 0x00000001;
+pub const ACE4_DIRECTORY_INHERIT_ACE: u32 = 0x00000002;
+pub const ACE4_NO_PROPAGATE_INHERIT_ACE: u32 = 0x00000004;
+pub const ACE4_INHERIT_ONLY_ACE: u32 = 0x00000008;
+pub const ACE4_SUCCESSFUL_ACCESS_ACE_FLAG: u32 = 0x00000010;
+pub const ACE4_FAILED_ACCESS_ACE_FLAG: u32 = 0x00000020;
+pub const ACE4_IDENTIFIER_GROUP: u32 = 0x00000040;
 
 /*
  * ACE mask
  */
-type Acemask4 = u32;
+pub type Acemask4 = u32;
 
 /*
  * ACE mask values
  */
-// const ACE4_READ_DATA: u32 = 0x00000001;
-// const ACE4_LIST_DIRECTORY: u32 = 0x00000001;
-// const ACE4_WRITE_DATA: u32 = 0x00000002;
-// const ACE4_ADD_FILE: u32 = 0x00000002;
-// const ACE4_APPEND_DATA: u32 = 0x00000004;
-// const ACE4_ADD_SUBDIRECTORY: u32 = 0x00000004;
-// const ACE4_READ_NAMED_ATTRS: u32 = 0x00000008;
-// const ACE4_WRITE_NAMED_ATTRS: u32 = 0x00000010;
-// const ACE4_EXECUTE: u32 = 0x00000020;
-// const ACE4_DELETE_CHILD: u32 = 0x00000040;
-// const ACE4_READ_ATTRIBUTES: u32 = 0x00000080;
-// const ACE4_WRITE_ATTRIBUTES: u32 = 0x00000100;
-
-// const ACE4_DELETE: u32 = 0x00010000;
-// const ACE4_READ_ACL: u32 = 0x00020000;
-// const ACE4_WRITE_ACL: u32 = 0x00040000;
-// const ACE4_WRITE_OWNER: u32 = 0x00080000;
-// const ACE4_SYNCHRONIZE: u32 = 0x00100000;
+pub const ACE4_READ_DATA: u32 = 0x00000001;
+pub const ACE4_LIST_DIRECTORY: u32 = 0x00000001;
+pub const ACE4_WRITE_DATA: u32 = 0x00000002;
+pub const ACE4_ADD_FILE: u32 = 0x00000002;
+pub const ACE4_APPEND_DATA: u32 = 0x00000004;
+pub const ACE4_ADD_SUBDIRECTORY: u32 = 0x00000004;
+pub const ACE4_READ_NAMED_ATTRS: u32 = 0x00000008;
+pub const ACE4_WRITE_NAMED_ATTRS: u32 = 0x00000010;
+pub const ACE4_EXECUTE: u32 = 0x00000020;
+pub const ACE4_DELETE_CHILD: u32 = 0x00000040;
+pub const ACE4_READ_ATTRIBUTES: u32 = 0x00000080;
+pub const ACE4_WRITE_ATTRIBUTES: u32 = 0x00000100;
+
+pub const ACE4_DELETE: u32 = 0x00010000;
+pub const ACE4_READ_ACL: u32 = 0x00020000;
+pub const ACE4_WRITE_ACL: u32 = 0x00040000;
+pub const ACE4_WRITE_OWNER: u32 = 0x00080000;
+pub const ACE4_SYNCHRONIZE: u32 = 0x00100000;
 
 /*
  * ACE4_GENERIC_READ - defined as a combination of
@@ -268,7 +268,7 @@ type Acemask4 = u32;
  *      ACE4_SYNCHRONIZE
  */
 
-// const ACE4_GENERIC_READ: u32 = 0x00120081;
+pub const ACE4_GENERIC_READ: u32 = 0x00120081;
 
 /*
  * ACE4_GENERIC_WRITE - defined as a combination of
@@ -279,7 +279,7 @@ type Acemask4 = u32;
  *      ACE4_APPEND_DATA |
  *      ACE4_SYNCHRONIZE
  */
-// const ACE4_GENERIC_WRITE: u32 = 0x00160106;
+pub const ACE4_GENERIC_WRITE: u32 = 0x00160106;
 
 /*
  * ACE4_GENERIC_EXECUTE - defined as a combination of
@@ -288,7 +288,7 @@ type Acemask4 = u32;
  *      ACE4_EXECUTE
  *      ACE4_SYNCHRONIZE
  */
-// const ACE4_GENERIC_EXECUTE: u32 = 0x001200A0;
+pub const ACE4_GENERIC_EXECUTE: u32 = 0x001200A0;
 
 /*
  * Access Control Entry definition
@@ -298,7 +298,7 @@ pub struct Nfsace4 {
     pub acetype: Acetype4,
     pub flag: Aceflag4,
     pub access_mask: Acemask4,
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     pub who: Vec<u8>,
 }
 
@@ -408,6 +408,31 @@ pub const FATTR4_MOUNTED_ON_FILEID: u32 = 55;
 pub struct Fattr4 {
     pub attrmask: Attrlist4<FileAttr>,
     pub attr_vals: Attrlist4<FileAttrValue>,
+    /// Set when the values sent by a client couldn't be decoded, attr_vals is
+    /// empty then and the operation has to fail with the mapped status.
+    #[serde(skip)]
+    pub decode_error: Option<AttrDecodeError>,
+}
+
+/*
+ * Why the attribute values of a Fattr4 couldn't be decoded, carries the
+ * number of the offending attribute
+ */
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AttrDecodeError {
+    /// An attribute bold doesn't know or can't decode the value of.
+    Unsupported(u32),
+    /// A value that is truncated or out of range for its attribute.
+    Invalid(u32),
+}
+
+impl From<&AttrDecodeError> for NfsStat4 {
+    fn from(error: &AttrDecodeError) -> Self {
+        match error {
+            AttrDecodeError::Unsupported(_) => NfsStat4::Nfs4errAttrnotsupp,
+            AttrDecodeError::Invalid(_) => NfsStat4::Nfs4errInval,
+        }
+    }
 }
 
 /*
@@ -424,8 +449,10 @@ pub struct ChangeInfo4 {
 pub struct ClientAddr4 {
     /* see
     pub struct rpcb in RFC 1833 */
+    #[serde(with = "crate::xdr::opaque")]
     pub rnetid: String, /* network id */
-    pub raddr: String,  /* universal address */
+    #[serde(with = "crate::xdr::opaque")]
+    pub raddr: String, /* universal address */
 }
 
 /*
@@ -454,21 +481,21 @@ pub struct Stateid4 {
 pub struct NfsClientId4 {
     #[serde(with = "serde_xdr::opaque_data::fixed_length")]
     pub verifier: [u8; NFS4_VERIFIER_SIZE],
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     pub id: Vec<u8>,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct OpenOwner4 {
     pub clientid: Clientid4,
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     pub owner: Vec<u8>,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct LockOwner4 {
     clientid: Clientid4,
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     owner: Vec<u8>,
 }
 
@@ -520,8 +547,8 @@ pub enum Close4res {
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Commit4args {
     /* CURRENT_FH: file */
-    offset: Offset4,
-    count: Count4,
+    pub offset: Offset4,
+    pub count: Count4,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -555,7 +582,7 @@ pub enum Createtype4 {
 pub struct Create4args {
     /* CURRENT_FH: directory for creation */
     pub objtype: Createtype4,
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     pub objname: Vec<u8>,
     pub createattrs: Fattr4,
 }
@@ -670,7 +697,7 @@ pub enum FileAttrValue {
     UniqueHandles(bool) = 9,
     LeaseTime(NfsLease4) = 10,
     RdattrError(NfsStat4) = 11,
-    Acl = 12,
+    Acl(Vec<Nfsace4>) = 12,
     AclSupport(u32) = 13,
     Archive = 14,
     Cansettime = 15,
@@ -707,8 +734,8 @@ pub enum FileAttrValue {
     System = 46,
     TimeAccess(Nfstime4) = 47,
     TimeAccessSet = 48,
-    TimeBackup = 49,
-    TimeCreate = 50,
+    TimeBackup(Nfstime4) = 49,
+    TimeCreate(Nfstime4) = 50,
     TimeDelta = 51,
     TimeMetadata(Nfstime4) = 52,
     TimeModify(Nfstime4) = 53,
@@ -736,7 +763,7 @@ pub enum Getattr4res {
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct GetFh4resok {
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     pub object: NfsFh4,
 }
 
@@ -858,7 +885,7 @@ pub enum Locku4res {
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Lookup4args {
     /* CURRENT_FH: directory */
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     pub objname: Vec<u8>,
 }
 
@@ -871,23 +898,23 @@ pub struct Lookup4res {
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct LookupP4res {
     /* CURRENT_FH: directory */
-    status: NfsStat4,
+    pub status: NfsStat4,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Nverify4args {
     /* CURRENT_FH: object */
-    obj_attributes: Fattr4,
+    pub obj_attributes: Fattr4,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Nverify4res {
-    status: NfsStat4,
+    pub status: NfsStat4,
 }
 
-// const OPEN4_SHARE_ACCESS_READ: u32 = 0x00000001;
-// const OPEN4_SHARE_ACCESS_WRITE: u32 = 0x00000002;
-// const OPEN4_SHARE_ACCESS_BOTH: u32 = 0x00000003;
+pub const OPEN4_SHARE_ACCESS_READ: u32 = 0x00000001;
+pub const OPEN4_SHARE_ACCESS_WRITE: u32 = 0x00000002;
+pub const OPEN4_SHARE_ACCESS_BOTH: u32 = 0x00000003;
 
 // const OPEN4_SHARE_DENY_NONE: u32 = 0x00000000;
 // const OPEN4_SHARE_DENY_READ: u32 = 0x00000001;
@@ -973,7 +1000,7 @@ pub enum OpenClaimType4 {
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct OpenClaimDelegateCur4 {
     delegate_stateid: Stateid4,
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     file: Vec<u8>,
 }
 
@@ -986,7 +1013,7 @@ pub enum OpenClaim4 {
     */
 
     /* CURRENT_FH: directory */
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     ClaimNull(Vec<u8>) = 0,
     /*
     * Right to the file established by an
@@ -1013,7 +1040,7 @@ pub enum OpenClaim4 {
      * of the client.  File is specified by name.
      */
     /* CURRENT_FH: directory */
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     ClaimDelegatePrev(Vec<u8>) = 3,
 }
 
@@ -1107,13 +1134,13 @@ pub enum Open4res {
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct OpenAttr4args {
     /* CURRENT_FH: object */
-    createdir: bool,
+    pub createdir: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct OpenAttr4res {
     /* CURRENT_FH: named attr directory */
-    status: NfsStat4,
+    pub status: NfsStat4,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -1156,7 +1183,7 @@ pub enum OpenDowngrade4res {
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct PutFh4args {
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     pub object: NfsFh4,
 }
 
@@ -1167,9 +1194,9 @@ pub struct PutFh4res {
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
-pub struct PutPupFh4res {
+pub struct PutPubFh4res {
     /* CURRENT_FH: public fh */
-    status: NfsStat4,
+    pub status: NfsStat4,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -1189,7 +1216,7 @@ pub struct Read4args {
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Read4resok {
     pub eof: bool,
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     pub data: Vec<u8>,
 }
 
@@ -1213,7 +1240,7 @@ pub struct Readdir4args {
 pub struct Entry4 {
     // pub len: u32,
     pub cookie: NfsCookie4,
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     pub name: Vec<u8>,
     pub attrs: Fattr4,
     pub nextentry: Option<Box<Entry4>>,
@@ -1252,7 +1279,7 @@ pub enum ReadLink4res {
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Remove4args {
     /* CURRENT_FH: directory */
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     pub target: Vec<u8>,
 }
 
@@ -1265,10 +1292,10 @@ pub struct Remove4res {
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Rename4args {
     /* SAVED_FH: source directory */
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     oldname: Vec<u8>,
     /* CURRENT_FH: target directory */
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     newname: Vec<u8>,
 }
 
@@ -1309,35 +1336,31 @@ pub struct SaveFh4res {
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct SecInfo4args {
     /* CURRENT_FH: directory */
-    #[serde(with = "serde_bytes")]
-    name: Vec<u8>,
+    #[serde(with = "crate::xdr::opaque")]
+    pub name: Vec<u8>,
 }
 
 /*
  * From RFC 2203
  */
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
-
-pub enum RpcGssSvc {
-    RpcGssSvcNone = 1,
-    RpcGssSvcIntegrity = 2,
-    RpcGssSvcPrivacy = 3,
-}
-
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct RpcSecGssInfo {
-    oid: SecOid4,
-    qop: Qop4,
-    service: RpcGssSvc,
+    #[serde(with = "crate::xdr::opaque")]
+    pub oid: SecOid4,
+    pub qop: Qop4,
+    // rpc_gss_svc_t, one of RPC_GSS_SVC_NONE, RPC_GSS_SVC_INTEGRITY and RPC_GSS_SVC_PRIVACY
+    pub service: u32,
 }
 
 /* RPCSEC_GSS has a value of '6'.  See RFC 2203 */
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+/// Serialized in utils.rs, only RPCSEC_GSS carries information after the flavor
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum SeCinfo4 {
+    Flavor(u32),
     FlavorInfo(RpcSecGssInfo),
 }
 
-type SecInfo4resok = Vec<SeCinfo4>;
+pub type SecInfo4resok = Vec<SeCinfo4>;
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 
@@ -1395,12 +1418,12 @@ pub struct SetClientIdConfirm4res {
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Verify4args {
     /* CURRENT_FH: object */
-    obj_attributes: Fattr4,
+    pub obj_attributes: Fattr4,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Verify4res {
-    status: NfsStat4,
+    pub status: NfsStat4,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -1417,8 +1440,8 @@ pub struct Write4args {
     pub stateid: Stateid4,
     pub offset: Offset4,
     pub stable: StableHow4,
-    #[serde(with = "serde_bytes")]
-    pub data: Vec<u8>,
+    #[serde(with = "crate::xdr::opaque")]
+    pub data: Bytes,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -1601,7 +1624,7 @@ pub enum NfsResOp4 {
     Oplockt(Lockt4res) = 13,
     Oplocku(Locku4res) = 14,
     Oplookup(Lookup4res) = 15,
-    Oplookupp(()) = 16,
+    Oplookupp(LookupP4res) = 16,
     Opnverify(Nverify4res) = 17,
     Opopen(Open4res) = 18,
     Opopenattr(OpenAttr4res) = 19,
@@ -1610,7 +1633,7 @@ pub enum NfsResOp4 {
     OpopenDowngrade(OpenDowngrade4res) = 21,
 
     Opputfh(PutFh4res) = 22,
-    Opputpubfh(()) = 23,
+    Opputpubfh(PutPubFh4res) = 23,
     Opputrootfh(PutRootFh4res) = 24,
     Opread(Read4res) = 25,
     Opreaddir(ReadDir4res) = 26,
@@ -1632,6 +1655,7 @@ pub enum NfsResOp4 {
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Compound4args {
+    #[serde(with = "crate::xdr::opaque")]
     pub tag: String,
     pub minor_version: u32,
     pub argarray: Vec<NfsArgOp>,
@@ -1640,6 +1664,7 @@ pub struct Compound4args {
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Compound4res {
     pub status: NfsStat4,
+    #[serde(with = "crate::xdr::opaque")]
     pub tag: String,
     #[serde(serialize_with = "write_argarray")]
     pub resarray: Vec<NfsResOp4>,
@@ -1650,7 +1675,7 @@ pub struct Compound4res {
  */
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct CbGetattr4args {
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     fh: NfsFh4,
     // #[serde(deserialize_with="read_bitmap", serialize_with="write_bitmap")]
     attr_request: Attrlist4<FileAttr>,
@@ -1671,7 +1696,7 @@ pub enum CbGetattr4res {
 pub struct CbRecall4args {
     stateid: Stateid4,
     truncate: bool,
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     fh: NfsFh4,
 }
 
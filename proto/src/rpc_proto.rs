@@ -1,8 +1,9 @@
 extern crate serde;
-extern crate serde_bytes;
 extern crate serde_derive;
 extern crate serde_xdr;
 
+use std::net::{IpAddr, SocketAddr};
+
 use serde_derive::{Deserialize, Serialize};
 
 use super::{
@@ -11,23 +12,64 @@ use super::{
     to_bytes,
 };
 
-#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+// https://datatracker.ietf.org/doc/html/rfc5531#section-9
+pub const RPC_VERSION: u32 = 2;
+
+// https://datatracker.ietf.org/doc/html/rfc7530#section-17
+pub const NFS4_PROGRAM: u32 = 100003;
+pub const NFS_V4: u32 = 4;
+pub const NFSPROC4_NULL: u32 = 0;
+pub const NFSPROC4_COMPOUND: u32 = 1;
+
+// https://datatracker.ietf.org/doc/html/rfc5531#section-8.2
+pub const AUTH_NONE: u32 = 0;
+pub const AUTH_SYS: u32 = 1;
+pub const AUTH_SHORT: u32 = 2;
+pub const AUTH_DH: u32 = 3;
+pub const RPCSEC_GSS: u32 = 6;
+// https://datatracker.ietf.org/doc/html/rfc9289#section-4.1
+pub const AUTH_TLS: u32 = 7;
+
+/// The body of an AUTH_SYS credential
+/// https://datatracker.ietf.org/doc/html/rfc5531#appendix-A.1
+#[derive(Clone, Debug, Deserialize, Serialize, Default, PartialEq, Eq)]
 pub struct AuthUnix {
-    pub stamp: u64,
+    pub stamp: u32,
+    #[serde(with = "crate::xdr::opaque")]
     pub machinename: String,
     pub uid: u32,
     pub gid: u32,
     pub gids: Vec<u32>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-#[repr(u32)]
+/// A credential or verifier, decoded from its flavor and opaque body, see
+/// utils.rs
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum OpaqueAuth {
-    AuthNull(Vec<u8>) = 0,
-    AuthUnix(AuthUnix) = 1,
+    AuthNull(Vec<u8>),
+    AuthUnix(AuthUnix),
     // not supported
-    AuthShort = 2,
-    AuthDes = 3,
+    AuthShort,
+    AuthDes,
+    // the body of a RPCSEC_GSS credential or verifier, see RpcGssCred
+    RpcsecGss(Vec<u8>),
+    // any other flavor
+    Unsupported(u32),
+    // a credential of a supported flavor with a body that could not be decoded
+    Malformed(u32),
+}
+
+impl OpaqueAuth {
+    pub fn flavor(&self) -> u32 {
+        match self {
+            OpaqueAuth::AuthNull(_) => AUTH_NONE,
+            OpaqueAuth::AuthUnix(_) => AUTH_SYS,
+            OpaqueAuth::AuthShort => AUTH_SHORT,
+            OpaqueAuth::AuthDes => AUTH_DH,
+            OpaqueAuth::RpcsecGss(_) => RPCSEC_GSS,
+            OpaqueAuth::Unsupported(flavor) | OpaqueAuth::Malformed(flavor) => *flavor,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -39,6 +81,10 @@ pub struct CallBody {
     pub cred: OpaqueAuth,
     pub verf: OpaqueAuth,
     pub args: Option<Compound4args>,
+    // the arguments of a call protected by RPCSEC_GSS, which can only be
+    // decoded with the security context of the call
+    #[serde(skip)]
+    pub gss_args: Option<RpcGssArgs>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -56,8 +102,8 @@ pub struct AcceptedReply {
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MismatchInfo {
-    low: u32,
-    high: u32,
+    pub low: u32,
+    pub high: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -67,16 +113,25 @@ pub enum ReplyBody {
     MsgDenied(RejectedReply) = 1,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-#[repr(u32)]
+/// The outcome of an accepted call, all `Success*` variants are sent as
+/// SUCCESS followed by their results, see utils.rs
+#[derive(Debug, Clone, Deserialize)]
 pub enum AcceptBody {
-    Success(Compound4res) = 0,
-    ProgUnavail = 1,
+    Success(Compound4res),
+    ProgUnavail,
     /// remote can't support version #
-    ProgMismatch(MismatchInfo) = 2,
-    ProcUnavail = 3,
+    ProgMismatch(MismatchInfo),
+    ProcUnavail,
     /// procedure can't decode params
-    GarbageArgs = 4,
+    GarbageArgs,
+    /// errors like memory allocation failure
+    SystemErr,
+    /// the reply to a RPCSEC_GSS context creation
+    SuccessGssInit(RpcGssInitRes),
+    /// results protected by the RPCSEC_GSS integrity service
+    SuccessIntegrity(RpcGssIntegData),
+    /// results protected by the RPCSEC_GSS privacy service
+    SuccessPrivacy(RpcGssPrivData),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -102,6 +157,147 @@ pub enum AuthStat {
     AuthRejectedverf = 4,
     /// rejected for security reasons
     AuthTooWeak = 5,
+    /// bogus response verifier
+    AuthInvalidresp = 6,
+    /// reason unknown
+    AuthFailed = 7,
+    AuthKerbGeneric = 8,
+    AuthTimeexpire = 9,
+    AuthTktFile = 10,
+    AuthDecode = 11,
+    AuthNetAddr = 12,
+    /// no credentials for user
+    RpcsecGssCredproblem = 13,
+    /// problem with context
+    RpcsecGssCtxproblem = 14,
+}
+
+/*
+ * RPCSEC_GSS version 1
+ * https://datatracker.ietf.org/doc/html/rfc2203#section-5
+ */
+pub const RPCSEC_GSS_VERS_1: u32 = 1;
+
+// rpc_gss_proc_t
+pub const RPCSEC_GSS_DATA: u32 = 0;
+pub const RPCSEC_GSS_INIT: u32 = 1;
+pub const RPCSEC_GSS_CONTINUE_INIT: u32 = 2;
+pub const RPCSEC_GSS_DESTROY: u32 = 3;
+
+// rpc_gss_service_t, krb5, krb5i and krb5p
+pub const RPC_GSS_SVC_NONE: u32 = 1;
+pub const RPC_GSS_SVC_INTEGRITY: u32 = 2;
+pub const RPC_GSS_SVC_PRIVACY: u32 = 3;
+
+// sequence numbers must stay below
+pub const MAXSEQ: u32 = 0x80000000;
+
+/// The body of a RPCSEC_GSS credential
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RpcGssCred {
+    // always RPCSEC_GSS_VERS_1
+    pub version: u32,
+    pub gss_proc: u32,
+    pub seq_num: u32,
+    pub service: u32,
+    #[serde(with = "crate::xdr::opaque")]
+    pub handle: Vec<u8>,
+}
+
+impl RpcGssCred {
+    pub fn from_bytes(body: &[u8]) -> Result<Self, anyhow::Error> {
+        crate::xdr::from_bytes(body)
+            .map_err(|e| anyhow::anyhow!("Error deserializing credential: {:?}", e))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, anyhow::Error> {
+        serde_xdr::to_bytes(self)
+            .map_err(|e| anyhow::anyhow!("Error serializing credential: {:?}", e))
+    }
+}
+
+/// The results of RPCSEC_GSS_INIT and RPCSEC_GSS_CONTINUE_INIT
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub struct RpcGssInitRes {
+    #[serde(with = "crate::xdr::opaque")]
+    pub handle: Vec<u8>,
+    pub gss_major: u32,
+    pub gss_minor: u32,
+    pub seq_window: u32,
+    #[serde(with = "crate::xdr::opaque")]
+    pub gss_token: Vec<u8>,
+}
+
+/// Arguments or results with a checksum, the body is the sequence number
+/// followed by the arguments or results
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RpcGssIntegData {
+    #[serde(with = "crate::xdr::opaque")]
+    pub databody_integ: Vec<u8>,
+    #[serde(with = "crate::xdr::opaque")]
+    pub checksum: Vec<u8>,
+}
+
+/// Arguments or results wrapped by the security context
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RpcGssPrivData {
+    #[serde(with = "crate::xdr::opaque")]
+    pub databody_priv: Vec<u8>,
+}
+
+/// The arguments of a call with a RPCSEC_GSS credential
+#[derive(Clone, Debug)]
+pub enum RpcGssArgs {
+    // the GSS token of a context creation
+    Init(Vec<u8>),
+    Integrity(RpcGssIntegData),
+    Privacy(RpcGssPrivData),
+}
+
+/// The sequence number and COMPOUND arguments in the body of protected data.
+pub fn decode_gss_databody(databody: &[u8]) -> Result<(u32, Compound4args), anyhow::Error> {
+    crate::xdr::from_bytes(databody)
+        .map_err(|e| anyhow::anyhow!("Error deserializing protected data: {:?}", e))
+}
+
+/// The body of protected data for the COMPOUND results of the call `seq_num`.
+pub fn encode_gss_databody(seq_num: u32, res: &Compound4res) -> Result<Vec<u8>, anyhow::Error> {
+    serde_xdr::to_bytes(&(seq_num, res))
+        .map_err(|e| anyhow::anyhow!("Error serializing protected data: {:?}", e))
+}
+
+/// The part of a call a RPCSEC_GSS verifier is computed over, from the xid up
+/// to and including the credential.
+pub fn call_header_bytes(xid: u32, call: &CallBody) -> Result<Vec<u8>, anyhow::Error> {
+    serde_xdr::to_bytes(&(
+        xid,
+        0_u32,
+        call.rpcvers,
+        call.prog,
+        call.vers,
+        call.proc,
+        &call.cred,
+    ))
+    .map_err(|e| anyhow::anyhow!("Error serializing call header: {:?}", e))
+}
+
+/// The socket address of a universal address, `h1.h2.h3.h4.p1.p2` for IPv4
+/// and an IPv6 address followed by `.p1.p2` for IPv6, e.g. `::1.8.1` for
+/// port 2049 on `::1`.
+///
+/// Please read: [RFC 5665, Section 5.2.3](https://datatracker.ietf.org/doc/html/rfc5665#section-5.2.3)
+pub fn parse_uaddr(uaddr: &str) -> Option<SocketAddr> {
+    let mut parts = uaddr.rsplitn(3, '.');
+    let low: u8 = parts.next()?.parse().ok()?;
+    let high: u8 = parts.next()?.parse().ok()?;
+    let ip: IpAddr = parts.next()?.parse().ok()?;
+    Some(SocketAddr::new(ip, u16::from_be_bytes([high, low])))
+}
+
+/// The universal address of `addr`, see [`parse_uaddr`].
+pub fn format_uaddr(addr: &SocketAddr) -> String {
+    let [high, low] = addr.port().to_be_bytes();
+    format!("{}.{}.{}", addr.ip(), high, low)
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -111,8 +307,8 @@ pub struct RpcCallMsg {
 }
 
 impl RpcCallMsg {
-    pub fn from_bytes(buffer: Vec<u8>) -> Result<Self, anyhow::Error> {
-        from_bytes(buffer)
+    pub fn from_bytes(buffer: impl Into<bytes::Bytes>) -> Result<Self, anyhow::Error> {
+        from_bytes(buffer.into())
     }
 }
 
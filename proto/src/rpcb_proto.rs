@@ -0,0 +1,102 @@
+use serde_derive::{Deserialize, Serialize};
+
+use super::rpc_proto::OpaqueAuth;
+
+// https://datatracker.ietf.org/doc/html/rfc1833
+pub const RPCBIND_PROGRAM: u32 = 100000;
+pub const RPCBIND_PORT: u16 = 111;
+pub const PMAP_V2: u32 = 2;
+pub const RPCBIND_V3: u32 = 3;
+pub const RPCBIND_V4: u32 = 4;
+
+// the procedures of the portmapper, version 2
+pub const PMAPPROC_NULL: u32 = 0;
+pub const PMAPPROC_SET: u32 = 1;
+pub const PMAPPROC_UNSET: u32 = 2;
+pub const PMAPPROC_GETPORT: u32 = 3;
+pub const PMAPPROC_DUMP: u32 = 4;
+
+// the procedures of rpcbind, versions 3 and 4
+pub const RPCBPROC_NULL: u32 = 0;
+pub const RPCBPROC_SET: u32 = 1;
+pub const RPCBPROC_UNSET: u32 = 2;
+pub const RPCBPROC_GETADDR: u32 = 3;
+pub const RPCBPROC_DUMP: u32 = 4;
+// version 4 only
+pub const RPCBPROC_GETVERSADDR: u32 = 9;
+
+pub const IPPROTO_TCP: u32 = 6;
+pub const IPPROTO_UDP: u32 = 17;
+
+/// A mapping of the portmapper, a program version and the port it is
+/// reached on with `prot`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Pmap {
+    pub prog: u32,
+    pub vers: u32,
+    pub prot: u32,
+    pub port: u32,
+}
+
+/// A mapping of rpcbind, a program version and the universal address it is
+/// reached on over the transport `r_netid`, e.g. `tcp6`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Rpcb {
+    pub r_prog: u32,
+    pub r_vers: u32,
+    #[serde(with = "crate::xdr::opaque")]
+    pub r_netid: String,
+    #[serde(with = "crate::xdr::opaque")]
+    pub r_addr: String,
+    #[serde(with = "crate::xdr::opaque")]
+    pub r_owner: String,
+}
+
+/// A call of the portmapper or rpcbind with the arguments `T`, the header is
+/// the one of [`crate::rpc_proto::RpcCallMsg`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RpcbCall<T> {
+    pub xid: u32,
+    // CALL
+    pub msg_type: u32,
+    pub rpcvers: u32,
+    pub prog: u32,
+    pub vers: u32,
+    pub proc: u32,
+    pub cred: OpaqueAuth,
+    pub verf: OpaqueAuth,
+    pub args: T,
+}
+
+/// An accepted reply to a call of the portmapper or rpcbind, `result`
+/// follows a SUCCESS or PROG_MISMATCH and is void otherwise.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RpcbReply<T> {
+    pub xid: u32,
+    // REPLY
+    pub msg_type: u32,
+    // MSG_ACCEPTED
+    pub reply_stat: u32,
+    pub verf: OpaqueAuth,
+    pub accept_stat: u32,
+    pub result: T,
+}
+
+/// The list of mappings a DUMP returns, XDR optional-data linked in a list:
+/// every mapping follows a TRUE and the list ends with FALSE.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RpcbList<T>(pub Vec<T>);
+
+impl<T: serde::Serialize> serde::Serialize for RpcbList<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeTuple;
+
+        let mut tuple = serializer.serialize_tuple(self.0.len() * 2 + 1)?;
+        for mapping in self.0.iter() {
+            tuple.serialize_element(&true)?;
+            tuple.serialize_element(mapping)?;
+        }
+        tuple.serialize_element(&false)?;
+        tuple.end()
+    }
+}
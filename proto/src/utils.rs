@@ -11,11 +11,22 @@ use serde::{
 };
 use tracing::{debug, error};
 
-use crate::nfs4_proto::Compound4args;
+use crate::{
+    nfs4_proto::Compound4args,
+    xdr::{Opaque, XdrDeserializer},
+};
 
 use super::{
-    nfs4_proto::{Attrlist4, Fattr4, FileAttr, FileAttrValue, Getattr4resok, NfsResOp4, NfsStat4},
-    rpc_proto::CallBody,
+    nfs4_proto::{
+        AttrDecodeError, Attrlist4, Fattr4, FileAttr, FileAttrValue, Getattr4resok, NfsResOp4,
+        NfsStat4, SeCinfo4,
+    },
+    rpc_proto::{
+        AcceptBody, AuthUnix, CallBody, OpaqueAuth, RpcGssArgs, RpcGssCred, AUTH_DH, AUTH_NONE,
+        AUTH_SHORT, AUTH_SYS, NFS4_PROGRAM, NFSPROC4_COMPOUND, NFS_V4, RPCSEC_GSS,
+        RPCSEC_GSS_CONTINUE_INIT, RPCSEC_GSS_DATA, RPCSEC_GSS_INIT, RPC_GSS_SVC_INTEGRITY,
+        RPC_GSS_SVC_PRIVACY, RPC_VERSION,
+    },
 };
 
 pub fn write_argarray<T, S>(v: &T, serializer: S) -> Result<S::Ok, S::Error>
@@ -95,8 +106,43 @@ impl<'de> Deserialize<'de> for CallBody {
                 let verf = seq
                     .next_element()?
                     .ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                // if proc == 0, then there are no args
-                if proc == 0 {
+                // https://datatracker.ietf.org/doc/html/rfc2203#section-5.2.2
+                // the arguments of RPCSEC_GSS calls depend on the credential
+                let gss_cred = match &cred {
+                    OpaqueAuth::RpcsecGss(body) => RpcGssCred::from_bytes(body).ok(),
+                    _ => None,
+                };
+                let gss_args = match gss_cred {
+                    Some(RpcGssCred { gss_proc, .. })
+                        if gss_proc == RPCSEC_GSS_INIT || gss_proc == RPCSEC_GSS_CONTINUE_INIT =>
+                    {
+                        let token: Opaque<Vec<u8>> = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(6, &self))?;
+                        Some(RpcGssArgs::Init(token.0))
+                    }
+                    Some(RpcGssCred {
+                        gss_proc, service, ..
+                    }) if gss_proc == RPCSEC_GSS_DATA && proc != 0 => match service {
+                        RPC_GSS_SVC_INTEGRITY => Some(RpcGssArgs::Integrity(
+                            seq.next_element()?
+                                .ok_or_else(|| de::Error::invalid_length(6, &self))?,
+                        )),
+                        RPC_GSS_SVC_PRIVACY => Some(RpcGssArgs::Privacy(
+                            seq.next_element()?
+                                .ok_or_else(|| de::Error::invalid_length(6, &self))?,
+                        )),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                // only the arguments of a NFSv4 COMPOUND are decoded, calls of
+                // other programs, versions and procedures are rejected unread
+                let compound = rpcvers == RPC_VERSION
+                    && prog == NFS4_PROGRAM
+                    && vers == NFS_V4
+                    && proc == NFSPROC4_COMPOUND;
+                if !compound || gss_args.is_some() {
                     // Procedure 0: NULL - No Operation
                     Ok(CallBody {
                         rpcvers,
@@ -106,6 +152,7 @@ impl<'de> Deserialize<'de> for CallBody {
                         cred,
                         verf,
                         args: None,
+                        gss_args,
                     })
                 } else {
                     // Procedure 1: COMPOUND - Compound Operations
@@ -120,6 +167,7 @@ impl<'de> Deserialize<'de> for CallBody {
                         cred,
                         verf,
                         args: Some(args),
+                        gss_args: None,
                     })
                 }
             }
@@ -130,14 +178,179 @@ impl<'de> Deserialize<'de> for CallBody {
     }
 }
 
+// the flavor and body of an OpaqueAuth on the wire
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OpaqueAuthRaw {
+    flavor: u32,
+    #[serde(with = "crate::xdr::opaque")]
+    body: Vec<u8>,
+}
+
+// the most a credential may carry
+// https://datatracker.ietf.org/doc/html/rfc5531#section-8.2
+const MAX_AUTH_BYTES: usize = 400;
+const MAX_MACHINE_NAME: usize = 255;
+const MAX_AUTH_GIDS: usize = 16;
+
+impl Serialize for OpaqueAuth {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let body = match self {
+            OpaqueAuth::AuthNull(body) | OpaqueAuth::RpcsecGss(body) => body.clone(),
+            OpaqueAuth::AuthUnix(auth) => {
+                serde_xdr::to_bytes(auth).map_err(serde::ser::Error::custom)?
+            }
+            _ => Vec::new(),
+        };
+        OpaqueAuthRaw {
+            flavor: self.flavor(),
+            body,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for OpaqueAuth {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let raw = OpaqueAuthRaw::deserialize(deserializer)?;
+        if raw.body.len() > MAX_AUTH_BYTES {
+            return Ok(OpaqueAuth::Malformed(raw.flavor));
+        }
+        Ok(match raw.flavor {
+            AUTH_NONE => OpaqueAuth::AuthNull(raw.body),
+            AUTH_SYS => match crate::xdr::from_bytes::<AuthUnix>(&raw.body) {
+                Ok(auth)
+                    if auth.machinename.len() <= MAX_MACHINE_NAME
+                        && auth.gids.len() <= MAX_AUTH_GIDS =>
+                {
+                    OpaqueAuth::AuthUnix(auth)
+                }
+                _ => OpaqueAuth::Malformed(AUTH_SYS),
+            },
+            AUTH_SHORT => OpaqueAuth::AuthShort,
+            AUTH_DH => OpaqueAuth::AuthDes,
+            RPCSEC_GSS => OpaqueAuth::RpcsecGss(raw.body),
+            flavor => OpaqueAuth::Unsupported(flavor),
+        })
+    }
+}
+
+impl Serialize for AcceptBody {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        // accept_stat, followed by the results of SUCCESS and PROG_MISMATCH
+        let mut seq = serializer.serialize_struct("AcceptBody", 2)?;
+        match self {
+            AcceptBody::Success(res) => {
+                seq.serialize_field("stat", &0_u32)?;
+                seq.serialize_field("results", res)?;
+            }
+            AcceptBody::SuccessGssInit(res) => {
+                seq.serialize_field("stat", &0_u32)?;
+                seq.serialize_field("results", res)?;
+            }
+            AcceptBody::SuccessIntegrity(res) => {
+                seq.serialize_field("stat", &0_u32)?;
+                seq.serialize_field("results", res)?;
+            }
+            AcceptBody::SuccessPrivacy(res) => {
+                seq.serialize_field("stat", &0_u32)?;
+                seq.serialize_field("results", res)?;
+            }
+            AcceptBody::ProgUnavail => seq.serialize_field("stat", &1_u32)?,
+            AcceptBody::ProgMismatch(info) => {
+                seq.serialize_field("stat", &2_u32)?;
+                seq.serialize_field("mismatch_info", info)?;
+            }
+            AcceptBody::ProcUnavail => seq.serialize_field("stat", &3_u32)?,
+            AcceptBody::GarbageArgs => seq.serialize_field("stat", &4_u32)?,
+            AcceptBody::SystemErr => seq.serialize_field("stat", &5_u32)?,
+        }
+        seq.end()
+    }
+}
+
+impl Serialize for SeCinfo4 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_struct("SeCinfo4", 2)?;
+        match self {
+            SeCinfo4::Flavor(flavor) => seq.serialize_field("flavor", flavor)?,
+            SeCinfo4::FlavorInfo(info) => {
+                seq.serialize_field("flavor", &RPCSEC_GSS)?;
+                seq.serialize_field("flavor_info", info)?;
+            }
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for SeCinfo4 {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct SeCinfo4Visitor;
+
+        impl<'de> Visitor<'de> for SeCinfo4Visitor {
+            type Value = SeCinfo4;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("union secinfo4")
+            }
+
+            fn visit_seq<V>(self, mut seq: V) -> Result<SeCinfo4, V::Error>
+            where
+                V: SeqAccess<'de>,
+            {
+                let flavor: u32 = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                if flavor != RPCSEC_GSS {
+                    return Ok(SeCinfo4::Flavor(flavor));
+                }
+                let info = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                Ok(SeCinfo4::FlavorInfo(info))
+            }
+        }
+
+        const FIELDS: &[&str] = &["flavor", "flavor_info"];
+        deserializer.deserialize_struct("SeCinfo4", FIELDS, SeCinfo4Visitor)
+    }
+}
+
 // deserialization helper for Fattr4
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct FattrRaw {
     attrmask: Vec<u32>,
-    #[serde(with = "serde_bytes")]
+    #[serde(with = "crate::xdr::opaque")]
     attr_vals: Vec<u8>,
 }
 impl FattrRaw {
+    // the first bit set in attrmask that has no FileAttr
+    fn unknown_attr(&self) -> Option<u32> {
+        for (idx, segment) in self.attrmask.iter().enumerate() {
+            for n in 0..32 {
+                let attr = (idx * 32 + n) as u32;
+                if (segment >> n) & 1 == 1 && FileAttr::from_u32(attr).is_none() {
+                    return Some(attr);
+                }
+            }
+        }
+        None
+    }
+
     fn to_fileattrs(&self) -> Attrlist4<FileAttr> {
         let mut attrmask = Attrlist4::<FileAttr>::new(None);
         for (idx, segment) in self.attrmask.iter().enumerate() {
@@ -154,63 +367,89 @@ impl FattrRaw {
         attrmask
     }
 
-    fn attrvalues_from_bytes(&self, fileattrs: &[FileAttr]) -> Attrlist4<FileAttrValue> {
+    // decodes the values of fileattrs, fails at the first attribute bold
+    // doesn't know the encoding of, as the values after it can't be found
+    fn attrvalues_from_bytes(
+        &self,
+        fileattrs: &[FileAttr],
+    ) -> Result<Attrlist4<FileAttrValue>, AttrDecodeError> {
         let mut attr_vals = Attrlist4::<FileAttrValue>::new(None);
-        let mut offset = 0;
-        for (idx, attr) in fileattrs.iter().enumerate() {
-            match attr {
-                FileAttr::Type => {
-                    todo!();
-                }
-                FileAttr::Change => {
-                    todo!();
-                }
-                FileAttr::Size => {
-                    let ele =
-                        u64::from_be_bytes(self.attr_vals[offset..offset + 8].try_into().unwrap());
-                    attr_vals.push(FileAttrValue::Size(ele));
-                    offset += idx + 4;
-                }
-                FileAttr::TimeAccess => {
-                    todo!();
-                }
-                FileAttr::TimeModify => {
-                    todo!();
-                }
-                FileAttr::TimeMetadata => {
-                    todo!();
-                }
-                FileAttr::MountedOnFileid => {
-                    todo!();
-                }
-                FileAttr::Owner => {
-                    todo!();
-                }
-                FileAttr::OwnerGroup => {
-                    todo!();
-                }
-                FileAttr::SpaceUsed => {
-                    todo!();
-                }
-                FileAttr::Numlinks => {
-                    todo!();
-                }
-                FileAttr::Mode => {
-                    let ele =
-                        u32::from_be_bytes(self.attr_vals[offset..offset + 4].try_into().unwrap());
-                    attr_vals.push(FileAttrValue::Mode(ele));
-                    offset += idx + 4;
-                }
-                _ => {
+        let mut reader = XdrDeserializer::new(&self.attr_vals);
+        for attr in fileattrs {
+            let number = attr.to_u32().unwrap_or_default();
+            let value = Self::attrvalue_from_bytes(attr, &mut reader)
+                .map_err(|e| {
+                    debug!("Cannot deserialize {:?}: {:?}", attr, e);
+                    AttrDecodeError::Invalid(number)
+                })?
+                .ok_or_else(|| {
                     error!("Cannot deserialize {:?}", attr);
-                    todo!()
+                    AttrDecodeError::Unsupported(number)
+                })?;
+            attr_vals.push(value);
+        }
+        Ok(attr_vals)
+    }
+
+    // decodes the value of a single attribute, None if bold doesn't know its
+    // encoding
+    fn attrvalue_from_bytes(
+        attr: &FileAttr,
+        reader: &mut XdrDeserializer,
+    ) -> Result<Option<FileAttrValue>, serde_xdr::CompatDeserializationError> {
+        let value = match attr {
+            FileAttr::SupportedAttrs => {
+                FileAttrValue::SupportedAttrs(Attrlist4::from_u32(read_xdr(reader)?))
+            }
+            FileAttr::Type => {
+                let ftype = read_xdr::<u32>(reader)?;
+                match FromPrimitive::from_u32(ftype) {
+                    Some(ftype) => FileAttrValue::Type(ftype),
+                    None => return Err(de::Error::custom("invalid file type")),
                 }
             }
-        }
-        attr_vals
+            FileAttr::FhExpireType => FileAttrValue::FhExpireType(read_xdr(reader)?),
+            FileAttr::Change => FileAttrValue::Change(read_xdr(reader)?),
+            FileAttr::Size => FileAttrValue::Size(read_xdr(reader)?),
+            FileAttr::LinkSupport => FileAttrValue::LinkSupport(read_xdr(reader)?),
+            FileAttr::SymlinkSupport => FileAttrValue::SymlinkSupport(read_xdr(reader)?),
+            FileAttr::NamedAttr => FileAttrValue::NamedAttr(read_xdr(reader)?),
+            FileAttr::Fsid => FileAttrValue::Fsid(read_xdr(reader)?),
+            FileAttr::UniqueHandles => FileAttrValue::UniqueHandles(read_xdr(reader)?),
+            FileAttr::LeaseTime => FileAttrValue::LeaseTime(read_xdr(reader)?),
+            FileAttr::RdattrError => {
+                let status = read_xdr::<u32>(reader)?;
+                match FromPrimitive::from_u32(status) {
+                    Some(status) => FileAttrValue::RdattrError(status),
+                    None => return Err(de::Error::custom("invalid status")),
+                }
+            }
+            FileAttr::Acl => FileAttrValue::Acl(read_xdr(reader)?),
+            FileAttr::AclSupport => FileAttrValue::AclSupport(read_xdr(reader)?),
+            FileAttr::Fileid => FileAttrValue::Fileid(read_xdr(reader)?),
+            FileAttr::Mode => FileAttrValue::Mode(read_xdr(reader)?),
+            FileAttr::Numlinks => FileAttrValue::Numlinks(read_xdr(reader)?),
+            FileAttr::Owner => FileAttrValue::Owner(read_xdr::<Opaque<_>>(reader)?.0),
+            FileAttr::OwnerGroup => FileAttrValue::OwnerGroup(read_xdr::<Opaque<_>>(reader)?.0),
+            FileAttr::SpaceUsed => FileAttrValue::SpaceUsed(read_xdr(reader)?),
+            FileAttr::TimeAccess => FileAttrValue::TimeAccess(read_xdr(reader)?),
+            FileAttr::TimeBackup => FileAttrValue::TimeBackup(read_xdr(reader)?),
+            FileAttr::TimeCreate => FileAttrValue::TimeCreate(read_xdr(reader)?),
+            FileAttr::TimeMetadata => FileAttrValue::TimeMetadata(read_xdr(reader)?),
+            FileAttr::TimeModify => FileAttrValue::TimeModify(read_xdr(reader)?),
+            FileAttr::MountedOnFileid => FileAttrValue::MountedOnFileid(read_xdr(reader)?),
+            _ => return Ok(None),
+        };
+        Ok(Some(value))
     }
 }
 
+fn read_xdr<T: serde::de::DeserializeOwned>(
+    reader: &mut XdrDeserializer,
+) -> Result<T, serde_xdr::CompatDeserializationError> {
+    T::deserialize(reader)
+}
+
 impl<'de> Deserialize<'de> for Fattr4 {
     fn deserialize<D>(deserializer: D) -> Result<Fattr4, D::Error>
     where
@@ -218,11 +457,21 @@ impl<'de> Deserialize<'de> for Fattr4 {
     {
         let fattr_raw = <FattrRaw as serde::Deserialize>::deserialize(deserializer)?;
         let attrmask = fattr_raw.to_fileattrs();
-        let attr_vals = fattr_raw.attrvalues_from_bytes(&attrmask);
+        // a value that can't be decoded fails the operation carrying it with
+        // ATTRNOTSUPP or INVAL, not the whole COMPOUND with GARBAGE_ARGS
+        let decoded = match fattr_raw.unknown_attr() {
+            Some(attr) => Err(AttrDecodeError::Unsupported(attr)),
+            None => fattr_raw.attrvalues_from_bytes(&attrmask),
+        };
+        let (attr_vals, decode_error) = match decoded {
+            Ok(attr_vals) => (attr_vals, None),
+            Err(e) => (Attrlist4::<FileAttrValue>::new(None), Some(e)),
+        };
 
         Ok(Fattr4 {
             attrmask,
             attr_vals,
+            decode_error,
         })
     }
 }
@@ -302,7 +551,8 @@ impl Attrlist4<FileAttrValue> {
         }
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
+    /// The XDR encoding of the values, as sent in `attr_vals`.
+    pub fn to_bytes(&self) -> Vec<u8> {
         let mut buffer: Vec<u8> = Vec::new();
         for val in &self.0 {
             match val {
@@ -352,6 +602,9 @@ impl Attrlist4<FileAttrValue> {
                 FileAttrValue::Fileid(v) => {
                     buffer.extend_from_slice(v.to_be_bytes().as_ref());
                 }
+                FileAttrValue::Acl(v) => {
+                    buffer.extend_from_slice(&serde_xdr::to_bytes(v).unwrap());
+                }
                 FileAttrValue::AclSupport(v) => {
                     buffer.extend_from_slice(v.to_be_bytes().as_ref());
                 }
@@ -362,6 +615,14 @@ impl Attrlist4<FileAttrValue> {
                     buffer.extend_from_slice(v.seconds.to_be_bytes().as_ref());
                     buffer.extend_from_slice(v.nseconds.to_be_bytes().as_ref());
                 }
+                FileAttrValue::TimeBackup(v) => {
+                    buffer.extend_from_slice(v.seconds.to_be_bytes().as_ref());
+                    buffer.extend_from_slice(v.nseconds.to_be_bytes().as_ref());
+                }
+                FileAttrValue::TimeCreate(v) => {
+                    buffer.extend_from_slice(v.seconds.to_be_bytes().as_ref());
+                    buffer.extend_from_slice(v.nseconds.to_be_bytes().as_ref());
+                }
                 FileAttrValue::TimeModify(v) => {
                     buffer.extend_from_slice(v.seconds.to_be_bytes().as_ref());
                     buffer.extend_from_slice(v.nseconds.to_be_bytes().as_ref());
@@ -374,12 +635,10 @@ impl Attrlist4<FileAttrValue> {
                     buffer.extend_from_slice(v.to_be_bytes().as_ref());
                 }
                 FileAttrValue::Owner(v) => {
-                    buffer.extend_from_slice((v.len() as u32).to_be_bytes().as_ref());
-                    buffer.extend_from_slice(v.as_bytes());
+                    buffer.extend_from_slice(&serde_xdr::to_bytes(v).unwrap());
                 }
                 FileAttrValue::OwnerGroup(v) => {
-                    buffer.extend_from_slice((v.len() as u32).to_be_bytes().as_ref());
-                    buffer.extend_from_slice(v.as_bytes());
+                    buffer.extend_from_slice(&serde_xdr::to_bytes(v).unwrap());
                 }
                 FileAttrValue::SpaceUsed(v) => {
                     buffer.extend_from_slice(v.to_be_bytes().as_ref());
@@ -413,7 +672,7 @@ impl<'de> Deserialize<'de> for Attrlist4<FileAttr> {
     where
         D: serde::Deserializer<'de>,
     {
-        let attrs_raw = <Vec<u32> as serde::Deserialize>::deserialize(deserializer).unwrap();
+        let attrs_raw = <Vec<u32> as serde::Deserialize>::deserialize(deserializer)?;
         let attrs_list = Attrlist4::from_u32(attrs_raw);
         Ok(attrs_list)
     }
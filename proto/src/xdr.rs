@@ -0,0 +1,472 @@
+//! Decoding of XDR with bounded lengths.
+//!
+//! serde-xdr allocates as much as the length of an opaque or a string on the
+//! wire says before reading it, so a few hostile bytes make it allocate
+//! gigabytes. [`XdrDeserializer`] decodes the same encoding out of the
+//! buffer it is handed and bounds every length by the bytes left in it.
+//! Decoded by [`from_shared`], opaque data held as [`Bytes`] is a slice of
+//! the buffer, e.g. the data of a WRITE is never copied.
+
+use std::{cell::Cell, fmt};
+
+use bytes::Bytes;
+use serde::{
+    de::{
+        self, value::U32Deserializer, DeserializeOwned, DeserializeSeed, EnumAccess,
+        IntoDeserializer, SeqAccess, VariantAccess, Visitor,
+    },
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_xdr::CompatDeserializationError;
+
+type Result<T> = std::result::Result<T, CompatDeserializationError>;
+
+fn invalid(error: impl fmt::Display) -> CompatDeserializationError {
+    de::Error::custom(error)
+}
+
+/// Decodes a `T` from `data`, trailing bytes are ignored like serde-xdr does.
+pub fn from_bytes<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
+    T::deserialize(&mut XdrDeserializer::new(data))
+}
+
+/// Decodes a `T` from `data` like [`from_bytes`], the opaque [`Bytes`]
+/// fields of `T` share the memory of `data`.
+pub fn from_shared<T: DeserializeOwned>(data: &Bytes) -> Result<T> {
+    T::deserialize(&mut XdrDeserializer::shared(data))
+}
+
+thread_local! {
+    // the opaque data XdrDeserializer hands to a visitor, for the visitor
+    // of Bytes to take it instead of copying it. It is only set while the
+    // visitor runs, decoding doesn't keep anything here between calls.
+    static SHARED: Cell<Option<Bytes>> = const { Cell::new(None) };
+}
+
+/// A deserializer of the XDR encoding of serde-xdr reading from a buffer.
+///
+/// Values can be decoded one after the other, the deserializer continues
+/// where the last one ended.
+pub struct XdrDeserializer<'de> {
+    // the bytes not decoded yet
+    data: &'de [u8],
+    // the buffer data is a part of, opaque data is sliced out of it
+    shared: Option<&'de Bytes>,
+}
+
+impl<'de> XdrDeserializer<'de> {
+    pub fn new(data: &'de [u8]) -> Self {
+        XdrDeserializer { data, shared: None }
+    }
+
+    /// A deserializer of `data` handing out opaque data as slices of `data`.
+    pub fn shared(data: &'de Bytes) -> Self {
+        XdrDeserializer {
+            data,
+            shared: Some(data),
+        }
+    }
+
+    /// The number of bytes not decoded yet.
+    pub fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    fn take(&mut self, length: usize, what: &'static str) -> Result<&'de [u8]> {
+        if length > self.data.len() {
+            return Err(de::Error::invalid_length(length, &what));
+        }
+        let (taken, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4, "a word")?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        let high = self.read_u32()? as u64;
+        Ok(high << 32 | self.read_u32()? as u64)
+    }
+
+    // the length of opaque data and the data, without its padding
+    fn read_opaque(&mut self) -> Result<&'de [u8]> {
+        let length = self.read_u32()? as usize;
+        let padded = self.take(length.div_ceil(4) * 4, "opaque data within the buffer")?;
+        Ok(&padded[..length])
+    }
+}
+
+impl<'de> Deserializer<'de> for &mut XdrDeserializer<'de> {
+    type Error = CompatDeserializationError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(de::Error::custom("XDR is not self-describing"))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.read_u32()? {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            value => Err(de::Error::invalid_value(
+                de::Unexpected::Unsigned(value as u64),
+                &"a bool",
+            )),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let value = self.read_u32()? as i32;
+        visitor.visit_i8(i8::try_from(value).map_err(invalid)?)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let value = self.read_u32()? as i32;
+        visitor.visit_i16(i16::try_from(value).map_err(invalid)?)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i32(self.read_u32()? as i32)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i64(self.read_u64()? as i64)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let value = self.read_u32()?;
+        visitor.visit_u8(u8::try_from(value).map_err(invalid)?)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let value = self.read_u32()?;
+        visitor.visit_u16(u16::try_from(value).map_err(invalid)?)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u32(self.read_u32()?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u64(self.read_u64()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f32(f32::from_bits(self.read_u32()?))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f64(f64::from_bits(self.read_u64()?))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let value = self.read_u32()?;
+        match char::from_u32(value) {
+            Some(value) => visitor.visit_char(value),
+            None => Err(de::Error::invalid_value(
+                de::Unexpected::Unsigned(value as u64),
+                &"a char",
+            )),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let data = self.read_opaque()?;
+        visitor.visit_borrowed_str(std::str::from_utf8(data).map_err(invalid)?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let data = self.read_opaque()?;
+        let Some(shared) = self.shared else {
+            return visitor.visit_borrowed_bytes(data);
+        };
+        SHARED.set(Some(shared.slice_ref(data)));
+        let value = visitor.visit_borrowed_bytes(data);
+        // the visitors of anything but Bytes leave it
+        SHARED.take();
+        value
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.read_u32()? {
+            0 => visitor.visit_none(),
+            1 => visitor.visit_some(self),
+            value => Err(de::Error::invalid_value(
+                de::Unexpected::Unsigned(value as u64),
+                &"an optional-data discriminant",
+            )),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let length = self.read_u32()?;
+        visitor.visit_seq(Elements {
+            deserializer: self,
+            left: length,
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, length: usize, visitor: V) -> Result<V::Value> {
+        let left = u32::try_from(length).map_err(invalid)?;
+        visitor.visit_seq(Elements {
+            deserializer: self,
+            left,
+        })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        length: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(length, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(de::Error::custom("XDR has no maps"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        let variant = self.read_u32()?;
+        if variant as usize >= variants.len() {
+            return Err(de::Error::custom(format!(
+                "invalid variant {} of enum {}",
+                variant, name
+            )));
+        }
+        visitor.visit_enum(Variant {
+            deserializer: self,
+            variant,
+        })
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(de::Error::custom("XDR has no identifiers"))
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(de::Error::custom("XDR is not self-describing"))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+// the elements of a sequence, a tuple or a struct
+struct Elements<'a, 'de> {
+    deserializer: &'a mut XdrDeserializer<'de>,
+    left: u32,
+}
+
+impl<'de> SeqAccess<'de> for Elements<'_, 'de> {
+    type Error = CompatDeserializationError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.left == 0 {
+            return Ok(None);
+        }
+        self.left -= 1;
+        seed.deserialize(&mut *self.deserializer).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        // every element takes a word at least, a length from the wire
+        // doesn't preallocate more than the buffer could hold
+        Some((self.left as usize).min(self.deserializer.remaining() / 4))
+    }
+}
+
+// the discriminant of an enum and the value of its arm
+struct Variant<'a, 'de> {
+    deserializer: &'a mut XdrDeserializer<'de>,
+    variant: u32,
+}
+
+impl<'a, 'de> EnumAccess<'de> for Variant<'a, 'de> {
+    type Error = CompatDeserializationError;
+    type Variant = &'a mut XdrDeserializer<'de>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self::Variant)> {
+        let variant: U32Deserializer<CompatDeserializationError> = self.variant.into_deserializer();
+        Ok((seed.deserialize(variant)?, self.deserializer))
+    }
+}
+
+impl<'de> VariantAccess<'de> for &mut XdrDeserializer<'de> {
+    type Error = CompatDeserializationError;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, length: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_tuple(length, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+}
+
+/// A type holding variable-length opaque data or a string on the wire.
+pub trait OpaqueData: Sized {
+    fn from_opaque(data: &[u8]) -> std::result::Result<Self, String>;
+
+    fn as_opaque(&self) -> &[u8];
+}
+
+impl OpaqueData for Bytes {
+    fn from_opaque(data: &[u8]) -> std::result::Result<Self, String> {
+        // shared by XdrDeserializer::shared if it is the same memory,
+        // copied out of any other deserializer
+        match SHARED.take() {
+            Some(shared) if shared.as_ptr() == data.as_ptr() && shared.len() == data.len() => {
+                Ok(shared)
+            }
+            _ => Ok(Bytes::copy_from_slice(data)),
+        }
+    }
+
+    fn as_opaque(&self) -> &[u8] {
+        self
+    }
+}
+
+impl OpaqueData for Vec<u8> {
+    fn from_opaque(data: &[u8]) -> std::result::Result<Self, String> {
+        Ok(data.to_vec())
+    }
+
+    fn as_opaque(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<const N: usize> OpaqueData for [u8; N] {
+    fn from_opaque(data: &[u8]) -> std::result::Result<Self, String> {
+        data.try_into()
+            .map_err(|_| format!("expected {} bytes, got {}", N, data.len()))
+    }
+
+    fn as_opaque(&self) -> &[u8] {
+        self
+    }
+}
+
+impl OpaqueData for String {
+    fn from_opaque(data: &[u8]) -> std::result::Result<Self, String> {
+        std::str::from_utf8(data)
+            .map(str::to_string)
+            .map_err(|e| e.to_string())
+    }
+
+    fn as_opaque(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+/// Variable-length opaque data and strings, a replacement for
+/// `serde_bytes`: `#[serde(with = "crate::xdr::opaque")]`. Only
+/// [`XdrDeserializer`] bounds their length, wire data is never decoded with
+/// serde-xdr.
+pub mod opaque {
+    use super::*;
+
+    pub fn serialize<T: OpaqueData, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(value.as_opaque())
+    }
+
+    pub fn deserialize<'de, T: OpaqueData, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<T, D::Error> {
+        deserializer.deserialize_bytes(OpaqueVisitor(std::marker::PhantomData))
+    }
+}
+
+struct OpaqueVisitor<T>(std::marker::PhantomData<T>);
+
+impl<T: OpaqueData> Visitor<'_> for OpaqueVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("variable-length opaque data")
+    }
+
+    fn visit_bytes<E: de::Error>(self, data: &[u8]) -> std::result::Result<T, E> {
+        T::from_opaque(data).map_err(de::Error::custom)
+    }
+}
+
+/// A value decoded with [`opaque`], for opaque data that isn't a field,
+/// e.g. `from_bytes::<Opaque<String>>(..)`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Opaque<T>(pub T);
+
+impl<T: OpaqueData> Serialize for Opaque<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        opaque::serialize(&self.0, serializer)
+    }
+}
+
+impl<'de, T: OpaqueData> Deserialize<'de> for Opaque<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        opaque::deserialize(deserializer).map(Opaque)
+    }
+}